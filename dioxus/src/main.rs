@@ -6,7 +6,7 @@ use depict::graph_drawing::error::{Error, Kind};
 use depict::graph_drawing::eval::{Val, Body};
 use depict::graph_drawing::frontend::log::Record;
 use depict::graph_drawing::frontend::dom::{draw, Drawing, Rect};
-use depict::graph_drawing::frontend::dioxus::{render, as_data_svg, DEFAULT_CSS};
+use depict::graph_drawing::frontend::dioxus::{render, as_data_svg, APP_CSS};
 
 use dioxus::prelude::*;
 use dioxus_desktop::{self, Config, WindowBuilder};
@@ -150,7 +150,7 @@ pub fn parse_highlights<'s>(data: &'s str) -> Result<Val<Cow<'s, str>>, Error> {
 
     // eprintln!("HIGHLIGHT PARSE {items:#?}");
 
-    let mut val = eval(&items);
+    let mut val = eval(&items)?;
 
     // eprintln!("HIGHLIGHT EVAL {val:#?}");
 
@@ -217,6 +217,7 @@ pub fn app(cx: Scope<AppProps>) -> Element {
     let status_h = drawing.get().status_h;
     let collisions = &drawing.get().collisions;
     let status_c = collisions.len();
+    let status_w = drawing.get().warnings.len();
 
     let show_logs = use_state(&cx, || {
         #[cfg(debug_assertions)]
@@ -371,7 +372,7 @@ pub fn app(cx: Scope<AppProps>) -> Element {
 
     let syntax_guide = depict::graph_drawing::frontend::dioxus::syntax_guide(cx)?;
 
-    let style_default = format!("{DEFAULT_CSS}\n.content {{margin-top: 240px; }}");
+    let style_default = format!("{APP_CSS}\n.content {{margin-top: 240px; }}");
     cx.render(rsx!{
         head {
             style {
@@ -513,7 +514,7 @@ pub fn app(cx: Scope<AppProps>) -> Element {
                         span {
                             class: "ui",
                             style: "font-style: italic;",
-                            "v: {status_v:?} h: {status_h:?} cn: {crossing_number:?}, c: {status_c:?}"
+                            "v: {status_v:?} h: {status_h:?} cn: {crossing_number:?}, c: {status_c:?}, w: {status_w:?}"
                         }
                     }
                 })
@@ -1,7 +1,7 @@
-use std::{path::Path, fs::File, io::{Write, stdout}};
+use std::{collections::HashSet, path::{Path, PathBuf}, fs::File, io::{Write, stdout}};
 
 use clap::Parser;
-use depict::graph_drawing::{error::Error, frontend::{dioxus::{render, DEFAULT_CSS}, dom::Drawing}};
+use depict::graph_drawing::{error::Error, export, frontend::{dioxus::{render, APP_CSS}, dom::Drawing, pdf::PdfOptions, png::PngOptions, RenderOptions}, lint::{self, Rule}, query::{self, Query}};
 
 use dioxus::prelude::{*};
 use walkdir::WalkDir;
@@ -14,15 +14,182 @@ pub struct Args {
     #[clap(short = 'e')]
     exprs: Vec<String>,
 
+    /// Where to write: a file, "-" for stdout, or a directory for images of several inputs
     #[clap(short = 'o', default_value = "-")]
     output: String,
+
+    /// Lint rules to disable, e.g. `-A missing-feedback`
+    #[clap(short = 'A')]
+    allow: Vec<String>,
+
+    /// Wrap box labels wider than this many pixels
+    #[clap(short = 'w')]
+    max_box_width: Option<f64>,
+
+    /// Read layout options from this JSON file
+    #[clap(short = 'c')]
+    options: Option<String>,
+
+    /// Which way vertical chains run: TB, BT, LR, or RL
+    #[clap(short = 'O')]
+    orientation: Option<String>,
+
+    /// How arrows run between their hops: straight or orthogonal
+    #[clap(short = 'E')]
+    edge_style: Option<String>,
+
+    /// Which hops of long vertical arrows carry their labels: head, top, middle, bottom, or repeated
+    #[clap(short = 'L')]
+    label_hop: Option<String>,
+
+    /// Theme of rendered drawings: classic, ink, or blueprint
+    #[clap(short = 'T')]
+    theme: Option<String>,
+
+    /// Draw a legend of the style blocks that each drawing uses
+    #[clap(short = 'K')]
+    legend: bool,
+
+    /// Write standalone SVG documents, one per drawing, rather than an HTML page
+    #[clap(short = 'S')]
+    svg: bool,
+
+    /// Write PNG images, one per drawing, rather than an HTML page
+    #[clap(short = 'P')]
+    png: bool,
+
+    /// Enlarge PNG images by this factor
+    #[clap(short = 'z', default_value = "1")]
+    scale: f32,
+
+    /// Pixels per inch of PNG images
+    #[clap(short = 'd', default_value = "96")]
+    dpi: f32,
+
+    /// Background color of PNG images, or "none" for transparent images
+    #[clap(short = 'b', default_value = "white")]
+    background: String,
+
+    /// Write PDF documents, one per drawing, rather than an HTML page
+    #[clap(short = 'D')]
+    pdf: bool,
+
+    /// Page size of PDF documents: drawing, a4, a3, letter, legal, or WIDTHxHEIGHT in points
+    #[clap(short = 'g', default_value = "drawing")]
+    page_size: String,
+
+    /// Turn PDF pages of standard sizes sideways
+    #[clap(short = 'l')]
+    landscape: bool,
+
+    /// How to scale drawings to fit PDF pages of standard sizes: shrink, fill, or natural
+    #[clap(short = 'F', default_value = "shrink")]
+    fit: String,
+
+    /// Write Mermaid flowcharts, one per model, rather than an HTML page
+    #[clap(short = 'M')]
+    mermaid: bool,
+
+    /// Write PlantUML component diagrams, one per model, rather than an HTML page
+    #[clap(short = 'U')]
+    plantuml: bool,
+}
+
+/// Errors reported by the command line, on top of depict's own
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error(transparent)]
+    DepictError{#[from] source: Error},
+    #[error("io error")]
+    IoError{#[from] source: std::io::Error},
+    #[error("several images need an output directory")]
+    OutputDirectoryError{inputs: usize},
+    #[error("duplicate output file")]
+    DuplicateOutputError{path: PathBuf},
+    #[error("invalid options")]
+    OptionsError{path: String, source: serde_json::Error},
+}
+
+/// What to write for each drawing in place of an HTML page
+enum Image {
+    Svg,
+    Png(PngOptions),
+    Pdf(PdfOptions),
+    Mermaid,
+    PlantUml,
+}
+
+impl Image {
+    /// File extension of images of this kind
+    fn extension(&self) -> &'static str {
+        match self {
+            Image::Svg => "svg",
+            Image::Png(_) => "png",
+            Image::Pdf(_) => "pdf",
+            Image::Mermaid => "mmd",
+            Image::PlantUml => "puml",
+        }
+    }
+}
+
+fn open_output(output: &str) -> Result<Option<Box<dyn Write>>, CliError> {
+    Ok(if output.is_empty() {
+        None
+    } else if output == "-" {
+        Some(Box::new(stdout()) as Box<dyn Write>)
+    } else {
+        Some(Box::new(File::create(output)?) as Box<dyn Write>)
+    })
+}
+
+/// Read every file under each of `paths`, then each of `exprs`, naming exprs `expr-N`.
+fn read_inputs(paths: &[String], exprs: &[String]) -> Result<Vec<(PathBuf, String)>, CliError> {
+    let mut inputs = vec![];
+    for path in paths {
+        for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                inputs.push((entry.path().to_path_buf(), std::fs::read_to_string(entry.path())?));
+            }
+        }
+    }
+    for (n, expr) in exprs.iter().enumerate() {
+        inputs.push((PathBuf::from(format!("expr-{n}")), expr.clone()));
+    }
+    Ok(inputs)
+}
+
+/// Write one image for each input: to `output` if there is only one, or else
+/// to `<stem>.<extension>` in the directory `output`, since images can't share a file.
+fn do_images(output: &str, lints: &lint::Config, options: &RenderOptions, image: &Image, inputs: Vec<(PathBuf, String)>) -> Result<(), CliError> {
+    if inputs.len() == 1 {
+        let mut output = open_output(output)?;
+        for (path, data) in inputs {
+            do_one_image(&mut output, lints, options, image, path, data)?;
+        }
+        return Ok(())
+    }
+    if output.is_empty() || output == "-" {
+        return Err(CliError::OutputDirectoryError{inputs: inputs.len()})
+    }
+    let dir = Path::new(output);
+    std::fs::create_dir_all(dir)?;
+    let mut written = HashSet::new();
+    for (path, data) in inputs {
+        let stem = path.file_stem().unwrap_or(path.as_os_str());
+        let file = dir.join(stem).with_extension(image.extension());
+        if !written.insert(file.clone()) {
+            return Err(CliError::DuplicateOutputError{path: file})
+        }
+        let mut output = Some(Box::new(File::create(&file)?) as Box<dyn Write>);
+        do_one_image(&mut output, lints, options, image, path, data)?;
+    }
+    Ok(())
 }
 
-fn do_one_path<P: AsRef<Path> + Clone>(output: &mut Option<Box<dyn Write>>, path: P) -> Result<(), Error> {
+fn do_one_path<P: AsRef<Path> + Clone>(output: &mut Option<Box<dyn Write>>, lints: &lint::Config, options: &RenderOptions, path: P) -> Result<(), CliError> {
     for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
         if entry.file_type().is_file() {
-            let data = std::fs::read_to_string(entry.path())
-                .expect("read error");
+            let data = std::fs::read_to_string(entry.path())?;
 
             output.as_mut().map(|output| {
                 output.write(r#"<div class="file">"#.as_bytes()).unwrap();
@@ -34,7 +201,7 @@ fn do_one_path<P: AsRef<Path> + Clone>(output: &mut Option<Box<dyn Write>>, path
                 output.write("</div>".as_bytes()).unwrap();
             });
             output.as_mut().map(|output| output.write(r#"<div class="drawing">"#.as_bytes()).unwrap());
-            do_one_expr(output, entry.path(), data)?;
+            do_one_expr(output, lints, options, entry.path(), data)?;
             output.as_mut().map(|output| output.write("</div>".as_bytes()).unwrap());
             output.as_mut().map(|output| output.write("</div>".as_bytes()).unwrap());
             output.as_mut().map(|output| output.write("</div>".as_bytes()).unwrap());
@@ -48,8 +215,47 @@ struct Props {
     drawing: Drawing,
 }
 
-fn do_one_expr<P: AsRef<Path> + Clone>(output: &mut Option<Box<dyn Write>>, _path: P, data: String) -> Result<(), Error> {
-    let drawing = depict::graph_drawing::frontend::dom::draw(data);
+fn report_warnings<P: AsRef<Path>>(path: P, data: &str, drawing: &Drawing) {
+    let path = path.as_ref().to_string_lossy();
+    for warning in drawing.warnings.iter() {
+        match &warning.span {
+            Some(span) => {
+                let prefix = &data[..span.start];
+                let line = prefix.matches('\n').count() + 1;
+                let col = prefix.chars().rev().take_while(|c| *c != '\n').count() + 1;
+                eprintln!("{path}:{line}:{col}: {warning}");
+            },
+            None => eprintln!("{path}: {warning}"),
+        }
+    }
+}
+
+fn do_one_image<P: AsRef<Path> + Clone>(output: &mut Option<Box<dyn Write>>, lints: &lint::Config, options: &RenderOptions, image: &Image, path: P, data: String) -> Result<(), Error> {
+    if let Image::Mermaid | Image::PlantUml = image {
+        // text exports come from the evaluated model and need no layout
+        let val = query::parse(&data)?;
+        let query = Query::new(&val)?;
+        let text = if let Image::Mermaid = image { export::to_mermaid(&query) } else { export::to_plantuml(&query) };
+        output.as_mut().map(|output| output.write(text.as_bytes()).unwrap());
+        return Ok(())
+    }
+    let drawing = depict::graph_drawing::frontend::dom::draw_with(data.clone(), lints, options)?;
+    report_warnings(path, &data, &drawing);
+    let bytes = match image {
+        Image::Svg => drawing.to_svg().into_bytes(),
+        Image::Png(png_options) => drawing.to_png(png_options)?,
+        Image::Pdf(pdf_options) => drawing.to_pdf(pdf_options)?,
+        Image::Mermaid | Image::PlantUml => unreachable!(),
+    };
+    output.as_mut().map(|output| output.write(&bytes).unwrap());
+    Ok(())
+}
+
+fn do_one_expr<P: AsRef<Path> + Clone>(output: &mut Option<Box<dyn Write>>, lints: &lint::Config, options: &RenderOptions, path: P, data: String) -> Result<(), Error> {
+    let drawing = depict::graph_drawing::frontend::dom::draw_with(data.clone(), lints, options);
+    if let Ok(drawing) = &drawing {
+        report_warnings(path, &data, drawing);
+    }
     output.as_mut().map(|output| {
         if let Ok(drawing) = drawing {
             fn app(cx: Scope<Props>) -> Element {
@@ -71,7 +277,7 @@ fn do_one_expr<P: AsRef<Path> + Clone>(output: &mut Option<Box<dyn Write>>, _pat
     Ok(())
 }
 
-fn main() -> Result<(), Error> {
+fn main() -> Result<(), CliError> {
     miette::set_hook(Box::new(|_| {
         Box::new(
             miette::MietteHandlerOpts::new()
@@ -85,14 +291,59 @@ fn main() -> Result<(), Error> {
 
     let args = Args::parse();
 
+    let mut lints = lint::Config::default();
+    for rule in args.allow.iter() {
+        lints.disable(rule.parse::<Rule>()?);
+    }
 
-    let mut output = if args.output.is_empty() {
-        None
-    } else if &args.output == "-" {
-        Some(Box::new(stdout()) as Box<dyn Write>)
+    let mut options = match &args.options {
+        Some(path) => {
+            let json = std::fs::read_to_string(path)?;
+            serde_json::from_str::<RenderOptions>(&json)
+                .map_err(|source| CliError::OptionsError{path: path.clone(), source})?
+        },
+        None => RenderOptions::default(),
+    };
+    if args.max_box_width.is_some() {
+        options.max_box_width = args.max_box_width;
+    }
+    if let Some(orientation) = &args.orientation {
+        options.orientation = orientation.parse()?;
+    }
+    if let Some(edge_style) = &args.edge_style {
+        options.edge_style = edge_style.parse()?;
+    }
+    if let Some(label_hop) = &args.label_hop {
+        options.label_hop = label_hop.parse()?;
+    }
+    if let Some(theme) = &args.theme {
+        options.theme = theme.parse()?;
+    }
+    if args.legend {
+        options.legend = true;
+    }
+
+    let image = if args.mermaid {
+        Some(Image::Mermaid)
+    } else if args.plantuml {
+        Some(Image::PlantUml)
+    } else if args.pdf {
+        let (page_size, fit) = (args.page_size.parse()?, args.fit.parse()?);
+        Some(Image::Pdf(PdfOptions{page_size, landscape: args.landscape, fit, ..Default::default()}))
+    } else if args.png {
+        let background = if args.background == "none" { None } else { Some(args.background.clone()) };
+        Some(Image::Png(PngOptions{scale: args.scale, dpi: args.dpi, background, ..Default::default()}))
+    } else if args.svg {
+        Some(Image::Svg)
     } else {
-        Some(Box::new(File::create(args.output).unwrap()) as Box<dyn Write>)
+        None
     };
+    if let Some(image) = &image {
+        let inputs = read_inputs(&args.paths, &args.exprs)?;
+        return do_images(&args.output, &lints, &options, image, inputs)
+    }
+
+    let mut output = open_output(&args.output)?;
 
     output.as_mut().map(|output| {
         output.write(r#"
@@ -115,7 +366,7 @@ fn main() -> Result<(), Error> {
             div.file { border: 1px dashed #aaa; }
         }
         "#.as_bytes()).unwrap();
-        output.write(DEFAULT_CSS.as_bytes()).unwrap();
+        output.write(APP_CSS.as_bytes()).unwrap();
         output.write(r#"
         </style>
         </head>
@@ -123,11 +374,11 @@ fn main() -> Result<(), Error> {
         "#.as_bytes()).unwrap()
     });
     for path in args.paths {
-        do_one_path(&mut output, path)?;
+        do_one_path(&mut output, &lints, &options, path)?;
     }
 
     for expr in args.exprs {
-        do_one_expr(&mut output, String::new(), expr)?;
+        do_one_expr(&mut output, &lints, &options, "<expr>", expr)?;
     }
     output.as_mut().map(|output| output.write("</body></html>".as_bytes()));
     Ok(())
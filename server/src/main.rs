@@ -2,7 +2,7 @@ use std::borrow::Cow;
 use std::net::SocketAddr;
 
 use axum::routing::get_service;
-use axum::{http::StatusCode, Json, response::IntoResponse, Router, routing::post};
+use axum::{http::{header, StatusCode}, Json, response::IntoResponse, Router, routing::post};
 
 use depict::graph_drawing::error::Error;
 use depict::graph_drawing::error::Kind;
@@ -10,6 +10,7 @@ use depict::graph_drawing::error::OrErrExt;
 use depict::graph_drawing::index::OriginalHorizontalRank;
 use depict::graph_drawing::index::VerticalRank;
 use depict::graph_drawing::layout::Obj;
+use depict::graph_drawing::lint::{self, Rule};
 
 use inflector::Inflector;
 
@@ -54,10 +55,14 @@ impl IntoResponse for DrawError {
 
 #[instrument]
 async fn draw<'s>(Json(draw_rx): Json<Draw>) -> Result<Json<DrawResp>, DrawError> {
-    let data = draw_rx.text.clone();
+    let Draw{text: data, options, allow} = draw_rx;
 
     tokio::task::spawn_blocking(move || {
-        let drawing = depict::graph_drawing::frontend::dom::draw(data)?;
+        let mut lints = lint::Config::default();
+        for rule in allow.iter() {
+            lints.disable(rule.parse::<Rule>()?);
+        }
+        let drawing = depict::graph_drawing::frontend::dom::draw_with(data, &lints, &options)?;
         let drawing = depict::rest::Drawing{
             crossing_number: drawing.crossing_number,
             viewbox_width: drawing.viewbox_width,
@@ -65,14 +70,17 @@ async fn draw<'s>(Json(draw_rx): Json<Draw>) -> Result<Json<DrawResp>, DrawError
                 depict::graph_drawing::frontend::dom::Node::Div{key, label, hpos, vpos, width, height, z_index, ..} => {
                     depict::rest::Node::Div{ key, label, hpos, vpos, width, height, z_index }
                 },
-                depict::graph_drawing::frontend::dom::Node::Svg{ key, path, z_index, rel, label, .. } => {
-                    depict::rest::Node::Svg{ key, path, z_index, rel, label: label.map(
-                        |depict::graph_drawing::frontend::dom::Label{text, hpos, width, vpos}| {
+                depict::graph_drawing::frontend::dom::Node::Svg{ key, path, z_index, rel, labels, .. } => {
+                    depict::rest::Node::Svg{ key, path, z_index, rel, labels: labels.into_iter().map(
+                        |depict::graph_drawing::frontend::dom::Label{text, hpos, width, vpos, ..}| {
                             depict::rest::Label{ text, hpos, width, vpos }
-                        })
+                        }).collect()
                     }
                 },
             }).collect::<Vec<_>>(),
+            warnings: drawing.warnings.into_iter().map(|w| {
+                depict::rest::Warning{ rule: w.rule.to_string(), message: w.message, span: w.span.map(|s| (s.start, s.end)) }
+            }).collect::<Vec<_>>(),
         };
 
         Result::<Json<DrawResp>, DrawError>::Ok(Json(DrawResp{
@@ -81,6 +89,41 @@ async fn draw<'s>(Json(draw_rx): Json<Draw>) -> Result<Json<DrawResp>, DrawError
     }).await?
 }
 
+/// Draw a "depiction" as a standalone SVG document.
+#[instrument]
+async fn render_svg(Json(draw_rx): Json<Draw>) -> Result<impl IntoResponse, DrawError> {
+    let Draw{text: data, options, ..} = draw_rx;
+
+    tokio::task::spawn_blocking(move || {
+        let drawing = depict::graph_drawing::frontend::dom::draw_with(data, &Default::default(), &options)?;
+        Result::<_, DrawError>::Ok(([(header::CONTENT_TYPE, "image/svg+xml")], drawing.to_svg()))
+    }).await?
+}
+
+/// Draw a "depiction" as a PNG image.
+#[instrument]
+async fn render_png(Json(draw_rx): Json<DrawPng>) -> Result<impl IntoResponse, DrawError> {
+    let DrawPng{text: data, options, png} = draw_rx;
+
+    tokio::task::spawn_blocking(move || {
+        let drawing = depict::graph_drawing::frontend::dom::draw_with(data, &Default::default(), &options)?;
+        let png = drawing.to_png(&png).map_err(Error::from)?;
+        Result::<_, DrawError>::Ok(([(header::CONTENT_TYPE, "image/png")], png))
+    }).await?
+}
+
+/// Draw a "depiction" as a PDF.
+#[instrument]
+async fn render_pdf(Json(draw_rx): Json<DrawPdf>) -> Result<impl IntoResponse, DrawError> {
+    let DrawPdf{text: data, options, pdf} = draw_rx;
+
+    tokio::task::spawn_blocking(move || {
+        let drawing = depict::graph_drawing::frontend::dom::draw_with(data, &Default::default(), &options)?;
+        let pdf = drawing.to_pdf(&pdf).map_err(Error::from)?;
+        Result::<_, DrawError>::Ok(([(header::CONTENT_TYPE, "application/pdf")], pdf))
+    }).await?
+}
+
 fn main() -> Result<(), hyper::Error> {
     tracing_subscriber::Registry::default()
         .with(tracing_error::ErrorLayer::default())
@@ -94,6 +137,9 @@ fn main() -> Result<(), hyper::Error> {
         .block_on(async move {
             let app = Router::new()
                 .route("/api/draw/v1", post(draw))
+                .route("/api/render/svg", post(render_svg))
+                .route("/api/render/png", post(render_png))
+                .route("/api/render/pdf", post(render_pdf))
                 .fallback(
                     get_service(ServeDir::new(std::env::var("WEBROOT").unwrap())).handle_error(|error: std::io::Error| async move {
                         (
@@ -152,6 +152,12 @@ pub mod error {
         UnimplementedDrawingStyleError { style: String },
         #[error("pomelo error")]
         PomeloError { span: Range<usize>, text: String },
+        #[error("template arity error")]
+        TemplateArityError { template: String, params: usize, args: usize },
+        #[error("unbound template parameter error")]
+        UnboundParameterError { template: String, param: String },
+        #[error("template argument error")]
+        TemplateArgumentError { template: String, arg: String },
     }
 
     #[non_exhaustive]
@@ -189,6 +195,42 @@ pub mod error {
         DeepNameError{name: String},
         #[error("unknown mode")]
         UnknownModeError{mode: String},
+        #[error("unknown lint rule")]
+        UnknownLintRuleError{rule: String},
+        #[error("unknown orientation")]
+        UnknownOrientationError{orientation: String},
+        #[error("unknown edge style")]
+        UnknownEdgeStyleError{edge_style: String},
+        #[error("unknown label hop")]
+        UnknownLabelHopError{label_hop: String},
+        #[error("unknown theme")]
+        UnknownThemeError{theme: String},
+        #[error("unknown shape")]
+        UnknownShapeError{shape: String},
+        #[error("unknown page size")]
+        UnknownPageSizeError{page_size: String},
+        #[error("unknown fit")]
+        UnknownFitError{fit: String},
+    }
+
+    #[cfg(feature="png")]
+    #[non_exhaustive]
+    #[derive(Debug, thiserror::Error)]
+    pub enum PngError {
+        #[error("svg error")]
+        SvgError{#[from] source: resvg::usvg::Error},
+        #[error("image size error")]
+        SizeError{width: f32, height: f32},
+        #[error("png encoding error")]
+        EncodingError{error: String},
+    }
+
+    #[cfg(feature="pdf")]
+    #[non_exhaustive]
+    #[derive(Debug, thiserror::Error)]
+    pub enum PdfError {
+        #[error("font error")]
+        FontError{font: String, error: String},
     }
 
     #[non_exhaustive]
@@ -213,7 +255,17 @@ pub mod error {
         #[error(transparent)]
         LogError{
             #[from] source: LogError,
-        }
+        },
+        #[cfg(feature="png")]
+        #[error(transparent)]
+        PngError{
+            #[from] source: PngError,
+        },
+        #[cfg(feature="pdf")]
+        #[error(transparent)]
+        PdfError{
+            #[from] source: PdfError,
+        },
     }
 
     /// A trait to use to annotate [Option] values with rich error information.
@@ -329,29 +381,34 @@ pub mod index {
 pub mod eval {
     //! The main "value" type of depiction parts.
 
-    use std::{collections::{HashMap, HashSet}, borrow::{Cow, Borrow}, vec::IntoIter, ops::Deref, slice::Iter};
+    use std::{collections::{HashMap, HashSet}, borrow::{Cow, Borrow}, fmt::Display, vec::IntoIter, ops::Deref, slice::Iter};
 
     use crate::{parser::Item};
+    use crate::graph_drawing::error::{Error, Kind};
     use crate::parser::visit::{*, Visit as VisitItem};
     use crate::graph_drawing::eval::visit::{*, Visit as VisitVal};
 
     /// What kind of relationship between processes does
     /// the containing [Val::Chain] describe?
-    #[derive(Clone, Debug, Eq, PartialEq)]
+    #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     pub enum Rel {
         Vertical,
         Horizontal,
     }
 
     /// What direction does this label between processes apply to?
-    #[derive(Clone, Debug, Eq, PartialEq, Hash)]
+    #[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
     pub enum Dir {
         Forward,
         Reverse,
     }
 
+    /// Where is a [Note] placed relative to the box it annotates?
+    ///
+    /// Positions are given in the layout's rank frame: "north" lies toward
+    /// the ranks that come first.
     #[non_exhaustive]
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     pub enum Position {
         /// north, west, outside
         NWO,
@@ -363,7 +420,45 @@ pub mod eval {
         SI,
     }
 
-    #[derive(Clone, Debug)]
+    impl Position {
+        /// The keyword that selects this position, as in `a @ nwo: note`
+        pub fn keyword(&self) -> &'static str {
+            match self {
+                Position::NWO => "nwo",
+                Position::NEO => "neo",
+                Position::NWI => "nwi",
+                Position::SI => "si",
+            }
+        }
+
+        pub fn from_keyword(keyword: &str) -> Option<Self> {
+            [Position::NWO, Position::NEO, Position::NWI, Position::SI]
+                .into_iter()
+                .find(|pos| pos.keyword() == keyword)
+        }
+
+        /// Does a note at this position lie inside the box it annotates?
+        pub fn is_inside(&self) -> bool {
+            matches!(self, Position::NWI | Position::SI)
+        }
+    }
+
+    impl Display for Position {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.keyword())
+        }
+    }
+
+    /// A callout attached to a process, written like
+    ///
+    /// ```text
+    /// controller @ nwo: runs every 10ms
+    /// plant [ valve ] @ dashed si: see appendix
+    /// ```
+    ///
+    /// Any words before the position keyword style the process, as with `@`
+    /// alone; the position defaults to [Position::NEO].
+    #[derive(Clone, Debug, PartialEq)]
     pub struct Note<V> {
         /// Content of the note
         pub label: V,
@@ -483,6 +578,9 @@ pub mod eval {
 
             /// Maybe this process is styled?
             style: Option<Vec<V>>,
+
+            /// Maybe this process is annotated?
+            notes: Option<Vec<Note<V>>>,
         },
         Chain {
             /// Maybe this chain is named?
@@ -516,6 +614,7 @@ pub mod eval {
                 label: None,
                 body: None,
                 style: None,
+                notes: None,
             }
         }
     }
@@ -594,6 +693,15 @@ pub mod eval {
                 _ => None,
             }
         }
+
+        pub fn notes_mut(&mut self) -> Option<&mut Vec<Note<V>>> {
+            match self {
+                Val::Process{ notes, .. } => {
+                    Some(notes.get_or_insert_with(Default::default))
+                },
+                _ => None,
+            }
+        }
     }
 
     impl<'s> From<Body<Cow<'s, str>>> for Item<'s> {
@@ -636,7 +744,7 @@ pub mod eval {
     impl<'s> From<Val<Cow<'s, str>>> for Item<'s> {
         fn from(value: Val<Cow<'s, str>>) -> Self {
             match value {
-                Val::Process { name, label, body, style } => {
+                Val::Process { name, label, body, style, notes } => {
                     let inner = match (name, label, body) {
                         (None, None, None) => unreachable!(),
                         (None, None, Some(body)) => body.into(),
@@ -655,11 +763,15 @@ pub mod eval {
                             Item::Colon(vec![to_item(name)], vec![to_item(label), body.into()])
                         },
                     };
-                    if let Some(style) = style {
+                    let inner = if let Some(style) = style {
                         Item::At(vec![inner], style.into_iter().map(|s| to_item(s)).collect::<Vec<_>>())
                     } else {
                         inner
-                    }
+                    };
+                    notes.into_iter().flatten().fold(inner, |inner, note| {
+                        let pos = Item::Text(Cow::from(note.pos.keyword()));
+                        Item::At(vec![inner], vec![Item::Colon(vec![pos], vec![to_item(note.label)])])
+                    })
                 },
                 Val::Chain { name, rel, path, labels, style } => {
                     let path = path.into_iter().map(|p| p.into()).collect::<Vec<_>>();
@@ -715,7 +827,7 @@ pub mod eval {
     impl<'s, 't> log::Log<Cow<'s, str>> for &'t Val<Cow<'s, str>> {
         fn log(&self, _cx: Cow<'s, str>, l: &mut log::Logger) -> Result<(), log::Error> {
             match self {
-                Val::Process { name, label, body, style } => {
+                Val::Process { name, label, body, style, .. } => {
                     let pname = &as_string2(name, label, "");
                     l.with_group(
                         "Process",
@@ -796,12 +908,177 @@ pub mod eval {
         }
     }
 
-    #[derive(Clone, Debug)]
+    #[derive(Debug)]
     struct Eval<'s> {
         stack: Vec<Thing>,
         value: Val<Cow<'s, str>>,
         comma_buffer: Option<Vec<Cow<'s, str>>>,
         model: Model<'s>,
+        templates: HashMap<Cow<'s, str>, Template<'s>>,
+        /// Errors found while expanding templates, which [eval()] reports
+        errors: Vec<Error>,
+    }
+
+    /// A reusable, parameterized model fragment, declared like
+    ///
+    /// ```text
+    /// loop: $ctl $proc [ $ctl $proc: act / sense; $proc gauge ]
+    /// ```
+    ///
+    /// and instantiated like
+    ///
+    /// ```text
+    /// heating: $loop thermostat furnace
+    /// ```
+    ///
+    /// Parameters are bound positionally to single-word arguments; instantiating
+    /// a template with the wrong number of arguments, or one whose body uses a
+    /// parameter that it does not declare, is an error.
+    /// Non-parameter processes like `gauge` are local to each instance and
+    /// are qualified by the instance name (e.g., `heating.gauge`) via the
+    /// usual [index]/[resolve] scope machinery.
+    #[derive(Clone, Debug)]
+    struct Template<'s> {
+        name: Cow<'s, str>,
+        params: Vec<Cow<'s, str>>,
+        body: Vec<Val<Cow<'s, str>>>,
+    }
+
+    fn is_param(text: &str) -> bool {
+        text.len() > 1 && text.starts_with('$')
+    }
+
+    fn qualify<'s>(instance: &str, local: &str) -> Cow<'s, str> {
+        Cow::Owned(format!("{instance}.{local}"))
+    }
+
+    /// Replace the parameter `text`, if it is one, by its argument.
+    fn substitute<'s>(text: &mut Cow<'s, str>, template: &Template<'s>, bindings: &HashMap<Cow<'s, str>, Cow<'s, str>>) -> Result<(), Error> {
+        if !is_param(text) {
+            return Ok(())
+        }
+        let Some(arg) = bindings.get(&*text) else {
+            return Err(Kind::UnboundParameterError{template: template.name.to_string(), param: text.to_string()}.into())
+        };
+        *text = arg.clone();
+        Ok(())
+    }
+
+    /// Bind template parameters in `val` and qualify the processes it defines,
+    /// recording the names of any (unqualified) local references.
+    fn bind<'s>(
+        val: &mut Val<Cow<'s, str>>,
+        template: &Template<'s>,
+        instance: &Cow<'s, str>,
+        bindings: &HashMap<Cow<'s, str>, Cow<'s, str>>,
+        locals: &mut Vec<Cow<'s, str>>,
+        defined: &mut HashSet<Cow<'s, str>>,
+    ) -> Result<(), Error> {
+        match val {
+            Val::Process { name, label, body, style, .. } => {
+                match label {
+                    Some(l) if is_param(l) => substitute(l, template, bindings)?,
+                    Some(l) if l == ">" || is_pattern(l) => {},
+                    Some(l) if name.is_none() && body.is_none() => {
+                        if !locals.contains(l) {
+                            locals.push(l.clone());
+                        }
+                    },
+                    _ => {
+                        if let Some(local) = name.clone().or_else(|| label.clone()) {
+                            *label = Some(qualify(instance, label.as_ref().unwrap_or(&local)));
+                            defined.insert(local.clone());
+                            name.get_or_insert(local);
+                        }
+                    },
+                }
+                for s in style.iter_mut().flatten() {
+                    substitute(s, template, bindings)?;
+                }
+                if let Some(body) = body {
+                    for val in body.as_mut().iter_mut() {
+                        bind(val, template, instance, bindings, locals, defined)?;
+                    }
+                }
+            },
+            Val::Chain { path, labels, style, .. } => {
+                for val in path.iter_mut() {
+                    bind(val, template, instance, bindings, locals, defined)?;
+                }
+                for level in labels.iter_mut() {
+                    for l in level.forward.iter_mut().flatten().chain(level.reverse.iter_mut().flatten()) {
+                        substitute(l, template, bindings)?;
+                    }
+                }
+                for s in style.iter_mut().flatten() {
+                    substitute(s, template, bindings)?;
+                }
+            },
+            Val::Style { .. } => {},
+        }
+        Ok(())
+    }
+
+    impl<'s> Template<'s> {
+        /// Expand this template into an ordinary named process containing
+        /// the template's body with `args` substituted for its parameters.
+        fn instantiate(&self, instance: &Cow<'s, str>, args: &[Cow<'s, str>]) -> Result<Val<Cow<'s, str>>, Error> {
+            if args.len() != self.params.len() {
+                return Err(Kind::TemplateArityError{template: self.name.to_string(), params: self.params.len(), args: args.len()}.into())
+            }
+            let bindings = self.params.iter().cloned().zip(args.iter().cloned()).collect::<HashMap<_, _>>();
+            let mut locals = vec![];
+            let mut defined = HashSet::new();
+            let mut body = self.body.clone();
+            for val in body.iter_mut() {
+                bind(val, self, instance, &bindings, &mut locals, &mut defined)?;
+            }
+            // declare the remaining locals so that `resolve` can qualify references to them
+            let mut parts = locals.into_iter()
+                .filter(|local| !defined.contains(local))
+                .map(|local| Val::Process{
+                    label: Some(qualify(instance, &local)),
+                    name: Some(local),
+                    body: None,
+                    style: None,
+                    notes: None,
+                })
+                .collect::<Vec<_>>();
+            parts.append(&mut body);
+            Ok(Val::Process{
+                name: Some(instance.clone()),
+                label: None,
+                body: Some(Body::All(parts)),
+                style: None,
+                notes: None,
+            })
+        }
+    }
+
+    /// Does `rhs` look like `$param... [ body ]`?
+    fn as_template_definition<'s, 't>(rhs: &'t [Item<'s>]) -> Option<(Vec<Cow<'s, str>>, &'t Vec<Item<'s>>)> {
+        let Some((Item::Sq(body), params)) = rhs.split_last() else { return None };
+        if params.is_empty() {
+            return None
+        }
+        params.iter()
+            .map(|p| match p {
+                Item::Text(p) if is_param(p) => Some(p.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|params| (params, body))
+    }
+
+    /// The words of `items`, in order.
+    fn words<'s>(items: &[Item<'s>], out: &mut Vec<Cow<'s, str>>) {
+        for item in items {
+            match item {
+                Item::Text(text) => out.push(text.clone()),
+                Item::Seq(items) | Item::Comma(items) => words(items, out),
+                _ => {},
+            }
+        }
     }
 
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -833,6 +1110,102 @@ pub mod eval {
             self.stack.pop();
             eprintln!("POP");
         }
+
+        fn child(&self) -> Self {
+            Eval {
+                stack: vec![],
+                value: Default::default(),
+                comma_buffer: None,
+                model: Default::default(),
+                templates: self.templates.clone(),
+                errors: vec![],
+            }
+        }
+
+        /// If `lhs: rhs` defines a template, record it.
+        fn define_template<'t>(&mut self, lhs: &'t Vec<Item<'s>>, rhs: &'t Vec<Item<'s>>) -> bool {
+            let [Item::Text(name)] = &lhs[..] else { return false };
+            let Some((params, body)) = as_template_definition(rhs) else { return false };
+            let mut ev = self.child();
+            ev.visit_model(body);
+            self.errors.append(&mut ev.errors);
+            self.templates.insert(name.clone(), Template{ name: name.clone(), params, body: ev.model.to_vec() });
+            true
+        }
+
+        /// What does an inline `@` apply to: the value being built, or else the one just pushed?
+        ///
+        /// A lone process like `c @ red` is pushed before its `@`, so this only differs from
+        /// styling `self.model.last_mut()` when a chain like `c * @ red` is still being built.
+        fn styled(&mut self) -> Option<&mut Val<Cow<'s, str>>> {
+            if self.value != Default::default() {
+                Some(&mut self.value)
+            } else {
+                self.model.last_mut()
+            }
+        }
+
+        /// Attach the note `[style...] [position]: text` to the styled value.
+        fn annotate<'t>(&mut self, lhs: &'t [Item<'s>], rhs: &'t [Item<'s>]) {
+            let mut style = vec![];
+            words(lhs, &mut style);
+            let pos = style.last().and_then(|word| Position::from_keyword(word));
+            if pos.is_some() {
+                style.pop();
+            }
+            let mut text = vec![];
+            words(rhs, &mut text);
+            let label = Cow::from(text.join(" ").replace("\\n", "\n"));
+            let note = Note{ label, pos: pos.unwrap_or(Position::NEO) };
+            if let Some(val) = self.styled() {
+                if !style.is_empty() {
+                    if let Some(s) = val.style_mut() {
+                        s.append(&mut style);
+                    }
+                }
+                if let Some(notes) = val.notes_mut() {
+                    notes.push(note);
+                }
+            }
+        }
+
+        /// If `lhs @ rhs` defines a style block `name: @ { property: value; ... }`, put it in `self.value`.
+        fn define_style<'t>(&mut self, lhs: &'t [Item<'s>], rhs: &'t [Item<'s>]) -> bool {
+            let [Item::Colon(name, rest)] = lhs else { return false };
+            let ([Item::Text(name)], []) = (&name[..], &rest[..]) else { return false };
+            let [Item::Br(properties)] = rhs else { return false };
+            let properties = properties
+                .iter()
+                .filter_map(|property| {
+                    let Item::Colon(property, value) = property else { return None };
+                    let [Item::Text(property)] = &property[..] else { return None };
+                    let mut value_words = vec![];
+                    words(value, &mut value_words);
+                    Some(Val::Process{ name: Some(property.clone()), label: Some(Cow::from(value_words.join(" "))), body: None, style: None, notes: None })
+                })
+                .collect::<Vec<_>>();
+            self.value = Val::Style{ name: Some(name.clone()), body: Some(Body::Any(properties)) };
+            true
+        }
+
+        /// If `lhs: rhs` instantiates a known template, expand it into `self.value`,
+        /// or record why it could not be expanded.
+        fn instantiate_template<'t>(&mut self, lhs: &'t Vec<Item<'s>>, rhs: &'t Vec<Item<'s>>) -> bool {
+            let [Item::Text(name)] = &lhs[..] else { return false };
+            let Some((Item::Text(head), args)) = rhs.split_first() else { return false };
+            let Some(template) = head.strip_prefix('$').and_then(|head| self.templates.get(head)) else { return false };
+            let args = args.iter()
+                .map(|a| match a {
+                    Item::Text(a) => Ok(a.clone()),
+                    _ => Err(Kind::TemplateArgumentError{template: template.name.to_string(), arg: format!("{a:?}")}.into()),
+                })
+                .collect::<Result<Vec<_>, Error>>();
+            match args.and_then(|args| template.instantiate(name, &args)) {
+                Ok(value) => self.value = value,
+                Err(error) => self.errors.push(error),
+            }
+            true
+        }
     }
 
     // Eval converts syntax into model parts...
@@ -850,6 +1223,9 @@ pub mod eval {
 
         fn visit_at(&mut self, lhs: &'t Vec<Item<'s>>, rhs: &'t Vec<Item<'s>>) {
             eprintln!("VISIT AT: {lhs:?} {rhs:?}");
+            if self.define_style(lhs, rhs) {
+                return;
+            }
             match lhs.len() {
                 0 => {
                     self.push(Thing::StyleBlock);
@@ -873,6 +1249,10 @@ pub mod eval {
 
         fn visit_colon(&mut self, lhs: &'t Vec<Item<'s>>, rhs: &'t Vec<Item<'s>>) {
             eprintln!("VISIT COLON: {lhs:?} {rhs:?}");
+            if self.stack.last() == Some(&Thing::StyleInline) {
+                self.annotate(lhs, rhs);
+                return;
+            }
             if self.stack.last() == Some(&Thing::ChainLabels) {
                 self.visit_colon_lhs(lhs);
                 self.visit_colon_rhs(rhs);
@@ -882,6 +1262,9 @@ pub mod eval {
                 0 => {
                     panic!("zero-lhs colon") },
                 1 => {
+                    if self.define_template(lhs, rhs) || self.instantiate_template(lhs, rhs) {
+                        return;
+                    }
                     self.push(Thing::Definition);
                     self.push(Thing::DefinitionHead);
                     self.visit_colon_lhs(lhs);
@@ -975,9 +1358,10 @@ pub mod eval {
 
         fn visit_sq(&mut self, sq: &'t Vec<Item<'s>>) {
             eprintln!("VISIT SQ: {sq:?}");
-            let mut ev = Eval { stack: vec![], value: Default::default(), model: Default::default(), comma_buffer: None, };
+            let mut ev = self.child();
             ev.visit_model(sq);
             eprintln!("-> {:?}", ev.value);
+            self.errors.append(&mut ev.errors);
             self.value.set_body(Some(Body::All(ev.model.to_vec())));
         }
 
@@ -1003,11 +1387,11 @@ pub mod eval {
                     self.value.set_name(text.clone());
                 },
                 Some(Thing::StyleInline) => {
-                    self.model.last_mut().or_else(|| Some(&mut self.value)).map(|prev| prev.style_mut().map(|style| style.push(text.clone())));
+                    self.styled().map(|prev| prev.style_mut().map(|style| style.push(text.clone())));
                 }
                 Some(Thing::ChainPath) => {
                     if text != "-" {
-                        self.value.path_mut().map(|path| path.push(Val::Process { name: None, label: Some(text.clone()), body: None, style: None }));
+                        self.value.path_mut().map(|path| path.push(Val::Process { name: None, label: Some(text.clone()), body: None, style: None, notes: None }));
                     }
                 }
                 Some(Thing::ChainLabels) => {
@@ -1160,7 +1544,7 @@ pub mod eval {
     fn merge<'s>(existing_process: &mut Val<Cow<'s, str>>, rhs: &mut Val<Cow<'s, str>>) {
         // eprintln!("EVAL_MERGE: {existing_process:#?} {rhs:#?}");
         match (existing_process, rhs) {
-            (Val::Process { body, style, .. }, Val::Process { body: rbody, style: rstyle, .. }) => {
+            (Val::Process { body, style, notes, .. }, Val::Process { body: rbody, style: rstyle, notes: rnotes, .. }) => {
                 if !(body.is_none() && rbody.is_none()) {
                     let rbody = <Vec<Val<Cow<'s, str>>> as AsMut<Vec<_>>>::as_mut(rbody.get_or_insert_with(Default::default).as_mut());
                     <Vec<Val<Cow<'s, str>>> as AsMut<Vec<_>>>::as_mut(body.get_or_insert_with(Default::default).as_mut()).append(rbody);
@@ -1169,6 +1553,9 @@ pub mod eval {
                     let rstyle = <Vec<Cow<'s, str>> as AsMut<Vec<_>>>::as_mut(rstyle.get_or_insert_with(Default::default).as_mut());
                     <Vec<Cow<'s, str>> as AsMut<Vec<_>>>::as_mut(style.get_or_insert_with(Default::default).as_mut()).append(rstyle);
                 }
+                if let Some(rnotes) = rnotes.as_mut() {
+                    notes.get_or_insert_with(Default::default).append(rnotes);
+                }
             },
             (Val::Style { body, ..}, Val::Style { body: rbody, ..}) => {
                 if !(body.is_none() && rbody.is_none()) {
@@ -1296,22 +1683,49 @@ pub mod eval {
         }
     }
 
+    /// Is `name` a pattern, like `*`, `sensor_*`, or `plant.*`, that selects processes to style
+    /// rather than naming one?
+    ///
+    /// `*` matches any run of characters, except that a trailing `.*` after the name of a container
+    /// matches the processes directly inside it.
+    pub fn is_pattern(name: &str) -> bool {
+        name.contains('*')
+    }
+
+    /// Does `pattern` (see [is_pattern()]) match `name`, ignoring containment?
+    pub fn glob(pattern: &str, name: &str) -> bool {
+        let mut parts = pattern.split('*');
+        let Some(mut rest) = name.strip_prefix(parts.next().unwrap_or_default()) else { return false };
+        let parts = parts.collect::<Vec<_>>();
+        let Some((last, middle)) = parts.split_last() else { return rest.is_empty() };
+        for part in middle {
+            let Some(ix) = rest.find(part) else { return false };
+            rest = &rest[ix + part.len()..];
+        }
+        rest.ends_with(last)
+    }
+
     /// What depiction do the given depict-expressions denote?
-    pub fn eval<'s, 't>(model: &'t Vec<Item<'s>>) -> Val<Cow<'s, str>> {
+    pub fn eval<'s, 't>(model: &'t Vec<Item<'s>>) -> Result<Val<Cow<'s, str>>, Error> {
         let mut ev = Eval{
             stack: vec![],
             value: Default::default(),
             comma_buffer: None,
             model: Default::default(),
+            templates: HashMap::new(),
+            errors: vec![],
         };
         ev.visit_model(model);
+        if !ev.errors.is_empty() {
+            return Err(ev.errors.remove(0))
+        }
         let mut scopes = HashMap::new();
         let mut val: Val<Cow<'s,str>> = Default::default();
         val.set_body(Some(Body::All(ev.model.to_vec())));
         let mut val2 = val.clone();
         index(&val2, &mut vec![], &mut scopes);
         resolve(&mut val, &mut vec![], &scopes);
-        val
+        Ok(val)
     }
 
 
@@ -1390,7 +1804,7 @@ pub mod eval {
 
         pub fn visit_val<V, T: Visit<V> + ?Sized>(v: &mut T, val: &Val<V>) {
             match val {
-                Val::Process { name, label, body, style } => v.visit_process(name, label, body, style),
+                Val::Process { name, label, body, style, .. } => v.visit_process(name, label, body, style),
                 Val::Chain { name, rel, path, labels, style } => v.visit_chain(name, rel, path, labels, style),
                 Val::Style { name, body } => v.visit_style(name, body),
             };
@@ -1501,10 +1915,10 @@ pub mod eval {
         const c: &'static str = "c";
         const d: &'static str = "d";
         const dash: &'static str = "-";
-        fn r<'s>() -> Val<Cow<'s, str>> { Val::Process{name: None, label: None, body: Some(Body::All(vec![])), style: None,} }
-        fn p<'s>() -> Val<Cow<'s, str>> { Val::Process{name: None, label: None, body: None, style: None, } }
+        fn r<'s>() -> Val<Cow<'s, str>> { Val::Process{name: None, label: None, body: Some(Body::All(vec![])), style: None, notes: None,} }
+        fn p<'s>() -> Val<Cow<'s, str>> { Val::Process{name: None, label: None, body: None, style: None, notes: None, } }
         fn l<'s>(x: &'static str) -> Val<Cow<'s, str>> { p().set_label(Some(x.into())).clone() }
-        fn mp<'s>(p: &Val<Cow<'s, str>>) -> Val<Cow<'s, str>> { Val::Process{name: None, label: None, body: Some(Body::All(vec![p.clone()])), style: None,}}
+        fn mp<'s>(p: &Val<Cow<'s, str>>) -> Val<Cow<'s, str>> { Val::Process{name: None, label: None, body: Some(Body::All(vec![p.clone()])), style: None, notes: None,}}
         fn t<'s>(x: &'static str) -> Item<'s> { Item::Text(Cow::from(x)) }
         fn vi<'s>(x: &[Item<'static>]) -> Vec<Item<'s>> { x.iter().cloned().collect::<Vec<_>>() }
         fn sq<'s>(x: &[Item<'static>]) -> Item<'s>{ Item::Sq(vi(x)) }
@@ -1517,21 +1931,21 @@ pub mod eval {
         #[test]
         fn test_eval_empty() {
             //
-            assert_eq!(eval(&vi(&[])), r());
+            assert_eq!(eval(&vi(&[])).unwrap(), r());
         }
 
 
         #[test]
         fn test_eval_single() {
             // a
-            assert_eq!(eval(&vi(&[t(a)])), mp(p().set_label(Some(a.into()))));
+            assert_eq!(eval(&vi(&[t(a)])).unwrap(), mp(p().set_label(Some(a.into()))));
         }
 
         #[test]
         fn test_eval_vert_chain() {
             // [ a b ]
             assert_eq!(
-                eval(&vi(&[sq(&[t(a), t(b)])])),
+                eval(&vi(&[sq(&[t(a), t(b)])])).unwrap(),
                 mp(p().set_body(Some(Body::All(vec![ l(a), l(b) ]))))
             );
         }
@@ -1540,7 +1954,7 @@ pub mod eval {
         fn test_eval_horz_chain() {
             // a b -
             assert_eq!(
-                eval(&vi(&[seq(&[t(a), t(b), t(dash)])])),
+                eval(&vi(&[seq(&[t(a), t(b), t(dash)])])).unwrap(),
                 mp(
                     &hc(&[l(a), l(b)])
                 )
@@ -1551,7 +1965,7 @@ pub mod eval {
         fn test_eval_single_nest_horz_chain() {
             // a [ - b c ]
             assert_eq!(
-                eval(&vi(&[seq(&[t(a), sq(&[seq(&[t(dash), t(b), t(c)])])])])),
+                eval(&vi(&[seq(&[t(a), sq(&[seq(&[t(dash), t(b), t(c)])])])])).unwrap(),
                 mp(l(a).set_body(Some(Body::All(vec![
                     hc(&[l(b), l(c)])
                 ]))))
@@ -1562,7 +1976,7 @@ pub mod eval {
         fn test_eval_nest_merge() {
             // a: b; a [ c ]
             assert_eq!(
-                eval(&vi(&[col(&[t(a)], &[t(b)]), seq(&[t(a), sq(&[t(c)])])])),
+                eval(&vi(&[col(&[t(a)], &[t(b)]), seq(&[t(a), sq(&[t(c)])])])).unwrap(),
                 mp(l(b)
                     .set_name(a.into())
                     .set_body(Some(Body::All(vec![l(c)]))))
@@ -1573,7 +1987,7 @@ pub mod eval {
         fn test_eval_long_chain() {
             // a b c: d / e : f / g
             assert_eq!(
-                eval(&vi(&[col(&[t(a), t(b), t(c)], &[col(&[sl(&[t("d")], &[t("e")])], &[sl(&[t("f")], &[t("g")])])])])),
+                eval(&vi(&[col(&[t(a), t(b), t(c)], &[col(&[sl(&[t("d")], &[t("e")])], &[sl(&[t("f")], &[t("g")])])])])).unwrap(),
                 mp(&Val::Chain{name: None, rel: Rel::Vertical, path: vec![l(a), l(b), l(c)], style: None, labels: vec![
                     Level{forward: Some(vec!["d".into()]), reverse: Some(vec!["e".into()])},
                     Level{forward: Some(vec!["f".into()]), reverse: Some(vec!["g".into()])}
@@ -1585,7 +1999,7 @@ pub mod eval {
         fn test_eval_comma_chain() {
             // a b: c d, e f
             assert_eq!(
-                eval(&vi(&[col(&[t(a), t(b)], &[cm(&[seq(&[t("c"), t("d")]), seq(&[t("e"), t("f")])])])])),
+                eval(&vi(&[col(&[t(a), t(b)], &[cm(&[seq(&[t("c"), t("d")]), seq(&[t("e"), t("f")])])])])).unwrap(),
                 mp(&Val::Chain{name: None, rel: Rel::Vertical, path: vec![l(a), l(b)], style: None, labels: vec![
                     Level{forward: Some(vec!["c d".into(), "e f".into()]), reverse: None},
                 ]})
@@ -1596,13 +2010,83 @@ pub mod eval {
         fn test_eval_slash_comma_chain() {
             // a b: c d, / e f,
             assert_eq!(
-                eval(&vi(&[col(&[t(a), t(b)], &[sl(&[cm(&[seq(&[t("c"), t("d")])])], &[cm(&[seq(&[t("e"), t("f")])])])])])),
+                eval(&vi(&[col(&[t(a), t(b)], &[sl(&[cm(&[seq(&[t("c"), t("d")])])], &[cm(&[seq(&[t("e"), t("f")])])])])])).unwrap(),
                 mp(&Val::Chain{name: None, rel: Rel::Vertical, path: vec![l(a), l(b)], style: None, labels: vec![
                     Level{forward: Some(vec!["c d".into()]), reverse: Some(vec!["e f".into()])},
                 ]})
             );
         }
 
+        #[test]
+        fn test_eval_template() {
+            // loop: $c $p $act [ $c $p: $act ]; x: $loop a b c
+            assert_eq!(
+                eval(&vi(&[
+                    col(&[t("loop")], &[t("$c"), t("$p"), t("$act"), sq(&[col(&[t("$c"), t("$p")], &[t("$act")])])]),
+                    col(&[t("x")], &[t("$loop"), t(a), t(b), t(c)]),
+                ])).unwrap(),
+                mp(&Val::Process{name: Some("x".into()), label: None, style: None, notes: None, body: Some(Body::All(vec![
+                    Val::Chain{name: None, rel: Rel::Vertical, path: vec![l(a), l(b)], style: None, labels: vec![
+                        Level{forward: Some(vec![c.into()]), reverse: None},
+                    ]}
+                ]))})
+            );
+        }
+
+        #[test]
+        fn test_eval_template_locals() {
+            // loop: $c [ $c s ]; x: $loop a; y: $loop a
+            let instance = |i: &'static str| {
+                let s = p().set_name("s".into()).set_label(Some(format!("{i}.s").into())).clone();
+                Val::Process{name: Some(i.into()), label: None, style: None, notes: None, body: Some(Body::All(vec![
+                    s.clone(),
+                    Val::Chain{name: None, rel: Rel::Vertical, path: vec![l(a), s], style: None, labels: vec![]},
+                ]))}
+            };
+            assert_eq!(
+                eval(&vi(&[
+                    col(&[t("loop")], &[t("$c"), sq(&[seq(&[t("$c"), t("s")])])]),
+                    col(&[t("x")], &[t("$loop"), t(a)]),
+                    col(&[t("y")], &[t("$loop"), t(a)]),
+                ])).unwrap(),
+                Val::Process{name: None, label: None, style: None, notes: None, body: Some(Body::All(vec![
+                    instance("x"),
+                    instance("y"),
+                ]))}
+            );
+        }
+
+        #[test]
+        fn test_eval_template_errors() {
+            // loop: $c $p [ $c $p ]; x: $loop a
+            let arity = eval(&vi(&[
+                col(&[t("loop")], &[t("$c"), t("$p"), sq(&[seq(&[t("$c"), t("$p")])])]),
+                col(&[t("x")], &[t("$loop"), t(a)]),
+            ]));
+            assert!(matches!(arity, Err(Error::GraphDrawingError{source: Kind::TemplateArityError{params: 2, args: 1, ..}})), "{arity:?}");
+
+            // loop: $c [ $c $q ]; x: $loop a
+            let unbound = eval(&vi(&[
+                col(&[t("loop")], &[t("$c"), sq(&[seq(&[t("$c"), t("$q")])])]),
+                col(&[t("x")], &[t("$loop"), t(a)]),
+            ]));
+            assert!(matches!(&unbound, Err(Error::GraphDrawingError{source: Kind::UnboundParameterError{param, ..}}) if param == "$q"), "{unbound:?}");
+
+            // loop: $c $p [ $c $p ]; y [ x: $loop a ]
+            let nested = eval(&vi(&[
+                col(&[t("loop")], &[t("$c"), t("$p"), sq(&[seq(&[t("$c"), t("$p")])])]),
+                seq(&[t("y"), sq(&[col(&[t("x")], &[t("$loop"), t(a)])])]),
+            ]));
+            assert!(matches!(nested, Err(Error::GraphDrawingError{source: Kind::TemplateArityError{params: 2, args: 1, ..}})), "{nested:?}");
+
+            // loop: $c [ $c ]; x: $loop a [ b ]
+            let argument = eval(&vi(&[
+                col(&[t("loop")], &[t("$c"), sq(&[t("$c")])]),
+                col(&[t("x")], &[t("$loop"), t(a), sq(&[t(b)])]),
+            ]));
+            assert!(matches!(argument, Err(Error::GraphDrawingError{source: Kind::TemplateArgumentError{..}})), "{argument:?}");
+        }
+
         mod prop {
             use std::borrow::Cow;
 
@@ -1623,6 +2107,7 @@ pub mod eval {
                         label: Some(Cow::from(s)),
                         body: None,
                         style: None,
+                        notes: None,
                     }),
                     ("[a-z]+", "[a-z]+").prop_map(|(s, t)| Val::Chain {
                         name: None,
@@ -1657,7 +2142,7 @@ pub mod eval {
                 #[test]
                 fn has_retraction(v in arb_val()) {
                     let a: Item = v.clone().into();
-                    let b = eval(&vec![a]);
+                    let b = eval(&vec![a]).unwrap();
                     assert_eq!(super::mp(&v), b);
                 }
             }
@@ -2156,6 +2641,8 @@ pub mod layout {
     use petgraph::dot::Dot;
     use petgraph::graph::{Graph, NodeIndex, EdgeReference};
     use petgraph::visit::{EdgeRef, IntoNodeReferences};
+    use unicode_segmentation::UnicodeSegmentation;
+    use unicode_width::UnicodeWidthStr;
     use sorted_vec::SortedVec;
 
     use crate::graph_drawing::error::{Error, Kind, OrErrExt, RankingError};
@@ -2174,6 +2661,9 @@ pub mod layout {
         chain: &'t Val<V>,
     ) -> Result<(), Error> {
         if let Val::Chain{rel, path, labels, ..} = chain {
+            if path.iter().any(|val| val.label().is_some_and(|node| eval::is_pattern(&node.to_string()))) {
+                return Ok(());
+            }
             if *rel == Rel::Horizontal {
                 for n in 0..path.len()-1 {
                     if let Val::Process{label: Some(al), ..} = &path[n] {
@@ -2192,6 +2682,7 @@ pub mod layout {
                             } else {
                                 std::mem::swap(&mut al, &mut bl);
                             }
+                            vcg.horz_edges.insert((al.clone(), bl.clone()));
                             if let Some(level) = labels.get(n) {
                                 let eval::Level{mut forward, mut reverse} = level.clone();
                                 if has_prior_orientation {
@@ -2265,12 +2756,20 @@ pub mod layout {
         /// vert_edge_labels maps (v,w,rel) node weight pairs to display edge labels.
         pub vert_edge_labels: HashMap<(V, V), eval::Level<V>>,
 
+        /// vert_edges records the vertical relationships written in the model, as opposed to
+        /// those implied by containment.
+        pub vert_edges: HashSet<(V, V)>,
+
         /// horz_constraints records directed horizontal constraint between pairs of nodes
         pub horz_constraints: HashSet<HorizontalConstraint<V>>,
 
         // horz_edge_labels maps (v,w) node weight pairs to label pairs
         pub horz_edge_labels: HashMap<(V, V), eval::Level<V>>,
 
+        /// horz_edges records the horizontal relationships written in the model, as opposed to
+        /// those implied by containment.
+        pub horz_edges: HashSet<(V, V)>,
+
         /// containers identifies which nodes are parents of contained nodes
         pub containers: HashSet<V>,
 
@@ -2288,6 +2787,9 @@ pub mod layout {
 
         /// container_depths records how many ranks each container spans
         pub container_depths: HashMap<V, usize>,
+
+        /// notes_by_node records the notes attached to each node or container
+        pub notes_by_node: HashMap<V, Vec<eval::Note<V>>>,
     }
 
     pub fn or_insert<V, E>(g: &mut Graph<V, E>, h: &mut HashMap<V, NodeIndex>, v: V) -> NodeIndex where V: Eq + Hash + Clone {
@@ -2354,25 +2856,39 @@ pub mod layout {
         }
     }
 
+    /// How many terminal columns wide is `s`?
+    ///
+    /// # Summary
+    ///
+    /// Counts extended grapheme clusters rather than bytes or `char`s, so that
+    /// combining marks and emoji ZWJ sequences occupy one cell, and counts
+    /// East Asian wide and fullwidth clusters as two cells.
+    pub fn display_width(s: &str) -> usize {
+        s.graphemes(true)
+            .map(|g| UnicodeWidthStr::width(g).min(2))
+            .sum()
+    }
+
+    /// Display width of a label, in columns; see [display_width].
     pub trait Len: IsEmpty {
         fn len(&self) -> usize;
     }
 
     impl Len for &str {
         fn len(&self) -> usize {
-            str::len(self)
+            display_width(self)
         }
     }
 
     impl Len for String {
         fn len(&self) -> usize {
-            str::len(self)
+            display_width(self)
         }
     }
 
     impl<'s> Len for Cow<'s, str> {
         fn len(&self) -> usize {
-            str::len(self)
+            display_width(self)
         }
     }
 
@@ -2393,9 +2909,12 @@ pub mod layout {
         }
         for val in body {
             // eprintln!("WALK_BODY CHAIN parent: {parent:?}, chain: {chain:#?}");
+            if let Val::Process{label: Some(node), notes: Some(notes), ..} = val {
+                vcg.notes_by_node.entry(node.clone()).or_default().extend(notes.iter().cloned());
+            }
             match val {
                 Val::Process{label: Some(node), body: None, ..} => {
-                    if node == ">" || node == "*" { continue; }
+                    if node == ">" || eval::is_pattern(node) { continue; }
                     or_insert(&mut vcg.vert, &mut vcg.vert_vxmap, node.clone());
                     vcg.vert_node_labels.insert(node.clone(), node.clone().into());
                     if let Some(parent) = parent {
@@ -2421,6 +2940,8 @@ pub mod layout {
                     parents = walk_body(queue, vcg, body, label, parents);
                 },
                 Val::Chain{path, rel, labels, ..} => {
+                    // chains of patterns select relationships to style; Styling::select() applies them
+                    if path.iter().any(|val| val.label().is_some_and(|node| eval::is_pattern(node))) { continue; }
                     queue.push((path, rel, labels, parent));
                     for val in path {
                         if let Val::Process{label: Some(node), ..} = val {
@@ -2461,27 +2982,33 @@ pub mod layout {
         let vert_vxmap = HashMap::<Cow<str>, NodeIndex>::new();
         let vert_node_labels = HashMap::new();
         let vert_edge_labels = HashMap::new();
+        let vert_edges = HashSet::new();
         let horz_constraints = HashSet::new();
         let horz_edge_labels = HashMap::new();
+        let horz_edges = HashSet::new();
         let containers = HashSet::new();
         let nodes_by_container = HashMap::new();
         let container_by_node = HashMap::new();
         let nodes_by_container_transitive = HashMap::new();
         let nesting_depths: HashMap<Cow<str>, usize> = HashMap::new();
         let container_depths: HashMap<Cow<str>, usize> = HashMap::new();
+        let notes_by_node = HashMap::new();
         let mut vcg = Vcg{
             vert,
             vert_vxmap,
             vert_node_labels,
             vert_edge_labels,
+            vert_edges,
             horz_constraints,
             horz_edge_labels,
+            horz_edges,
             containers,
             nodes_by_container,
             container_by_node,
             nodes_by_container_transitive,
             nesting_depths,
             container_depths,
+            notes_by_node,
         };
 
         calculate_hcg(&mut vcg, process)?;
@@ -2529,6 +3056,7 @@ pub mod layout {
                     std::mem::swap(&mut src_ix, &mut dst_ix);
                 }
 
+                vcg.vert_edges.insert((src.clone(), dst.clone()));
                 let mut edge_type = "vertical";
                 if let Some(level) = labels_by_level.get(n) {
                     let eval::Level{mut forward, mut reverse} = level.clone();
@@ -3213,7 +3741,10 @@ pub mod layout {
             }
         }
 
-        for container in containers.iter() {
+        // number gaps in a fixed order so that layouts do not vary between runs
+        let mut containers_in_order = containers.iter().collect::<Vec<_>>();
+        containers_in_order.sort();
+        for container in containers_in_order {
             let cd = container_depths[container];
             let (ovr, _) = node_to_loc[&Obj::Container(ObjContainer{vl: container.clone()})];
             for vr in (ovr.0+1)..(ovr.0+1+cd) {
@@ -3588,11 +4119,17 @@ pub mod layout {
             eprintln!("NODE_TO_LOC0: {n2l:#?}");
             // hops_by_edge.log((), logs);
 
-            let mut bubbles = HashMap::<(VerticalRank, Option<V>), Vec<Obj<V>>>::new();
+            // visit everything in a fixed order so that ties between equally good layouts
+            // are broken the same way every time
+            let mut bubbles = BTreeMap::<(VerticalRank, Option<V>), Vec<Obj<V>>>::new();
             let mut bubble_by_loc = HashMap::<LocIx, Option<V>>::new();
             let mut bhr_by_loc = HashMap::<LocIx, usize>::new();
             let mut container_by_obj = HashMap::<Obj<V>, Option<V>>::new();
-            for (vl, container) in container_by_node.iter() {
+            let mut nodes_in_order = container_by_node.iter().collect::<Vec<_>>();
+            nodes_in_order.sort_by_key(|(vl, _)| node_to_loc[&Obj::from_vl(*vl, containers)]);
+            let mut containers_in_order = containers.iter().collect::<Vec<_>>();
+            containers_in_order.sort();
+            for (vl, container) in nodes_in_order {
                 let obj = Obj::from_vl(vl, containers);
                 let loc = node_to_loc[&obj];
                 let bubble = bubbles.entry((loc.0, container.clone())).or_default();
@@ -3653,7 +4190,7 @@ pub mod layout {
                 }
             }
 
-            for container in containers.iter() {
+            for container in containers_in_order.iter().copied() {
                 let cd = container_depths[container];
                 let container_obj = &Obj::Container(ObjContainer { vl: container.clone() });
                 let cloc = node_to_loc[container_obj];
@@ -3733,7 +4270,7 @@ pub mod layout {
             let mut iterations = 0;
 
             let mut hops_by_level2 = hops_by_level.clone();
-            for container in containers.iter() {
+            for container in containers_in_order.iter().copied() {
                 let cd = container_depths[container];
                 let container_obj = &Obj::Container(ObjContainer{vl: container.clone()});
                 let cloc = node_to_loc[container_obj];
@@ -4093,2540 +4630,7839 @@ pub mod layout {
     use super::index::{SolvedHorizontalRank, VarRank};
 }
 
-pub mod geometry {
-    //! Generate beautiful geometry consistent with given relations.
+pub mod lint {
+    //! Semantic checks for models that parse and draw but are probably mistakes
     //!
     //! # Summary
     //!
-    //! The purpose of the `geometry` module is to generate beautiful geometry
-    //! (positions, widths, paths) consistent with given geometric relations between
-    //! graphical objects to be positioned (ex: "object A should be placed to the left
-    //! of object b").
-    //!
-    //! # Guide-level explanation
-    //!
-    //! Convex optimization is a powerful framework for finding sets of numbers
-    //! (representing the coordinates of points and guides) that "solve" a given
-    //! layout problem in an optimal way consistent with given costs and constraints.
-    //!
-    //! Conveniently, it allows us to express both geometric constraints like
-    //! "the horizontal position of the right-hand border of box A must be less
-    //! the horizontal position of the left-hand side of the bounding box of hop C1"
-    //! as well as more flexible desiderata like "subject to these constraints,
-    //! minimize the square of the difference of the positions of hops C1.1 and C2.2".
+    //! [check()] walks an evaluated model (via [Visit]) and its [Vcg] and reports
+    //! [Warning]s for each enabled [Rule]. Rules can be switched off via [Config].
     //!
     //! # Reference-level explanation
     //!
-    //! `geometry` is implemented in terms of the [OSQP](https://osqp.org) convex
-    //! quadratic program solver and its Rust bindings in the [osqp] crate.
-    //!
-    //! Abstractly, the data and the steps required to convert geometric relations into geometry are:
-    //!
-    //! 1. the input data need to be organized (here, via [`calculate_sols()`]) so that constraints can be generated and so that the optimization objective can be formed.
-    //! 2. then, once constraints and the objective are generated, they need to be formatted as an [osqp::CscMatrix] and associated `&[f64]` slices, passed to [osqp::Problem], and solved.
-    //! 3. then, the resulting [osqp_rust::Solution] needs to be destructured so that the resulting solution values can be returned to [`position_sols()`]'s caller as a [GeometrySolution].
-
-    use enum_kinds::EnumKind;
-    use ordered_float::OrderedFloat;
-    #[cfg(all(not(feature="osqp"), feature="osqp-rust"))]
-    use osqp_rust as osqp;
-    #[cfg(all(feature="osqp", not(feature="osqp-rust")))]
-    use osqp as osqp;
-
-    use petgraph::EdgeDirection::{Outgoing, Incoming};
-    use petgraph::Graph;
-    use petgraph::algo::is_cyclic_directed;
-    use petgraph::dot::Dot;
-    use petgraph::visit::{EdgeRef};
-
-    use crate::graph_drawing::layout::ObjNode;
-    #[allow(unused_imports)]
-    use crate::graph_drawing::osqp::{as_diag_csc_matrix, print_tuples, as_scipy, as_numpy};
-
-    use super::error::{LayoutError};
-    use super::frontend::log::Name;
-    use super::osqp::{Constraints, Monomial, Vars, Fresh, Var, Sol, Coeff};
-
-    use super::error::Error;
-    use super::index::{VerticalRank, OriginalHorizontalRank, SolvedHorizontalRank, VarRank};
-    use super::layout::{Obj, Hop, Vcg, LayoutProblem, Graphic, LayoutSolution, Len, L2n, V2n, or_insert, ObjHop, ObjContainer};
-
+    //! The lexer hands the parser slices of the source text, which [eval()](crate::graph_drawing::eval::eval)
+    //! and [Vcg] keep borrowing, so each name in a [Warning] still records where it was written.
+    //! Names that evaluation builds anew, like the qualified locals of templates, have no span.
     use std::borrow::Cow;
-    use std::cmp::{max};
-    use std::collections::{HashMap, BTreeMap};
-    use std::fmt::{Debug, Display};
-    use std::hash::Hash;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::fmt::Display;
+    use std::ops::Range;
+    use std::str::FromStr;
 
-    #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd, EnumKind)]
-    #[enum_kind(AnySolKind)]
-    pub enum AnySol {
-        L(VarRank),
-        R(VarRank),
-        S(VarRank),
-        T(VarRank),
-        B(VarRank),
-        H(VerticalRank),
-        V(SolvedHorizontalRank),
-        F(usize),
-        #[default]
-        Default,
-    }
+    use crate::graph_drawing::error::{Error, TypeError};
+    use crate::graph_drawing::eval::{is_pattern, Body, Level, Rel, Val};
+    use crate::graph_drawing::eval::visit::{Visit, visit_chain, visit_process};
+    use crate::graph_drawing::layout::Vcg;
 
-    impl Fresh for AnySol {
-        fn fresh(index: usize) -> Self {
-            Self::F(index)
+    /// Which semantic check produced a [Warning]?
+    #[non_exhaustive]
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+    pub enum Rule {
+        /// A vertical relationship carries no control actions
+        UnlabeledAction,
+        /// A controlled process has no feedback path back to its controller
+        MissingFeedback,
+        /// Two process labels differ only by case
+        CaseDuplicate,
+        /// A process is contained in a container that it is also vertically related to
+        ContainedAndAbove,
+    }
+
+    impl Rule {
+        pub const ALL: [Rule; 4] = [
+            Rule::UnlabeledAction,
+            Rule::MissingFeedback,
+            Rule::CaseDuplicate,
+            Rule::ContainedAndAbove,
+        ];
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                Rule::UnlabeledAction => "unlabeled-action",
+                Rule::MissingFeedback => "missing-feedback",
+                Rule::CaseDuplicate => "case-duplicate",
+                Rule::ContainedAndAbove => "contained-and-above",
+            }
         }
     }
 
-    impl Display for AnySol {
+    impl Display for Rule {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            match self {
-                AnySol::L(loc) => write!(f, "l{}", loc.0),
-                AnySol::R(loc) => write!(f, "r{}", loc.0),
-                AnySol::S(hop) => write!(f, "s{}", hop.0),
-                AnySol::T(loc) => write!(f, "t{}", loc.0),
-                AnySol::B(loc) => write!(f, "b{}", loc.0),
-                AnySol::H(ovr) => write!(f, "h{}", ovr.0),
-                AnySol::V(shr) => write!(f, "v{}", shr.0),
-                AnySol::F(idx) => write!(f, "f{}", idx),
-                AnySol::Default => write!(f, "def"),
-            }
+            write!(f, "{}", self.name())
         }
     }
 
-    impl Name for AnySol {
-        fn name(&self) -> String {
-            format!("{}", self)
+    impl FromStr for Rule {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Rule::ALL
+                .iter()
+                .find(|rule| rule.name() == s)
+                .copied()
+                .ok_or_else(|| TypeError::UnknownLintRuleError{rule: s.into()}.into())
         }
     }
 
-    impl<'a> TryFrom<&'a AnySol> for VarRank {
-        type Error = &'a AnySol;
+    /// Which [Rule]s should [check()] apply?
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct Config {
+        disabled: BTreeSet<Rule>,
+    }
 
-        fn try_from(sol: &'a AnySol) -> Result<Self, Self::Error> {
-            match sol {
-                AnySol::L(vr) |  AnySol::R(vr) | AnySol::S(vr) | AnySol::T(vr) | AnySol::B(vr) => Ok(*vr),
-                _ => Err(sol),
-            }
+    impl Config {
+        pub fn enable(&mut self, rule: Rule) -> &mut Self {
+            self.disabled.remove(&rule);
+            self
+        }
+
+        pub fn disable(&mut self, rule: Rule) -> &mut Self {
+            self.disabled.insert(rule);
+            self
+        }
+
+        pub fn is_enabled(&self, rule: Rule) -> bool {
+            !self.disabled.contains(&rule)
         }
     }
 
-    #[derive(Clone, Debug)]
-    pub struct LocRow<V: Graphic> {
-        pub ovr: VerticalRank,
-        pub ohr: OriginalHorizontalRank,
-        pub shr: SolvedHorizontalRank,
-        pub loc: Obj<V>,
-        pub n: VarRank,
+    /// A probable modeling mistake
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct Warning {
+        pub rule: Rule,
+        pub message: String,
+        /// Byte range of the offending text in the source, if found
+        pub span: Option<Range<usize>>,
     }
 
-    #[derive(Clone, Debug)]
-    pub struct HopRow<V: Graphic> {
-        pub lvl: VerticalRank,
-        pub mhr: OriginalHorizontalRank,
-        pub nhr: OriginalHorizontalRank,
-        pub vl: V,
-        pub wl: V,
-        pub n: VarRank,
+    impl Display for Warning {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "warning[{}]: {}", self.rule, self.message)
+        }
     }
 
-    #[derive(Clone, Debug, PartialEq, PartialOrd)]
-    pub struct NodeSize {
-        pub width: f64,
-        pub left: f64,
-        pub right: f64,
-        pub height: f64,
+    /// Where in `data` was `text` written, if it is a slice of `data`?
+    ///
+    /// Text that evaluation made rather than borrowed, like the qualified names of template
+    /// locals and joined or unescaped labels, lies outside of `data` and has no span.
+    fn source_span(data: &str, text: &str) -> Option<Range<usize>> {
+        let (source, text) = (data.as_bytes().as_ptr_range(), text.as_bytes().as_ptr_range());
+        if text.start < source.start || text.end > source.end {
+            return None
+        }
+        let start = text.start as usize - source.start as usize;
+        Some(start..start + (text.end as usize - text.start as usize))
     }
 
-    #[derive(Clone, Debug, PartialEq, PartialOrd)]
-    pub struct HopSize {
-        pub width: f64,
-        pub left: f64,
-        pub right: f64,
-        pub height: f64,
-        pub top: f64,
-        pub bottom: f64,
+    fn is_nonempty<V>(labels: &Option<Vec<V>>) -> bool {
+        labels.as_ref().is_some_and(|labels| !labels.is_empty())
     }
 
-    #[derive(Clone, Debug, Default)]
-    pub struct GeometryProblem<V: Graphic> {
-        pub varrank_by_obj: HashMap<Obj<V>, VarRank>,
-        pub loc_by_varrank: HashMap<VarRank, LocIx>,
-        pub size_by_loc: HashMap<LocIx, NodeSize>,
-        pub size_by_hop: HashMap<(VerticalRank, OriginalHorizontalRank, V, V), HopSize>,
-        pub height_scale: Option<f64>,
-        pub line_height: Option<f64>,
-        pub char_width: Option<f64>,
-        pub nesting_top_padding: Option<f64>,
-        pub nesting_bottom_padding: Option<f64>,
+    fn is_selector(level: &Level<Cow<str>>) -> bool {
+        level.forward.iter().flatten().chain(level.reverse.iter().flatten()).any(|l| l == ">" || is_pattern(l))
     }
 
-    use crate::graph_drawing::frontend::log::{self, names, Names};
+    struct Walk<'a, 's> {
+        config: &'a Config,
+        labels: BTreeSet<Cow<'s, str>>,
+        unlabeled: BTreeSet<(Cow<'s, str>, Cow<'s, str>)>,
+    }
 
-    impl<V: Graphic> log::Log for HashMap<Obj<V>, VarRank> {
-        fn log(&self, _cx: (), l: &mut log::Logger) -> Result<(), log::Error> {
-            l.with_map("obj_by_varrank", "ObjByVarrank", self.iter(), |obj, sol, l| {
-                let src_names = obj.names();
-                l.log_pair(
-                    "Varrank",
-                    names![sol],
-                    format!("{sol}"),
-                    "Obj",
-                    src_names,
-                    format!("{obj}"),
-                )
-            })
+    impl<'a, 's> Visit<Cow<'s, str>> for Walk<'a, 's> {
+        fn visit_process(&mut self, name: &Option<Cow<'s, str>>, label: &Option<Cow<'s, str>>, body: &Option<Body<Cow<'s, str>>>, style: &Option<Vec<Cow<'s, str>>>) {
+            if let Some(label) = label {
+                if label != ">" && !is_pattern(label) {
+                    self.labels.insert(label.clone());
+                }
+            }
+            visit_process(self, name, label, body, style);
         }
-    }
 
-    impl<CX: L2n> log::Log<CX> for HashMap<LocIx, NodeSize> {
-        fn log(&self, cx: CX, l: &mut log::Logger) -> Result<(), log::Error> {
-            l.with_map("size_by_loc", "SizeByLoc", self.iter(), |loc_ix, size, l| {
-                let mut src_names = loc_ix.names();
-                src_names.append(&mut cx(loc_ix.0, loc_ix.1));
-                l.log_pair(
-                    "Loc",
-                    src_names,
-                    format!("{:?}, {:?}", loc_ix.0, loc_ix.1),
-                    "NodeSize",
-                    vec![],
-                    format!("{size:?}"))
-            })
+        fn visit_chain(&mut self, name: &Option<Cow<'s, str>>, rel: &Rel, path: &Vec<Val<Cow<'s, str>>>, labels: &Vec<Level<Cow<'s, str>>>, style: &Option<Vec<Cow<'s, str>>>) {
+            visit_chain(self, name, rel, path, labels, style);
+            if *rel != Rel::Vertical || !self.config.is_enabled(Rule::UnlabeledAction) || labels.iter().any(is_selector) {
+                return
+            }
+            if path.iter().any(|val| val.label().is_some_and(|label| is_pattern(label))) {
+                return
+            }
+            for (n, pair) in path.windows(2).enumerate() {
+                let (Some(src), Some(dst)) = (pair[0].label(), pair[1].label()) else { continue };
+                let has_actions = labels.get(n).is_some_and(|level| is_nonempty(&level.forward));
+                if !has_actions {
+                    self.unlabeled.insert((src.clone(), dst.clone()));
+                }
+            }
+        }
+
+        fn visit_style(&mut self, _name: &Option<Cow<'s, str>>, _body: &Option<Body<Cow<'s, str>>>) {
+            // style blocks do not declare processes
         }
     }
 
-    impl<V: Graphic + Display + log::Name, CX: L2n> log::Log<CX> for HashMap<HopIx<V>, HopSize> {
-        fn log(&self, cx: CX, l: &mut log::Logger) -> Result<(), log::Error> {
-            l.with_map("size_by_hop", "SizeByHop", self.iter(), |(ovr, ohr, vl, wl), size, l| {
-                let mut src_names = names![ovr, ohr, vl, wl];
-                src_names.append(&mut cx(*ovr, *ohr));
-                l.log_pair(
-                    "Loc",
-                    src_names,
-                    format!("{ovr}v, {ohr}h"),
-                    "HopSize",
-                    vec![],
-                    format!("{size:?}")
-                )
-            })
+    /// Check `val` (evaluated from the source text `data`) and its `vcg` for probable mistakes.
+    pub fn check<'s>(data: &str, val: &Val<Cow<'s, str>>, vcg: &Vcg<Cow<'s, str>, Cow<'s, str>>, config: &Config) -> Vec<Warning> {
+        let mut warnings = vec![];
+        let mut walk = Walk{ config, labels: BTreeSet::new(), unlabeled: BTreeSet::new() };
+        walk.visit_val(val);
+
+        for (src, dst) in walk.unlabeled.iter() {
+            warnings.push(Warning{
+                rule: Rule::UnlabeledAction,
+                message: format!("{src} is placed above {dst} but no control actions are given"),
+                span: source_span(data, dst),
+            });
+        }
+
+        if config.is_enabled(Rule::CaseDuplicate) {
+            let mut labels_by_case: HashMap<String, Vec<&Cow<str>>> = HashMap::new();
+            for label in walk.labels.iter() {
+                labels_by_case.entry(label.to_lowercase()).or_default().push(label);
+            }
+            for labels in labels_by_case.values().filter(|labels| labels.len() > 1) {
+                let names = itertools::join(labels.iter(), ", ");
+                warnings.push(Warning{
+                    rule: Rule::CaseDuplicate,
+                    message: format!("labels differ only by case: {names}"),
+                    span: source_span(data, labels[labels.len()-1]),
+                });
+            }
+        }
+
+        if config.is_enabled(Rule::MissingFeedback) {
+            // influence flows down control actions and up feedback
+            let mut influences: HashMap<&Cow<str>, HashSet<&Cow<str>>> = HashMap::new();
+            for ((src, dst), level) in vcg.vert_edge_labels.iter().chain(vcg.horz_edge_labels.iter()) {
+                if is_nonempty(&level.forward) {
+                    influences.entry(src).or_default().insert(dst);
+                }
+                if is_nonempty(&level.reverse) {
+                    influences.entry(dst).or_default().insert(src);
+                }
+            }
+            for ((src, dst), level) in vcg.vert_edge_labels.iter() {
+                if !is_nonempty(&level.forward) {
+                    continue
+                }
+                let mut seen = HashSet::new();
+                let mut queue = vec![dst];
+                while let Some(node) = queue.pop() {
+                    if seen.insert(node) {
+                        queue.extend(influences.get(node).into_iter().flatten());
+                    }
+                }
+                if !seen.contains(src) {
+                    warnings.push(Warning{
+                        rule: Rule::MissingFeedback,
+                        message: format!("{src} controls {dst} but receives no feedback from it"),
+                        span: source_span(data, dst),
+                    });
+                }
+            }
+        }
+
+        if config.is_enabled(Rule::ContainedAndAbove) {
+            for (container, nodes) in vcg.nodes_by_container_transitive.iter() {
+                let Some(cx) = vcg.vert_vxmap.get(container) else { continue };
+                for node in nodes {
+                    let Some(nx) = vcg.vert_vxmap.get(node) else { continue };
+                    let is_vertical = vcg.vert.edges_connecting(*nx, *cx)
+                        .chain(vcg.vert.edges_connecting(*cx, *nx))
+                        .any(|er| er.weight() == "vertical" || er.weight() == "implied_vertical");
+                    if is_vertical {
+                        warnings.push(Warning{
+                            rule: Rule::ContainedAndAbove,
+                            message: format!("{node} is contained in {container} but is also placed above or below it"),
+                            span: source_span(data, node),
+                        });
+                    }
+                }
+            }
         }
+
+        warnings.sort_by(|a, b| (a.rule, &a.message).cmp(&(b.rule, &b.message)));
+        warnings
     }
 
-    /// ovr, ohr
-    pub type LocIx = (VerticalRank, OriginalHorizontalRank);
+    #[cfg(test)]
+    mod tests {
+        use logos::Logos;
 
-    pub type HopIx<V> = (VerticalRank, OriginalHorizontalRank, V, V);
+        use crate::graph_drawing::eval::eval;
+        use crate::graph_drawing::frontend::log::Logger;
+        use crate::graph_drawing::layout::calculate_vcg;
+        use crate::parser::{Parser, Token};
 
-    /// ovr, ohr -> loc
-    pub type LocNodeMap<V> = HashMap<LocIx, Obj<V>>;
+        use super::*;
 
-    /// lvl -> (mhr, nhr)
-    pub type HopMap = BTreeMap<VerticalRank, (OriginalHorizontalRank, OriginalHorizontalRank)>;
+        fn warnings(data: &str, config: &Config) -> Vec<Warning> {
+            let mut p = Parser::new();
+            for tk in Token::lexer(data) {
+                p.parse(tk).unwrap();
+            }
+            let items = p.end_of_input().unwrap();
+            let val = eval(&items).unwrap();
+            let vcg = calculate_vcg(&val, &mut Logger::new()).unwrap();
+            check(data, &val, &vcg, config)
+        }
 
-    pub fn calculate_sols<'s, V>(
-        layout_problem: &'s LayoutProblem<V>,
-        layout_solution: &'s LayoutSolution,
-    ) -> GeometryProblem<V> where
-        V: Display + Graphic + log::Name
-    {
-        let LayoutProblem{loc_to_node, hops_by_level, hops_by_edge, ..} = layout_problem;
-        let LayoutSolution{solved_locs, ..} = layout_solution;
+        fn rules(data: &str, config: &Config) -> Vec<Rule> {
+            warnings(data, config).into_iter().map(|w| w.rule).collect::<Vec<_>>()
+        }
 
-        // eprintln!("SOLVED_LOCS {solved_locs:#?}");
+        #[test]
+        fn test_lint_clean() {
+            assert_eq!(rules("person microwave: open / beep", &Config::default()), vec![]);
+        }
 
-        let mut var_counter = 0;
-        let mut var_rank = || {
-            let n = var_counter;
-            var_counter += 1;
-            VarRank(n)
-        };
+        #[test]
+        fn test_lint_rules() {
+            let config = Config::default();
+            assert_eq!(rules("a b", &config), vec![Rule::UnlabeledAction]);
+            assert_eq!(rules("a b: act", &config), vec![Rule::MissingFeedback]);
+            assert_eq!(rules("a b: act / fb; b c: act", &config), vec![Rule::MissingFeedback]);
+            assert_eq!(rules("a b c", &config), vec![Rule::UnlabeledAction, Rule::UnlabeledAction]);
+            assert_eq!(rules("Pump; pump", &config), vec![Rule::CaseDuplicate]);
+            assert_eq!(rules("a [ b ]; b a: act / fb", &config), vec![Rule::ContainedAndAbove]);
+            // selectors of labels to style, like selectors of processes, are not relationships
+            assert_eq!(rules("a b: act / fb; a b: / f* @ red", &config), vec![]);
+        }
 
-        let mut varrank_by_obj = HashMap::new();
-        let mut loc_by_varrank = HashMap::new();
+        #[test]
+        fn test_lint_config() {
+            let mut config = Config::default();
+            config.disable(Rule::UnlabeledAction);
+            assert_eq!(rules("a b", &config), vec![]);
+            assert_eq!("missing-feedback".parse::<Rule>().unwrap(), Rule::MissingFeedback);
+            assert!("no-such-rule".parse::<Rule>().is_err());
+        }
 
-        for (ovr, ohr, _shr, obj) in solved_locs
-            .iter()
-            .flat_map(|(ovr, nodes)| nodes
-                .iter()
-                .map(|(ohr, shr)| (*ovr, *ohr, *shr, &loc_to_node[&(*ovr,*ohr)]))) {
-            let rank = var_rank();
-            varrank_by_obj.insert(obj.clone(), rank);
-            loc_by_varrank.insert(rank, (ovr, ohr));
+        #[test]
+        fn test_lint_span() {
+            // the span is that of the relationship, not of the first mention of its process
+            let spans = warnings("q; p q: act", &Config::default()).into_iter().map(|w| w.span).collect::<Vec<_>>();
+            assert_eq!(spans, vec![Some(5..6)]);
+            assert_eq!(source_span("ab", &String::from("b")), None);
+            // template arguments are written in the source, but template locals are named during evaluation
+            let spans = |data| warnings(data, &Config::default()).into_iter().map(|w| w.span).collect::<Vec<_>>();
+            assert_eq!(spans("loop: $c $p [ $c $p ]; x: $loop a b"), vec![Some(34..35)]);
+            assert_eq!(spans("loop: $c [ $c q ]; x: $loop a"), vec![None]);
         }
+    }
+}
 
-        for (mhr, _nhr, vl, wl, lvl) in hops_by_level
-            .iter()
-            .flat_map(|h|
-                h.1.iter().map(|Hop{mhr, nhr, vl, wl, lvl}| {
-                    (*mhr, *nhr, vl.clone(), wl.clone(), *lvl)
-                })
-            )
-            .chain(
-                hops_by_edge.iter().map(|((vl, wl), hops)| {
-                    let (lvl, (mhr, nhr)) = {
-                        #[allow(clippy::unwrap_used)] // an edge with no hops really should panic
-                        let kv = hops.last_key_value().unwrap();
-                        (*kv.0, *kv.1)
-                    };
-                    (nhr, OriginalHorizontalRank(std::usize::MAX - mhr.0), vl.clone(), wl.clone(), lvl+1)
-                })
-            ) {
-            let rank = var_rank();
-            varrank_by_obj.insert(Obj::Hop(ObjHop{lvl: lvl, mhr, vl: vl.clone(), wl: wl.clone()}), rank);
-            loc_by_varrank.insert(rank, (lvl, mhr));
+pub mod stpa {
+    //! Control structure extraction for STPA
+    //!
+    //! # Summary
+    //!
+    //! System-Theoretic Process Analysis (STPA) starts from a table of control
+    //! loops: which controllers issue which control actions to which controlled
+    //! processes, and which feedback they receive in return.
+    //!
+    //! [ControlStructure::from_vcg()] reads this table off of a [Vcg]'s edge labels,
+    //! where forward labels are control actions and reverse labels are feedback, and
+    //! [ControlStructure::to_csv()] and [ControlStructure::to_markdown()] export it
+    //! for unsafe-control-action analysis.
+    use std::fmt::Display;
+
+    use crate::graph_drawing::eval::{Level, Rel};
+    use crate::graph_drawing::layout::{Graphic, Vcg};
+
+    /// One row of a [ControlStructure]
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ControlLoop {
+        pub controller: String,
+        pub process: String,
+        /// Is the controller above ([Rel::Vertical]) or beside ([Rel::Horizontal]) the process?
+        pub rel: Rel,
+        pub actions: Vec<String>,
+        pub feedback: Vec<String>,
+    }
+
+    impl ControlLoop {
+        /// Does the controller issue control actions but receive no feedback?
+        pub fn is_missing_feedback(&self) -> bool {
+            !self.actions.is_empty() && self.feedback.is_empty()
         }
+    }
 
-        let size_by_loc = HashMap::new();
-        let size_by_hop = HashMap::new();
+    /// The control loops of a model, ordered by controller, then by controlled process
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct ControlStructure {
+        pub loops: Vec<ControlLoop>,
+    }
 
-        let height_scale = None;
-        let line_height = Some(20.);
-        let char_width = Some(8.66);
-        let nesting_top_padding = None;
-        let nesting_bottom_padding = None;
+    fn labels<V: Display>(labels: &Option<Vec<V>>) -> Vec<String> {
+        labels.iter()
+            .flatten()
+            .map(|label| label.to_string())
+            .filter(|label| !label.is_empty() && label != "_")
+            .collect()
+    }
 
-        GeometryProblem{
-            varrank_by_obj,
-            loc_by_varrank,
-            size_by_loc,
-            size_by_hop,
-            height_scale,
-            line_height,
-            char_width,
-            nesting_top_padding,
-            nesting_bottom_padding
+    fn rel_name(rel: &Rel) -> &'static str {
+        match rel {
+            Rel::Vertical => "vertical",
+            Rel::Horizontal => "horizontal",
         }
     }
 
-    #[derive(Debug, Default)]
-    pub struct OptimizationProblem<S: Sol, C: Coeff> {
-        v: Vars<S>,
-        c: Constraints<S, C>,
-        pd: Vec<Monomial<S, C>>,
-        q: Vec<Monomial<S, C>>,
+    fn csv_field(field: &str) -> String {
+        if field.contains(&[',', '"', '\n', '\r'][..]) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.into()
+        }
     }
 
-    use std::fmt::Write;
+    fn markdown_cell(cell: &str) -> String {
+        cell.replace('|', "\\|").replace('\n', " ")
+    }
 
-    impl<C: Coeff, CX: V2n> log::Log<(String, CX)> for OptimizationProblem<AnySol, C> {
-        fn log(&self, cx: (String, CX), l: &mut log::Logger) -> Result<(), log::Error> {
-            let names = |monomial: &Monomial<AnySol, C>| {
-                let mut ret = monomial.names();
-                match monomial.var.sol {
-                    AnySol::L(ls) | AnySol::R(ls) | AnySol::T(ls) | AnySol::B(ls) | AnySol::S(ls) => {
-                        ret.append(&mut (cx.1)(ls));
-                    },
-                    _ => {},
-                }
-                ret
-            };
-            l.with_group("OptimizationProblem", cx.0.clone(), Vec::<String>::new(), |l| {
-                l.with_map(cx.0.clone(), "Vars", self.v.iter(), |sol, var, l| {
-                    let mut src_names = sol.names();
-                    match sol {
-                        AnySol::L(ls) | AnySol::R(ls) | AnySol::T(ls) | AnySol::B(ls) | AnySol::S(ls) => {
-                            src_names.append(&mut (cx.1)(*ls));
-                        },
-                        _ => {},
+    impl ControlStructure {
+        pub fn from_vcg<V: Graphic, E: Graphic>(vcg: &Vcg<V, E>) -> Self {
+            let name = |v: &V| vcg.vert_node_labels.get(v).cloned().unwrap_or_else(|| v.to_string());
+            let mut loops = vec![];
+            for (rel, edge_labels) in [(Rel::Vertical, &vcg.vert_edge_labels), (Rel::Horizontal, &vcg.horz_edge_labels)] {
+                for ((src, dst), Level{forward, reverse}) in edge_labels.iter() {
+                    let actions = labels(forward);
+                    let feedback = labels(reverse);
+                    if actions.is_empty() && feedback.is_empty() {
+                        continue
                     }
-                    l.log_pair(
-                        "AnySol",
-                        src_names,
-                        format!("{sol}"),
-                        "Var",
-                        var.names(),
-                        format!("v{}", var.index)
+                    loops.push(ControlLoop{
+                        controller: name(src),
+                        process: name(dst),
+                        rel: rel.clone(),
+                        actions,
+                        feedback,
+                    });
+                }
+            }
+            loops.sort_by(|a, b| {
+                (&a.controller, &a.process, a.rel == Rel::Horizontal).cmp(&(&b.controller, &b.process, b.rel == Rel::Horizontal))
+            });
+            Self{ loops }
+        }
+
+        /// Which control loops lack feedback from the controlled process?
+        pub fn missing_feedback(&self) -> impl Iterator<Item=&ControlLoop> {
+            self.loops.iter().filter(|l| l.is_missing_feedback())
+        }
+
+        /// Export as CSV, one row per control loop, with multiple actions or feedback separated by "; ".
+        pub fn to_csv(&self) -> String {
+            let mut csv = String::from("controller,controlled process,relation,control actions,feedback,missing feedback\n");
+            for l in self.loops.iter() {
+                let row = [
+                    csv_field(&l.controller),
+                    csv_field(&l.process),
+                    rel_name(&l.rel).into(),
+                    csv_field(&l.actions.join("; ")),
+                    csv_field(&l.feedback.join("; ")),
+                    l.is_missing_feedback().to_string(),
+                ];
+                csv.push_str(&row.join(","));
+                csv.push('\n');
+            }
+            csv
+        }
+
+        /// Export as a Markdown table, marking control loops that lack feedback.
+        pub fn to_markdown(&self) -> String {
+            let mut md = String::from("| Controller | Controlled Process | Relation | Control Actions | Feedback |\n");
+            md.push_str("|---|---|---|---|---|\n");
+            for l in self.loops.iter() {
+                let feedback = if l.is_missing_feedback() {
+                    "**missing**".into()
+                } else {
+                    markdown_cell(&l.feedback.join(", "))
+                };
+                md.push_str(&format!(
+                    "| {} | {} | {} | {} | {} |\n",
+                    markdown_cell(&l.controller),
+                    markdown_cell(&l.process),
+                    rel_name(&l.rel),
+                    markdown_cell(&l.actions.join(", ")),
+                    feedback,
+                ));
+            }
+            md
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use logos::Logos;
+
+        use crate::graph_drawing::eval::eval;
+        use crate::graph_drawing::frontend::log::Logger;
+        use crate::graph_drawing::layout::calculate_vcg;
+        use crate::parser::{Parser, Token};
+
+        use super::*;
+
+        fn control_structure(data: &str) -> ControlStructure {
+            let mut p = Parser::new();
+            for tk in Token::lexer(data) {
+                p.parse(tk).unwrap();
+            }
+            let items = p.end_of_input().unwrap();
+            let val = eval(&items).unwrap();
+            let vcg = calculate_vcg(&val, &mut Logger::new()).unwrap();
+            ControlStructure::from_vcg(&vcg)
+        }
+
+        #[test]
+        fn test_stpa_table() {
+            let cs = control_structure("driver car: brake, steer / speed; car engine: throttle");
+            assert_eq!(cs.loops.len(), 2);
+            assert_eq!(cs.loops[0].controller, "car");
+            assert_eq!(cs.loops[0].process, "engine");
+            assert_eq!(cs.loops[1].actions, vec!["brake", "steer"]);
+            assert_eq!(cs.loops[1].feedback, vec!["speed"]);
+            assert_eq!(cs.missing_feedback().map(|l| &l.process[..]).collect::<Vec<_>>(), vec!["engine"]);
+        }
+
+        #[test]
+        fn test_stpa_export() {
+            let cs = control_structure("driver car: brake, steer / speed");
+            assert_eq!(cs.to_csv(), "controller,controlled process,relation,control actions,feedback,missing feedback\ndriver,car,vertical,brake; steer,speed,false\n");
+            assert!(cs.to_markdown().ends_with("| driver | car | vertical | brake, steer | speed |\n"));
+            assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+        }
+    }
+}
+
+pub mod query {
+    //! Typed queries over evaluated models
+    //!
+    //! # Summary
+    //!
+    //! [Query] answers questions like "what does `controller` send to `plant`?" or
+    //! "what contains `valve`?" about an evaluated model so that downstream tools
+    //! need not re-implement [calculate_vcg] or poke at [Vcg] directly.
+    //!
+    //! ```ignore
+    //! let val = query::parse("controller plant: setpoint / reading")?;
+    //! let q = Query::new(&val)?;
+    //! let actions = q.labels_on("controller", "plant", Dir::Forward).collect::<Vec<_>>();
+    //! ```
+    use std::borrow::Cow;
+
+    use logos::Logos;
+
+    use crate::graph_drawing::error::{Error, Kind};
+    use crate::graph_drawing::eval::{eval, Dir, Rel, Val};
+    use crate::graph_drawing::frontend::log::Logger;
+    use crate::graph_drawing::frontend::styling::Styling;
+    use crate::graph_drawing::layout::{calculate_vcg, Vcg};
+    use crate::parser::{Parser, Token};
+
+    /// Parse and evaluate `data`.
+    pub fn parse(data: &str) -> Result<Val<Cow<'_, str>>, Error> {
+        let mut p = Parser::new();
+        let mut lex = Token::lexer(data);
+        while let Some(tk) = lex.next() {
+            p.parse(tk)
+                .map_err(|_| {
+                    Kind::PomeloError{span: lex.span(), text: lex.slice().into()}
+                })?
+        }
+        let items = p.end_of_input()
+            .map_err(|_| {
+                Kind::PomeloError{span: lex.span(), text: lex.slice().into()}
+            })?;
+        eval(&items)
+    }
+
+    /// A relationship between two processes, oriented from `src` to `dst`
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Edge<'q, 's> {
+        pub src: &'q Cow<'s, str>,
+        pub dst: &'q Cow<'s, str>,
+        pub rel: Rel,
+        /// Labels for `src` acting on `dst`, e.g., control actions
+        pub forward: &'q [Cow<'s, str>],
+        /// Labels for `dst` informing `src`, e.g., feedback
+        pub reverse: &'q [Cow<'s, str>],
+    }
+
+    /// Processes, containment, relationships, and styles of an evaluated model
+    #[derive(Clone, Debug)]
+    pub struct Query<'s> {
+        vcg: Vcg<Cow<'s, str>, Cow<'s, str>>,
+        styling: Styling<Cow<'s, str>>,
+    }
+
+    impl<'s> Query<'s> {
+        pub fn new(val: &Val<Cow<'s, str>>) -> Result<Self, Error> {
+            let mut styling = Styling::new(val)?;
+            let vcg = calculate_vcg(val, &mut Logger::new())?;
+            styling.select(&vcg);
+            Ok(Self{ vcg, styling })
+        }
+
+        /// The underlying vertical constraint graph
+        pub fn vcg(&self) -> &Vcg<Cow<'s, str>, Cow<'s, str>> {
+            &self.vcg
+        }
+
+        /// All processes, in sorted order
+        pub fn processes(&self) -> impl Iterator<Item=&Cow<'s, str>> {
+            let mut processes = self.vcg.vert_vxmap.keys().collect::<Vec<_>>();
+            processes.sort();
+            processes.into_iter()
+        }
+
+        /// Processes that are not contained in any other process, in sorted order
+        pub fn roots(&self) -> impl Iterator<Item=&Cow<'s, str>> {
+            self.processes().filter(|p| self.parent_of(p).is_none())
+        }
+
+        /// The immediate container of `process`, if any
+        pub fn parent_of(&self, process: &str) -> Option<&Cow<'s, str>> {
+            self.vcg.container_by_node.get(process).and_then(|c| c.as_ref())
+        }
+
+        /// The immediate contents of `process`, in sorted order
+        pub fn children_of(&self, process: &str) -> impl Iterator<Item=&Cow<'s, str>> {
+            let mut children = self.vcg.nodes_by_container.get(process).into_iter().flatten().collect::<Vec<_>>();
+            children.sort();
+            children.into_iter()
+        }
+
+        /// The containers of `process`, innermost first
+        pub fn ancestors<'q>(&'q self, process: &str) -> impl Iterator<Item=&'q Cow<'s, str>> + 'q {
+            std::iter::successors(self.parent_of(process), move |p| self.parent_of(p))
+        }
+
+        /// All labeled or unlabeled relationships written in the model, in sorted order
+        pub fn edges(&self) -> impl Iterator<Item=Edge<'_, 's>> {
+            let mut edges = vec![];
+            for (rel, written, edge_labels) in [
+                (Rel::Vertical, &self.vcg.vert_edges, &self.vcg.vert_edge_labels),
+                (Rel::Horizontal, &self.vcg.horz_edges, &self.vcg.horz_edge_labels),
+            ] {
+                for (src, dst) in written.iter() {
+                    let level = edge_labels.get(&(src.clone(), dst.clone()));
+                    edges.push(Edge{
+                        src,
+                        dst,
+                        rel: rel.clone(),
+                        forward: level.and_then(|l| l.forward.as_deref()).unwrap_or_default(),
+                        reverse: level.and_then(|l| l.reverse.as_deref()).unwrap_or_default(),
+                    });
+                }
+            }
+            edges.sort_by(|a, b| (a.src, a.dst, a.rel == Rel::Horizontal).cmp(&(b.src, b.dst, b.rel == Rel::Horizontal)));
+            edges.into_iter()
+        }
+
+        /// Relationships between `src` and `dst`, oriented from `src` to `dst` regardless of how they were drawn
+        pub fn edges_between<'q>(&'q self, src: &'q str, dst: &'q str) -> impl Iterator<Item=Edge<'q, 's>> + 'q {
+            self.edges().filter_map(move |e| {
+                if e.src == src && e.dst == dst {
+                    Some(e)
+                } else if e.src == dst && e.dst == src {
+                    Some(Edge{ src: e.dst, dst: e.src, rel: e.rel, forward: e.reverse, reverse: e.forward })
+                } else {
+                    None
+                }
+            })
+        }
+
+        /// Labels of relationships from `src` to `dst` in direction `dir`
+        pub fn labels_on<'q>(&'q self, src: &'q str, dst: &'q str, dir: Dir) -> impl Iterator<Item=&'q Cow<'s, str>> + 'q {
+            self.edges_between(src, dst).flat_map(move |e| match dir {
+                Dir::Forward => e.forward.iter(),
+                Dir::Reverse => e.reverse.iter(),
+            })
+        }
+
+        /// Styles applied to `process`, in sorted order
+        pub fn styles_of(&self, process: &str) -> impl Iterator<Item=&Cow<'s, str>> {
+            self.styling.style_by_name.get(process).into_iter().flatten()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_query_containment() {
+            let val = parse("plant [ boiler [ valve ] ]; operator").unwrap();
+            let q = Query::new(&val).unwrap();
+            assert_eq!(q.roots().collect::<Vec<_>>(), vec!["operator", "plant"]);
+            assert_eq!(q.children_of("plant").collect::<Vec<_>>(), vec!["boiler"]);
+            assert_eq!(q.ancestors("valve").collect::<Vec<_>>(), vec!["boiler", "plant"]);
+            assert_eq!(q.parent_of("plant"), None);
+        }
+
+        #[test]
+        fn test_query_edges() {
+            let val = parse("controller plant: setpoint / reading; plant sensor").unwrap();
+            let q = Query::new(&val).unwrap();
+            assert_eq!(q.labels_on("controller", "plant", Dir::Forward).collect::<Vec<_>>(), vec!["setpoint"]);
+            assert_eq!(q.labels_on("plant", "controller", Dir::Forward).collect::<Vec<_>>(), vec!["reading"]);
+            assert_eq!(q.edges_between("sensor", "plant").count(), 1);
+            assert_eq!(q.edges_between("controller", "sensor").count(), 0);
+            assert_eq!(q.edges().count(), 2);
+        }
+
+        #[test]
+        fn test_query_edges_ignore_containment() {
+            let val = parse("a [ b ]; b c; - b d").unwrap();
+            let q = Query::new(&val).unwrap();
+            let edges = q.edges().map(|e| (e.src.to_string(), e.dst.to_string(), e.rel)).collect::<Vec<_>>();
+            assert_eq!(edges, vec![
+                ("b".into(), "c".into(), Rel::Vertical),
+                ("b".into(), "d".into(), Rel::Horizontal),
+            ]);
+        }
+    }
+}
+
+pub mod export {
+    //! Mermaid and PlantUML export of evaluated models
+    //!
+    //! # Summary
+    //!
+    //! [to_mermaid()] and [to_plantuml()] translate the processes, containment, and
+    //! relationships that a [Query] reads off of a model's [Vcg](crate::graph_drawing::layout::Vcg)
+    //! into Mermaid flowchart and PlantUML component syntax so that models can be
+    //! rendered by tools that already understand those formats, like Markdown previewers.
+    //!
+    //! Neither export carries depict's geometry: containers become subgraphs or packages,
+    //! forward labels label an arrow from the chain's first process to its second, and
+    //! reverse labels label a second arrow pointing back. PlantUML arrows keep their
+    //! relationship's direction; Mermaid arrows all flow top to bottom.
+    //!
+    //! ```ignore
+    //! let val = query::parse("controller plant: setpoint / reading")?;
+    //! let mermaid = export::to_mermaid(&Query::new(&val)?);
+    //! ```
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    use crate::graph_drawing::eval::{Dir, Rel};
+    use crate::graph_drawing::query::Query;
+
+    /// One arrow to export, pointing the way its labels flow
+    struct Arrow<'q, 's> {
+        src: &'q Cow<'s, str>,
+        dst: &'q Cow<'s, str>,
+        rel: Rel,
+        dir: Dir,
+        label: String,
+    }
+
+    fn label(labels: &[Cow<str>]) -> String {
+        labels.iter()
+            .filter(|label| !label.is_empty() && *label != "_")
+            .map(|label| &label[..])
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Split each relationship of `query` into a forward arrow, unless it has only reverse labels,
+    /// and a reverse arrow, if it has reverse labels.
+    fn arrows<'q, 's>(query: &'q Query<'s>) -> Vec<Arrow<'q, 's>> {
+        let mut arrows = vec![];
+        for edge in query.edges() {
+            let (forward, reverse) = (label(edge.forward), label(edge.reverse));
+            if !forward.is_empty() || reverse.is_empty() {
+                arrows.push(Arrow{src: edge.src, dst: edge.dst, rel: edge.rel.clone(), dir: Dir::Forward, label: forward});
+            }
+            if !reverse.is_empty() {
+                arrows.push(Arrow{src: edge.dst, dst: edge.src, rel: edge.rel, dir: Dir::Reverse, label: reverse});
+            }
+        }
+        arrows
+    }
+
+    /// Identifiers safe to use in either syntax, numbered in sorted order of the processes they stand for
+    fn ids<'q>(query: &'q Query) -> HashMap<&'q str, String> {
+        query.processes()
+            .enumerate()
+            .map(|(n, process)| (&process[..], format!("n{n}")))
+            .collect()
+    }
+
+    fn mermaid_text(text: &str) -> String {
+        text.replace('"', "#quot;").replace('\n', "<br>")
+    }
+
+    fn plantuml_text(text: &str) -> String {
+        text.replace('"', "<U+0022>").replace('\n', "\\n")
+    }
+
+    fn mermaid_process(out: &mut String, query: &Query, ids: &HashMap<&str, String>, process: &str, depth: usize) {
+        let indent = "    ".repeat(depth);
+        let id = &ids[process];
+        let text = mermaid_text(process);
+        let mut children = query.children_of(process).peekable();
+        if children.peek().is_none() {
+            out.push_str(&format!("{indent}{id}[\"{text}\"]\n"));
+            return
+        }
+        out.push_str(&format!("{indent}subgraph {id}[\"{text}\"]\n"));
+        for child in children {
+            mermaid_process(out, query, ids, child, depth + 1);
+        }
+        out.push_str(&format!("{indent}end\n"));
+    }
+
+    /// Export `query` as a top-to-bottom Mermaid flowchart.
+    ///
+    /// Mermaid cannot pin the direction of individual links, so horizontal relationships
+    /// become ordinary links, each preceded by a `%%` comment saying which side its source
+    /// is placed on.
+    pub fn to_mermaid(query: &Query) -> String {
+        let ids = ids(query);
+        let mut out = String::from("flowchart TB\n");
+        for root in query.roots() {
+            mermaid_process(&mut out, query, &ids, root, 1);
+        }
+        for Arrow{src, dst, rel, dir, label} in arrows(query) {
+            let (src, dst) = (&ids[&src[..]], &ids[&dst[..]]);
+            if rel == Rel::Horizontal {
+                let side = if dir == Dir::Forward { "left" } else { "right" };
+                out.push_str(&format!("    %% {src} is {side} of {dst}\n"));
+            }
+            let label = if label.is_empty() { label } else { format!("|\"{}\"|", mermaid_text(&label)) };
+            out.push_str(&format!("    {src} -->{label} {dst}\n"));
+        }
+        out
+    }
+
+    fn plantuml_process(out: &mut String, query: &Query, ids: &HashMap<&str, String>, process: &str, depth: usize) {
+        let indent = "  ".repeat(depth);
+        let id = &ids[process];
+        let text = plantuml_text(process);
+        let mut children = query.children_of(process).peekable();
+        if children.peek().is_none() {
+            out.push_str(&format!("{indent}component \"{text}\" as {id}\n"));
+            return
+        }
+        out.push_str(&format!("{indent}package \"{text}\" as {id} {{\n"));
+        for child in children {
+            plantuml_process(out, query, ids, child, depth + 1);
+        }
+        out.push_str(&format!("{indent}}}\n"));
+    }
+
+    /// Export `query` as a PlantUML component diagram whose arrows point down, up, right, or left
+    /// according to their relationship and direction.
+    pub fn to_plantuml(query: &Query) -> String {
+        let ids = ids(query);
+        let mut out = String::from("@startuml\n");
+        for root in query.roots() {
+            plantuml_process(&mut out, query, &ids, root, 0);
+        }
+        for Arrow{src, dst, rel, dir, label} in arrows(query) {
+            let hint = match (rel, dir) {
+                (Rel::Vertical, Dir::Forward) => "down",
+                (Rel::Vertical, Dir::Reverse) => "up",
+                (Rel::Horizontal, Dir::Forward) => "right",
+                (Rel::Horizontal, Dir::Reverse) => "left",
+            };
+            let label = if label.is_empty() { label } else { format!(" : {}", plantuml_text(&label)) };
+            out.push_str(&format!("{} -{hint}-> {}{label}\n", ids[&src[..]], ids[&dst[..]]));
+        }
+        out.push_str("@enduml\n");
+        out
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::graph_drawing::query::parse;
+
+        use super::*;
+
+        #[test]
+        fn test_mermaid() {
+            let val = parse("plant [ controller valve: open / closed ]; - operator plant: alarm").unwrap();
+            let q = Query::new(&val).unwrap();
+            assert_eq!(to_mermaid(&q), concat!(
+                "flowchart TB\n",
+                "    n1[\"operator\"]\n",
+                "    subgraph n2[\"plant\"]\n",
+                "        n0[\"controller\"]\n",
+                "        n3[\"valve\"]\n",
+                "    end\n",
+                "    n0 -->|\"open\"| n3\n",
+                "    n3 -->|\"closed\"| n0\n",
+                "    %% n1 is left of n2\n",
+                "    n1 -->|\"alarm\"| n2\n",
+            ));
+            let val = parse("- a b: x / y").unwrap();
+            let mermaid = to_mermaid(&Query::new(&val).unwrap());
+            assert!(mermaid.ends_with("    %% n0 is left of n1\n    n0 -->|\"x\"| n1\n    %% n1 is right of n0\n    n1 -->|\"y\"| n0\n"), "{mermaid}");
+            assert_eq!(mermaid_text("\"a\"\nb"), "#quot;a#quot;<br>b");
+        }
+
+        #[test]
+        fn test_plantuml() {
+            let val = parse("driver car: brake / speed; - car radio; car engine").unwrap();
+            let q = Query::new(&val).unwrap();
+            assert_eq!(to_plantuml(&q), concat!(
+                "@startuml\n",
+                "component \"car\" as n0\n",
+                "component \"driver\" as n1\n",
+                "component \"engine\" as n2\n",
+                "component \"radio\" as n3\n",
+                "n0 -down-> n2\n",
+                "n0 -right-> n3\n",
+                "n1 -down-> n0 : brake\n",
+                "n0 -up-> n1 : speed\n",
+                "@enduml\n",
+            ));
+            assert_eq!(plantuml_text("\"a\"\nb"), "<U+0022>a<U+0022>\\nb");
+        }
+    }
+}
+
+pub mod diff {
+    //! Semantic differences between two versions of a model
+    //!
+    //! # Summary
+    //!
+    //! [diff()] evaluates two sources, aligns their processes by resolved name, and
+    //! reports [Change]s to processes, containment, relationships, and labels.
+    //!
+    //! [dom::draw_diff()](crate::graph_drawing::frontend::dom::draw_diff) draws the
+    //! union of both versions with `added`, `removed`, `renamed`, `moved`, and `changed`
+    //! CSS classes applied to the affected elements.
+    //!
+    //! # Reference-level explanation
+    //!
+    //! A removed process is considered renamed to an added process when both have the
+    //! same container, contents, and relationships (with the same labels) and when
+    //! neither has any other such counterpart.
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+    use crate::graph_drawing::error::Error;
+    use crate::graph_drawing::eval::{Dir, Rel};
+    use crate::graph_drawing::query::{parse, Query};
+
+    #[non_exhaustive]
+    #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+    pub enum Change {
+        AddedProcess{name: String, container: Option<String>},
+        RemovedProcess{name: String, container: Option<String>},
+        RenamedProcess{from: String, to: String},
+        MovedProcess{name: String, from: Option<String>, to: Option<String>},
+        AddedEdge{src: String, dst: String, rel: Rel},
+        RemovedEdge{src: String, dst: String, rel: Rel},
+        AddedLabel{src: String, dst: String, rel: Rel, dir: Dir, label: String},
+        RemovedLabel{src: String, dst: String, rel: Rel, dir: Dir, label: String},
+    }
+
+    /// The [Change]s between two models, in sorted order
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct Diff {
+        pub changes: Vec<Change>,
+    }
+
+    type Labels = BTreeMap<Dir, BTreeSet<String>>;
+
+    /// Orient `(src, dst)` so that `src <= dst`, flipping `dir` to match.
+    fn orient<'a>(src: &'a str, dst: &'a str, dir: Dir) -> (&'a str, &'a str, Dir) {
+        if src <= dst {
+            (src, dst, dir)
+        } else {
+            let dir = match dir { Dir::Forward => Dir::Reverse, Dir::Reverse => Dir::Forward };
+            (dst, src, dir)
+        }
+    }
+
+    /// Collect `q`'s relationships, oriented by [orient()], with process names mapped through `rename`.
+    fn edges(q: &Query, rename: &HashMap<String, String>) -> BTreeMap<(String, String, Rel), Labels> {
+        let name = |p: &str| rename.get(p).cloned().unwrap_or_else(|| p.to_string());
+        let mut edges: BTreeMap<(String, String, Rel), Labels> = BTreeMap::new();
+        for e in q.edges() {
+            let (src, dst) = (name(e.src), name(e.dst));
+            let entry = edges.entry({
+                let (src, dst, _) = orient(&src, &dst, Dir::Forward);
+                (src.to_string(), dst.to_string(), e.rel.clone())
+            }).or_default();
+            for (dir, labels) in [(Dir::Forward, e.forward), (Dir::Reverse, e.reverse)] {
+                let (_, _, dir) = orient(&src, &dst, dir);
+                let set = entry.entry(dir).or_default();
+                set.extend(labels.iter().map(|l| l.to_string()).filter(|l| l != "_"));
+            }
+        }
+        edges
+    }
+
+    /// A process's container, contents, and (neighbor, rel, outgoing labels, incoming labels) relationships
+    type Signature = (Option<String>, Vec<String>, Vec<(String, Rel, Vec<String>, Vec<String>)>);
+
+    /// Describe `process` by everything but its name.
+    fn signature(q: &Query, process: &str) -> Signature {
+        let parent = q.parent_of(process).map(|p| p.to_string());
+        let children = q.children_of(process).map(|c| c.to_string()).collect();
+        let mut relationships = q.edges()
+            .filter_map(|e| {
+                let strings = |labels: &[std::borrow::Cow<str>]| labels.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+                if e.src == process {
+                    Some((e.dst.to_string(), e.rel.clone(), strings(e.forward), strings(e.reverse)))
+                } else if e.dst == process {
+                    Some((e.src.to_string(), e.rel.clone(), strings(e.reverse), strings(e.forward)))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        relationships.sort();
+        (parent, children, relationships)
+    }
+
+    /// Compute the semantic differences between models `old` and `new`.
+    pub fn diff(old: &str, new: &str) -> Result<Diff, Error> {
+        let old_val = parse(old)?;
+        let new_val = parse(new)?;
+        let old_q = Query::new(&old_val)?;
+        let new_q = Query::new(&new_val)?;
+
+        let old_processes = old_q.processes().map(|p| p.to_string()).collect::<BTreeSet<_>>();
+        let new_processes = new_q.processes().map(|p| p.to_string()).collect::<BTreeSet<_>>();
+        let removed = old_processes.difference(&new_processes).collect::<Vec<_>>();
+        let added = new_processes.difference(&old_processes).collect::<Vec<_>>();
+
+        let mut changes = vec![];
+
+        let mut rename = HashMap::new();
+        let added_signatures = added.iter().map(|q| signature(&new_q, q)).collect::<Vec<_>>();
+        let removed_signatures = removed.iter().map(|p| signature(&old_q, p)).collect::<Vec<_>>();
+        for (p, p_sig) in removed.iter().zip(removed_signatures.iter()) {
+            let candidates = added.iter().zip(added_signatures.iter()).filter(|(_, q_sig)| *q_sig == p_sig).collect::<Vec<_>>();
+            let rivals = removed_signatures.iter().filter(|sig| *sig == p_sig).count();
+            if let [(q, _)] = &candidates[..] {
+                if rivals == 1 {
+                    rename.insert(p.to_string(), q.to_string());
+                    changes.push(Change::RenamedProcess{from: p.to_string(), to: q.to_string()});
+                }
+            }
+        }
+        let renamed = rename.values().cloned().collect::<BTreeSet<_>>();
+
+        for p in removed.iter().filter(|p| !rename.contains_key(**p)) {
+            changes.push(Change::RemovedProcess{name: p.to_string(), container: old_q.parent_of(p).map(|c| c.to_string())});
+        }
+        for q in added.iter().filter(|q| !renamed.contains(**q)) {
+            changes.push(Change::AddedProcess{name: q.to_string(), container: new_q.parent_of(q).map(|c| c.to_string())});
+        }
+
+        for (p, q) in old_processes.iter()
+            .map(|p| (p, rename.get(p).unwrap_or(p)))
+            .filter(|(_, q)| new_processes.contains(*q))
+        {
+            let from = old_q.parent_of(p).map(|c| rename.get(&c[..]).cloned().unwrap_or_else(|| c.to_string()));
+            let to = new_q.parent_of(q).map(|c| c.to_string());
+            if from != to {
+                changes.push(Change::MovedProcess{name: q.clone(), from, to});
+            }
+        }
+
+        let old_edges = edges(&old_q, &rename);
+        let new_edges = edges(&new_q, &HashMap::new());
+        let no_labels = Labels::new();
+        for key in old_edges.keys().chain(new_edges.keys()).collect::<BTreeSet<_>>() {
+            let (src, dst, rel) = key.clone();
+            let old_labels = old_edges.get(key);
+            let new_labels = new_edges.get(key);
+            match (old_labels, new_labels) {
+                (Some(_), None) => changes.push(Change::RemovedEdge{src: src.clone(), dst: dst.clone(), rel: rel.clone()}),
+                (None, Some(_)) => changes.push(Change::AddedEdge{src: src.clone(), dst: dst.clone(), rel: rel.clone()}),
+                _ => {},
+            }
+            let old_labels = old_labels.unwrap_or(&no_labels);
+            let new_labels = new_labels.unwrap_or(&no_labels);
+            for dir in [Dir::Forward, Dir::Reverse] {
+                let old_set = old_labels.get(&dir).cloned().unwrap_or_default();
+                let new_set = new_labels.get(&dir).cloned().unwrap_or_default();
+                for label in old_set.difference(&new_set) {
+                    changes.push(Change::RemovedLabel{src: src.clone(), dst: dst.clone(), rel: rel.clone(), dir: dir.clone(), label: label.clone()});
+                }
+                for label in new_set.difference(&old_set) {
+                    changes.push(Change::AddedLabel{src: src.clone(), dst: dst.clone(), rel: rel.clone(), dir: dir.clone(), label: label.clone()});
+                }
+            }
+        }
+
+        changes.sort();
+        Ok(Diff{ changes })
+    }
+
+    impl Diff {
+        pub fn is_empty(&self) -> bool {
+            self.changes.is_empty()
+        }
+
+        /// Which CSS class, if any, marks process `name`?
+        pub fn node_class(&self, name: &str) -> Option<&'static str> {
+            self.changes.iter().find_map(|c| match c {
+                Change::AddedProcess{name: n, ..} if n == name => Some("added"),
+                Change::RemovedProcess{name: n, ..} if n == name => Some("removed"),
+                Change::RenamedProcess{to, ..} if to == name => Some("renamed"),
+                Change::MovedProcess{name: n, ..} if n == name => Some("moved"),
+                _ => None,
+            })
+        }
+
+        /// Which CSS class, if any, marks the arrow from `src` to `dst` in direction `dir`?
+        pub fn arrow_class(&self, src: &str, dst: &str, rel: &Rel, dir: &Dir) -> Option<&'static str> {
+            let (src, dst, dir) = orient(src, dst, dir.clone());
+            let mut added = false;
+            let mut removed = false;
+            for c in self.changes.iter() {
+                match c {
+                    Change::AddedEdge{src: s, dst: d, rel: r} if (&s[..], &d[..], r) == (src, dst, rel) => return Some("added"),
+                    Change::RemovedEdge{src: s, dst: d, rel: r} if (&s[..], &d[..], r) == (src, dst, rel) => return Some("removed"),
+                    Change::AddedLabel{src: s, dst: d, rel: r, dir: di, ..} if (&s[..], &d[..], r, di) == (src, dst, rel, &dir) => added = true,
+                    Change::RemovedLabel{src: s, dst: d, rel: r, dir: di, ..} if (&s[..], &d[..], r, di) == (src, dst, rel, &dir) => removed = true,
+                    _ => {},
+                }
+            }
+            match (added, removed) {
+                (true, true) => Some("changed"),
+                (true, false) => Some("added"),
+                (false, true) => Some("removed"),
+                (false, false) => None,
+            }
+        }
+
+        /// Model source for the processes, relationships, and labels that were removed,
+        /// suitable for appending to the new model's source to draw both versions together.
+        pub fn removed_source(&self) -> String {
+            let mut lines = vec![];
+            let mut removed_labels: BTreeMap<(&String, &String, &Rel), Labels> = BTreeMap::new();
+            for c in self.changes.iter() {
+                match c {
+                    Change::RemovedProcess{name, container: Some(container)} => lines.push(format!("{container} [ {name} ]")),
+                    Change::RemovedProcess{name, container: None} => lines.push(name.clone()),
+                    Change::RemovedEdge{src, dst, rel} => { removed_labels.entry((src, dst, rel)).or_default(); },
+                    Change::RemovedLabel{src, dst, rel, dir, label} => {
+                        removed_labels.entry((src, dst, rel)).or_default().entry(dir.clone()).or_default().insert(label.clone());
+                    },
+                    _ => {},
+                }
+            }
+            for ((src, dst, rel), labels) in removed_labels {
+                let chain = match rel {
+                    Rel::Vertical => format!("{src} {dst}"),
+                    Rel::Horizontal => format!("{src} {dst} -"),
+                };
+                let join = |dir| itertools::join(labels.get(&dir).into_iter().flatten(), ", ");
+                let (forward, reverse) = (join(Dir::Forward), join(Dir::Reverse));
+                match (forward.is_empty(), reverse.is_empty()) {
+                    (true, true) => lines.push(chain),
+                    (false, true) => lines.push(format!("{chain}: {forward}")),
+                    _ => lines.push(format!("{chain}: {forward} / {reverse}")),
+                }
+            }
+            lines.join("\n")
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_diff_processes() {
+            let d = diff("a [ b ]; c; d e", "a [ b ]; a [ c ]; d f; g").unwrap();
+            assert_eq!(d.changes, vec![
+                Change::AddedProcess{name: "g".into(), container: None},
+                Change::RenamedProcess{from: "e".into(), to: "f".into()},
+                Change::MovedProcess{name: "c".into(), from: None, to: Some("a".into())},
+            ]);
+            assert_eq!(d.node_class("f"), Some("renamed"));
+            assert_eq!(d.node_class("a"), None);
+        }
+
+        #[test]
+        fn test_diff_labels() {
+            let d = diff("a b: x, y / z; b c", "a b: x, w / z").unwrap();
+            assert!(d.changes.contains(&Change::RemovedProcess{name: "c".into(), container: None}));
+            assert!(d.changes.contains(&Change::RemovedEdge{src: "b".into(), dst: "c".into(), rel: Rel::Vertical}));
+            assert!(d.changes.contains(&Change::AddedLabel{src: "a".into(), dst: "b".into(), rel: Rel::Vertical, dir: Dir::Forward, label: "w".into()}));
+            assert!(d.changes.contains(&Change::RemovedLabel{src: "a".into(), dst: "b".into(), rel: Rel::Vertical, dir: Dir::Forward, label: "y".into()}));
+            assert_eq!(d.arrow_class("a", "b", &Rel::Vertical, &Dir::Forward), Some("changed"));
+            assert_eq!(d.arrow_class("a", "b", &Rel::Vertical, &Dir::Reverse), None);
+            assert_eq!(d.arrow_class("b", "c", &Rel::Vertical, &Dir::Forward), Some("removed"));
+            assert_eq!(d.removed_source(), "c\na b: y\nb c");
+            assert!(diff("a b: x", "a b: x").unwrap().is_empty());
+        }
+    }
+}
+
+pub mod geometry {
+    //! Generate beautiful geometry consistent with given relations.
+    //!
+    //! # Summary
+    //!
+    //! The purpose of the `geometry` module is to generate beautiful geometry
+    //! (positions, widths, paths) consistent with given geometric relations between
+    //! graphical objects to be positioned (ex: "object A should be placed to the left
+    //! of object b").
+    //!
+    //! # Guide-level explanation
+    //!
+    //! Convex optimization is a powerful framework for finding sets of numbers
+    //! (representing the coordinates of points and guides) that "solve" a given
+    //! layout problem in an optimal way consistent with given costs and constraints.
+    //!
+    //! Conveniently, it allows us to express both geometric constraints like
+    //! "the horizontal position of the right-hand border of box A must be less
+    //! the horizontal position of the left-hand side of the bounding box of hop C1"
+    //! as well as more flexible desiderata like "subject to these constraints,
+    //! minimize the square of the difference of the positions of hops C1.1 and C2.2".
+    //!
+    //! # Reference-level explanation
+    //!
+    //! `geometry` is implemented in terms of the [OSQP](https://osqp.org) convex
+    //! quadratic program solver and its Rust bindings in the [osqp] crate.
+    //!
+    //! Abstractly, the data and the steps required to convert geometric relations into geometry are:
+    //!
+    //! 1. the input data need to be organized (here, via [`calculate_sols()`]) so that constraints can be generated and so that the optimization objective can be formed.
+    //! 2. then, once constraints and the objective are generated, they need to be formatted as an [osqp::CscMatrix] and associated `&[f64]` slices, passed to [osqp::Problem], and solved.
+    //! 3. then, the resulting [osqp_rust::Solution] needs to be destructured so that the resulting solution values can be returned to [`position_sols()`]'s caller as a [GeometrySolution].
+
+    use enum_kinds::EnumKind;
+    use ordered_float::OrderedFloat;
+    #[cfg(all(not(feature="osqp"), feature="osqp-rust"))]
+    use osqp_rust as osqp;
+    #[cfg(all(feature="osqp", not(feature="osqp-rust")))]
+    use osqp as osqp;
+
+    use petgraph::EdgeDirection::{Outgoing, Incoming};
+    use petgraph::Graph;
+    use petgraph::algo::is_cyclic_directed;
+    use petgraph::dot::Dot;
+    use petgraph::visit::{EdgeRef};
+
+    use crate::graph_drawing::layout::ObjNode;
+    #[allow(unused_imports)]
+    use crate::graph_drawing::osqp::{as_diag_csc_matrix, print_tuples, as_scipy, as_numpy};
+
+    use super::error::{LayoutError};
+    use super::frontend::log::Name;
+    use super::frontend::{RenderOptions, measure::{TextMeasurer, TextRole}};
+    use super::osqp::{Constraints, Monomial, Vars, Fresh, Var, Sol, Coeff};
+
+    use super::error::Error;
+    use super::index::{VerticalRank, OriginalHorizontalRank, SolvedHorizontalRank, VarRank};
+    use super::layout::{Obj, Hop, Vcg, LayoutProblem, Graphic, LayoutSolution, Len, L2n, V2n, or_insert, ObjHop, ObjContainer};
+
+    use std::borrow::Cow;
+    use std::cmp::{max};
+    use std::collections::{HashMap, BTreeMap};
+    use std::fmt::{Debug, Display};
+    use std::hash::Hash;
+
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd, EnumKind)]
+    #[enum_kind(AnySolKind)]
+    pub enum AnySol {
+        L(VarRank),
+        R(VarRank),
+        S(VarRank),
+        T(VarRank),
+        B(VarRank),
+        H(VerticalRank),
+        V(SolvedHorizontalRank),
+        F(usize),
+        #[default]
+        Default,
+    }
+
+    impl Fresh for AnySol {
+        fn fresh(index: usize) -> Self {
+            Self::F(index)
+        }
+    }
+
+    impl Display for AnySol {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                AnySol::L(loc) => write!(f, "l{}", loc.0),
+                AnySol::R(loc) => write!(f, "r{}", loc.0),
+                AnySol::S(hop) => write!(f, "s{}", hop.0),
+                AnySol::T(loc) => write!(f, "t{}", loc.0),
+                AnySol::B(loc) => write!(f, "b{}", loc.0),
+                AnySol::H(ovr) => write!(f, "h{}", ovr.0),
+                AnySol::V(shr) => write!(f, "v{}", shr.0),
+                AnySol::F(idx) => write!(f, "f{}", idx),
+                AnySol::Default => write!(f, "def"),
+            }
+        }
+    }
+
+    impl Name for AnySol {
+        fn name(&self) -> String {
+            format!("{}", self)
+        }
+    }
+
+    impl<'a> TryFrom<&'a AnySol> for VarRank {
+        type Error = &'a AnySol;
+
+        fn try_from(sol: &'a AnySol) -> Result<Self, Self::Error> {
+            match sol {
+                AnySol::L(vr) |  AnySol::R(vr) | AnySol::S(vr) | AnySol::T(vr) | AnySol::B(vr) => Ok(*vr),
+                _ => Err(sol),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct LocRow<V: Graphic> {
+        pub ovr: VerticalRank,
+        pub ohr: OriginalHorizontalRank,
+        pub shr: SolvedHorizontalRank,
+        pub loc: Obj<V>,
+        pub n: VarRank,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct HopRow<V: Graphic> {
+        pub lvl: VerticalRank,
+        pub mhr: OriginalHorizontalRank,
+        pub nhr: OriginalHorizontalRank,
+        pub vl: V,
+        pub wl: V,
+        pub n: VarRank,
+    }
+
+    #[derive(Clone, Debug, PartialEq, PartialOrd)]
+    pub struct NodeSize {
+        pub width: f64,
+        pub left: f64,
+        pub right: f64,
+        pub height: f64,
+        /// room above the box for notes outside it
+        pub top: f64,
+        /// room along the inside of the box's bottom edge for notes
+        pub bottom: f64,
+    }
+
+    #[derive(Clone, Debug, PartialEq, PartialOrd)]
+    pub struct HopSize {
+        pub width: f64,
+        pub left: f64,
+        pub right: f64,
+        pub height: f64,
+        pub top: f64,
+        pub bottom: f64,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    pub struct GeometryProblem<V: Graphic> {
+        pub varrank_by_obj: HashMap<Obj<V>, VarRank>,
+        pub loc_by_varrank: HashMap<VarRank, LocIx>,
+        pub size_by_loc: HashMap<LocIx, NodeSize>,
+        pub size_by_hop: HashMap<(VerticalRank, OriginalHorizontalRank, V, V), HopSize>,
+        /// The variables and size of the legend, if any, which [position_sols()] places beside everything else
+        pub legend: Option<(VarRank, NodeSize)>,
+    }
+
+    impl<V: Graphic> GeometryProblem<V> {
+        /// Make room for a legend of size `size`, positioned by fresh variables.
+        pub fn reserve_legend(&mut self, size: NodeSize) {
+            self.legend = Some((VarRank(self.loc_by_varrank.len()), size));
+        }
+    }
+
+    use crate::graph_drawing::frontend::log::{self, names, Names};
+
+    impl<V: Graphic> log::Log for HashMap<Obj<V>, VarRank> {
+        fn log(&self, _cx: (), l: &mut log::Logger) -> Result<(), log::Error> {
+            l.with_map("obj_by_varrank", "ObjByVarrank", self.iter(), |obj, sol, l| {
+                let src_names = obj.names();
+                l.log_pair(
+                    "Varrank",
+                    names![sol],
+                    format!("{sol}"),
+                    "Obj",
+                    src_names,
+                    format!("{obj}"),
+                )
+            })
+        }
+    }
+
+    impl<CX: L2n> log::Log<CX> for HashMap<LocIx, NodeSize> {
+        fn log(&self, cx: CX, l: &mut log::Logger) -> Result<(), log::Error> {
+            l.with_map("size_by_loc", "SizeByLoc", self.iter(), |loc_ix, size, l| {
+                let mut src_names = loc_ix.names();
+                src_names.append(&mut cx(loc_ix.0, loc_ix.1));
+                l.log_pair(
+                    "Loc",
+                    src_names,
+                    format!("{:?}, {:?}", loc_ix.0, loc_ix.1),
+                    "NodeSize",
+                    vec![],
+                    format!("{size:?}"))
+            })
+        }
+    }
+
+    impl<V: Graphic + Display + log::Name, CX: L2n> log::Log<CX> for HashMap<HopIx<V>, HopSize> {
+        fn log(&self, cx: CX, l: &mut log::Logger) -> Result<(), log::Error> {
+            l.with_map("size_by_hop", "SizeByHop", self.iter(), |(ovr, ohr, vl, wl), size, l| {
+                let mut src_names = names![ovr, ohr, vl, wl];
+                src_names.append(&mut cx(*ovr, *ohr));
+                l.log_pair(
+                    "Loc",
+                    src_names,
+                    format!("{ovr}v, {ohr}h"),
+                    "HopSize",
+                    vec![],
+                    format!("{size:?}")
+                )
+            })
+        }
+    }
+
+    /// ovr, ohr
+    pub type LocIx = (VerticalRank, OriginalHorizontalRank);
+
+    pub type HopIx<V> = (VerticalRank, OriginalHorizontalRank, V, V);
+
+    /// ovr, ohr -> loc
+    pub type LocNodeMap<V> = HashMap<LocIx, Obj<V>>;
+
+    /// lvl -> (mhr, nhr)
+    pub type HopMap = BTreeMap<VerticalRank, (OriginalHorizontalRank, OriginalHorizontalRank)>;
+
+    pub fn calculate_sols<'s, V>(
+        layout_problem: &'s LayoutProblem<V>,
+        layout_solution: &'s LayoutSolution,
+    ) -> GeometryProblem<V> where
+        V: Display + Graphic + log::Name
+    {
+        let LayoutProblem{loc_to_node, hops_by_level, hops_by_edge, ..} = layout_problem;
+        let LayoutSolution{solved_locs, ..} = layout_solution;
+
+        // eprintln!("SOLVED_LOCS {solved_locs:#?}");
+
+        let mut var_counter = 0;
+        let mut var_rank = || {
+            let n = var_counter;
+            var_counter += 1;
+            VarRank(n)
+        };
+
+        let mut varrank_by_obj = HashMap::new();
+        let mut loc_by_varrank = HashMap::new();
+
+        for (ovr, ohr, _shr, obj) in solved_locs
+            .iter()
+            .flat_map(|(ovr, nodes)| nodes
+                .iter()
+                .map(|(ohr, shr)| (*ovr, *ohr, *shr, &loc_to_node[&(*ovr,*ohr)]))) {
+            let rank = var_rank();
+            varrank_by_obj.insert(obj.clone(), rank);
+            loc_by_varrank.insert(rank, (ovr, ohr));
+        }
+
+        for (mhr, _nhr, vl, wl, lvl) in hops_by_level
+            .iter()
+            .flat_map(|h|
+                h.1.iter().map(|Hop{mhr, nhr, vl, wl, lvl}| {
+                    (*mhr, *nhr, vl.clone(), wl.clone(), *lvl)
+                })
+            )
+            .chain(
+                hops_by_edge.iter().map(|((vl, wl), hops)| {
+                    let (lvl, (mhr, nhr)) = {
+                        #[allow(clippy::unwrap_used)] // an edge with no hops really should panic
+                        let kv = hops.last_key_value().unwrap();
+                        (*kv.0, *kv.1)
+                    };
+                    (nhr, OriginalHorizontalRank(std::usize::MAX - mhr.0), vl.clone(), wl.clone(), lvl+1)
+                })
+            ) {
+            let rank = var_rank();
+            varrank_by_obj.insert(Obj::Hop(ObjHop{lvl: lvl, mhr, vl: vl.clone(), wl: wl.clone()}), rank);
+            loc_by_varrank.insert(rank, (lvl, mhr));
+        }
+
+        let size_by_loc = HashMap::new();
+        let size_by_hop = HashMap::new();
+
+        GeometryProblem{
+            varrank_by_obj,
+            loc_by_varrank,
+            size_by_loc,
+            size_by_hop,
+            legend: None,
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct OptimizationProblem<S: Sol, C: Coeff> {
+        v: Vars<S>,
+        c: Constraints<S, C>,
+        pd: Vec<Monomial<S, C>>,
+        q: Vec<Monomial<S, C>>,
+    }
+
+    use std::fmt::Write;
+
+    impl<C: Coeff, CX: V2n> log::Log<(String, CX)> for OptimizationProblem<AnySol, C> {
+        fn log(&self, cx: (String, CX), l: &mut log::Logger) -> Result<(), log::Error> {
+            let names = |monomial: &Monomial<AnySol, C>| {
+                let mut ret = monomial.names();
+                match monomial.var.sol {
+                    AnySol::L(ls) | AnySol::R(ls) | AnySol::T(ls) | AnySol::B(ls) | AnySol::S(ls) => {
+                        ret.append(&mut (cx.1)(ls));
+                    },
+                    _ => {},
+                }
+                ret
+            };
+            l.with_group("OptimizationProblem", cx.0.clone(), Vec::<String>::new(), |l| {
+                l.with_map(cx.0.clone(), "Vars", self.v.iter(), |sol, var, l| {
+                    let mut src_names = sol.names();
+                    match sol {
+                        AnySol::L(ls) | AnySol::R(ls) | AnySol::T(ls) | AnySol::B(ls) | AnySol::S(ls) => {
+                            src_names.append(&mut (cx.1)(*ls));
+                        },
+                        _ => {},
+                    }
+                    l.log_pair(
+                        "AnySol",
+                        src_names,
+                        format!("{sol}"),
+                        "Var",
+                        var.names(),
+                        format!("v{}", var.index)
+                    )
+                })?;
+                l.with_set(cx.0.clone(), "Constraints", self.c.iter(), |(lower, monomials, upper), l| {
+                    let mut s = String::new();
+                    let mut monomial_names = vec![];
+                    write!(s, "{lower} <= ")?;
+                    for term in monomials.iter() {
+                        monomial_names.append(&mut names(term));
+                        write!(s, "{term} ")?;
+                    }
+                    if *upper != f64::INFINITY {
+                        writeln!(s, "<= {upper}")?;
+                    }
+                    let monomial_names = monomial_names.into_iter().map(|n| n.name()).collect::<Vec<_>>();
+                    l.log_element("Constraint", monomial_names, s)?;
+                    Ok(())
+                })?;
+                l.with_set(cx.0.clone(), "Quadratic Objective", self.pd.iter(), |monomial, l| {
+                    l.log_element("Monomial", names(monomial).into_iter().map(|n| n.name()).collect::<Vec<_>>(), format!("{monomial}"))
+                })?;
+                l.with_set(cx.0.clone(), "Linear Objective", self.q.iter(), |monomial, l| {
+                    l.log_element("Monomial", names(monomial).into_iter().map(|n| n.name()).collect::<Vec<_>>(), format!("{monomial}"))
+                })?;
+                Ok(())
+            })
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct GeometrySolution {
+        pub ls: BTreeMap<VarRank, f64>,
+        pub rs: BTreeMap<VarRank, f64>,
+        pub ss: BTreeMap<VarRank, f64>,
+        pub ts: BTreeMap<VarRank, f64>,
+        pub bs: BTreeMap<VarRank, f64>,
+        pub status_h: OSQPStatusKind,
+        pub status_v: OSQPStatusKind,
+    }
+
+    impl<CX: V2n> log::Log<CX> for GeometrySolution {
+        fn log(&self, cx: CX, l: &mut log::Logger) -> Result<(), log::Error> {
+            l.with_group("Coordinates", "", Vec::<String>::new(), |l| {
+                l.with_map("ls", "BTreeMap<Sol, f64>", self.ls.iter(), |ls, coord, l| {
+                    let mut src_names = ls.names();
+                    src_names.append(&mut cx(*ls));
+                    l.log_pair(
+                        "Sol",
+                        src_names,
+                        format!("{ls}"),
+                        "f64",
+                        vec![],
+                        format!("{:.0}", coord.round())
+                    )
+                })?;
+                l.with_map("rs", "BTreeMap<Sol, f64>", self.rs.iter(), |ls, coord, l| {
+                    let mut src_names = ls.names();
+                    src_names.append(&mut cx(*ls));
+                    l.log_pair(
+                        "Sol",
+                        src_names,
+                        format!("{ls}"),
+                        "f64",
+                        vec![],
+                        format!("{:.0}", coord.round())
+                    )
+                })?;
+                l.with_map("ts", "BTreeMap<Sol, f64>", self.ts.iter(), |ls, coord, l| {
+                    let mut src_names = ls.names();
+                    src_names.append(&mut cx(*ls));
+                    l.log_pair(
+                        "Sol",
+                        src_names,
+                        format!("{ls}"),
+                        "f64",
+                        vec![],
+                        format!("{:.0}", coord.round())
+                    )
+                })?;
+                l.with_map("bs", "BTreeMap<Sol, f64>", self.bs.iter(), |ls, coord, l| {
+                    let mut src_names = ls.names();
+                    src_names.append(&mut cx(*ls));
+                    l.log_pair(
+                        "Sol",
+                        src_names,
+                        format!("{ls}"),
+                        "f64",
+                        vec![],
+                        format!("{:.0}", coord.round())
+                    )
+                })?;
+                l.with_map("ss", "BTreeMap<Sol, f64>", self.ss.iter(), |hs, coord, l| {
+                    let mut src_names = hs.names();
+                    src_names.append(&mut cx(*hs));
+                    l.log_pair(
+                        "HopSol",
+                        src_names,
+                        format!("{hs}"),
+                        "f64",
+                        vec![],
+                        format!("{:.0}", coord.round())
                     )
                 })?;
-                l.with_set(cx.0.clone(), "Constraints", self.c.iter(), |(lower, monomials, upper), l| {
-                    let mut s = String::new();
-                    let mut monomial_names = vec![];
-                    write!(s, "{lower} <= ")?;
-                    for term in monomials.iter() {
-                        monomial_names.append(&mut names(term));
-                        write!(s, "{term} ")?;
+                Ok(())
+            })
+        }
+    }
+
+    /// How wide must `vl`'s box be drawn to hold its text, at least?
+    fn min_text_width<V: Graphic, E: Graphic>(vcg: &Vcg<V, E>, measurer: &dyn TextMeasurer, vl: &V) -> f64 {
+        let width = vcg.vert_node_labels.get(vl).map_or(0., |label| measurer.width(label, TextRole::Box));
+        f64::max(20., width)
+    }
+
+    /// Widen `min_width` (across ranks) to make room for the arrows leaving and entering `vl`.
+    fn update_min_width<V: Graphic + Len, E: Graphic + PartialEq<str>>(
+        vcg: &Vcg<V, E>,
+        layout_problem: &LayoutProblem<V>,
+        layout_solution: &LayoutSolution,
+        geometry_problem: &GeometryProblem<V>,
+        min_width: &mut OrderedFloat<f64>,
+        vl: &V
+    ) -> Result<(), Error> {
+        let GeometryProblem{size_by_hop, ..} = geometry_problem;
+
+        let (v_out_first_hops, w_in_last_hops) = order_adjacent_vertical_edges(vcg, layout_problem, layout_solution, vl)?;
+
+        let out_width: f64 = v_out_first_hops
+            .iter()
+            .filter_map(|idx| {
+                size_by_hop.get(idx).map(|sz| sz.left + sz.right)
+            })
+            .sum();
+        let in_width: f64 = w_in_last_hops
+            .iter()
+            .filter_map(|idx| {
+                size_by_hop.get(idx).map(|sz| sz.left + sz.right)
+            })
+            .sum();
+
+        let of = OrderedFloat::<f64>::from;
+
+        let out_width = of(out_width);
+        let in_width = of(in_width);
+
+        // min_width += max_by(out_width, in_width, |a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Greater));
+        *min_width = max(*min_width, max(in_width, out_width));
+        // eprintln!("lvl: {}, vl: {}, wl: {}, hops: {:?}", lvl, vl, wl, hops);
+        Ok(())
+    }
+
+    fn order_adjacent_vertical_edges<V: Graphic, E: Graphic + PartialEq<str>>(
+        vcg: &Vcg<V, E>,
+        layout_problem: &LayoutProblem<V>,
+        layout_solution: &LayoutSolution,
+        vl: &V
+    ) -> Result<(Vec<(VerticalRank, OriginalHorizontalRank, V, V)>, Vec<(VerticalRank, OriginalHorizontalRank, V, V)>), Error> {
+        let Vcg{vert: dag, vert_vxmap: dag_map, containers, ..} = vcg;
+        let LayoutProblem{node_to_loc, hops_by_edge, ..} = layout_problem;
+        let LayoutSolution{solved_locs, ..} = layout_solution;
+        let loc_ix = node_to_loc[&Obj::from_vl(vl, containers)];
+        let v_ers = dag.edges_directed(dag_map[vl], Outgoing).filter(|er| er.weight() == "vertical").into_iter().collect::<Vec<_>>();
+        let w_ers = dag.edges_directed(dag_map[vl], Incoming).filter(|er| er.weight() == "vertical").into_iter().collect::<Vec<_>>();
+        let mut v_dsts = v_ers
+            .iter()
+            .map(|er| {
+                dag
+                    .node_weight(er.target())
+                    .cloned()
+                    .ok_or_else::<Error, _>(|| LayoutError::OsqpError{error: "missing node weight".into()}.into())
+            })
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut w_srcs = w_ers
+            .iter()
+            .map(|er| {
+                dag
+                    .node_weight(er.source())
+                    .cloned()
+                    .ok_or_else::<Error, _>(|| LayoutError::OsqpError{error: "missing node weight".into()}.into())
+            })
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        v_dsts.sort();
+        v_dsts.dedup();
+        v_dsts.sort_by_key(|dst| {
+            let hops = hops_by_edge.get(&(vl.clone(), dst.clone()));
+            let nhr = hops.map(|hops| hops.values().next().unwrap().1).unwrap_or_else(|| {
+                node_to_loc[&Obj::from_vl(dst, containers)].1
+            });
+            solved_locs[&(loc_ix.0 + 1)][&nhr]
+        });
+        let v_outs = v_dsts
+            .iter()
+            .map(|dst| { (vl.clone(), dst.clone()) })
+            .collect::<Vec<_>>();
+        w_srcs.sort();
+        w_srcs.dedup();
+        w_srcs.sort_by_key(|src| {
+            let hops = hops_by_edge.get(&(src.clone(), vl.clone()));
+            let mhr = hops.map(|hops| hops.values().rev().next().unwrap().0).unwrap_or_else(|| {
+                node_to_loc[&Obj::from_vl(src, containers)].1
+            });
+            solved_locs[&(VerticalRank(loc_ix.0.0 - 1))][&mhr]
+        });
+        let w_ins = w_srcs
+            .iter()
+            .map(|src| { (src.clone(), vl.clone()) })
+            .collect::<Vec<_>>();
+        let v_out_first_hops = v_outs
+            .iter()
+            .filter_map(|(vl, wl)| {
+                hops_by_edge.get(&(vl.clone(), wl.clone()))
+                    .and_then(|hops| hops.iter().next())
+                    .and_then(|(lvl, (mhr, _nhr))|
+                        Some((*lvl, *mhr, vl.clone(), wl.clone())))
+            })
+            .collect::<Vec<_>>();
+        let w_in_last_hops = w_ins
+            .iter()
+            .filter_map(|(vl, wl)| {
+                hops_by_edge.get(&(vl.clone(), wl.clone()))
+                    .and_then(|hops| hops.iter().rev().next())
+                    .and_then(|(lvl, (mhr, _nhr))|
+                        Some((*lvl, *mhr, vl.clone(), wl.clone()))
+                    )
+            })
+            .collect::<Vec<_>>();
+        Ok((v_out_first_hops, w_in_last_hops))
+    }
+
+    fn left(sloc: (VerticalRank, SolvedHorizontalRank)) -> Option<(VerticalRank, SolvedHorizontalRank)> {
+        sloc.1.checked_sub(1).map(|shrl| (sloc.0, shrl))
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Direction {
+        Vertical,
+        Horizontal,
+    }
+
+    impl Display for Direction {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Direction::Vertical => write!(f, "Vertical"),
+                Direction::Horizontal => write!(f, "Horizontal"),
+            }
+        }
+    }
+
+    #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
+    pub struct GeomLabel<V: Graphic, E: Graphic> {
+        vl: V,
+        wl: V,
+        rel: E,
+    }
+
+    impl<V: Graphic, E: Graphic> Display for GeomLabel<V, E> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Label({}, {}, {})", self.vl, self.wl, self.rel)
+        }
+    }
+
+    #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
+    pub enum Geom<V: Graphic, E: Graphic> {
+        Node(Obj<V>),
+        Hop(Obj<V>),
+        Container(Obj<V>),
+        Border(Obj<V>),
+        Label(GeomLabel<V, E>),
+    }
+
+    impl<V: Graphic, E: Graphic> From<Obj<V>> for Geom<V, E> {
+        fn from(value: Obj<V>) -> Self {
+            match value {
+                Obj::Node(_) => Geom::Node(value),
+                Obj::Hop(_) => Geom::Hop(value),
+                Obj::Container(_) => Geom::Container(value),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    impl<V: Graphic, E: Graphic> Geom<V, E> {
+        pub fn as_vl(&self) -> Option<&V> {
+            match self {
+                Geom::Node(v) | Geom::Hop(v) | Geom::Container(v) | Geom::Border(v) => v.as_vl(),
+                Geom::Label(label) => Some(&label.vl),
+            }
+        }
+
+        pub fn as_wl(&self) -> Option<&V> {
+            match self {
+                Geom::Node(v) | Geom::Hop(v) | Geom::Container(v) | Geom::Border(v) => v.as_wl(),
+                Geom::Label(label) => Some(&label.wl),
+            }
+        }
+    }
+
+    impl<V: Graphic, E: Graphic> Display for Geom<V, E> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Geom::Node(v) | Geom::Hop(v) | Geom::Container(v) | Geom::Border(v) => write!(f, "{v}"),
+                Geom::Label(label) => write!(f, "{label}"),
+            }
+        }
+    }
+
+
+    #[derive(Clone, Debug)]
+    enum ObjEdgeReason {
+        SolvedLeft,
+        VerticalEdge(String),
+        HorizontalEdge(String),
+        AdjacentHop,
+    }
+
+
+    #[derive(Clone, Debug)]
+    struct ObjEdge {
+        name: usize,
+        reason: ObjEdgeReason,
+        dir: Direction,
+        margin: OrderedFloat<f64>,
+    }
+
+    impl Display for ObjEdge {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "no: {}, \nreason: {:?}, \ndir: {}, \nmargin: {}", self.name, self.reason, self.dir, self.margin)
+        }
+    }
+
+
+    #[derive(Clone, Debug)]
+    struct ConEdgeMargin {
+        margin: OrderedFloat<f64>,
+    }
+
+    #[derive(Clone, Debug)]
+    enum ConEdgeFlavor {
+        Margin(ConEdgeMargin),
+        Symmetrize(),
+    }
+
+    impl Display for ConEdgeFlavor {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ConEdgeFlavor::Margin(ConEdgeMargin{margin}) => write!(f, "margin: {}", margin.0),
+                ConEdgeFlavor::Symmetrize() => write!(f, "symmetrize"),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct ConEdge {
+        name: usize,
+        reason: String,
+        dir: Direction,
+        flavor: ConEdgeFlavor,
+    }
+
+    impl Display for ConEdge {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "no: {}, \nreason: {}, {}, {}", self.name, self.reason, self.dir, self.flavor)
+        }
+    }
+
+
+    pub fn position_sols<V, E>(
+        vcg: &Vcg<V, E>,
+        layout_problem: &LayoutProblem<V>,
+        layout_solution: &LayoutSolution,
+        geometry_problem: &GeometryProblem<V>,
+        measurer: &dyn TextMeasurer,
+        options: &RenderOptions,
+        logs: &mut log::Logger,
+    ) -> Result<(OptimizationProblem<AnySol, OrderedFloat<f64>>, OptimizationProblem<AnySol, OrderedFloat<f64>>), Error>
+    where
+        V: Graphic + Len + std::cmp::PartialEq<str>,
+        E: Graphic + std::cmp::PartialEq<str>,
+    {
+        let containers = &vcg.containers;
+        let nodes_by_container = &vcg.nodes_by_container;
+        let nodes_by_container_transitive = &vcg.nodes_by_container_transitive;
+        let loc_to_node = &layout_problem.loc_to_node;
+        let node_to_loc = &layout_problem.node_to_loc;
+        let hops_by_edge = &layout_problem.hops_by_edge;
+        let solved_locs = &layout_solution.solved_locs;
+        let size_by_loc = &geometry_problem.size_by_loc;
+        let size_by_hop = &geometry_problem.size_by_hop;
+        let varrank_by_obj = &geometry_problem.varrank_by_obj;
+        let loc_by_varrank = &geometry_problem.loc_by_varrank;
+        let line_height = &measurer.line_height(TextRole::Label);
+
+        let of = OrderedFloat::<f64>::from;
+
+        // extent of vertical arrows' labels along their arrows
+        let label_main = |labels: &Option<Vec<V>>| labels.as_ref().map_or(0., |labels| if options.orientation.is_transposed() {
+            labels.iter().map(|label| measurer.width(&label.to_string(), TextRole::Label)).fold(0., f64::max)
+        } else {
+            labels.len() as f64 * line_height
+        });
+
+        // let obj_count = all_locs.len() + all_hops.len();
+        // 1. Record how all objects are positioned relative to one another.
+        let mut obj_graph = Graph::<Geom<V, E>, ObjEdge>::new();
+        let mut obj_vxmap = HashMap::new();
+        let mut solved_vxmap = HashMap::new();
+        for (loc_ix, obj) in loc_to_node.iter() {
+            let ix = or_insert(&mut obj_graph, &mut obj_vxmap, obj.clone().into());
+            let shr = solved_locs[&loc_ix.0][&loc_ix.1];
+            solved_vxmap.insert((loc_ix.0, shr), ix);
+        }
+
+        let solved_to_orig = solved_locs.iter()
+            .flat_map(|(ovr, row)|
+                row.iter().map(|(ohr, shr)| (*ovr, *ohr, *shr))
+            )
+            .map(|(ovr, ohr, shr)| ((ovr, shr), (ovr, ohr)))
+            .collect::<HashMap<_, _>>();
+
+        let mut obj_edges = 0;
+        let mut obj_edge = |dir: Direction, reason: ObjEdgeReason, margin: f64| {
+            let e = ObjEdge{name: obj_edges, reason, dir, margin: of(margin)};
+            obj_edges += 1;
+            e
+        };
+
+        for (solved, orig) in solved_to_orig.iter() {
+            if let Some(left) = left(*solved) {
+                eprintln!("solved: {solved:?}, orig: {orig:?}, left: {left:?}");
+                let left_ix = solved_vxmap[&left];
+                let solved_ix = solved_vxmap[solved];
+                let left_obj = &loc_to_node[orig];
+                let solved_obj = &loc_to_node[&solved_to_orig[&left]];
+                eprintln!("solved left obj: {left_obj}, solved_obj: {solved_obj}");
+                if let (Obj::Hop(ObjHop{wl, ..}), Obj::Container(ObjContainer{vl: container})) = (left_obj, solved_obj) {
+                    if nodes_by_container_transitive[container].contains(wl) {
+                        eprintln!("skipping container-hop solved-left");
+                        continue;
+                    }
+                }
+                if !obj_graph.contains_edge(left_ix, solved_ix) &&
+                    !matches!((left_obj, solved_obj),
+                        (Obj::Container(ObjContainer{vl: container}),
+                        Obj::Node(ObjNode{vl: wl}) | Obj::Container(ObjContainer{vl: wl}))
+                        if nodes_by_container[container].contains(wl)) {
+                    obj_graph.add_edge(left_ix, solved_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::SolvedLeft, options.node_spacing));
+                }
+            }
+            // todo: hop edges, nested edges, ...
+        }
+
+        if let Some(svg) = as_svg(&vcg.vert,
+            &|_, (_vx, vl)| {
+                format!(r##"color="#000000"; class="box highlight_{vl}""##)
+            }, &|g, er| {
+                let src = g.node_weight(er.source()).unwrap();
+                let dst = g.node_weight(er.target()).unwrap();
+                format!(r##"color="#000000"; class="arrow {src}_{dst}""##)
+            }
+        ) {
+            logs.log_svg(Some("lang_graph"), None::<String>, Vec::<String>::new(), svg)?;
+        }
+
+        for er in vcg.vert.edge_references() {
+            let ew = er.weight();
+            if ew != "vertical" && ew != "implied_vertical" { continue; }
+            let src = vcg.vert.node_weight(er.source()).unwrap();
+            let dst = vcg.vert.node_weight(er.target()).unwrap();
+            let src_obj = Obj::from_vl(src, containers);
+            let dst_obj = Obj::from_vl(dst, containers);
+            let src_loc = node_to_loc[&src_obj];
+            let dst_loc = node_to_loc[&dst_obj];
+            let src_ix = solved_vxmap[&(src_loc.0, solved_locs[&src_loc.0][&src_loc.1])];
+            let dst_ix = solved_vxmap[&(dst_loc.0, solved_locs[&dst_loc.0][&dst_loc.1])];
+
+            eprintln!("OBJ_GRAPH VERT EDGE: {src_obj:?}, {dst_obj:?}");
+            eprintln!("SOLVED_VXMAP: {solved_vxmap:?}");
+            eprintln!("SOLVED_LOCS: {solved_locs:?}");
+            eprintln!("NODE_TO_LOC: {node_to_loc:?}");
+
+            if ew == "implied_vertical" {
+                obj_graph.add_edge(src_ix, dst_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), options.rank_separation));
+                continue;
+            }
+
+            // labels sit beside arrowheads, so arrows that leave or enter containers
+            // need room for them between the container and the box outside it
+            if let Some(level) = vcg.vert_edge_labels.get(&(src.clone(), dst.clone())) {
+                for (container, contents) in nodes_by_container_transitive.iter() {
+                    let container_loc = node_to_loc[&Obj::from_vl(container, containers)];
+                    let container_ix = solved_vxmap[&(container_loc.0, solved_locs[&container_loc.0][&container_loc.1])];
+                    match (contents.contains(src), contents.contains(dst)) {
+                        (true, false) => {
+                            let margin = label_main(&level.forward) + options.rank_separation;
+                            obj_graph.add_edge(container_ix, dst_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), margin));
+                        },
+                        (false, true) => {
+                            let margin = label_main(&level.reverse) + options.rank_separation;
+                            obj_graph.add_edge(src_ix, container_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), margin));
+                        },
+                        _ => {},
+                    }
+                }
+            }
+
+            let hops = &hops_by_edge.get(&(src.clone(), dst.clone()));
+            let hops = if hops.is_none() {
+                vec![src_loc, dst_loc]
+            } else {
+                let mut hops = hops.unwrap().iter().map(|(ovr, (ohr, mhr))| (*ovr, *ohr, *mhr)).collect::<Vec<_>>();
+                let last = hops.last().unwrap();
+                hops.push((last.0 + 1, last.2, OriginalHorizontalRank(usize::MAX - last.1.0)));
+                hops.into_iter().map(|(ovr, ohr, _)| (ovr, ohr)).collect::<Vec<_>>()
+            };
+
+            let (src_out_first_hops, _src_in_last_hops) = order_adjacent_vertical_edges(vcg, layout_problem, layout_solution, src)?;
+            let (_dst_out_first_hops, dst_in_last_hops) = order_adjacent_vertical_edges(vcg, layout_problem, layout_solution, dst)?;
+
+            let mut prev_hop_ix = None;
+            for hop in hops.iter() {
+                let initial = hop == &src_loc;
+                let terminal = hop == &dst_loc;
+                let hop_ix = or_insert(&mut obj_graph, &mut obj_vxmap, Geom::Hop(Obj::Hop(ObjHop{vl: src.clone(), wl: dst.clone(), lvl: hop.0, mhr: hop.1})));
+                if initial {
+                    obj_graph.add_edge(src_ix, hop_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), options.hop_separation));
+                    let hop_pos = src_out_first_hops.iter().position(|h| (&h.2, &h.3) == (src, dst)).unwrap();
+                    let hop_left = hop_pos.checked_sub(1).and_then(|hop_left_pos| src_out_first_hops.get(hop_left_pos));
+                    if let Some(hop_left) = hop_left {
+                        let hop_left_ix = or_insert(&mut obj_graph, &mut obj_vxmap, Geom::Hop(Obj::Hop(ObjHop{vl: hop_left.2.clone(), wl: hop_left.3.clone(), lvl: hop_left.0, mhr: hop_left.1})));
+                        obj_graph.add_edge(hop_left_ix, hop_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::AdjacentHop, options.arrow_separation));
+                    }
+                }
+                if terminal {
+                    obj_graph.add_edge(hop_ix, dst_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), options.hop_separation));
+                    let hop_pos = dst_in_last_hops.iter().position(|h| (&h.2, &h.3) == (src, dst)).unwrap();
+                    let hop_left = hop_pos.checked_sub(1).and_then(|hop_left_pos| dst_in_last_hops.get(hop_left_pos));
+                    if let Some(hop_left) = hop_left {
+                        let hop_left_ix = or_insert(&mut obj_graph, &mut obj_vxmap, Geom::Hop(Obj::Hop(ObjHop{vl: hop_left.2.clone(), wl: hop_left.3.clone(), lvl: hop_left.0, mhr: hop_left.1})));
+                        obj_graph.add_edge(hop_left_ix, hop_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::AdjacentHop, options.arrow_separation));
+                    }
+                }
+                if let Some(prev_hop_ix) = prev_hop_ix {
+                    obj_graph.add_edge(prev_hop_ix, hop_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), options.hop_separation));
+                }
+                prev_hop_ix = Some(hop_ix);
+            }
+        }
+        eprintln!("OBJ_GRAPH: {:?}", Dot::new(&obj_graph));
+
+        for ((src, dst), labels) in vcg.horz_edge_labels.iter() {
+            let src_obj = Obj::from_vl(src, containers);
+            let dst_obj = Obj::from_vl(dst, containers);
+            let src_loc = node_to_loc[&src_obj];
+            let dst_loc = node_to_loc[&dst_obj];
+            let src_ix = solved_vxmap[&(src_loc.0, solved_locs[&src_loc.0][&src_loc.1])];
+            let dst_ix = solved_vxmap[&(dst_loc.0, solved_locs[&dst_loc.0][&dst_loc.1])];
+            // labels lie along horizontal arrows, except when ranks run horizontally
+            let label_extent = |labels: &Vec<V>| if options.orientation.is_transposed() {
+                labels.len() as f64 * line_height
+            } else {
+                labels
+                    .iter()
+                    .map(|label| measurer.width(&label.to_string(), TextRole::Label))
+                    .fold(0., f64::max)
+            };
+            let forward_width = labels.forward
+                .as_ref()
+                .map(label_extent)
+                .unwrap_or(2. * options.arrow_spacing);
+            let reverse_width = labels.reverse
+                .as_ref()
+                .map(label_extent)
+                .unwrap_or(2. * options.arrow_spacing);
+            let margin = 1.2 * f64::max(forward_width, reverse_width) + options.horizontal_arrow_padding;
+            obj_graph.add_edge(src_ix, dst_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::HorizontalEdge(format!("{labels:?}")), margin));
+        }
+
+        // eprintln!("obj graph: {}", Dot::new(&obj_graph));
+
+        if let Some(obj_svg) = as_svg(&obj_graph,
+            &|_, (_gx, geom)| {
+                match geom {
+                    Geom::Node(obj) | Geom::Container(obj) => format!(r##"color="#000000"; class="box highlight_{obj}""##),
+                    Geom::Hop(obj) => {
+                        let Some(vl) = obj.as_vl() else {return format!(r##"color="#000000"; class="{obj}""##)};
+                        let Some(wl) = obj.as_wl() else {return format!(r##"color="#000000"; class="{obj}""##)};
+                        format!(r##"color="#000000"; class="arrow {vl}_{wl} {obj}""##)
+                    },
+                    _ => String::new(),
+                }
+            }, &|g, er| {
+                let src = g.node_weight(er.source()).unwrap().as_vl().unwrap();
+                let dst = g.node_weight(er.target()).unwrap().as_wl().unwrap();
+                format!(r##"color="#000000"; class="arrow {src}_{dst}""##)
+            }
+        ) {
+            logs.log_svg(Some("obj_graph"), None::<String>, Vec::<String>::new(), obj_svg).unwrap();
+        }
+
+
+        // 2. Map objects to their corresponding positioning variables and
+        // constraints between those variables.
+        let mut con_graph = Graph::<AnySol, ConEdge>::new();
+        let mut con_vxmap = HashMap::new();
+
+        let mut con_edges = 0;
+        let mut con_edge = |reason: String, dir: Direction, flavor: ConEdgeFlavor| {
+            let e = ConEdge{name: con_edges, reason, dir, flavor};
+            con_edges += 1;
+            e
+        };
+
+        let mut horz_out_labels_by_node = HashMap::<&V, Vec<Option<Vec<V>>>>::new();
+        let mut horz_in_labels_by_node = HashMap::<&V, Vec<Option<Vec<V>>>>::new();
+        for ((src, dst), labels) in vcg.horz_edge_labels.iter() {
+            horz_out_labels_by_node.entry(src).or_default().push(labels.forward.clone());
+            horz_in_labels_by_node.entry(src).or_default().push(labels.reverse.clone());
+            horz_out_labels_by_node.entry(dst).or_default().push(labels.reverse.clone());
+            horz_in_labels_by_node.entry(dst).or_default().push(labels.forward.clone());
+        }
+
+        for geom in obj_graph.node_weights() {
+            match geom {
+                Geom::Node(obj@Obj::Node(ObjNode{vl})) => {
+                    let ls = varrank_by_obj[&obj];
+                    let left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(ls));
+                    let right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(ls));
+                    let top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(ls));
+                    let bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(ls));
+                    let node_size = &size_by_loc[&node_to_loc[obj]];
+                    let mut width = of(node_size.width);
+                    update_min_width(vcg, layout_problem, layout_solution, geometry_problem, &mut width, vl)?;
+                    let mut height = of(node_size.height);
+                    // a box's drawn width runs down the rank frame when ranks run horizontally
+                    let text_width = of(min_text_width(vcg, measurer, vl));
+                    if options.orientation.is_transposed() {
+                        height = max(height, text_width);
+                    } else {
+                        width = max(width, text_width);
+                    }
+                    let height = height + node_size.bottom;
+                    con_graph.add_edge(left, right, con_edge("node-width".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: width})));
+                    con_graph.add_edge(top, bottom, con_edge("node-height".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: height})));
+                },
+                Geom::Hop(obj@Obj::Hop(ObjHop{vl, wl, lvl, mhr})) => {
+                    let hs = varrank_by_obj[&obj];
+                    let hops = &hops_by_edge[&(vl.clone(), wl.clone())];
+                    let top_hop = hops.first_key_value().unwrap();
+                    let bottom_hop = hops.last_key_value().unwrap();
+                    let hst = varrank_by_obj[&Obj::Hop(ObjHop{vl: vl.clone(), wl: wl.clone(), lvl: *top_hop.0, mhr: top_hop.1.0})];
+                    let hsb = varrank_by_obj[&Obj::Hop(ObjHop{vl: vl.clone(), wl: wl.clone(), lvl: VerticalRank(bottom_hop.0.0+1), mhr: bottom_hop.1.1})];
+                    let guide_sol = or_insert(&mut con_graph, &mut con_vxmap, AnySol::S(hs));
+                    let top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(hst));
+                    let bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(hsb));
+                    let left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(hs));
+                    let right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(hs));
+                    let hop_size = &size_by_hop.get(&(*lvl, *mhr, vl.clone(), wl.clone())).unwrap_or_else(|| {
+                        eprintln!("WARNING: con_graph: no size for hop: {obj}");
+                        &HopSize{width: 10., left: 5., right: 5., height: 0., top: 0., bottom: 0.}
+                    });
+                    con_graph.add_edge(top, bottom, con_edge("hop-height".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(hop_size.height)})));
+                    con_graph.add_edge(left, guide_sol, con_edge("hop-left".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(hop_size.left)})));
+                    con_graph.add_edge(guide_sol, right, con_edge("hop-right".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(hop_size.right)})));
+                },
+                Geom::Container(obj@Obj::Container(ObjContainer{vl})) => {
+                    let container = vl;
+                    let container_sol = varrank_by_obj[&obj];
+                    let left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(container_sol));
+                    let right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(container_sol));
+                    let top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(container_sol));
+                    let bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(container_sol));
+                    let node_size = &size_by_loc[&node_to_loc[obj]];
+                    let mut width = of(node_size.width);
+                    update_min_width(vcg, layout_problem, layout_solution, geometry_problem, &mut width, vl)?;
+                    let mut height = of(20.);
+                    // as for boxes; node_size.height is then the measured length of the label
+                    let text_width = of(min_text_width(vcg, measurer, vl));
+                    if options.orientation.is_transposed() {
+                        height = max(height, max(text_width, of(node_size.height)));
+                    } else {
+                        width = max(width, text_width);
+                    }
+                    // room for container labels with more than one line, which sit
+                    // beside rather than above the contents when ranks run horizontally
+                    let (label_above, label_beside) = if options.orientation.is_transposed() {
+                        (0., node_size.width)
+                    } else {
+                        (6. + f64::max(0., node_size.height - options.box_height), 0.)
+                    };
+                    con_graph.add_edge(left, right, con_edge("container-width".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: width})));
+                    con_graph.add_edge(top, bottom, con_edge("container-height".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: height})));
+
+                    for node in &nodes_by_container[container] {
+                        let child_sol = varrank_by_obj[&Obj::from_vl(node, containers)];
+                        // XXX: child might be a container too..., or on a deeper rank
+                        let child_left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(child_sol));
+                        let child_right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(child_sol));
+                        let child_top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(child_sol));
+                        let child_bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(child_sol));
+                        let padding = options.container_padding;
+                        let max_out_label_height = horz_out_labels_by_node.get(node).and_then(|ls| ls.iter().filter_map(|l| l.as_ref().map(|l| l.len())).max()).unwrap_or(0) as f64;
+                        let max_in_label_height = horz_in_labels_by_node.get(node).and_then(|ls| ls.iter().filter_map(|l| l.as_ref().map(|l| l.len())).max()).unwrap_or(0) as f64;
+                        let max_in_label_width = horz_in_labels_by_node.get(node).into_iter().flatten().flatten().flatten().map(|v| measurer.width(&v.to_string(), TextRole::Label)).fold(0., f64::max);
+                        let child_notes = size_by_loc.get(&node_to_loc[&Obj::from_vl(node, containers)]).map_or(0., |size| size.top);
+                        // labels of arrows entering or leaving the container sit beside their heads, inside it
+                        let contents = &nodes_by_container_transitive[container];
+                        let entering_label = vcg.vert_edge_labels.iter()
+                            .filter(|((src, dst), _)| dst == node && !contents.contains(src))
+                            .map(|(_, level)| label_main(&level.forward))
+                            .fold(0., f64::max);
+                        let leaving_label = vcg.vert_edge_labels.iter()
+                            .filter(|((src, dst), _)| src == node && !contents.contains(dst))
+                            .map(|(_, level)| label_main(&level.reverse))
+                            .fold(0., f64::max);
+                        let child_top_margin = 2. * padding + label_above + max_out_label_height * line_height + child_notes + entering_label;
+                        let child_bottom_margin = padding + max_in_label_height * line_height + node_size.bottom + leaving_label;
+                        let child_left_margin = 2. * padding + max_in_label_width + label_beside;
+                        let child_right_margin = 2. * padding + max_in_label_width;
+                        con_graph.add_edge(left, child_left, con_edge("child-padding-left".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_left_margin)})));
+                        con_graph.add_edge(child_right, right, con_edge("child-padding-right".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_right_margin)})));
+                        con_graph.add_edge(top, child_top, con_edge("child-padding-top".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_top_margin)})));
+                        con_graph.add_edge(child_bottom, bottom, con_edge("child-padding-bottom".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_bottom_margin)})));
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        for er in obj_graph.edge_references() {
+            let edge = er.weight();
+            // eprintln!("EDGE IX: {:?} -> {:?}, {}", er.source(), er.target(), edge);
+            let src = obj_graph.node_weight(er.source()).unwrap();
+            let dst = obj_graph.node_weight(er.target()).unwrap();
+            if matches!((src, dst),
+                (Geom::Container(Obj::Container(ObjContainer{vl: container})),
+                    Geom::Node(Obj::Node(ObjNode{vl: wl})) | Geom::Container(Obj::Container(ObjContainer{vl: wl})))
+                    if nodes_by_container[container].contains(wl)) {
+                continue;
+            }
+
+            macro_rules! sol_for {
+                ($node:ident) => {
+                    match $node {
+                        Geom::Node(v) => {varrank_by_obj[v]},
+                        Geom::Hop(v) => {varrank_by_obj[v]},
+                        Geom::Container(v) => {varrank_by_obj[v]},
+                        _ => unimplemented!(),
+                    }
+                };
+            }
+            macro_rules! impl_con {
+                ($src:ident, $src_sol_con:ident, $dst:ident, $dst_sol_con:ident, $dir: ident, $edge:ident) => {
+                    {
+                        let src_guide_sol = AnySol::$src_sol_con(sol_for!($src));
+                        let dst_guide_sol = AnySol::$dst_sol_con(sol_for!($dst));
+                        let src_ix = *con_vxmap.get(&src_guide_sol).expect(&format!("no entry for src key: {src_guide_sol}, src: ({src}) -> dst: ({dst}), edge: {edge}, in\n{varrank_by_obj:#?}\ncon_vxmap: {con_vxmap:#?}\n"));
+                        let dst_ix = *con_vxmap.get(&dst_guide_sol).expect(&format!("no entry for dst key: {dst_guide_sol}, src: ({src}) -> dst: ({dst}), edge: {edge}, in\n{varrank_by_obj:#?}\ncon_vxmap: {con_vxmap:#?}\n"));
+                        con_graph.add_edge(src_ix, dst_ix, con_edge(format!("obj-edge: {edge}"), $dir, $edge.clone()));
+                    }
+                };
+            }
+
+            enum Flavor {
+                Box,
+                Arrow,
+            }
+            impl<V: Graphic, E: Graphic> From<&Geom<V, E>> for Flavor {
+                fn from(value: &Geom<V, E>) -> Self {
+                    match value {
+                        Geom::Node(_) | Geom::Container(_) | Geom::Border(_) | Geom::Label(_) => Flavor::Box,
+                        Geom::Hop(_) => Flavor::Arrow,
+                    }
+                }
+            }
+            let src_flavor: Flavor = src.into();
+            let dst_flavor: Flavor = dst.into();
+
+            let margin = ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(edge.margin.0)});
+            // leave room for notes above the lower box
+            let dst_top = match dst {
+                Geom::Node(obj) | Geom::Container(obj) => size_by_loc.get(&node_to_loc[obj]).map_or(0., |size| size.top),
+                _ => 0.,
+            };
+            let above = ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(edge.margin.0 + dst_top)});
+            let symmetrize = ConEdgeFlavor::Symmetrize();
+            let horizontal = Direction::Horizontal;
+            let vertical = Direction::Vertical;
+            match (src_flavor, dst_flavor, &edge.reason, edge.dir) {
+                (Flavor::Box, Flavor::Box, _, Direction::Vertical) => {
+                    impl_con![src, B, dst, T, vertical, above];
+                },
+                (Flavor::Box, Flavor::Box, ObjEdgeReason::HorizontalEdge(_), Direction::Horizontal) => {
+                    impl_con![src, R, dst, L, horizontal, margin];
+                    impl_con![src, T, dst, T, vertical, symmetrize];
+                    impl_con![src, B, dst, B, vertical, symmetrize];
+                },
+                (Flavor::Box, Flavor::Box, _, Direction::Horizontal) => {
+                    impl_con![src, R, dst, L, horizontal, margin];
+                },
+                (Flavor::Box, Flavor::Arrow, _, Direction::Vertical) => {
+                    impl_con![src, B, dst, T, vertical, margin];
+                    impl_con![src, L, dst, L, horizontal, margin];
+                    impl_con![dst, L, dst, S, horizontal, margin];
+                    impl_con![dst, S, dst, R, horizontal, margin];
+                    impl_con![dst, R, src, R, horizontal, margin];
+                },
+                (Flavor::Box, Flavor::Arrow, _, Direction::Horizontal) => {
+                    impl_con![src, R, dst, S, horizontal, margin];
+                },
+                (Flavor::Arrow, Flavor::Box, _, Direction::Vertical) => {
+                    impl_con![src, B, dst, T, vertical, above];
+                    impl_con![dst, L, src, L, horizontal, margin];
+                    impl_con![src, L, src, S, horizontal, margin];
+                    impl_con![src, S, dst, R, horizontal, margin];
+                    impl_con![src, R, dst, R, horizontal, margin];
+                },
+                (Flavor::Arrow, Flavor::Box, _, Direction::Horizontal) => {
+                    impl_con![src, S, dst, L, horizontal, margin];
+                },
+                (Flavor::Arrow, Flavor::Arrow, _, Direction::Vertical) => {
+                    impl_con![src, S, dst, S, horizontal, symmetrize];
+                },
+                (Flavor::Arrow, Flavor::Arrow, _, Direction::Horizontal) => {
+                    impl_con![src, R, dst, L, horizontal, margin];
+                },
+            }
+        }
+
+        // eprintln!("con graph: {}", Dot::new(&con_graph));
+
+        if let Some(con_svg) = as_svg(
+            &con_graph,
+            &|_, (_sx, sol)| {
+                let Ok(varrank) = sol.try_into() else { return String::new() };
+                let obj = &loc_to_node[&loc_by_varrank[&varrank]];
+                match obj {
+                    Obj::Node(_) | Obj::Container(_)=> format!(r##"color="#000000"; class="box highlight_{obj}""##),
+                    Obj::Hop(ObjHop{vl, wl, ..}) => format!(r##"color="#000000"; class="arrow {vl}_{wl}""##),
+                    _ => unimplemented!(),
+                }
+            }, &|g, er| {
+                let src: Result<VarRank, _> = g.node_weight(er.source()).unwrap().try_into();
+                let dst: Result<VarRank, _> = g.node_weight(er.target()).unwrap().try_into();
+                let Ok(src) = src else { return r##"color="#000000"; class="arrow""##.into(); };
+                let Ok(dst) = dst else { return r##"color="#000000"; class="arrow""##.into(); };
+                let src = (&loc_to_node[&loc_by_varrank[&src]]).as_vl().unwrap();
+                let dst = (&loc_to_node[&loc_by_varrank[&dst]]).as_wl().unwrap();
+                format!(r##"color="#000000"; class="arrow {src}_{dst}""##)
+            }
+        ) {
+            logs.log_svg(Some("con_graph"), None::<String>, Vec::<String>::new(), con_svg).unwrap();
+        }
+
+        // the legend sits right of everything else, as high as it can
+        if let Some((legend, size)) = &geometry_problem.legend {
+            let rights = con_vxmap.keys().filter(|sol| matches!(sol, AnySol::R(_))).copied().collect::<Vec<_>>();
+            let left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(*legend));
+            let right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(*legend));
+            let top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(*legend));
+            let bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(*legend));
+            for sol in rights {
+                con_graph.add_edge(con_vxmap[&sol], left, con_edge("legend-left".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(options.node_spacing)})));
+            }
+            con_graph.add_edge(left, right, con_edge("legend-width".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(size.width)})));
+            con_graph.add_edge(top, bottom, con_edge("legend-height".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(size.height)})));
+        }
+
+        #[cfg(debug_assertions)]
+        if is_cyclic_directed(&con_graph) {
+            eprintln!("ERROR: CYCLIC CONSTRAINT GRAPH DETECTED");
+        }
+
+        // 3. Translate those variables and constraints into optimization problems to be solved.
+        let mut vertical_problem = OptimizationProblem { v: Vars::new(), c: Constraints::new(), pd: vec![], q: vec![] };
+        let mut horizontal_problem = OptimizationProblem { v: Vars::new(), c: Constraints::new(), pd: vec![], q: vec![] };
+
+        for (sol, _ix) in con_vxmap.iter() {
+            match sol {
+                AnySol::L(_) | AnySol::R(_) | AnySol::S(_) | AnySol::V(_) => {
+                    let var = horizontal_problem.v.get(*sol);
+                    horizontal_problem.c.push((of(0.), vec![var.clone()], of(f64::INFINITY)));
+                    horizontal_problem.q.push(var.clone());
+                },
+                AnySol::T(_) | AnySol::B(_) | AnySol::H(_) => {
+                    let var = vertical_problem.v.get(*sol);
+                    vertical_problem.c.push((of(0.), vec![var.clone()], of(f64::INFINITY)));
+                    vertical_problem.q.push(var.clone());
+                },
+                _ => {},
+            }
+        }
+        for er in con_graph.edge_references() {
+            let src = con_graph.node_weight(er.source()).unwrap();
+            let tgt = con_graph.node_weight(er.target()).unwrap();
+            let wgt = er.weight();
+            match (wgt.dir, &wgt.flavor) {
+                (Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin})) => {
+                    horizontal_problem.c.leqc(&mut horizontal_problem.v, *src, *tgt, *margin);
+                },
+                (Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin})) => {
+                    vertical_problem.c.leqc(&mut vertical_problem.v, *src, *tgt, *margin);
+                },
+                (Direction::Horizontal, ConEdgeFlavor::Symmetrize()) => {
+                    horizontal_problem.c.sym(&mut horizontal_problem.v, &mut horizontal_problem.pd, *src, *tgt, 10.);
+                }
+                (Direction::Vertical, ConEdgeFlavor::Symmetrize()) => {
+                    vertical_problem.c.sym(&mut vertical_problem.v, &mut vertical_problem.pd, *src, *tgt, 1000.);
+                }
+            }
+        }
+
+        Ok((horizontal_problem, vertical_problem))
+    }
+
+    use petgraph::visit::{IntoEdgeReferences, IntoNodeReferences, NodeIndexable, GraphProp};
+
+    #[cfg(feature="desktop")]
+    fn as_svg<'a, G: IntoNodeReferences + IntoEdgeReferences + NodeIndexable + GraphProp>(
+        graph: G,
+        node_attrs: &'a dyn Fn(G, G::NodeRef) -> String,
+        edge_attrs: &'a dyn Fn(G, G::EdgeRef) -> String
+    ) -> Option<String> where
+        G::NodeWeight: Display,
+        G::EdgeWeight: Display,
+    {
+        use std::{process::{Stdio, Command}, io::Write};
+
+        let dot = format!(r#"digraph {{
+                bgcolor="transparent";
+                {}
+            }}"#, petgraph::dot::Dot::with_attr_getters(graph,
+            &[petgraph::dot::Config::GraphContentOnly],
+            edge_attrs,
+            node_attrs,
+            ));
+        let mut child = Command::new("dot")
+            .arg("-Tsvg")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to execute dot");
+        let mut stdin = child.stdin.take().expect("Failed to open stdin");
+        std::thread::spawn(move || {
+            stdin.write_all(dot.as_bytes()).expect("Failed to write to stdin");
+        });
+        let output = child.wait_with_output().expect("Failed to read stdout");
+        let svg = String::from_utf8_lossy(&output.stdout);
+        let svg = svg.replace("#000000", "currentColor");
+        // Some(format!("data:image/svg+xml;utf8,{svg}"))
+        Some(svg.into())
+    }
+
+    #[cfg(not(feature="desktop"))]
+    fn as_svg<'a, G: IntoNodeReferences + IntoEdgeReferences + NodeIndexable + GraphProp>(
+        _graph: G,
+        _node_attrs: &'a dyn Fn(G, G::NodeRef) -> String,
+        _edge_attrs: &'a dyn Fn(G, G::EdgeRef) -> String
+    ) -> Option<String> where
+        G::NodeWeight: Display,
+        G::EdgeWeight: Display,
+    {
+        None
+    }
+
+    #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+    pub enum OSQPStatusKind {
+        Solved,
+        SolvedInaccurate,
+        MaxIterationsReached,
+        TimeLimitReached,
+        #[default] Default,
+    }
+
+    fn solve_problem<C: Coeff>(
+        optimization_problem: &OptimizationProblem<AnySol, C>,
+        options: &RenderOptions,
+    ) -> Result<(Vec<(Var<AnySol>, f64)>, OSQPStatusKind), Error> {
+        let OptimizationProblem{v, c, pd, q} = optimization_problem;
+
+        let settings = osqp::Settings::default()
+            .verbose(false)
+            // .adaptive_rho(false)
+            // .rho(6.)
+            // .check_termination(Some(200))
+            // .adaptive_rho_fraction(1.0) // https://github.com/osqp/osqp/issues/378
+            // .adaptive_rho_interval(Some(25))
+            .eps_abs(options.eps_abs)
+            .eps_rel(options.eps_rel)
+            // .max_iter(128_000)
+            // .max_iter(400)
+            // .polish(true)
+        ;
+        #[cfg(debug_assertions)]
+        let settings = settings.verbose(true);
+        let settings = &settings;
+
+        let n = v.len();
+
+        let sparse_pd = &pd[..];
+        // eprintln!("sparsePd: {sparse_pd:?}");
+        let p2 = as_diag_csc_matrix(Some(n), Some(n), sparse_pd);
+        // print_tuples("P2", &p2);
+
+        let mut q2 = Vec::with_capacity(n);
+        q2.resize(n, 0.);
+        for q in q.iter() {
+            if matches!(q.var.sol, AnySol::L(_) | AnySol::T(_)) {
+                q2[q.var.index] -= 0.5 * q.coeff.into();
+            } else {
+                q2[q.var.index] += q.coeff.into();
+            }
+        }
+
+        let mut l2 = vec![];
+        let mut u2 = vec![];
+        for (l, _, u) in c.iter() {
+            l2.push((*l).into());
+            u2.push((*u).into());
+        }
+        // eprintln!("V[{}]: {v}", v.len());
+        // eprintln!("C[{}]: {c}", &c.len());
+
+        let a2: osqp::CscMatrix = c.clone().into();
+
+        // eprintln!("P2[{},{}]: {p2:?}", p2.nrows, p2.ncols);
+        // eprintln!("Q2[{}]: {q2:?}", q2.len());
+        // eprintln!("L2[{}]: {l2:?}", l2.len());
+        // eprintln!("U2[{}]: {u2:?}", u2.len());
+        // eprintln!("A2[{},{}]: {a2:?}", a2.nrows, a2.ncols);
+        // eprintln!("NUMPY");
+        // eprintln!("import osqp");
+        // eprintln!("import numpy as np");
+        // eprintln!("import scipy.sparse as sp");
+        // eprintln!("");
+        // eprintln!("inf = np.inf");
+        // eprintln!("np.set_printoptions(precision=1, suppress=True)");
+        // as_scipy("P", &p2);
+        // as_numpy("q", &q2);
+        // as_scipy("A", &a2);
+        // as_numpy("l", &l2);
+        // as_numpy("u", &u2);
+        // eprintln!("m = osqp.OSQP()");
+        // eprintln!("m.setup(P=P, q=q, A=A, l=l, u=u)");
+        // eprintln!("r = m.solve()");
+        // eprintln!("r.info.status");
+        // eprintln!("r.x");
+
+        let mut prob = osqp::Problem::new(p2, &q2[..], a2, &l2[..], &u2[..], settings)
+            .map_err(|e| Error::from(LayoutError::from(e)))?;
+
+        let result = prob.solve();
+        // eprintln!("STATUS {:?}", result);
+        let solution = match result {
+            osqp::Status::Solved(solution) => Ok((solution, OSQPStatusKind::Solved)),
+            osqp::Status::SolvedInaccurate(solution) => Ok((solution, OSQPStatusKind::SolvedInaccurate)),
+            osqp::Status::MaxIterationsReached(solution) => Ok((solution, OSQPStatusKind::MaxIterationsReached)),
+            osqp::Status::TimeLimitReached(solution) => Ok((solution, OSQPStatusKind::TimeLimitReached)),
+            _ => Err(LayoutError::OsqpError{error: "failed to solve problem".into(),}),
+        }?;
+        let x = solution.0.x();
+
+        // eprintln!("{:?}", x);
+        let mut solutions = v.iter().map(|(_sol, var)| (*var, x[var.index])).collect::<Vec<_>>();
+        solutions.sort_by_key(|(a, _)| *a);
+        // for (var, val) in solutions {
+        //     if !matches!(var.sol, AnySol::F(_)) {
+        //         eprintln!("{} = {}", var.sol, val);
+        //     }
+        // }
+
+        Ok((solutions, solution.1))
+    }
+
+    fn extract_variable<Idx: Copy + Debug + Ord, Val: Copy + Debug>(
+        v: &Vars<AnySol>,
+        solutions: &Vec<(Var<AnySol>, Val)>,
+        kind: AnySolKind,
+        _name: Cow<str>,
+        extract_index: impl Fn(AnySol) -> Idx,
+    ) -> BTreeMap<Idx, Val> {
+        let mut vs = v.iter()
+            .filter_map(|(sol, var)| {
+                if AnySolKind::from(sol) == kind {
+                    let sol_idx = extract_index(*sol);
+                    Some((sol_idx, solutions[var.index].1))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        vs.sort_by_key(|(idx, _)| *idx);
+        // eprintln!("{name}: {vs:?}");
+        let vs = vs.iter().copied().collect::<BTreeMap<Idx, Val>>();
+        vs
+    }
+
+    pub fn solve_optimization_problems(
+        horizontal_problem: &OptimizationProblem<AnySol, OrderedFloat<f64>>,
+        vertical_problem: &OptimizationProblem<AnySol, OrderedFloat<f64>>,
+        options: &RenderOptions,
+    ) -> Result<GeometrySolution, Error> {
+        let OptimizationProblem{v: vh, ..} = horizontal_problem;
+        let OptimizationProblem{v: vv, ..} = vertical_problem;
+
+        // eprintln!("SOLVE HORIZONTAL");
+        let (solutions_h, status_h) = solve_problem(horizontal_problem, options)?;
+
+        // eprintln!("SOLVE VERTICAL");
+        let (solutions_v, status_v) = solve_problem(vertical_problem, options)?;
+
+        let ls = extract_variable(&vh, &solutions_h, AnySolKind::L, "ls".into(), |s| {
+            if let AnySol::L(l) = s { l } else { panic!() }
+        });
+
+        let rs = extract_variable(&vh, &solutions_h, AnySolKind::R, "rs".into(), |s| {
+            if let AnySol::R(r) = s { r } else { panic!() }
+        });
+
+        let ss = extract_variable(&vh, &solutions_h, AnySolKind::S, "ss".into(), |s| {
+            if let AnySol::S(s) = s { s } else { panic!() }
+        });
+
+        let ts = extract_variable(&vv, &solutions_v, AnySolKind::T, "ts".into(), |s| {
+            if let AnySol::T(t) = s { t } else { panic!() }
+        });
+
+        let bs = extract_variable(&vv, &solutions_v, AnySolKind::B, "bs".into(), |s| {
+            if let AnySol::B(b) = s { b } else { panic!() }
+        });
+
+        let res = GeometrySolution{ls, rs, ss, ts, bs, status_h, status_v};
+        Ok(res)
+    }
+
+}
+
+pub mod frontend {
+    use std::{borrow::Cow, collections::HashMap};
+
+    use logos::Logos;
+    use ordered_float::OrderedFloat;
+    use self_cell::self_cell;
+
+    use crate::{graph_drawing::{layout::{minimize_edge_crossing, calculate_vcg, rank, calculate_locs_and_hops, ObjNode}, eval::{eval}, geometry::{calculate_sols, position_sols, HopSize}}, parser::{Item, Parser, Token}};
+
+    use self::styling::Styling;
+    use self::legend::Legend;
+    use self::measure::{TextMeasurer, TextRole};
+
+    use super::{layout::{Vcg, LayoutProblem, Graphic, Len, Obj, RankedPaths, LayoutSolution, ObjContainer}, geometry::{GeometryProblem, GeometrySolution, NodeSize, OptimizationProblem, AnySol, solve_optimization_problems}, error::{Error, Kind, OrErrExt, TypeError}, eval::{Val, Position}, index::OriginalHorizontalRank};
+
+    use log::{names};
+
+    use unicode_segmentation::UnicodeSegmentation;
+
+    /// Greedily break `line` after spaces, dashes, underscores, or dots
+    /// (or, failing that, between grapheme clusters) so that each piece fits in `max_width`.
+    fn wrap_line(line: &str, max_width: f64, measurer: &dyn TextMeasurer) -> Vec<String> {
+        let mut words = vec![];
+        let mut start = 0;
+        for (ix, c) in line.char_indices() {
+            if matches!(c, ' ' | '-' | '_' | '.') {
+                words.push(&line[start..ix + c.len_utf8()]);
+                start = ix + c.len_utf8();
+            }
+        }
+        if start < line.len() {
+            words.push(&line[start..]);
+        }
+
+        let fits = |text: &str| measurer.width(text.trim_end(), TextRole::Box) <= max_width;
+        let mut lines = vec![];
+        let mut current = String::new();
+        for word in words {
+            if fits(&format!("{current}{word}")) {
+                current.push_str(word);
+                continue;
+            }
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current).trim_end().to_string());
+            }
+            for g in word.graphemes(true) {
+                if !current.is_empty() && !fits(&format!("{current}{g}")) {
+                    lines.push(std::mem::take(&mut current));
+                }
+                current.push_str(g);
+            }
+        }
+        if !current.is_empty() || lines.is_empty() {
+            lines.push(current.trim_end().to_string());
+        }
+        lines
+    }
+
+    /// Expand explicit `\n` line breaks in node labels and, if `max_box_width` is given,
+    /// wrap lines that would be wider than it.
+    pub fn wrap_labels<V: Graphic, E: Graphic>(
+        vcg: &mut Vcg<V, E>,
+        measurer: &dyn TextMeasurer,
+        max_box_width: Option<f64>,
+    ) {
+        for label in vcg.vert_node_labels.values_mut() {
+            if !label.contains("\\n") && max_box_width.is_none() {
+                continue
+            }
+            let lines = label.split("\\n");
+            *label = match max_box_width {
+                Some(max_width) => lines.flat_map(|line| wrap_line(line, max_width, measurer)).collect::<Vec<_>>().join("\n"),
+                None => lines.collect::<Vec<_>>().join("\n"),
+            };
+        }
+    }
+
+    /// How wide and tall will a note reading `text` be when drawn?
+    pub fn note_size(text: &str, measurer: &dyn TextMeasurer, options: &RenderOptions) -> (f64, f64) {
+        let lines = text.lines().count().max(1) as f64;
+        let width = measurer.width(text, TextRole::Label) + options.box_padding;
+        let height = lines * measurer.line_height(TextRole::Label) + options.note_spacing;
+        (width, height)
+    }
+
+    pub fn estimate_widths<I: Graphic>(
+        vcg: &Vcg<I, I>,
+        styling: &Styling<I>,
+        layout_problem: &LayoutProblem<I>,
+        geometry_problem: &mut GeometryProblem<I>,
+        measurer: &dyn TextMeasurer,
+        options: &RenderOptions,
+    ) -> Result<(), Error> where
+        I: Graphic + Len,
+    {
+        let line_height = measurer.line_height(TextRole::Label);
+        let arrow_width = 0.0;
+        let box_padding = options.box_padding;
+        // sizes are measured in the rank frame, whose axes are swapped when ranks run horizontally
+        let transposed = options.orientation.is_transposed();
+        let label_width = |labels: &Option<Vec<I>>| labels
+            .as_ref()
+            .and_then(|labels| labels
+                .iter()
+                .map(|label| OrderedFloat(measurer.width(&label.to_string(), TextRole::Label)))
+                .max()
+            )
+            .map(|width| width.0);
+        let label_height = |labels: &Option<Vec<I>>| labels
+            .as_ref()
+            .map(|labels| labels.len() as f64 * line_height);
+        // extent of labels across and along ranks
+        let label_cross = |labels: &Option<Vec<I>>| if transposed { label_height(labels) } else { label_width(labels) }
+            .map(|cross| cross + options.label_padding);
+        let label_main = |labels: &Option<Vec<I>>| if transposed { label_width(labels) } else { label_height(labels) };
+
+        let vert = &vcg.vert;
+        let vert_node_labels = &vcg.vert_node_labels;
+        let vert_edge_labels = &vcg.vert_edge_labels;
+        let horz_edge_labels = &vcg.horz_edge_labels;
+        let size_by_loc = &mut geometry_problem.size_by_loc;
+        let size_by_hop = &mut geometry_problem.size_by_hop;
+        let hops_by_edge = &layout_problem.hops_by_edge;
+        let loc_to_node = &layout_problem.loc_to_node;
+
+        // eprintln!("LOC_TO_NODE WIDTHS: {loc_to_node:#?}");
+
+        for (loc, node) in loc_to_node.iter() {
+            let (ovr, ohr) = loc;
+            match node {
+                Obj::Node(ObjNode{vl}) | Obj::Container(ObjContainer{vl}) => {
+                    let label = vert_node_labels
+                        .get(vl)
+                        .or_err(Kind::KeyNotFoundError{key: vl.to_string()})?
+                        .clone();
+                    // if !label.is_screaming_snake_case() {
+                    //     label = label.to_title_case();
+                    // }
+                    let mut left = 0.;
+                    let mut right = 0.;
+                    for (hc, lvl) in horz_edge_labels.iter() {
+                        if hc.0 == *vl {
+                            right = f64::max(right, label_cross(&lvl.forward).unwrap_or(0.));
+                        }
+                        if hc.1 == *vl {
+                            left = f64::max(left, label_cross(&lvl.reverse).unwrap_or(0.));
+                        }
+                    }
+                    let lines = label.lines().count().max(1);
+                    let width = measurer.width(&label, TextRole::Box) + box_padding;
+                    let height = options.box_height + (lines - 1) as f64 * measurer.line_height(TextRole::Box);
+                    // shaped boxes surround their labels; containers keep the room their contents need
+                    let (width, height) = match node {
+                        Obj::Node(_) => styling.shape(vl).grow(width, height),
+                        _ => (width, height),
+                    };
+                    let (mut width, mut height) = if transposed { (height, width) } else { (width, height) };
+                    // notes stack outward from the box edge they are attached to
+                    let (mut nwo, mut neo, mut bottom) = (0., 0., 0.);
+                    for note in vcg.notes_by_node.get(vl).into_iter().flatten() {
+                        let (note_width, note_height) = note_size(&note.label.to_string(), measurer, options);
+                        let (note_width, note_height) = if transposed { (note_height, note_width) } else { (note_width, note_height) };
+                        width = f64::max(width, note_width + 2. * options.note_spacing);
+                        let room = note_height + options.note_spacing;
+                        match note.pos {
+                            Position::NWO => nwo += room,
+                            Position::NEO => neo += room,
+                            Position::NWI => height += room,
+                            Position::SI => bottom += room,
+                        }
+                    }
+                    let size = NodeSize{
+                        width,
+                        left,
+                        right,
+                        height,
+                        top: f64::max(nwo, neo),
+                        bottom,
+                    };
+                    size_by_loc.insert((*ovr, *ohr), size);
+                },
+                _ => {},
+            }
+        }
+
+        for ((vl, wl), hops) in hops_by_edge.iter() {
+            let mut action_width = options.arrow_spacing;
+            let mut percept_width = options.arrow_spacing;
+            let mut unlabeled_width = options.arrow_spacing;
+            let ex = vert.find_edge(vcg.vert_vxmap[vl], vcg.vert_vxmap[wl]).unwrap();
+            let ew = vert.edge_weight(ex).unwrap();
+            if *ew != "vertical" { continue };
+
+            let labels = vert_edge_labels.get(&(vl.clone(), wl.clone()));
+            let mut height = 10.;
+            if let Some(labels) = labels {
+                let forward_label_width = label_cross(&labels.forward);
+                let reverse_label_width = label_cross(&labels.reverse);
+
+                let level_height = f64::max(
+                    label_main(&labels.forward).unwrap_or(0.),
+                    label_main(&labels.reverse).unwrap_or(0.),
+                );
+
+                action_width = forward_label_width.map(|label_width| arrow_width + label_width).unwrap_or(2. * options.arrow_spacing);
+                percept_width = reverse_label_width.map(|label_width| arrow_width + label_width).unwrap_or(2. * options.arrow_spacing);
+                unlabeled_width = 2. * options.arrow_spacing;
+
+                height = level_height;
+            }
+
+            let mut hops = hops.clone();
+            let (lvl, (mhr, nhr)) = {
+                #[allow(clippy::unwrap_used)] // an edge with no hops really should panic
+                let kv = hops.last_key_value().unwrap();
+                (*kv.0, *kv.1)
+            };
+            hops.insert(lvl+1, (nhr, OriginalHorizontalRank(std::usize::MAX - mhr.0)));
+
+            // only the hops that carry labels need room for them; arrows leaving
+            // containers are drawn without the hops that cross their container's ranks
+            let skipped = vcg.container_depths.get(vl).copied().and_then(|d| d.checked_sub(1)).unwrap_or(0);
+            let drawn = hops.len().saturating_sub(skipped);
+            let forward_hops = options.label_hop.vertices(drawn, true);
+            let reverse_hops = options.label_hop.vertices(drawn, false);
+            // repeated labels stack along the arrow
+            if labels.is_some() {
+                height *= forward_hops.len().max(reverse_hops.len()) as f64;
+            }
+            let (forward_hops, reverse_hops) = (forward_hops.concat(), reverse_hops.concat());
+
+            for (k, (lvl, (mhr, _nhr))) in hops.iter().enumerate() {
+                let k = k.checked_sub(skipped);
+                let carries = |label_hops: &Vec<usize>| k.is_some_and(|k| label_hops.contains(&k));
+                let left = if carries(&forward_hops) { action_width } else { unlabeled_width };
+                let right = if carries(&reverse_hops) { percept_width } else { unlabeled_width };
+                size_by_hop.insert((*lvl, *mhr, vl.clone(), wl.clone()), HopSize{
+                    width: 0.,
+                    left,
+                    right,
+                    height,
+                    top: 0.,
+                    bottom: 0.,
+                });
+                if size_by_loc.get(&(*lvl, *mhr)).is_none() {
+                    size_by_loc.insert((*lvl, *mhr), NodeSize{
+                        width: left + right,
+                        left: 0.,
+                        right: 0.,
+                        height: 0.,
+                        top: 0.,
+                        bottom: 0.,
+                    });
+                }
+            }
+        }
+
+        // eprintln!("SIZE_BY_LOC: {size_by_loc:#?}");
+        // eprintln!("SIZE_BY_HOP: {size_by_hop:#?}");
+
+        Ok(())
+    }
+
+    pub mod measure {
+        //! Text measurement for sizing boxes and labels
+        //!
+        //! # Summary
+        //!
+        //! [render()](super::render) asks a [TextMeasurer] how wide each box and label
+        //! will be when drawn. [DefaultMeasurer] estimates this from font metrics that
+        //! approximate the fonts selected by the default CSS; front-ends that can measure
+        //! text exactly (e.g., via a browser's canvas) can instead supply any
+        //! `Fn(&str, TextRole) -> f64` as [RenderOptions::text_measurer](super::RenderOptions::text_measurer).
+        //!
+        //! The desktop and web front-ends do not supply one yet, so their drawings are sized
+        //! by [DefaultMeasurer]'s estimates rather than by the fonts that the webview renders.
+
+        use std::fmt::Debug;
+        use std::sync::Arc;
+
+        use unicode_segmentation::UnicodeSegmentation;
+
+        use crate::graph_drawing::layout::display_width;
+
+        /// Which kind of text is being measured?
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum TextRole {
+            /// The name of a process, drawn inside its box
+            Box,
+            /// The label of a relationship, drawn beside its arrow
+            Label,
+        }
+
+        pub trait TextMeasurer {
+            /// How many pixels wide will the widest line of `text` be?
+            fn width(&self, text: &str, role: TextRole) -> f64;
+
+            /// How many pixels tall is one line of text?
+            fn line_height(&self, _role: TextRole) -> f64 {
+                20.
+            }
+        }
+
+        impl<F: Fn(&str, TextRole) -> f64> TextMeasurer for F {
+            fn width(&self, text: &str, role: TextRole) -> f64 {
+                self(text, role)
+            }
+        }
+
+        /// A [TextMeasurer] that can be cloned and sent between threads along with the
+        /// [RenderOptions](super::RenderOptions) that carry it
+        ///
+        /// Clones compare equal to one another; other measurers compare unequal.
+        #[derive(Clone)]
+        pub struct SharedMeasurer(pub Arc<dyn TextMeasurer + Send + Sync>);
+
+        impl SharedMeasurer {
+            pub fn new(measurer: impl TextMeasurer + Send + Sync + 'static) -> Self {
+                Self(Arc::new(measurer))
+            }
+        }
+
+        impl Debug for SharedMeasurer {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("SharedMeasurer(..)")
+            }
+        }
+
+        impl PartialEq for SharedMeasurer {
+            fn eq(&self, other: &Self) -> bool {
+                Arc::ptr_eq(&self.0, &other.0)
+            }
+        }
+
+        impl TextMeasurer for SharedMeasurer {
+            fn width(&self, text: &str, role: TextRole) -> f64 {
+                self.0.width(text, role)
+            }
+
+            fn line_height(&self, role: TextRole) -> f64 {
+                self.0.line_height(role)
+            }
+        }
+
+        /// The generic font families that [FontMetrics] and the [bundled fonts](super::fonts) stand in for
+        #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+        pub enum Family {
+            /// `serif`, like Times
+            Serif,
+            /// `sans-serif`, like Helvetica
+            Sans,
+            /// `monospace`
+            Mono,
+        }
+
+        impl Family {
+            /// The family standing in for the CSS font family list `families`: the first of them that
+            /// is generic or that resembles a bundled font, or else serif, as in browsers
+            pub fn from_css(families: &str) -> Self {
+                for family in families.split(',') {
+                    let family = family.trim().trim_matches(|c| c == '"' || c == '\'').to_ascii_lowercase();
+                    match family.as_str() {
+                        "serif" | "ui-serif" | "times" | "times new roman" | "georgia" => return Family::Serif,
+                        "sans-serif" | "ui-sans-serif" | "system-ui" | "helvetica" | "arial" => return Family::Sans,
+                        "monospace" | "ui-monospace" | "sfmono-regular" | "menlo" | "monaco" | "consolas" | "liberation mono" | "courier new" => return Family::Mono,
+                        _ => {},
+                    }
+                }
+                Family::Serif
+            }
+        }
+
+        /// Advance widths for a font at a given size
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct FontMetrics {
+            /// Advance widths of the printable ASCII characters `' '..='~'`, in ems
+            pub advances: [f64; 95],
+            /// Advance width of one column of any other grapheme cluster, in ems
+            pub fallback: f64,
+            /// Font size, in pixels
+            pub size: f64,
+            /// Line height, in pixels
+            pub line_height: f64,
+        }
+
+        /// Times-Roman advance widths, in thousandths of an em
+        const TIMES_ADVANCES: [u16; 95] = [
+            250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278,
+            500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444,
+            921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722,
+            556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500,
+            333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500,
+            500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541,
+        ];
+
+        /// Helvetica advance widths, in thousandths of an em
+        const HELVETICA_ADVANCES: [u16; 95] = [
+            278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+            556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+            1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+            667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+            333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+            556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+        ];
+
+        impl FontMetrics {
+            fn from_advances(units: &[u16; 95], fallback: f64) -> Self {
+                let mut advances = [0.; 95];
+                for (advance, units) in advances.iter_mut().zip(units.iter()) {
+                    *advance = *units as f64 / 1000.;
+                }
+                Self{ advances, fallback, size: 16., line_height: 20. }
+            }
+
+            /// The browser-default serif font at 16px, used for box text.
+            pub fn serif() -> Self {
+                Self::from_advances(&TIMES_ADVANCES, 0.5)
+            }
+
+            /// A Helvetica-like sans-serif font at 16px.
+            pub fn sans() -> Self {
+                Self::from_advances(&HELVETICA_ADVANCES, 0.556)
+            }
+
+            /// `ui-monospace` at .875rem, used for label text.
+            pub fn monospace() -> Self {
+                let advance = 8.66 / 14.;
+                Self{ advances: [advance; 95], fallback: advance, size: 14., line_height: 20. }
+            }
+
+            /// Metrics approximating `family`
+            pub fn for_family(family: Family) -> Self {
+                match family {
+                    Family::Serif => Self::serif(),
+                    Family::Sans => Self::sans(),
+                    Family::Mono => Self::monospace(),
+                }
+            }
+
+            /// How many pixels wide is the widest line of `text`?
+            ///
+            /// Printable ASCII uses the advance table; every other grapheme
+            /// cluster uses `fallback` per column of its [display_width].
+            pub fn width(&self, text: &str) -> f64 {
+                text.lines()
+                    .map(|line| line.graphemes(true).map(|g| {
+                        let advance = match g.as_bytes() {
+                            [c @ b' '..=b'~'] => self.advances[(c - b' ') as usize],
+                            _ => self.fallback * display_width(g) as f64,
+                        };
+                        advance * self.size
+                    }).sum::<f64>())
+                    .fold(0., f64::max)
+            }
+        }
+
+        /// Estimate text sizes from embedded [FontMetrics] for the default CSS fonts.
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct DefaultMeasurer {
+            pub box_font: FontMetrics,
+            pub label_font: FontMetrics,
+        }
+
+        impl Default for DefaultMeasurer {
+            fn default() -> Self {
+                Self{ box_font: FontMetrics::serif(), label_font: FontMetrics::monospace() }
+            }
+        }
+
+        impl DefaultMeasurer {
+            fn font(&self, role: TextRole) -> &FontMetrics {
+                match role {
+                    TextRole::Box => &self.box_font,
+                    TextRole::Label => &self.label_font,
+                }
+            }
+        }
+
+        impl TextMeasurer for DefaultMeasurer {
+            fn width(&self, text: &str, role: TextRole) -> f64 {
+                self.font(role).width(text)
+            }
+
+            fn line_height(&self, role: TextRole) -> f64 {
+                self.font(role).line_height
+            }
+        }
+    }
+
+    pub mod styling {
+        //! Styles
+        //!
+        //! # Summary
+        //!
+        //! Inline styles like `p @ red` give processes, arrows, and labels CSS classes.
+        //!
+        //! Style blocks like `warn: @ { stroke: red; fill: yellow; dash: 4 2; weight: bold; shape: rounded }`
+        //! additionally give such classes [Properties], which [Properties::css()] turns into CSS rules for the
+        //! Dioxus renderer and which [as_svg()](super::svg::as_svg) inlines into its SVG.
+        //!
+        //! Processes and arrows inside containers also inherit the style blocks applied to those containers.
+        use std::{borrow::Cow, collections::{HashMap, BTreeMap, BTreeSet}};
+
+        use crate::graph_drawing::{eval::{visit::{*}, glob, is_pattern, Val, Body, Dir, Rel, Level}, error::{Error, TypeError}, layout::{Graphic, Vcg}};
+
+        use super::theme::Arrowhead;
+
+        /// Depth of the caps of [Shape::Cylinder] and [Shape::Queue]
+        const CAP: f64 = 6.;
+        /// Height of the figure of [Shape::Actor]
+        const FIGURE: f64 = 36.;
+
+        /// Outlines of boxes
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(rename_all = "lowercase"))]
+        pub enum Shape {
+            /// A rectangle
+            #[default]
+            Rect,
+            /// A rectangle with rounded corners
+            Rounded,
+            /// A rectangle with semicircular ends
+            Pill,
+            /// An ellipse around the label
+            Ellipse,
+            /// A diamond around the label, as for decisions
+            Diamond,
+            /// An upright cylinder, as for databases
+            Cylinder,
+            /// A cylinder on its side, as for queues
+            Queue,
+            /// A stick figure above the label, as for people
+            Actor,
+        }
+
+        impl Shape {
+            /// The corner radius of rectangular shapes that have one
+            pub fn radius(&self) -> Option<f64> {
+                match self {
+                    Shape::Rounded => Some(6.),
+                    Shape::Pill => Some(9999.),
+                    _ => None,
+                }
+            }
+
+            /// The size of a box of this shape that holds a label of size `width` by `height`
+            pub fn grow(&self, width: f64, height: f64) -> (f64, f64) {
+                match self {
+                    Shape::Rect | Shape::Rounded | Shape::Pill => (width, height),
+                    Shape::Ellipse => (width * std::f64::consts::SQRT_2, height * std::f64::consts::SQRT_2),
+                    Shape::Diamond => (2. * width, 2. * height),
+                    Shape::Cylinder => (width, height + 2. * CAP),
+                    Shape::Queue => (width + 2. * CAP, height),
+                    Shape::Actor => (f64::max(width, 2. * FIGURE / 3.), height + FIGURE),
+                }
+            }
+
+            /// How far below the top of a box of this shape its label starts
+            pub fn text_inset(&self, height: f64) -> f64 {
+                match self {
+                    Shape::Rect | Shape::Rounded | Shape::Pill | Shape::Queue => 0.,
+                    Shape::Ellipse => height * (1. - std::f64::consts::FRAC_1_SQRT_2) / 2.,
+                    Shape::Diamond => height / 4.,
+                    Shape::Cylinder => 2. * CAP,
+                    Shape::Actor => FIGURE,
+                }
+            }
+
+            /// SVG path data for the outline of a `width` by `height` box of this shape,
+            /// unless the box is drawn as a rectangle with [Shape::radius()]
+            pub fn outline(&self, width: f64, height: f64) -> Option<String> {
+                let (w, h) = (width, height);
+                let (cx, cy) = (w / 2., h / 2.);
+                match self {
+                    Shape::Rect | Shape::Rounded | Shape::Pill => None,
+                    Shape::Ellipse => Some(format!("M 0 {cy} A {cx} {cy} 0 1 0 {w} {cy} A {cx} {cy} 0 1 0 0 {cy} z")),
+                    Shape::Diamond => Some(format!("M {cx} 0 L {w} {cy} L {cx} {h} L 0 {cy} z")),
+                    Shape::Cylinder => {
+                        let b = h - CAP;
+                        Some(format!("M 0 {CAP} A {cx} {CAP} 0 0 1 {w} {CAP} L {w} {b} A {cx} {CAP} 0 0 1 0 {b} z M 0 {CAP} A {cx} {CAP} 0 0 0 {w} {CAP}"))
+                    },
+                    Shape::Queue => {
+                        let r = w - CAP;
+                        Some(format!("M {CAP} 0 L {r} 0 A {CAP} {cy} 0 0 1 {r} {h} L {CAP} {h} A {CAP} {cy} 0 0 1 {CAP} 0 z M {r} 0 A {CAP} {cy} 0 0 0 {r} {h}"))
+                    },
+                    Shape::Actor => {
+                        let (l, r) = (cx - FIGURE / 4., cx + FIGURE / 4.);
+                        Some(format!("M {} 7 a 6 6 0 1 0 12 0 a 6 6 0 1 0 -12 0 M {cx} 13 L {cx} 25 M {l} 17 L {r} 17 M {} 35 L {cx} 25 L {} 35", cx - 6., l + 1., r - 1.))
+                    },
+                }
+            }
+
+            /// How far inside the edge of a `width` by `height` box of this shape its outline lies,
+            /// `dx` from the middle of that edge
+            ///
+            /// Widths and heights are measured in the rank frame, which is transposed when ranks run
+            /// horizontally: then the caps of cylinders face across ranks and those of queues, along them.
+            pub fn inset(&self, dx: f64, width: f64, height: f64, transposed: bool) -> f64 {
+                let u = if width > 0. { (2. * dx / width).clamp(-1., 1.) } else { 0. };
+                let curve = |depth: f64| depth * (1. - (1. - u * u).sqrt());
+                match (self, transposed) {
+                    (Shape::Ellipse, _) => curve(height / 2.),
+                    (Shape::Diamond, _) => height / 2. * u.abs(),
+                    (Shape::Cylinder, false) | (Shape::Queue, true) => curve(CAP),
+                    _ => 0.,
+                }
+            }
+        }
+
+        impl std::fmt::Display for Shape {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(match self {
+                    Shape::Rect => "rect",
+                    Shape::Rounded => "rounded",
+                    Shape::Pill => "pill",
+                    Shape::Ellipse => "ellipse",
+                    Shape::Diamond => "diamond",
+                    Shape::Cylinder => "cylinder",
+                    Shape::Queue => "queue",
+                    Shape::Actor => "actor",
+                })
+            }
+        }
+
+        impl std::str::FromStr for Shape {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    "rect" => Ok(Shape::Rect),
+                    "rounded" => Ok(Shape::Rounded),
+                    "pill" => Ok(Shape::Pill),
+                    "ellipse" => Ok(Shape::Ellipse),
+                    "diamond" => Ok(Shape::Diamond),
+                    "cylinder" | "database" => Ok(Shape::Cylinder),
+                    "queue" => Ok(Shape::Queue),
+                    "actor" => Ok(Shape::Actor),
+                    _ => Err(TypeError::UnknownShapeError{shape: s.into()}.into()),
+                }
+            }
+        }
+
+        /// How the line of an arrow is drawn
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(rename_all = "lowercase"))]
+        pub enum Line {
+            /// A solid line
+            #[default]
+            Solid,
+            /// A dashed line
+            Dashed,
+            /// A dotted line
+            Dotted,
+        }
+
+        impl Line {
+            /// The SVG `stroke-dasharray` of this line, if it is broken
+            pub fn dash(&self) -> Option<&'static str> {
+                match self {
+                    Line::Solid => None,
+                    Line::Dashed => Some("6 4"),
+                    Line::Dotted => Some("1 4"),
+                }
+            }
+        }
+
+        /// How an arrow is drawn, from the keywords among its classes
+        ///
+        /// # Summary
+        ///
+        /// Arrows styled like `a b: > @ dashed open` or `a b: go @ dotted both` are drawn with:
+        ///
+        /// * `solid`, `dashed`, or `dotted` lines,
+        /// * `closed`, `open`, `vee`, `diamond`, or `none` arrowheads, instead of the [Theme](super::theme::Theme)'s,
+        /// * `both`: arrowheads at both ends of one line,
+        /// * `thin`, `thick`, or `heavy` lines, relative to the theme's stroke width.
+        ///
+        /// Keywords applied to an arrow's labels also apply to the arrow; later keywords override earlier ones.
+        #[derive(Clone, Copy, Debug, Default, PartialEq)]
+        pub struct ArrowStyle {
+            /// How the line is drawn
+            pub line: Line,
+            /// The arrowhead, if not the theme's
+            pub head: Option<Arrowhead>,
+            /// Whether the arrow has heads at both ends
+            pub both: bool,
+            /// Stroke width, relative to the theme's, if not 1
+            pub weight: Option<f64>,
+        }
+
+        impl ArrowStyle {
+            /// The classes that style arrows
+            pub const KEYWORDS: [&'static str; 12] = [
+                "solid", "dashed", "dotted",
+                "closed", "open", "vee", "diamond", "none",
+                "both",
+                "thin", "thick", "heavy",
+            ];
+
+            /// Is `class` one of the [ArrowStyle::KEYWORDS]?
+            pub fn is_keyword(class: &str) -> bool {
+                Self::KEYWORDS.contains(&class)
+            }
+
+            /// The style given by the keywords among `classes`
+            pub fn from_classes(classes: &str) -> Self {
+                let mut style = Self::default();
+                for class in classes.split_whitespace() {
+                    match class {
+                        "solid" => style.line = Line::Solid,
+                        "dashed" => style.line = Line::Dashed,
+                        "dotted" => style.line = Line::Dotted,
+                        "closed" => style.head = Some(Arrowhead::Triangle),
+                        "open" => style.head = Some(Arrowhead::Open),
+                        "vee" => style.head = Some(Arrowhead::Vee),
+                        "diamond" => style.head = Some(Arrowhead::Diamond),
+                        "none" => style.head = Some(Arrowhead::None),
+                        "both" => style.both = true,
+                        "thin" => style.weight = Some(0.5),
+                        "thick" => style.weight = Some(2.),
+                        "heavy" => style.weight = Some(3.),
+                        _ => {},
+                    }
+                }
+                style
+            }
+
+            /// The arrowhead of this arrow, given the theme's `default`
+            pub fn arrowhead(&self, default: Arrowhead) -> Arrowhead {
+                self.head.unwrap_or(default)
+            }
+
+            /// Room for arrowheads at the start and end of the path of an arrow, which runs from its
+            /// source to its destination and points `forward` or back, given the theme's `default` arrowhead
+            pub fn gaps(&self, forward: bool, default: Arrowhead) -> (f64, f64) {
+                let length = self.arrowhead(default).length();
+                let start = if !forward || self.both { length } else { 0. };
+                let end = if forward || self.both { length } else { 0. };
+                (start, end)
+            }
+        }
+
+        /// The visual properties that a style block defines
+        #[derive(Clone, Debug, Default, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+        pub struct Properties {
+            /// Color of outlines and arrows
+            pub stroke: Option<String>,
+            /// Color inside boxes
+            pub fill: Option<String>,
+            /// Dash pattern of outlines and arrows, as for SVG's `stroke-dasharray`
+            pub dash: Option<String>,
+            /// Font weight of box and label text, as for CSS's `font-weight`
+            pub weight: Option<String>,
+            /// Outline of boxes: one of the [Shape]s, like `rounded`, `diamond`, or `cylinder`
+            pub shape: Option<String>,
+            /// What the style means, for [legends](super::legend)
+            pub description: Option<String>,
+        }
+
+        impl Properties {
+            /// Set `property` to `value`, returning whether `property` is known.
+            pub fn set(&mut self, property: &str, value: &str) -> bool {
+                let slot = match property {
+                    "stroke" => &mut self.stroke,
+                    "fill" => &mut self.fill,
+                    "dash" => &mut self.dash,
+                    "weight" => &mut self.weight,
+                    "shape" => &mut self.shape,
+                    "description" => &mut self.description,
+                    _ => return false,
+                };
+                *slot = Some(value.to_string());
+                true
+            }
+
+            /// Override these properties by those that `other` sets.
+            pub fn apply(&mut self, other: &Properties) {
+                for (slot, value) in [
+                    (&mut self.stroke, &other.stroke),
+                    (&mut self.fill, &other.fill),
+                    (&mut self.dash, &other.dash),
+                    (&mut self.weight, &other.weight),
+                    (&mut self.shape, &other.shape),
+                    (&mut self.description, &other.description),
+                ] {
+                    if value.is_some() {
+                        slot.clone_from(value);
+                    }
+                }
+            }
+
+            /// The [Shape] of boxes with these properties, if `shape` names one
+            pub fn shape(&self) -> Option<Shape> {
+                self.shape.as_deref()?.parse().ok()
+            }
+
+            /// The corner radius of boxes with these properties, if `shape` asks for one
+            pub fn radius(&self) -> Option<f64> {
+                self.shape()?.radius()
+            }
+
+            /// CSS declarations for HTML boxes, which are drawn with inline borders
+            pub fn div_css(&self) -> Vec<String> {
+                let mut css = vec![];
+                if let Some(stroke) = &self.stroke { css.push(format!("border-color: {stroke} !important")); }
+                if let Some(fill) = &self.fill { css.push(format!("background-color: {fill}")); }
+                if self.dash.is_some() { css.push("border-style: dashed !important".into()); }
+                if let Some(weight) = &self.weight { css.push(format!("font-weight: {weight}")); }
+                if let Some(radius) = self.radius() { css.push(format!("border-radius: {radius}px")); }
+                css
+            }
+
+            /// CSS declarations for the SVG outlines of boxes
+            pub fn rect_css(&self) -> Vec<String> {
+                let mut css = self.path_css();
+                if let Some(fill) = &self.fill { css.push(format!("fill: {fill}")); }
+                if let Some(radius) = self.radius() { css.push(format!("rx: {radius}px")); }
+                css
+            }
+
+            /// CSS declarations for arrows and for the SVG outlines of boxes
+            pub fn path_css(&self) -> Vec<String> {
+                let mut css = vec![];
+                if let Some(stroke) = &self.stroke { css.push(format!("stroke: {stroke}")); }
+                if let Some(dash) = &self.dash { css.push(format!("stroke-dasharray: {dash}")); }
+                css
+            }
+
+            /// CSS declarations for the text of boxes and labels
+            pub fn text_css(&self) -> Vec<String> {
+                self.weight.iter().map(|weight| format!("font-weight: {weight}")).collect()
+            }
+
+            /// CSS rules giving these properties to the boxes, arrows, and labels classed `class`
+            pub fn css(&self, class: &str) -> String {
+                let rules = [
+                    (format!("div.box.{class}"), self.div_css()),
+                    (format!("g.box.{class} rect, .box.{class} path.outline"), self.rect_css()),
+                    (format!("g.box.{class} text, .label.{class}"), self.text_css()),
+                    (format!(".arrow.{class} path, path.{class}"), self.path_css()),
+                    (format!(".arrow.{class} marker path"), self.stroke.iter().map(|stroke| format!("fill: {stroke}")).collect()),
+                ];
+                rules
+                    .into_iter()
+                    .filter(|(_, declarations)| !declarations.is_empty())
+                    .map(|(selector, declarations)| format!("{selector} {{ {}; }}\n", declarations.join("; ")))
+                    .collect()
+            }
+        }
+
+        /// CSS rules for the style blocks `styles`, by name
+        pub fn stylesheet(styles: &BTreeMap<String, Properties>) -> String {
+            styles.iter().map(|(class, properties)| properties.css(class)).collect()
+        }
+
+        /// The properties that the style blocks among `classes` give, later ones overriding earlier ones
+        pub fn resolve(styles: &BTreeMap<String, Properties>, classes: &str) -> Properties {
+            let mut properties = Properties::default();
+            for class in classes.split_whitespace() {
+                if let Some(block) = styles.get(class) {
+                    properties.apply(block);
+                }
+            }
+            properties
+        }
+
+        /// A style statement whose processes are [patterns](is_pattern), applied by [Styling::select()]
+        #[derive(Clone, Debug)]
+        pub enum Selector<V> {
+            /// `pattern @ style`
+            Process{pattern: V, style: Vec<V>},
+            /// `pattern pattern...: labels @ style`
+            Chain{rel: Rel, path: Vec<V>, labels: Vec<Level<V>>, style: Vec<V>},
+        }
+
+        #[derive(Clone, Debug, Default)]
+        pub struct Styling<V: Graphic> {
+            pub style_by_name: HashMap<V, BTreeSet<V>>,
+            pub style_by_arrow: HashMap<(V, V, Dir), BTreeSet<V>>,
+            pub style_by_label: HashMap<(V, V, Dir, V), BTreeSet<V>>,
+            pub style_by_label_star: HashMap<(V, V, Dir), BTreeSet<V>>,
+            /// The properties of each style block, by name
+            pub blocks: BTreeMap<V, Properties>,
+            /// The innermost container of each process placed in one
+            pub container_by_name: HashMap<V, V>,
+            /// Style statements that select what they style by pattern
+            pub selectors: Vec<Selector<V>>,
+            containers: Vec<V>,
+        }
+
+        impl<V: Graphic> Visit<V> for Styling<V> {
+            fn visit_process(&mut self, name: &Option<V>, label: &Option<V>, body: &Option<Body<V>>, style: &Option<Vec<V>>) {
+                eprintln!("STYLE PROCESS: {name:?} {label:?} {body:?} {style:?}");
+                // TODO: correctly construct nested keys.
+                let key = name.as_ref().or(label.as_ref());
+                if let (Some(key), Some(style)) = (key, style) {
+                    if is_pattern(&key.to_string()) {
+                        self.selectors.push(Selector::Process{pattern: key.clone(), style: style.clone()});
+                        return
+                    }
+                }
+                if let (Some(key), Some(container)) = (key, self.containers.last()) {
+                    self.container_by_name.entry(key.clone()).or_insert_with(|| container.clone());
+                }
+                match (key, body) {
+                    (Some(key), Some(_)) => {
+                        self.containers.push(key.clone());
+                        visit_process(self, name, label, body, style);
+                        self.containers.pop();
+                    },
+                    _ => visit_process(self, name, label, body, style),
+                }
+                let Some(key) = key else { return };
+                let Some(style) = style else { return };
+                for style in style {
+                    self.style_by_name.entry(key.clone()).or_default().insert(style.clone());
+                }
+            }
+
+            fn visit_style(&mut self, name: &Option<V>, body: &Option<Body<V>>) {
+                let Some(name) = name else { return };
+                let block = self.blocks.entry(name.clone()).or_default();
+                for property in body.iter().flat_map(|body| body.iter()) {
+                    let Val::Process{name: Some(property), label: Some(value), ..} = property else { continue };
+                    let (property, value) = (property.to_string(), value.to_string());
+                    if !block.set(&property, &value) {
+                        eprintln!("WARNING: style block {name}: unknown property: {property}");
+                    }
+                    if property == "shape" && value.parse::<Shape>().is_err() {
+                        eprintln!("WARNING: style block {name}: unknown shape: {value}");
+                    }
+                }
+            }
+
+            fn visit_chain(&mut self, name: &Option<V>, rel: &Rel, path: &Vec<Val<V>>, labels: &Vec<Level<V>>, style: &Option<Vec<V>>) {
+                eprintln!("STYLE CHAIN: {name:?} {rel:?} {path:?} {labels:?} {style:?}");
+                if path.iter().any(|val| val.key().is_some_and(|key| is_pattern(&key.to_string()))) {
+                    if let Some(style) = style {
+                        let path = path.iter().filter_map(|val| val.key().cloned()).collect();
+                        self.selectors.push(Selector::Chain{rel: rel.clone(), path, labels: labels.clone(), style: style.clone()});
+                    }
+                    return
+                }
+                visit_chain(self, name, rel, path, labels, style);
+                let Some(style) = style else { return };
+
+                for (n, pair) in path.windows(2).enumerate() {
+                    let Some(p0) = pair[0].key().cloned() else {continue};
+                    let Some(p1) = pair[1].key().cloned() else {continue};
+                    let Some(level) = labels.get(n) else {
+                        for s in style {
+                            self.style_by_name.entry(p0.clone()).or_default().insert(s.clone());
+                            self.style_by_name.entry(p1.clone()).or_default().insert(s.clone());
+                            self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                            self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                            self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                            self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                        }
+                        return;
+                    };
+                    if let Some(forward) = &level.forward {
+                        for label in forward {
+                            if *label == "*" {
+                                if level.reverse.is_none() {
+                                    for s in style {
+                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                                        self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                                        self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                                    }
+                                } else {
+                                    for s in style {
+                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                                        self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                                    }
+                                }
+                            } else if *label == ">" {
+                                if level.reverse.is_none() && forward.len() == 1 {
+                                    for s in style {
+                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                                    }
+                                } else {
+                                    for s in style {
+                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
+                                    }
+                                }
+                            } else {
+                                for s in style {
+                                    self.style_by_label.entry((p0.clone(), p1.clone(), Dir::Forward, label.clone())).or_default().insert(s.clone());
+                                }
+                            }
+                        }
                     }
-                    if *upper != f64::INFINITY {
-                        writeln!(s, "<= {upper}")?;
+                    if let Some(reverse) = &level.reverse {
+                        for label in reverse {
+                            if *label == "*" {
+                                for s in style {
+                                    self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                                    self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                                }
+                            } else if *label == ">" {
+                                for s in style {
+                                    self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
+                                }
+                            } else {
+                                for s in style {
+                                    self.style_by_label.entry((p0.clone(), p1.clone(), Dir::Reverse, label.clone())).or_default().insert(s.clone());
+                                }
+                            }
+                        }
                     }
-                    let monomial_names = monomial_names.into_iter().map(|n| n.name()).collect::<Vec<_>>();
-                    l.log_element("Constraint", monomial_names, s)?;
-                    Ok(())
-                })?;
-                l.with_set(cx.0.clone(), "Quadratic Objective", self.pd.iter(), |monomial, l| {
-                    l.log_element("Monomial", names(monomial).into_iter().map(|n| n.name()).collect::<Vec<_>>(), format!("{monomial}"))
-                })?;
-                l.with_set(cx.0.clone(), "Linear Objective", self.q.iter(), |monomial, l| {
-                    l.log_element("Monomial", names(monomial).into_iter().map(|n| n.name()).collect::<Vec<_>>(), format!("{monomial}"))
-                })?;
-                Ok(())
-            })
+                }
+            }
         }
-    }
 
-    #[derive(Debug, Default)]
-    pub struct GeometrySolution {
-        pub ls: BTreeMap<VarRank, f64>,
-        pub rs: BTreeMap<VarRank, f64>,
-        pub ss: BTreeMap<VarRank, f64>,
-        pub ts: BTreeMap<VarRank, f64>,
-        pub bs: BTreeMap<VarRank, f64>,
-        pub status_h: OSQPStatusKind,
-        pub status_v: OSQPStatusKind,
+        impl<V: Graphic> Styling<V> {
+            pub fn new(val: &Val<V>) -> Result<Self, Error> {
+                let mut s = Self {
+                    style_by_name: Default::default(),
+                    style_by_arrow: Default::default(),
+                    style_by_label: Default::default(),
+                    style_by_label_star: Default::default(),
+                    blocks: Default::default(),
+                    container_by_name: Default::default(),
+                    selectors: Default::default(),
+                    containers: Default::default(),
+                };
+                s.visit_val(val);
+                eprintln!("STYLING: {s:#?}");
+                Ok(s)
+            }
+
+            /// The style blocks that `name` inherits from its containers, outermost first
+            pub fn inherited(&self, name: &V) -> Vec<V> {
+                let mut containers = vec![];
+                let mut name = name;
+                while let Some(container) = self.container_by_name.get(name) {
+                    if containers.contains(&container) || containers.len() > self.container_by_name.len() {
+                        break
+                    }
+                    containers.push(container);
+                    name = container;
+                }
+                let mut inherited = vec![];
+                for container in containers.into_iter().rev() {
+                    for style in self.style_by_name.get(container).into_iter().flatten() {
+                        if self.blocks.contains_key(style) && !inherited.contains(style) {
+                            inherited.push(style.clone());
+                        }
+                    }
+                }
+                inherited
+            }
+
+            /// The style blocks that the arrows between `vl` and `wl` inherit from the containers of both
+            pub fn inherited_by_arrow(&self, vl: &V, wl: &V) -> Vec<V> {
+                let wl_inherited = self.inherited(wl);
+                self.inherited(vl).into_iter().filter(|style| wl_inherited.contains(style)).collect()
+            }
+
+            /// The [ArrowStyle] keywords applied to the labels `labels` of the arrow from `vl` to `wl`, which style that arrow too
+            pub fn label_keywords(&self, vl: &V, wl: &V, dir: &Dir, labels: &[V]) -> BTreeSet<V> {
+                labels
+                    .iter()
+                    .flat_map(|label| self.style_by_label.get(&(vl.clone(), wl.clone(), dir.clone(), label.clone())))
+                    .flatten()
+                    .filter(|style| ArrowStyle::is_keyword(&style.to_string()))
+                    .cloned()
+                    .collect()
+            }
+
+            /// The shape of the box of process `name`, from the style blocks applied to it or inherited
+            pub fn shape(&self, name: &V) -> Shape {
+                let inherited = self.inherited(name);
+                let properties = self.properties(inherited.iter().chain(self.style_by_name.get(name).into_iter().flatten()));
+                properties.shape().unwrap_or_default()
+            }
+
+            /// The properties of the style blocks named by `classes`, later ones overriding earlier ones
+            pub fn properties<'a>(&self, classes: impl IntoIterator<Item=&'a V>) -> Properties where V: 'a {
+                let mut properties = Properties::default();
+                for class in classes {
+                    if let Some(block) = self.blocks.get(class) {
+                        properties.apply(block);
+                    }
+                }
+                properties
+            }
+        }
+
+        impl<'s> Styling<Cow<'s, str>> {
+            /// Apply the [Selector]s recorded from the model to the processes and relationships of `vcg`
+            ///
+            /// Selectors style the arrows they match, not the processes at their ends. Labels in selectors
+            /// are patterns too: `* *: go @ red` styles every arrow labeled `go`, and those labels, while
+            /// `* y: > @ red` styles every forward arrow into `y`.
+            pub fn select(&mut self, vcg: &Vcg<Cow<'s, str>, Cow<'s, str>>) {
+                let matches = |pattern: &str, name: &str| match pattern.strip_suffix(".*") {
+                    Some(container) if !is_pattern(container) => vcg.nodes_by_container
+                        .get(container)
+                        .is_some_and(|nodes| nodes.contains(name)),
+                    _ => glob(pattern, name),
+                };
+                for selector in self.selectors.clone() {
+                    match selector {
+                        Selector::Process{pattern, style} => {
+                            for name in vcg.vert_node_labels.keys().filter(|name| matches(&pattern, name)) {
+                                self.style_by_name.entry(name.clone()).or_default().extend(style.iter().cloned());
+                            }
+                        },
+                        Selector::Chain{rel, path, labels, style} => {
+                            let edges = match rel {
+                                Rel::Vertical => &vcg.vert_edge_labels,
+                                Rel::Horizontal => &vcg.horz_edge_labels,
+                            };
+                            for (n, pair) in path.windows(2).enumerate() {
+                                let level = labels.get(n);
+                                for ((vl, wl), actual) in edges.iter().filter(|((vl, wl), _)| matches(&pair[0], vl) && matches(&pair[1], wl)) {
+                                    for (dir, wanted, actual) in [
+                                        (Dir::Forward, level.map(|level| &level.forward), &actual.forward),
+                                        (Dir::Reverse, level.map(|level| &level.reverse), &actual.reverse),
+                                    ] {
+                                        let Some(actual) = actual else { continue };
+                                        let arrow = (vl.clone(), wl.clone(), dir.clone());
+                                        let wanted = match wanted {
+                                            None => vec![Cow::from("*")],
+                                            Some(Some(wanted)) => wanted.clone(),
+                                            Some(None) => continue,
+                                        };
+                                        for pattern in wanted {
+                                            let labeled = actual.iter().filter(|label| pattern != "*" && pattern != ">" && glob(&pattern, label)).cloned().collect::<Vec<_>>();
+                                            if pattern != "*" && pattern != ">" && labeled.is_empty() {
+                                                continue
+                                            }
+                                            self.style_by_arrow.entry(arrow.clone()).or_default().extend(style.iter().cloned());
+                                            if pattern == "*" {
+                                                self.style_by_label_star.entry(arrow.clone()).or_default().extend(style.iter().cloned());
+                                            }
+                                            for label in labeled {
+                                                self.style_by_label.entry((vl.clone(), wl.clone(), dir.clone(), label)).or_default().extend(style.iter().cloned());
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        },
+                    }
+                }
+            }
+        }
     }
 
-    impl<CX: V2n> log::Log<CX> for GeometrySolution {
-        fn log(&self, cx: CX, l: &mut log::Logger) -> Result<(), log::Error> {
-            l.with_group("Coordinates", "", Vec::<String>::new(), |l| {
-                l.with_map("ls", "BTreeMap<Sol, f64>", self.ls.iter(), |ls, coord, l| {
-                    let mut src_names = ls.names();
-                    src_names.append(&mut cx(*ls));
-                    l.log_pair(
-                        "Sol",
-                        src_names,
-                        format!("{ls}"),
-                        "f64",
-                        vec![],
-                        format!("{:.0}", coord.round())
-                    )
-                })?;
-                l.with_map("rs", "BTreeMap<Sol, f64>", self.rs.iter(), |ls, coord, l| {
-                    let mut src_names = ls.names();
-                    src_names.append(&mut cx(*ls));
-                    l.log_pair(
-                        "Sol",
-                        src_names,
-                        format!("{ls}"),
-                        "f64",
-                        vec![],
-                        format!("{:.0}", coord.round())
-                    )
-                })?;
-                l.with_map("ts", "BTreeMap<Sol, f64>", self.ts.iter(), |ls, coord, l| {
-                    let mut src_names = ls.names();
-                    src_names.append(&mut cx(*ls));
-                    l.log_pair(
-                        "Sol",
-                        src_names,
-                        format!("{ls}"),
-                        "f64",
-                        vec![],
-                        format!("{:.0}", coord.round())
-                    )
-                })?;
-                l.with_map("bs", "BTreeMap<Sol, f64>", self.bs.iter(), |ls, coord, l| {
-                    let mut src_names = ls.names();
-                    src_names.append(&mut cx(*ls));
-                    l.log_pair(
-                        "Sol",
-                        src_names,
-                        format!("{ls}"),
-                        "f64",
-                        vec![],
-                        format!("{:.0}", coord.round())
-                    )
-                })?;
-                l.with_map("ss", "BTreeMap<Sol, f64>", self.ss.iter(), |hs, coord, l| {
-                    let mut src_names = hs.names();
-                    src_names.append(&mut cx(*hs));
-                    l.log_pair(
-                        "HopSol",
-                        src_names,
-                        format!("{hs}"),
-                        "f64",
-                        vec![],
-                        format!("{:.0}", coord.round())
-                    )
-                })?;
-                Ok(())
-            })
+
+    pub mod theme {
+        //! Themes
+        //!
+        //! # Summary
+        //!
+        //! A [Theme] gives drawings their colors, fonts, stroke widths, and arrowheads, in a light
+        //! variant and, optionally, a dark one for readers who prefer dark color schemes. Pick one of
+        //! the [built-in](Theme::BUILTIN) themes, or build your own, with
+        //! [RenderOptions::theme](super::RenderOptions::theme); [Theme::css()] is what the renderers in
+        //! [dioxus](super::dioxus) and [svg](super::svg) embed in their output, while the [pdf](super::pdf)
+        //! renderer, which has no CSS, draws with the light variant's colors directly.
+        //!
+        //! # Class contract
+        //!
+        //! Custom CSS can target the classes of drawn elements:
+        //!
+        //! * `box`: processes, containers, and notes: HTML `div`s, or SVG `g`s holding a `rect` and `text`s
+        //! * `outline`: the SVG `path`s drawn in place of the borders of boxes with non-rectangular [Shape](super::styling::Shape)s
+        //! * `container`: boxes that contain other boxes
+        //! * `note`, `note_nwo`, `note_neo`, `note_nwi`, `note_si`: notes and where they sit; `{owner}_note`: the notes of `owner`
+        //! * `legend`: the frame of the [legend](super::legend); `legend_entry`: its boxes and arrows, which also carry the names of their style blocks
+        //! * `highlight_{key}`: the HTML box keyed `key`
+        //! * `arrow`: arrows: HTML `div`s holding an `svg`, or SVG `path`s
+        //! * `vertical`, `horizontal`: which way an arrow's relationship runs
+        //! * `forward`, `reverse`: whether an arrow points along its relationship or back
+        //! * `{src}_{dst}`: the arrows between the processes `src` and `dst`
+        //! * `label`: arrow labels, which also carry their arrow's `vertical`/`horizontal` and `forward`/`reverse` classes
+        //! * `dashed`, `dotted`, `thin`, `thick`, `heavy`, and the other [ArrowStyle] keywords: how arrows are drawn
+        //! * `added`, `removed`, `renamed`, `moved`, `changed`: differences marked by [draw_diff()](super::dom::draw_diff)
+        //! * the classes applied with `@`, like `red`, `hidden`, or the names of style blocks
+        use crate::graph_drawing::error::{Error, TypeError};
+
+        use super::styling::ArrowStyle;
+
+        const MONOSPACE: &str = r#"ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,"Liberation Mono","Courier New",monospace"#;
+
+        /// The shape of arrowheads
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(rename_all = "lowercase"))]
+        pub enum Arrowhead {
+            /// A filled triangle
+            #[default]
+            Triangle,
+            /// An outlined triangle
+            Open,
+            /// A filled triangle with a notched back
+            Vee,
+            /// A filled diamond
+            Diamond,
+            /// No arrowhead at all
+            None,
+        }
+
+        impl Arrowhead {
+            /// SVG path data for this arrowhead, pointing right in a 10 by 10 marker
+            pub fn path(&self) -> &'static str {
+                match self {
+                    Arrowhead::Triangle => "M 0 0 L 10 5 L 0 10 z",
+                    Arrowhead::Open => "M 0 0 L 10 5 L 0 10",
+                    Arrowhead::Vee => "M 0 0 L 10 5 L 0 10 L 4 5 z",
+                    Arrowhead::Diamond => "M 0 5 L 5 0 L 10 5 L 5 10 z",
+                    Arrowhead::None => "",
+                }
+            }
+
+            /// Is this arrowhead filled, rather than only outlined?
+            pub fn is_filled(&self) -> bool {
+                !matches!(self, Arrowhead::Open | Arrowhead::None)
+            }
+
+            /// Room to leave between the end of an arrow and the box it points at
+            pub fn length(&self) -> f64 {
+                match self {
+                    Arrowhead::None => 0.,
+                    _ => 7.,
+                }
+            }
+
+            /// The name of this arrowhead, as for SVG marker ids
+            pub fn name(&self) -> &'static str {
+                match self {
+                    Arrowhead::Triangle => "triangle",
+                    Arrowhead::Open => "open",
+                    Arrowhead::Vee => "vee",
+                    Arrowhead::Diamond => "diamond",
+                    Arrowhead::None => "none",
+                }
+            }
+        }
+
+        /// The colors of one variant of a [Theme], as CSS colors
+        #[derive(Clone, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+        pub struct Palette {
+            /// Color of box text
+            pub text: String,
+            /// Color of box outlines
+            pub stroke: String,
+            /// Color inside boxes
+            pub fill: String,
+            /// Color of arrows and arrowheads
+            pub arrow: String,
+            /// Color of arrow labels
+            pub label: String,
+            /// Color behind arrow labels
+            pub label_background: String,
+        }
+
+        impl Palette {
+            fn new(text: &str, stroke: &str, fill: &str, arrow: &str, label: &str, label_background: &str) -> Self {
+                Self {
+                    text: text.into(),
+                    stroke: stroke.into(),
+                    fill: fill.into(),
+                    arrow: arrow.into(),
+                    label: label.into(),
+                    label_background: label_background.into(),
+                }
+            }
+        }
+
+        /// Colors, fonts, stroke widths, and arrowheads for drawings
+        #[derive(Clone, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(default))]
+        pub struct Theme {
+            /// Name of the theme, recorded in SVG output
+            pub name: String,
+            /// Colors for light color schemes
+            pub light: Palette,
+            /// Colors for dark color schemes, if different
+            pub dark: Option<Palette>,
+            /// CSS font family of box text
+            pub box_font: String,
+            /// CSS font family of arrow labels and notes
+            pub label_font: String,
+            /// Width of box outlines
+            pub box_stroke_width: f64,
+            /// Width of arrows
+            pub arrow_stroke_width: f64,
+            /// Shape of arrowheads
+            pub arrowhead: Arrowhead,
+        }
+
+        impl Default for Theme {
+            fn default() -> Self {
+                Self::classic()
+            }
+        }
+
+        impl Theme {
+            /// Names of the built-in themes, for [Theme::builtin()]
+            pub const BUILTIN: [&'static str; 3] = ["classic", "ink", "blueprint"];
+
+            /// The built-in theme named `name`, if any
+            pub fn builtin(name: &str) -> Option<Self> {
+                match name {
+                    "classic" => Some(Self::classic()),
+                    "ink" => Some(Self::ink()),
+                    "blueprint" => Some(Self::blueprint()),
+                    _ => None,
+                }
+            }
+
+            /// depict's classic look: black outlines and arrows, and gray boxes on dark backgrounds
+            pub fn classic() -> Self {
+                Self {
+                    name: "classic".into(),
+                    light: Palette::new("black", "black", "transparent", "black", "black", "white"),
+                    dark: Some(Palette::new("black", "black", "#aaa", "#eee", "black", "#aaa")),
+                    box_font: "serif".into(),
+                    label_font: MONOSPACE.into(),
+                    box_stroke_width: 1.,
+                    arrow_stroke_width: 1.,
+                    arrowhead: Arrowhead::Triangle,
+                }
+            }
+
+            /// Heavier black lines and open arrowheads on white, for printing
+            pub fn ink() -> Self {
+                Self {
+                    name: "ink".into(),
+                    light: Palette::new("#111", "#111", "white", "#111", "#111", "white"),
+                    dark: None,
+                    box_font: "Helvetica,Arial,sans-serif".into(),
+                    label_font: MONOSPACE.into(),
+                    box_stroke_width: 1.5,
+                    arrow_stroke_width: 1.5,
+                    arrowhead: Arrowhead::Open,
+                }
+            }
+
+            /// Blues, in light and dark variants
+            pub fn blueprint() -> Self {
+                Self {
+                    name: "blueprint".into(),
+                    light: Palette::new("#0b2e59", "#0b2e59", "#e8f0fb", "#1f5fa8", "#0b2e59", "#f5f8fd"),
+                    dark: Some(Palette::new("#e8f0fb", "#9cc3f0", "#0b2e59", "#9cc3f0", "#e8f0fb", "#12375f")),
+                    box_font: "ui-sans-serif,system-ui,sans-serif".into(),
+                    label_font: MONOSPACE.into(),
+                    box_stroke_width: 1.,
+                    arrow_stroke_width: 1.25,
+                    arrowhead: Arrowhead::Vee,
+                }
+            }
+
+            fn palette_css(&self, palette: &Palette) -> String {
+                let Palette{text, stroke, fill, arrow, label, label_background} = palette;
+                let Theme{box_stroke_width, arrow_stroke_width, ..} = self;
+                format!(r#"div.box {{ color: {text}; background-color: {fill}; border-color: {stroke}; border-width: {box_stroke_width}px; }}
+g.box rect, .box path.outline {{ stroke: {stroke}; fill: {fill}; stroke-width: {box_stroke_width}; }}
+g.box text {{ fill: {text}; }}
+.arrow {{ color: {arrow}; }}
+.arrow svg, path.arrow {{ stroke: {arrow}; stroke-width: {arrow_stroke_width}; }}
+marker path {{ fill: {arrow}; stroke: {arrow}; }}
+div.label {{ color: {label}; background-color: {label_background}; }}
+g.label {{ fill: {label}; }}
+"#)
+            }
+
+            /// CSS giving drawings this theme's look
+            pub fn css(&self) -> String {
+                let Theme{box_font, label_font, ..} = self;
+                let mut css = format!(r#"path {{ stroke-dasharray: none; }}
+div.box {{ font-family: {box_font}; }}
+div.label {{ padding: 2px 0px; white-space: pre; z-index: 50; box-sizing: border-box; font-family: {label_font}; }}
+g.box text {{ font-family: {box_font}; stroke: none; }}
+g.label {{ font-family: {label_font}; stroke: none; }}
+.box.note {{ border-style: dashed !important; font-family: {label_font}; }}
+g.box.note rect {{ stroke-dasharray: 4 2; }}
+g.box.note text {{ font-family: {label_font}; }}
+.box.legend {{ border-style: dotted !important; }}
+g.box.legend rect {{ stroke-dasharray: 1 3; }}
+marker.open path, marker.none path {{ fill: none !important; }}
+"#);
+                css.push_str(&self.palette_css(&self.light));
+                if let Some(dark) = &self.dark {
+                    css.push_str(&format!("@media (prefers-color-scheme: dark) {{\n{}}}\n", self.palette_css(dark)));
+                }
+                for keyword in ArrowStyle::KEYWORDS {
+                    let style = ArrowStyle::from_classes(keyword);
+                    let mut declarations = vec![];
+                    if let Some(dash) = style.line.dash() { declarations.push(format!("stroke-dasharray: {dash}")); }
+                    if let Some(weight) = style.weight { declarations.push(format!("stroke-width: {}", weight * self.arrow_stroke_width)); }
+                    if !declarations.is_empty() {
+                        css.push_str(&format!(".arrow.{keyword} svg > path, path.arrow.{keyword} {{ {}; }}\n", declarations.join("; ")));
+                    }
+                }
+                css.push_str(CLASS_CSS);
+                css
+            }
+        }
+
+        impl std::str::FromStr for Theme {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Theme::builtin(s).ok_or_else(|| TypeError::UnknownThemeError{theme: s.into()}.into())
+            }
         }
+
+        /// CSS for the classes that every theme supports
+        const CLASS_CSS: &str = r#"
+            .red {
+                color: white;
+                background-color: red;
+            }
+            .arrow.red {
+                background-color: inherit;
+            }
+            .arrow.red path {
+                stroke: red;
+            }
+            .arrow.red marker path {
+                fill: red;
+            }
+            g.box.red rect {
+                fill: red;
+            }
+            g.box.red text {
+                fill: white;
+            }
+            @media (prefers-color-scheme: dark) {
+                .red {
+                    background-color: red;
+                    color: black;
+                }
+                div.red.label {
+                    background-color: red;
+                }
+                g.box.red text {
+                    fill: black;
+                }
+            }
+
+            .box.added, div.label.added { background-color: #cfc; }
+            .box.removed, div.label.removed { background-color: #fcc; }
+            .box.renamed, .box.moved, div.label.changed { background-color: #ffc; }
+            g.box.added rect { fill: #cfc; }
+            g.box.removed rect { fill: #fcc; stroke-dasharray: 4 2; }
+            g.box.renamed rect, g.box.moved rect { fill: #ffc; }
+            .arrow.added path, path.arrow.added { stroke: green; }
+            .arrow.removed path, path.arrow.removed { stroke: red; stroke-dasharray: 4 2; }
+            .arrow.changed path, path.arrow.changed { stroke: orange; }
+            g.label.added { fill: green; }
+            g.label.removed { fill: red; }
+            g.label.changed { fill: orange; }
+
+            .hidden { display: none; }
+        "#;
     }
 
-    fn update_min_width<V: Graphic + Len, E: Graphic + PartialEq<str>>(
-        vcg: &Vcg<V, E>,
-        layout_problem: &LayoutProblem<V>,
-        layout_solution: &LayoutSolution,
-        geometry_problem: &GeometryProblem<V>,
-        min_width: &mut OrderedFloat<f64>,
-        vl: &V
-    ) -> Result<(), Error> {
-        let GeometryProblem{size_by_hop, ..} = geometry_problem;
+    pub mod legend {
+        //! Legends
+        //!
+        //! # Summary
+        //!
+        //! Once a drawing uses several style blocks, readers need a key to them. With
+        //! [RenderOptions::legend](super::RenderOptions::legend) set, [render()](super::render())
+        //! collects a [Legend] of the style blocks that the drawing actually uses and reserves room for it
+        //! beside the drawing; [draw()](super::dom::draw) then draws one entry per style block: a box in
+        //! that style, for blocks applied to boxes, or else an arrow in that style.
+        //!
+        //! Entries read as the `description` property of their style block, if it has one:
+        //!
+        //! ```text
+        //! warn: @ { stroke: red; description: needs review }
+        //! ```
+        //!
+        //! and as the name of the style block otherwise.
+        use std::collections::BTreeSet;
+
+        use crate::graph_drawing::layout::{Graphic, Vcg};
+
+        use super::RenderOptions;
+        use super::dom::label_gap;
+        use super::measure::{TextMeasurer, TextRole};
+        use super::styling::{Properties, Styling};
+
+        /// How an [Entry] illustrates its style
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub enum Swatch {
+            /// A box in the style, reading as the entry's text
+            Box,
+            /// An arrow in the style, labeled with the entry's text
+            Arrow,
+        }
+
+        /// One style block used by a drawing
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Entry {
+            /// The name of the style block
+            pub style: String,
+            /// The description of the style block, or else its name
+            pub text: String,
+            pub swatch: Swatch,
+            /// The properties of the style block
+            pub properties: Properties,
+        }
+
+        /// The entries of a legend, in order of the names of their style blocks
+        #[derive(Clone, Debug, Default, PartialEq)]
+        pub struct Legend {
+            pub entries: Vec<Entry>,
+        }
+
+        impl Legend {
+            /// Title of legends
+            pub const TITLE: &'static str = "Legend";
+            /// Minimum length of the arrows of [Swatch::Arrow] entries
+            pub const ARROW_LENGTH: f64 = 40.;
+
+            /// The legend of the style blocks of `styling` that are applied to the processes or relationships of `vcg`
+            pub fn new<V: Graphic>(styling: &Styling<V>, vcg: &Vcg<V, V>) -> Self {
+                let by_box = vcg.vert_node_labels
+                    .keys()
+                    .flat_map(|name| styling.inherited(name).into_iter().chain(styling.style_by_name.get(name).into_iter().flatten().cloned()))
+                    .collect::<BTreeSet<_>>();
+                let by_arrow = styling.style_by_arrow.values()
+                    .chain(styling.style_by_label.values())
+                    .chain(styling.style_by_label_star.values())
+                    .flatten()
+                    .cloned()
+                    .collect::<BTreeSet<_>>();
+                let entries = styling.blocks
+                    .iter()
+                    .filter_map(|(style, properties)| {
+                        let swatch = if by_box.contains(style) {
+                            Swatch::Box
+                        } else if by_arrow.contains(style) {
+                            Swatch::Arrow
+                        } else {
+                            return None
+                        };
+                        let style = style.to_string();
+                        let text = properties.description.clone().unwrap_or_else(|| style.clone());
+                        Some(Entry{style, text, swatch, properties: properties.clone()})
+                    })
+                    .collect();
+                Self{entries}
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.entries.is_empty()
+            }
 
-        let (v_out_first_hops, w_in_last_hops) = order_adjacent_vertical_edges(vcg, layout_problem, layout_solution, vl)?;
+            /// How wide and tall will the swatch and text of `entry` be when drawn?
+            pub fn entry_size(entry: &Entry, measurer: &dyn TextMeasurer, options: &RenderOptions) -> (f64, f64) {
+                match entry.swatch {
+                    Swatch::Box => {
+                        let width = measurer.width(&entry.text, TextRole::Box) + options.box_padding;
+                        entry.properties.shape().unwrap_or_default().grow(width, options.box_height)
+                    },
+                    Swatch::Arrow => {
+                        let width = f64::max(measurer.width(&entry.text, TextRole::Label), Self::ARROW_LENGTH);
+                        (width, measurer.line_height(TextRole::Label) + label_gap(measurer))
+                    },
+                }
+            }
 
-        let out_width: f64 = v_out_first_hops
-            .iter()
-            .filter_map(|idx| {
-                size_by_hop.get(idx).map(|sz| sz.left + sz.right)
-            })
-            .sum();
-        let in_width: f64 = w_in_last_hops
-            .iter()
-            .filter_map(|idx| {
-                size_by_hop.get(idx).map(|sz| sz.left + sz.right)
-            })
-            .sum();
+            /// How wide and tall will this legend be when drawn?
+            ///
+            /// Legends hold their title and then their entries, one per row, inside a padded frame.
+            pub fn size(&self, measurer: &dyn TextMeasurer, options: &RenderOptions) -> (f64, f64) {
+                let padding = options.container_padding;
+                let mut width = measurer.width(Self::TITLE, TextRole::Box) + options.box_padding;
+                let mut height = options.box_height;
+                for entry in self.entries.iter() {
+                    let (entry_width, entry_height) = Self::entry_size(entry, measurer, options);
+                    width = f64::max(width, entry_width + 2. * padding);
+                    height += entry_height + padding;
+                }
+                (width, height)
+            }
 
-        let of = OrderedFloat::<f64>::from;
+            /// TikZ commands drawing this legend right of the picture drawn so far
+            pub fn to_tikz(&self) -> String {
+                let mut rows = vec![format!("\\node[font=\\bfseries] {{{}}};", Self::TITLE)];
+                for Entry{text, swatch, properties, ..} in self.entries.iter() {
+                    let mut style = vec![];
+                    if let Some(stroke) = properties.stroke.as_deref().and_then(tikz_color) {
+                        style.push(format!("draw={stroke}"));
+                    }
+                    if properties.dash.is_some() {
+                        style.push("dashed".into());
+                    }
+                    if properties.weight.is_some() {
+                        style.push("font=\\bfseries".into());
+                    }
+                    let text = tikz_escape(text);
+                    let row = match swatch {
+                        Swatch::Box => {
+                            if !style.iter().any(|s| s.starts_with("draw")) {
+                                style.insert(0, "draw".into());
+                            }
+                            if let Some(fill) = properties.fill.as_deref().and_then(tikz_color) {
+                                style.push(format!("fill={fill}"));
+                            }
+                            format!("\\node[{}] {{{text}}};", style.join(", "))
+                        },
+                        Swatch::Arrow => {
+                            style.push("-{Stealth[]}".into());
+                            format!("\\draw[{}] (0, 0) -- node[above] {{{text}}} (1.5, 0);", style.join(", "))
+                        },
+                    };
+                    rows.push(row);
+                }
+                format!(
+                    "\\matrix[draw, dotted, anchor=north west, row sep=1mm, nodes={{solid}}] at ($(current bounding box.north east) + (0.5, 0)$) {{\n{}\n}};\n",
+                    rows.into_iter().map(|row| format!("  {row} \\\\")).collect::<Vec<_>>().join("\n")
+                )
+            }
+        }
 
-        let out_width = of(out_width);
-        let in_width = of(in_width);
+        /// Escape the characters of `text` that TeX treats specially.
+        fn tikz_escape(text: &str) -> String {
+            let mut escaped = String::new();
+            for c in text.chars() {
+                if matches!(c, '$' | '&' | '%' | '#' | '_' | '{' | '}') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        }
 
-        let orig_width = max(max(of(20.), *min_width), of(9. * vl.len() as f64));
-        // min_width += max_by(out_width, in_width, |a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Greater));
-        *min_width = max(orig_width, max(in_width, out_width));
-        // eprintln!("lvl: {}, vl: {}, wl: {}, hops: {:?}", lvl, vl, wl, hops);
-        Ok(())
-    }
+        /// The xcolor equivalent of the CSS color `color`, if it is a color name or a hex triplet
+        fn tikz_color(color: &str) -> Option<String> {
+            let Some(hex) = color.strip_prefix('#') else {
+                return color.chars().all(|c| c.is_ascii_alphabetic()).then(|| color.to_string())
+            };
+            let hex = match hex.len() {
+                3 => hex.chars().flat_map(|c| [c, c]).collect(),
+                6 => hex.to_string(),
+                _ => return None,
+            };
+            let channel = |n: usize| u8::from_str_radix(hex.get(2 * n..2 * n + 2)?, 16).ok();
+            Some(format!("{{rgb,255:red,{};green,{};blue,{}}}", channel(0)?, channel(1)?, channel(2)?))
+        }
 
-    fn order_adjacent_vertical_edges<V: Graphic, E: Graphic + PartialEq<str>>(
-        vcg: &Vcg<V, E>,
-        layout_problem: &LayoutProblem<V>,
-        layout_solution: &LayoutSolution,
-        vl: &V
-    ) -> Result<(Vec<(VerticalRank, OriginalHorizontalRank, V, V)>, Vec<(VerticalRank, OriginalHorizontalRank, V, V)>), Error> {
-        let Vcg{vert: dag, vert_vxmap: dag_map, containers, ..} = vcg;
-        let LayoutProblem{node_to_loc, hops_by_edge, ..} = layout_problem;
-        let LayoutSolution{solved_locs, ..} = layout_solution;
-        let loc_ix = node_to_loc[&Obj::from_vl(vl, containers)];
-        let v_ers = dag.edges_directed(dag_map[vl], Outgoing).filter(|er| er.weight() == "vertical").into_iter().collect::<Vec<_>>();
-        let w_ers = dag.edges_directed(dag_map[vl], Incoming).filter(|er| er.weight() == "vertical").into_iter().collect::<Vec<_>>();
-        let mut v_dsts = v_ers
-            .iter()
-            .map(|er| {
-                dag
-                    .node_weight(er.target())
-                    .cloned()
-                    .ok_or_else::<Error, _>(|| LayoutError::OsqpError{error: "missing node weight".into()}.into())
-            })
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
-        let mut w_srcs = w_ers
-            .iter()
-            .map(|er| {
-                dag
-                    .node_weight(er.source())
-                    .cloned()
-                    .ok_or_else::<Error, _>(|| LayoutError::OsqpError{error: "missing node weight".into()}.into())
-            })
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
-        v_dsts.sort();
-        v_dsts.dedup();
-        v_dsts.sort_by_key(|dst| {
-            let hops = hops_by_edge.get(&(vl.clone(), dst.clone()));
-            let nhr = hops.map(|hops| hops.values().next().unwrap().1).unwrap_or_else(|| {
-                node_to_loc[&Obj::from_vl(dst, containers)].1
-            });
-            solved_locs[&(loc_ix.0 + 1)][&nhr]
-        });
-        let v_outs = v_dsts
-            .iter()
-            .map(|dst| { (vl.clone(), dst.clone()) })
-            .collect::<Vec<_>>();
-        w_srcs.sort();
-        w_srcs.dedup();
-        w_srcs.sort_by_key(|src| {
-            let hops = hops_by_edge.get(&(src.clone(), vl.clone()));
-            let mhr = hops.map(|hops| hops.values().rev().next().unwrap().0).unwrap_or_else(|| {
-                node_to_loc[&Obj::from_vl(src, containers)].1
-            });
-            solved_locs[&(VerticalRank(loc_ix.0.0 - 1))][&mhr]
-        });
-        let w_ins = w_srcs
-            .iter()
-            .map(|src| { (src.clone(), vl.clone()) })
-            .collect::<Vec<_>>();
-        let v_out_first_hops = v_outs
-            .iter()
-            .filter_map(|(vl, wl)| {
-                hops_by_edge.get(&(vl.clone(), wl.clone()))
-                    .and_then(|hops| hops.iter().next())
-                    .and_then(|(lvl, (mhr, _nhr))|
-                        Some((*lvl, *mhr, vl.clone(), wl.clone())))
-            })
-            .collect::<Vec<_>>();
-        let w_in_last_hops = w_ins
-            .iter()
-            .filter_map(|(vl, wl)| {
-                hops_by_edge.get(&(vl.clone(), wl.clone()))
-                    .and_then(|hops| hops.iter().rev().next())
-                    .and_then(|(lvl, (mhr, _nhr))|
-                        Some((*lvl, *mhr, vl.clone(), wl.clone()))
-                    )
-            })
-            .collect::<Vec<_>>();
-        Ok((v_out_first_hops, w_in_last_hops))
-    }
+        #[cfg(test)]
+        mod tests {
+            use pretty_assertions::assert_eq;
 
-    fn left(sloc: (VerticalRank, SolvedHorizontalRank)) -> Option<(VerticalRank, SolvedHorizontalRank)> {
-        sloc.1.checked_sub(1).map(|shrl| (sloc.0, shrl))
-    }
+            use crate::graph_drawing::frontend::{dom::{self, Node}, tests::rect, Orientation};
 
-    #[derive(Clone, Copy, Debug)]
-    enum Direction {
-        Vertical,
-        Horizontal,
+            use super::*;
+
+            #[test]
+            pub fn test_legend() {
+                let model = "warn: @ { stroke: red; description: needs review }; fb: @ { dash: 4 2 }; unused: @ { fill: blue }; a b: go; b c: back; b @ warn; b c: back @ fb";
+                let drawing = dom::draw(model.into()).unwrap();
+                assert!(drawing.legend.is_none() && drawing.nodes.iter().all(|n| !n.key().starts_with("legend")));
+
+                let draw = |orientation| {
+                    let options = RenderOptions{legend: true, orientation, ..Default::default()};
+                    dom::draw_with(model.into(), &Default::default(), &options).unwrap()
+                };
+                let drawing = draw(Orientation::TopToBottom);
+                let legend = drawing.legend.as_ref().unwrap();
+                let entries = legend.entries.iter().map(|e| (e.style.as_str(), e.text.as_str(), e.swatch)).collect::<Vec<_>>();
+                assert_eq!(entries, vec![("fb", "fb", Swatch::Arrow), ("warn", "needs review", Swatch::Box)]);
+                assert!(drawing.collisions.is_empty(), "{:?}", drawing.collisions);
+
+                let outline = rect(&drawing.nodes, "legend");
+                for key in ["a", "b", "c"] {
+                    assert!(rect(&drawing.nodes, key).r < outline.l, "{key}");
+                }
+                assert!(outline.r <= drawing.viewbox_width + 1.);
+                let entry = rect(&drawing.nodes, "legend_warn");
+                assert!(outline.l < entry.l && entry.r < outline.r && outline.t < entry.t && entry.b < outline.b);
+                assert!(drawing.nodes.iter().any(|n| matches!(n, Node::Svg{key, classes, labels, ..} if key == "legend_fb" && classes.contains("fb") && labels[0].text == "fb")));
+                let tikz = legend.to_tikz();
+                assert!(tikz.contains("\\node[draw=red] {needs review};") && tikz.contains("\\draw[dashed, -{Stealth[]}]"));
+
+                // legends sit beside the drawing's ranks, wherever they run
+                let drawing = draw(Orientation::LeftToRight);
+                let outline = rect(&drawing.nodes, "legend");
+                for key in ["a", "b", "c"] {
+                    assert!(rect(&drawing.nodes, key).b < outline.t, "{key}");
+                }
+            }
+        }
     }
 
-    impl Display for Direction {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// Which way do vertical chains run?
+    ///
+    /// # Summary
+    ///
+    /// Layout always happens in a top-to-bottom "rank frame" in which vertical chains
+    /// run down and horizontal chains run right; [dom::draw()] then reflects or transposes
+    /// the solved positions into the requested orientation.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+    pub enum Orientation {
+        /// Vertical chains run top to bottom; horizontal chains, left to right
+        #[default]
+        #[cfg_attr(feature = "serde", serde(rename = "TB"))]
+        TopToBottom,
+        /// Vertical chains run bottom to top; horizontal chains, left to right
+        #[cfg_attr(feature = "serde", serde(rename = "BT"))]
+        BottomToTop,
+        /// Vertical chains run left to right; horizontal chains, top to bottom
+        #[cfg_attr(feature = "serde", serde(rename = "LR"))]
+        LeftToRight,
+        /// Vertical chains run right to left; horizontal chains, top to bottom
+        #[cfg_attr(feature = "serde", serde(rename = "RL"))]
+        RightToLeft,
+    }
+
+    impl Orientation {
+        /// Do ranks run horizontally, so that the rank frame's axes must be swapped?
+        pub fn is_transposed(&self) -> bool {
+            matches!(self, Orientation::LeftToRight | Orientation::RightToLeft)
+        }
+
+        /// Map `(x, y)` from a rank frame whose vertical extent is `y0..y1` into this orientation.
+        pub fn transform(&self, (x, y): (f64, f64), (y0, y1): (f64, f64)) -> (f64, f64) {
             match self {
-                Direction::Vertical => write!(f, "Vertical"),
-                Direction::Horizontal => write!(f, "Horizontal"),
+                Orientation::TopToBottom => (x, y),
+                Orientation::BottomToTop => (x, y0 + y1 - y),
+                Orientation::LeftToRight => (y, x),
+                Orientation::RightToLeft => (y0 + y1 - y, x),
             }
         }
     }
 
-    #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
-    pub struct GeomLabel<V: Graphic, E: Graphic> {
-        vl: V,
-        wl: V,
-        rel: E,
+    impl std::fmt::Display for Orientation {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(match self {
+                Orientation::TopToBottom => "TB",
+                Orientation::BottomToTop => "BT",
+                Orientation::LeftToRight => "LR",
+                Orientation::RightToLeft => "RL",
+            })
+        }
     }
 
-    impl<V: Graphic, E: Graphic> Display for GeomLabel<V, E> {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Label({}, {}, {})", self.vl, self.wl, self.rel)
+    impl std::str::FromStr for Orientation {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "TB" => Ok(Orientation::TopToBottom),
+                "BT" => Ok(Orientation::BottomToTop),
+                "LR" => Ok(Orientation::LeftToRight),
+                "RL" => Ok(Orientation::RightToLeft),
+                _ => Err(TypeError::UnknownOrientationError{orientation: s.into()}.into()),
+            }
         }
     }
 
-    #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
-    pub enum Geom<V: Graphic, E: Graphic> {
-        Node(Obj<V>),
-        Hop(Obj<V>),
-        Container(Obj<V>),
-        Border(Obj<V>),
-        Label(GeomLabel<V, E>),
+    /// How are arrows drawn between their hops?
+    ///
+    /// # Summary
+    ///
+    /// Straight arrows run diagonally from hop to hop. Orthogonal arrows run
+    /// along the axes, turning (with rounded corners) halfway between hops.
+    ///
+    /// Individual arrows may also be drawn orthogonally by styling them `@ orthogonal`.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(rename_all = "lowercase"))]
+    pub enum EdgeStyle {
+        #[default]
+        Straight,
+        Orthogonal,
     }
 
-    impl<V: Graphic, E: Graphic> From<Obj<V>> for Geom<V, E> {
-        fn from(value: Obj<V>) -> Self {
-            match value {
-                Obj::Node(_) => Geom::Node(value),
-                Obj::Hop(_) => Geom::Hop(value),
-                Obj::Container(_) => Geom::Container(value),
-                _ => unimplemented!(),
-            }
+    impl std::fmt::Display for EdgeStyle {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(match self {
+                EdgeStyle::Straight => "straight",
+                EdgeStyle::Orthogonal => "orthogonal",
+            })
         }
     }
 
-    impl<V: Graphic, E: Graphic> Geom<V, E> {
-        pub fn as_vl(&self) -> Option<&V> {
-            match self {
-                Geom::Node(v) | Geom::Hop(v) | Geom::Container(v) | Geom::Border(v) => v.as_vl(),
-                Geom::Label(label) => Some(&label.vl),
+    impl std::str::FromStr for EdgeStyle {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "straight" => Ok(EdgeStyle::Straight),
+                "orthogonal" => Ok(EdgeStyle::Orthogonal),
+                _ => Err(TypeError::UnknownEdgeStyleError{edge_style: s.into()}.into()),
             }
         }
+    }
 
-        pub fn as_wl(&self) -> Option<&V> {
+    /// Which hops of an arrow spanning several ranks carry its labels?
+    ///
+    /// # Summary
+    ///
+    /// By default, labels sit beside their arrow's head: at the bottom of forward
+    /// arrows and at the top of reverse arrows. Room for a label is reserved
+    /// beside only the hops that carry it.
+    #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(rename_all = "lowercase"))]
+    pub enum LabelHop {
+        /// Beside the arrowhead
+        #[default]
+        Head,
+        /// Beside the topmost hop
+        Top,
+        /// Beside the middle hop, or halfway along the middle segment
+        Middle,
+        /// Beside the bottommost hop
+        Bottom,
+        /// Halfway along every segment
+        Repeated,
+    }
+
+    impl LabelHop {
+        /// Which of the `n` vertices of an arrow's path carry its label, given
+        /// whether the arrow is `forward`?
+        ///
+        /// Each group of vertices carries one copy of the label: beside the vertex, or
+        /// halfway along the segment between two consecutive vertices.
+        pub fn vertices(&self, n: usize, forward: bool) -> Vec<Vec<usize>> {
+            let last = n.saturating_sub(1);
             match self {
-                Geom::Node(v) | Geom::Hop(v) | Geom::Container(v) | Geom::Border(v) => v.as_wl(),
-                Geom::Label(label) => Some(&label.wl),
+                LabelHop::Head if forward => vec![vec![last]],
+                LabelHop::Head | LabelHop::Top => vec![vec![0]],
+                LabelHop::Bottom => vec![vec![last]],
+                LabelHop::Middle => {
+                    let (lo, hi) = (last / 2, last.div_ceil(2));
+                    if lo == hi { vec![vec![lo]] } else { vec![vec![lo, hi]] }
+                },
+                LabelHop::Repeated if last == 0 => vec![vec![0]],
+                LabelHop::Repeated => (0..last).map(|k| vec![k, k + 1]).collect(),
             }
         }
     }
 
-    impl<V: Graphic, E: Graphic> Display for Geom<V, E> {
+    impl std::fmt::Display for LabelHop {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            match self {
-                Geom::Node(v) | Geom::Hop(v) | Geom::Container(v) | Geom::Border(v) => write!(f, "{v}"),
-                Geom::Label(label) => write!(f, "{label}"),
-            }
+            f.write_str(match self {
+                LabelHop::Head => "head",
+                LabelHop::Top => "top",
+                LabelHop::Middle => "middle",
+                LabelHop::Bottom => "bottom",
+                LabelHop::Repeated => "repeated",
+            })
         }
     }
 
+    impl std::str::FromStr for LabelHop {
+        type Err = Error;
 
-    #[derive(Clone, Debug)]
-    enum ObjEdgeReason {
-        SolvedLeft,
-        VerticalEdge(String),
-        HorizontalEdge(String),
-        AdjacentHop,
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "head" => Ok(LabelHop::Head),
+                "top" => Ok(LabelHop::Top),
+                "middle" => Ok(LabelHop::Middle),
+                "bottom" => Ok(LabelHop::Bottom),
+                "repeated" => Ok(LabelHop::Repeated),
+                _ => Err(TypeError::UnknownLabelHopError{label_hop: s.into()}.into()),
+            }
+        }
     }
 
+    /// Tunable layout parameters for [render()]
+    ///
+    /// # Summary
+    ///
+    /// Distances are in pixels. The defaults reproduce depict's classic look;
+    /// with the `serde` feature, missing fields deserialize to their defaults,
+    /// so that clients need only send the options they want to change.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(default))]
+    pub struct RenderOptions {
+        /// Which way vertical chains run
+        pub orientation: Orientation,
+        /// How arrows run between their hops
+        pub edge_style: EdgeStyle,
+        /// Radius of the corners of orthogonal arrows
+        pub corner_radius: f64,
+        /// Which hops of long vertical arrows carry their labels
+        pub label_hop: LabelHop,
+        /// Horizontal gap between adjacent boxes in a rank
+        pub node_spacing: f64,
+        /// Vertical gap between boxes on adjacent ranks that are ordered but not connected by an arrow
+        pub rank_separation: f64,
+        /// Vertical gap between consecutive hops of an arrow, and between an arrow's ends and its boxes
+        pub hop_separation: f64,
+        /// Horizontal gap between neighboring arrows
+        pub arrow_separation: f64,
+        /// Room on each side of an unlabeled vertical arrow
+        pub arrow_spacing: f64,
+        /// Room beside the labels of a horizontal arrow
+        pub horizontal_arrow_padding: f64,
+        /// Extra room beside every arrow label
+        ///
+        /// [dom::draw()] increases this when labels collide and lays the drawing out again.
+        pub label_padding: f64,
+        /// Horizontal padding inside boxes
+        pub box_padding: f64,
+        /// Height of a box with a one-line label
+        pub box_height: f64,
+        /// Padding between containers and their contents
+        pub container_padding: f64,
+        /// Font size of box text
+        pub box_font_size: f64,
+        /// Font size of arrow labels
+        pub label_font_size: f64,
+        /// Height of one line of text
+        pub line_height: f64,
+        /// Wrap box labels wider than this
+        pub max_box_width: Option<f64>,
+        /// Gap between notes and the edges of the boxes they annotate
+        pub note_spacing: f64,
+        /// Size of the viewbox of an empty drawing
+        pub viewbox_width: f64,
+        pub viewbox_height: f64,
+        /// Colors, fonts, and arrowheads of rendered drawings
+        pub theme: theme::Theme,
+        /// Draw a [legend] of the style blocks that the drawing uses beside it
+        pub legend: bool,
+        /// OSQP absolute tolerance
+        pub eps_abs: f64,
+        /// OSQP relative tolerance
+        pub eps_rel: f64,
+        /// Sizes text in place of the estimate from the font sizes above, e.g., with a browser's canvas
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub text_measurer: Option<measure::SharedMeasurer>,
+    }
+
+    impl Default for RenderOptions {
+        fn default() -> Self {
+            Self {
+                orientation: Orientation::default(),
+                edge_style: EdgeStyle::default(),
+                corner_radius: 6.,
+                label_hop: LabelHop::default(),
+                node_spacing: 40.,
+                rank_separation: 40.,
+                hop_separation: 10.,
+                arrow_separation: 80.,
+                arrow_spacing: 10.,
+                horizontal_arrow_padding: 50.,
+                label_padding: 0.,
+                box_padding: 10.,
+                box_height: 26.,
+                container_padding: 10.,
+                box_font_size: 16.,
+                label_font_size: 14.,
+                line_height: 20.,
+                max_box_width: None,
+                note_spacing: 4.,
+                viewbox_width: 1024.,
+                viewbox_height: 400.,
+                theme: theme::Theme::default(),
+                legend: false,
+                eps_abs: 1e-1,
+                eps_rel: 1e-2,
+                text_measurer: None,
+            }
+        }
+    }
+
+    impl RenderOptions {
+        /// The [TextMeasurer] that sizes text: [RenderOptions::text_measurer] if set, or else
+        /// a [DefaultMeasurer](measure::DefaultMeasurer) for the theme's fonts at the configured
+        /// font sizes and line height.
+        pub fn measurer(&self) -> measure::SharedMeasurer {
+            if let Some(measurer) = &self.text_measurer {
+                return measurer.clone()
+            }
+            let font = |families: &str, size: f64| measure::FontMetrics{
+                size,
+                line_height: self.line_height,
+                ..measure::FontMetrics::for_family(measure::Family::from_css(families))
+            };
+            measure::SharedMeasurer::new(measure::DefaultMeasurer{
+                box_font: font(&self.theme.box_font, self.box_font_size),
+                label_font: font(&self.theme.label_font, self.label_font_size),
+            })
+        }
+    }
 
-    #[derive(Clone, Debug)]
-    struct ObjEdge {
-        name: usize,
-        reason: ObjEdgeReason,
-        dir: Direction,
-        margin: OrderedFloat<f64>,
+    #[derive(Default)]
+    pub struct Depiction<'s> {
+        pub items: Vec<Item<'s>>,
+        pub val: Val<Cow<'s, str>>,
+        pub styling: Styling<Cow<'s, str>>,
+        pub legend: Option<Legend>,
+        pub vcg: Vcg<Cow<'s, str>, Cow<'s, str>>,
+        pub paths_by_rank: RankedPaths<Cow<'s, str>>,
+        pub layout_problem: LayoutProblem<Cow<'s, str>>,
+        pub layout_solution: LayoutSolution,
+        pub geometry_problem: GeometryProblem<Cow<'s, str>>,
+        pub horizontal_problem: OptimizationProblem<AnySol, OrderedFloat<f64>>,
+        pub vertical_problem: OptimizationProblem<AnySol, OrderedFloat<f64>>,
+        pub geometry_solution: GeometrySolution,
     }
 
-    impl Display for ObjEdge {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "no: {}, \nreason: {:?}, \ndir: {}, \nmargin: {}", self.name, self.reason, self.dir, self.margin)
+    self_cell!{
+        pub struct RenderCell<'s> {
+            owner: Cow<'s, str>,
+
+            #[covariant]
+            dependent: Depiction,
         }
     }
 
+    pub fn render<'s>(data: Cow<'s, str>, options: &RenderOptions, logs: &mut log::Logger) -> Result<RenderCell<'s>, Error> {
+        let measurer = &options.measurer();
+        RenderCell::try_new(data, |data| {
+            let mut p = Parser::new();
+            let mut lex = Token::lexer(data);
+            while let Some(tk) = lex.next() {
+                p.parse(tk)
+                    .map_err(|_| {
+                        Kind::PomeloError{span: lex.span(), text: lex.slice().into()}
+                    })?
+            }
 
-    #[derive(Clone, Debug)]
-    struct ConEdgeMargin {
-        margin: OrderedFloat<f64>,
-    }
+            let items = p.end_of_input()
+                .map_err(|_| {
+                    Kind::PomeloError{span: lex.span(), text: lex.slice().into()}
+                })?;
 
-    #[derive(Clone, Debug)]
-    enum ConEdgeFlavor {
-        Margin(ConEdgeMargin),
-        Symmetrize(),
-    }
+            eprintln!("PARSE {items:#?}");
 
-    impl Display for ConEdgeFlavor {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            match self {
-                ConEdgeFlavor::Margin(ConEdgeMargin{margin}) => write!(f, "margin: {}", margin.0),
-                ConEdgeFlavor::Symmetrize() => write!(f, "symmetrize"),
-            }
-        }
-    }
+            let val = eval(&items)?;
 
-    #[derive(Clone, Debug)]
-    struct ConEdge {
-        name: usize,
-        reason: String,
-        dir: Direction,
-        flavor: ConEdgeFlavor,
-    }
+            eprintln!("EVAL {val:#?}");
 
-    impl Display for ConEdge {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "no: {}, \nreason: {}, {}, {}", self.name, self.reason, self.dir, self.flavor)
-        }
-    }
+            let mut styling = Styling::new(&val)?;
 
+            let mut vcg = calculate_vcg(&val, logs)?;
 
-    pub fn position_sols<V, E>(
-        vcg: &Vcg<V, E>,
-        layout_problem: &LayoutProblem<V>,
-        layout_solution: &LayoutSolution,
-        geometry_problem: &GeometryProblem<V>,
-        logs: &mut log::Logger,
-    ) -> Result<(OptimizationProblem<AnySol, OrderedFloat<f64>>, OptimizationProblem<AnySol, OrderedFloat<f64>>), Error>
-    where
-        V: Graphic + Len + std::cmp::PartialEq<str>,
-        E: Graphic + std::cmp::PartialEq<str>,
-    {
-        let containers = &vcg.containers;
-        let nodes_by_container = &vcg.nodes_by_container;
-        let nodes_by_container_transitive = &vcg.nodes_by_container_transitive;
-        let loc_to_node = &layout_problem.loc_to_node;
-        let node_to_loc = &layout_problem.node_to_loc;
-        let hops_by_edge = &layout_problem.hops_by_edge;
-        let solved_locs = &layout_solution.solved_locs;
-        let size_by_loc = &geometry_problem.size_by_loc;
-        let size_by_hop = &geometry_problem.size_by_hop;
-        let varrank_by_obj = &geometry_problem.varrank_by_obj;
-        let loc_by_varrank = &geometry_problem.loc_by_varrank;
-        let char_width = &geometry_problem.char_width.unwrap();
-        let line_height = &geometry_problem.line_height.unwrap();
+            styling.select(&vcg);
 
-        let of = OrderedFloat::<f64>::from;
+            wrap_labels(&mut vcg, measurer, options.max_box_width);
 
-        // let obj_count = all_locs.len() + all_hops.len();
-        // 1. Record how all objects are positioned relative to one another.
-        let mut obj_graph = Graph::<Geom<V, E>, ObjEdge>::new();
-        let mut obj_vxmap = HashMap::new();
-        let mut solved_vxmap = HashMap::new();
-        for (loc_ix, obj) in loc_to_node.iter() {
-            let ix = or_insert(&mut obj_graph, &mut obj_vxmap, obj.clone().into());
-            let shr = solved_locs[&loc_ix.0][&loc_ix.1];
-            solved_vxmap.insert((loc_ix.0, shr), ix);
-        }
+            // eprintln!("HCG {hcg:#?}");
 
-        let solved_to_orig = solved_locs.iter()
-            .flat_map(|(ovr, row)|
-                row.iter().map(|(ohr, shr)| (*ovr, *ohr, *shr))
-            )
-            .map(|(ovr, ohr, shr)| ((ovr, shr), (ovr, ohr)))
-            .collect::<HashMap<_, _>>();
+            let Vcg{vert, containers, nodes_by_container_transitive: nodes_by_container, container_depths, ..} = &vcg;
 
-        let mut obj_edges = 0;
-        let mut obj_edge = |dir: Direction, reason: ObjEdgeReason, margin: f64| {
-            let e = ObjEdge{name: obj_edges, reason, dir, margin: of(margin)};
-            obj_edges += 1;
-            e
-        };
+            // eprintln!("DISTANCE VERT: {:?}", Dot::new(&vert));
 
-        for (solved, orig) in solved_to_orig.iter() {
-            if let Some(left) = left(*solved) {
-                eprintln!("solved: {solved:?}, orig: {orig:?}, left: {left:?}");
-                let left_ix = solved_vxmap[&left];
-                let solved_ix = solved_vxmap[solved];
-                let left_obj = &loc_to_node[orig];
-                let solved_obj = &loc_to_node[&solved_to_orig[&left]];
-                eprintln!("solved left obj: {left_obj}, solved_obj: {solved_obj}");
-                if let (Obj::Hop(ObjHop{wl, ..}), Obj::Container(ObjContainer{vl: container})) = (left_obj, solved_obj) {
-                    if nodes_by_container_transitive[container].contains(wl) {
-                        eprintln!("skipping container-hop solved-left");
-                        continue;
+            let distance = {
+                let containers = &containers;
+                let nodes_by_container = &nodes_by_container;
+                let container_depths = &container_depths;
+                |src: Cow<str>, dst: Cow<str>, l: &mut log::Logger| {
+                    if !containers.contains(&src) {
+                        // if hcg.constraints.contains(&HorizontalConstraint{a: src.clone(), b: dst.clone()})
+                        // || hcg.constraints.contains(&HorizontalConstraint{a: dst.clone(), b: src.clone()}) {
+                        //     0
+                        // } else {
+                        //     -1
+                        // }
+                        l.log_pair(
+                            "(V, V)",
+                            names![src, dst],
+                            format!("{}, {}", src, dst),
+                            "isize, &str",
+                            vec![],
+                            format!("-1, src-not-container")
+                        ).unwrap();
+                        -1
+                    } else {
+                        if nodes_by_container[&src].contains(&dst) {
+                            l.log_pair(
+                                "(V, V)",
+                                names![src, dst],
+                                format!("{}, {}", src, dst),
+                                "isize, &str",
+                                vec![],
+                                format!("-1, src-contains-dst")
+                            ).unwrap();
+                            -1
+                        } else {
+                            l.log_pair(
+                                "(V, V)",
+                                names![src, dst],
+                                format!("{}, {}", src, dst),
+                                "isize, &str",
+                                vec![],
+                                format!("{}, container_depths", -(container_depths[&src] as isize))
+                            ).unwrap();
+                            -(container_depths[&src] as isize)
+                        }
                     }
                 }
-                if !obj_graph.contains_edge(left_ix, solved_ix) &&
-                    !matches!((left_obj, solved_obj),
-                        (Obj::Container(ObjContainer{vl: container}),
-                        Obj::Node(ObjNode{vl: wl}) | Obj::Container(ObjContainer{vl: wl}))
-                        if nodes_by_container[container].contains(wl)) {
-                    obj_graph.add_edge(left_ix, solved_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::SolvedLeft, 40.));
-                }
-            }
-            // todo: hop edges, nested edges, ...
-        }
+            };
 
-        if let Some(svg) = as_svg(&vcg.vert,
-            &|_, (_vx, vl)| {
-                format!(r##"color="#000000"; class="box highlight_{vl}""##)
-            }, &|g, er| {
-                let src = g.node_weight(er.source()).unwrap();
-                let dst = g.node_weight(er.target()).unwrap();
-                format!(r##"color="#000000"; class="arrow {src}_{dst}""##)
-            }
-        ) {
-            logs.log_svg(Some("lang_graph"), None::<String>, Vec::<String>::new(), svg)?;
-        }
+            let filtered_vert = vert.filter_map(|_vx, vl| Some(vl.clone()), |_ex, er| { if er == "horizontal" || er == "implied_horizontal" { None } else { Some(er.clone()) }});
 
-        for er in vcg.vert.edge_references() {
-            let ew = er.weight();
-            if ew != "vertical" && ew != "implied_vertical" { continue; }
-            let src = vcg.vert.node_weight(er.source()).unwrap();
-            let dst = vcg.vert.node_weight(er.target()).unwrap();
-            let src_obj = Obj::from_vl(src, containers);
-            let dst_obj = Obj::from_vl(dst, containers);
-            let src_loc = node_to_loc[&src_obj];
-            let dst_loc = node_to_loc[&dst_obj];
-            let src_ix = solved_vxmap[&(src_loc.0, solved_locs[&src_loc.0][&src_loc.1])];
-            let dst_ix = solved_vxmap[&(dst_loc.0, solved_locs[&dst_loc.0][&dst_loc.1])];
+            let paths_by_rank = rank(&filtered_vert, distance, logs)?;
 
-            eprintln!("OBJ_GRAPH VERT EDGE: {src_obj:?}, {dst_obj:?}");
-            eprintln!("SOLVED_VXMAP: {solved_vxmap:?}");
-            eprintln!("SOLVED_LOCS: {solved_locs:?}");
-            eprintln!("NODE_TO_LOC: {node_to_loc:?}");
+            logs.with_map("paths_by_rank", "BTreeMap<VerticalRank, SortedVec<(V, V)>>", paths_by_rank.iter(), |rank, paths, l| {
+                l.with_map(format!("paths_by_rank[{rank}]"), "SortedVec<(V, V)>", paths.iter().map(|p| (&p.0, &p.1)), |from, to, l| {
+                    l.log_pair(
+                        "V",
+                        names![from],
+                        format!("{from}"),
+                        "V",
+                        names![to],
+                        format!("{to}"),
+                    )
+                })
+            })?;
 
-            if ew == "implied_vertical" {
-                obj_graph.add_edge(src_ix, dst_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), 40.));
-                continue;
+            let mut layout_problem = calculate_locs_and_hops(&val, &paths_by_rank, &vcg, logs)?;
+
+            // ... adjust problem for horizontal edges
+
+            let layout_solution = minimize_edge_crossing(&vcg, &layout_problem, logs)?;
+            layout_problem.loc_to_node = layout_problem.loc_to_node.into_iter().filter(|(_loc, node)| !matches!(node, Obj::Gap(..))).collect::<HashMap<_, _>>();
+            layout_problem.node_to_loc = layout_problem.node_to_loc.into_iter().filter(|(node, _loc)| !matches!(node, Obj::Gap(..))).collect::<HashMap<_, _>>();
+
+            let mut geometry_problem = calculate_sols(&layout_problem, &layout_solution);
+
+            estimate_widths(&vcg, &styling, &layout_problem, &mut geometry_problem, measurer, options)?;
+
+            let legend = options.legend
+                .then(|| Legend::new(&styling, &vcg))
+                .filter(|legend| !legend.is_empty());
+            if let Some(legend) = &legend {
+                let (width, height) = legend.size(measurer, options);
+                let (width, height) = if options.orientation.is_transposed() { (height, width) } else { (width, height) };
+                geometry_problem.reserve_legend(NodeSize{width, left: 0., right: 0., height, top: 0., bottom: 0.});
             }
 
-            let hops = &hops_by_edge.get(&(src.clone(), dst.clone()));
-            let hops = if hops.is_none() {
-                vec![src_loc, dst_loc]
-            } else {
-                let mut hops = hops.unwrap().iter().map(|(ovr, (ohr, mhr))| (*ovr, *ohr, *mhr)).collect::<Vec<_>>();
-                let last = hops.last().unwrap();
-                hops.push((last.0 + 1, last.2, OriginalHorizontalRank(usize::MAX - last.1.0)));
-                hops.into_iter().map(|(ovr, ohr, _)| (ovr, ohr)).collect::<Vec<_>>()
-            };
+            let (horizontal_problem, vertical_problem) = position_sols(&vcg, &layout_problem, &layout_solution, &geometry_problem, measurer, options, logs)?;
 
-            let (src_out_first_hops, _src_in_last_hops) = order_adjacent_vertical_edges(vcg, layout_problem, layout_solution, src)?;
-            let (_dst_out_first_hops, dst_in_last_hops) = order_adjacent_vertical_edges(vcg, layout_problem, layout_solution, dst)?;
+            let geometry_solution = solve_optimization_problems(&horizontal_problem, &vertical_problem, options)?;
+
+            Ok(Depiction{
+                items,
+                val,
+                styling,
+                legend,
+                vcg,
+                paths_by_rank,
+                layout_problem,
+                layout_solution,
+                geometry_problem,
+                horizontal_problem,
+                vertical_problem,
+                geometry_solution,
+            })
+        })
+    }
+
+    pub mod log {
+
+        #[derive(Clone, Debug, PartialEq, PartialOrd)]
+        pub enum Record {
+            String { name: Option<String>, ty: Option<String>, names: Vec<String>, val: String, },
+            Group { name: Option<String>, ty: Option<String>, names: Vec<String>, val: Vec<Record>, },
+            Svg { name: Option<String>, ty: Option<String>, names: Vec<String>, val: String, }
+        }
+
+        #[derive(Clone, Debug, thiserror::Error)]
+        #[error("LogError")]
+        pub enum Error {
+            FmtError{ #[from] source: std::fmt::Error }
+        }
 
-            let mut prev_hop_ix = None;
-            for hop in hops.iter() {
-                let initial = hop == &src_loc;
-                let terminal = hop == &dst_loc;
-                let hop_ix = or_insert(&mut obj_graph, &mut obj_vxmap, Geom::Hop(Obj::Hop(ObjHop{vl: src.clone(), wl: dst.clone(), lvl: hop.0, mhr: hop.1})));
-                if initial {
-                    obj_graph.add_edge(src_ix, hop_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), 10.));
-                    let hop_pos = src_out_first_hops.iter().position(|h| (&h.2, &h.3) == (src, dst)).unwrap();
-                    let hop_left = hop_pos.checked_sub(1).and_then(|hop_left_pos| src_out_first_hops.get(hop_left_pos));
-                    if let Some(hop_left) = hop_left {
-                        let hop_left_ix = or_insert(&mut obj_graph, &mut obj_vxmap, Geom::Hop(Obj::Hop(ObjHop{vl: hop_left.2.clone(), wl: hop_left.3.clone(), lvl: hop_left.0, mhr: hop_left.1})));
-                        obj_graph.add_edge(hop_left_ix, hop_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::AdjacentHop, 80.));
-                    }
-                }
-                if terminal {
-                    obj_graph.add_edge(hop_ix, dst_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), 10.));
-                    let hop_pos = dst_in_last_hops.iter().position(|h| (&h.2, &h.3) == (src, dst)).unwrap();
-                    let hop_left = hop_pos.checked_sub(1).and_then(|hop_left_pos| dst_in_last_hops.get(hop_left_pos));
-                    if let Some(hop_left) = hop_left {
-                        let hop_left_ix = or_insert(&mut obj_graph, &mut obj_vxmap, Geom::Hop(Obj::Hop(ObjHop{vl: hop_left.2.clone(), wl: hop_left.3.clone(), lvl: hop_left.0, mhr: hop_left.1})));
-                        obj_graph.add_edge(hop_left_ix, hop_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::AdjacentHop, 80.));
-                    }
-                }
-                if let Some(prev_hop_ix) = prev_hop_ix {
-                    obj_graph.add_edge(prev_hop_ix, hop_ix, obj_edge(Direction::Vertical, ObjEdgeReason::VerticalEdge(ew.to_string()), 10.));
-                }
-                prev_hop_ix = Some(hop_ix);
-            }
+        pub trait Log<Cx = ()> {
+            fn log(&self, cx: Cx, l: &mut Logger) -> Result<(), Error>;
         }
-        eprintln!("OBJ_GRAPH: {:?}", Dot::new(&obj_graph));
 
-        for ((src, dst), labels) in vcg.horz_edge_labels.iter() {
-            let src_obj = Obj::from_vl(src, containers);
-            let dst_obj = Obj::from_vl(dst, containers);
-            let src_loc = node_to_loc[&src_obj];
-            let dst_loc = node_to_loc[&dst_obj];
-            let src_ix = solved_vxmap[&(src_loc.0, solved_locs[&src_loc.0][&src_loc.1])];
-            let dst_ix = solved_vxmap[&(dst_loc.0, solved_locs[&dst_loc.0][&dst_loc.1])];
-            let forward_label_width = labels.forward
-                .as_ref()
-                .and_then(|forward| forward
-                    .iter()
-                    .map(|label| label.len())
-                    .max()
-                );
-            let reverse_label_width = labels.reverse
-                .as_ref()
-                .and_then(|reverse| reverse
-                    .iter()
-                    .map(|label| label.len())
-                    .max()
-                );
-            let forward_width = forward_label_width.map(|label_width| char_width * label_width as f64).unwrap_or(20.);
-            let reverse_width = reverse_label_width.map(|label_width| char_width * label_width as f64).unwrap_or(20.);
-            let margin = 1.2 * f64::max(forward_width, reverse_width) + 50.;
-            obj_graph.add_edge(src_ix, dst_ix, obj_edge(Direction::Horizontal, ObjEdgeReason::HorizontalEdge(format!("{labels:?}")), margin));
+        pub trait Name {
+            fn name(&self) -> String;
         }
 
-        // eprintln!("obj graph: {}", Dot::new(&obj_graph));
+        impl Name for String {
+            fn name(&self) -> String {
+                self.clone()
+            }
+        }
 
-        if let Some(obj_svg) = as_svg(&obj_graph,
-            &|_, (_gx, geom)| {
-                match geom {
-                    Geom::Node(obj) | Geom::Container(obj) => format!(r##"color="#000000"; class="box highlight_{obj}""##),
-                    Geom::Hop(obj) => {
-                        let Some(vl) = obj.as_vl() else {return format!(r##"color="#000000"; class="{obj}""##)};
-                        let Some(wl) = obj.as_wl() else {return format!(r##"color="#000000"; class="{obj}""##)};
-                        format!(r##"color="#000000"; class="arrow {vl}_{wl} {obj}""##)
-                    },
-                    _ => String::new(),
-                }
-            }, &|g, er| {
-                let src = g.node_weight(er.source()).unwrap().as_vl().unwrap();
-                let dst = g.node_weight(er.target()).unwrap().as_wl().unwrap();
-                format!(r##"color="#000000"; class="arrow {src}_{dst}""##)
+        impl<'s> Name for std::borrow::Cow<'s, str> {
+            fn name(&self) -> String {
+                self.to_string()
             }
-        ) {
-            logs.log_svg(Some("obj_graph"), None::<String>, Vec::<String>::new(), obj_svg).unwrap();
         }
 
+        pub trait Names<'n> {
+            fn names(&self) -> Vec<Box<dyn Name + 'n>>;
+        }
 
-        // 2. Map objects to their corresponding positioning variables and
-        // constraints between those variables.
-        let mut con_graph = Graph::<AnySol, ConEdge>::new();
-        let mut con_vxmap = HashMap::new();
+        #[macro_export]
+        macro_rules! names {
+            ($($x:expr),*) => {{
+                let mut v = Vec::new();
+                $(
+                    v.push(Box::new(($x).clone()) as Box<dyn crate::graph_drawing::frontend::log::Name>);
+                )*
+                v
+            }}
+        }
 
-        let mut con_edges = 0;
-        let mut con_edge = |reason: String, dir: Direction, flavor: ConEdgeFlavor| {
-            let e = ConEdge{name: con_edges, reason, dir, flavor};
-            con_edges += 1;
-            e
-        };
+        pub use names;
 
-        let mut horz_out_labels_by_node = HashMap::<&V, Vec<Option<Vec<V>>>>::new();
-        let mut horz_in_labels_by_node = HashMap::<&V, Vec<Option<Vec<V>>>>::new();
-        for ((src, dst), labels) in vcg.horz_edge_labels.iter() {
-            horz_out_labels_by_node.entry(src).or_default().push(labels.forward.clone());
-            horz_in_labels_by_node.entry(src).or_default().push(labels.reverse.clone());
-            horz_out_labels_by_node.entry(dst).or_default().push(labels.reverse.clone());
-            horz_in_labels_by_node.entry(dst).or_default().push(labels.forward.clone());
+        impl<'n, A: Clone + Name + 'n> Names<'n> for A {
+            fn names(&self) -> Vec<Box<dyn Name + 'n>> {
+                names![self]
+            }
         }
 
-        for geom in obj_graph.node_weights() {
-            match geom {
-                Geom::Node(obj@Obj::Node(ObjNode{vl})) => {
-                    let ls = varrank_by_obj[&obj];
-                    let left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(ls));
-                    let right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(ls));
-                    let top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(ls));
-                    let bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(ls));
-                    let node_width = &size_by_loc[&node_to_loc[obj]];
-                    let mut width = of(node_width.width);
-                    update_min_width(vcg, layout_problem, layout_solution, geometry_problem, &mut width, vl)?;
-                    let height = 26.;
-                    con_graph.add_edge(left, right, con_edge("node-width".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: width})));
-                    con_graph.add_edge(top, bottom, con_edge("node-height".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(height)})));
-                },
-                Geom::Hop(obj@Obj::Hop(ObjHop{vl, wl, lvl, mhr})) => {
-                    let hs = varrank_by_obj[&obj];
-                    let hops = &hops_by_edge[&(vl.clone(), wl.clone())];
-                    let top_hop = hops.first_key_value().unwrap();
-                    let bottom_hop = hops.last_key_value().unwrap();
-                    let hst = varrank_by_obj[&Obj::Hop(ObjHop{vl: vl.clone(), wl: wl.clone(), lvl: *top_hop.0, mhr: top_hop.1.0})];
-                    let hsb = varrank_by_obj[&Obj::Hop(ObjHop{vl: vl.clone(), wl: wl.clone(), lvl: VerticalRank(bottom_hop.0.0+1), mhr: bottom_hop.1.1})];
-                    let guide_sol = or_insert(&mut con_graph, &mut con_vxmap, AnySol::S(hs));
-                    let top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(hst));
-                    let bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(hsb));
-                    let left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(hs));
-                    let right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(hs));
-                    let hop_size = &size_by_hop.get(&(*lvl, *mhr, vl.clone(), wl.clone())).unwrap_or_else(|| {
-                        eprintln!("WARNING: con_graph: no size for hop: {obj}");
-                        &HopSize{width: 10., left: 5., right: 5., height: 0., top: 0., bottom: 0.}
-                    });
-                    con_graph.add_edge(top, bottom, con_edge("hop-height".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(hop_size.height)})));
-                    con_graph.add_edge(left, guide_sol, con_edge("hop-left".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(hop_size.left)})));
-                    con_graph.add_edge(guide_sol, right, con_edge("hop-right".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(hop_size.right)})));
-                },
-                Geom::Container(obj@Obj::Container(ObjContainer{vl})) => {
-                    let container = vl;
-                    let container_sol = varrank_by_obj[&obj];
-                    let left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(container_sol));
-                    let right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(container_sol));
-                    let top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(container_sol));
-                    let bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(container_sol));
-                    let node_width = &size_by_loc[&node_to_loc[obj]];
-                    let mut width = of(node_width.width);
-                    update_min_width(vcg, layout_problem, layout_solution, geometry_problem, &mut width, vl)?;
-                    let height = 20.;
-                    con_graph.add_edge(left, right, con_edge("container-width".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: width})));
-                    con_graph.add_edge(top, bottom, con_edge("container-height".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(height)})));
-
-                    for node in &nodes_by_container[container] {
-                        let child_sol = varrank_by_obj[&Obj::from_vl(node, containers)];
-                        // XXX: child might be a container too..., or on a deeper rank
-                        let child_left = or_insert(&mut con_graph, &mut con_vxmap, AnySol::L(child_sol));
-                        let child_right = or_insert(&mut con_graph, &mut con_vxmap, AnySol::R(child_sol));
-                        let child_top = or_insert(&mut con_graph, &mut con_vxmap, AnySol::T(child_sol));
-                        let child_bottom = or_insert(&mut con_graph, &mut con_vxmap, AnySol::B(child_sol));
-                        let padding = 10.;
-                        let max_out_label_height = horz_out_labels_by_node.get(node).and_then(|ls| ls.iter().filter_map(|l| l.as_ref().map(|l| l.len())).max()).unwrap_or(0) as f64;
-                        let max_in_label_height = horz_in_labels_by_node.get(node).and_then(|ls| ls.iter().filter_map(|l| l.as_ref().map(|l| l.len())).max()).unwrap_or(0) as f64;
-                        let max_in_label_width = horz_in_labels_by_node.get(node).and_then(|ls| ls.iter().filter_map(|l| l.as_ref()).flat_map(|v| v.iter().map(|v| v.len())).max()).unwrap_or(0) as f64;
-                        let child_top_margin = 2. * padding + 6. + max_out_label_height * line_height;
-                        let child_bottom_margin = padding + max_in_label_height * line_height;
-                        let child_left_margin = 2. * padding + char_width * max_in_label_width;
-                        let child_right_margin = 2. * padding + char_width * max_in_label_width;
-                        con_graph.add_edge(left, child_left, con_edge("child-padding-left".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_left_margin)})));
-                        con_graph.add_edge(child_right, right, con_edge("child-padding-right".into(), Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_right_margin)})));
-                        con_graph.add_edge(top, child_top, con_edge("child-padding-top".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_top_margin)})));
-                        con_graph.add_edge(child_bottom, bottom, con_edge("child-padding-bottom".into(), Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(child_bottom_margin)})));
-                    }
-                },
-                _ => {}
+        impl<'n, A1: Clone + Name + 'n, A2: Clone + Name + 'n> Names<'n> for (A1, A2) {
+            fn names(&self) -> Vec<Box<dyn Name + 'n>> {
+                names![self.0, self.1]
             }
         }
 
-        for er in obj_graph.edge_references() {
-            let edge = er.weight();
-            // eprintln!("EDGE IX: {:?} -> {:?}, {}", er.source(), er.target(), edge);
-            let src = obj_graph.node_weight(er.source()).unwrap();
-            let dst = obj_graph.node_weight(er.target()).unwrap();
-            if matches!((src, dst),
-                (Geom::Container(Obj::Container(ObjContainer{vl: container})),
-                    Geom::Node(Obj::Node(ObjNode{vl: wl})) | Geom::Container(Obj::Container(ObjContainer{vl: wl})))
-                    if nodes_by_container[container].contains(wl)) {
-                continue;
+        #[derive(Clone, Debug, Default)]
+        pub struct Logger {
+            logs: Vec<Record>,
+        }
+
+        impl Logger {
+            pub fn new() -> Self {
+                Self {
+                    logs: vec![],
+                }
             }
 
-            macro_rules! sol_for {
-                ($node:ident) => {
-                    match $node {
-                        Geom::Node(v) => {varrank_by_obj[v]},
-                        Geom::Hop(v) => {varrank_by_obj[v]},
-                        Geom::Container(v) => {varrank_by_obj[v]},
-                        _ => unimplemented!(),
+            pub fn with_map<K, V, F>(
+                &mut self,
+                name: impl Into<String>,
+                ty: impl Into<String>,
+                pairs: impl IntoIterator<Item=(K, V)>,
+                mut f: F
+            ) -> Result<(), Error> where
+                F: FnMut(K, V, &mut Logger) -> Result<(), Error>
+            {
+                self.with_group(ty.into(), name.into(), Vec::<String>::new(), |l| {
+                    for (k, v) in pairs.into_iter() {
+                        f(k, v, l)?
                     }
-                };
+                    Ok(())
+                })?;
+                Ok(())
             }
-            macro_rules! impl_con {
-                ($src:ident, $src_sol_con:ident, $dst:ident, $dst_sol_con:ident, $dir: ident, $edge:ident) => {
-                    {
-                        let src_guide_sol = AnySol::$src_sol_con(sol_for!($src));
-                        let dst_guide_sol = AnySol::$dst_sol_con(sol_for!($dst));
-                        let src_ix = *con_vxmap.get(&src_guide_sol).expect(&format!("no entry for src key: {src_guide_sol}, src: ({src}) -> dst: ({dst}), edge: {edge}, in\n{varrank_by_obj:#?}\ncon_vxmap: {con_vxmap:#?}\n"));
-                        let dst_ix = *con_vxmap.get(&dst_guide_sol).expect(&format!("no entry for dst key: {dst_guide_sol}, src: ({src}) -> dst: ({dst}), edge: {edge}, in\n{varrank_by_obj:#?}\ncon_vxmap: {con_vxmap:#?}\n"));
-                        con_graph.add_edge(src_ix, dst_ix, con_edge(format!("obj-edge: {edge}"), $dir, $edge.clone()));
+
+            pub fn with_set<V, F>(
+                &mut self,
+                name: impl Into<String>,
+                ty: impl Into<String>,
+                elements: impl IntoIterator<Item=V>,
+                mut f: F
+            ) -> Result<(), Error> where
+                F: FnMut(V, &mut Logger) -> Result<(), Error>
+            {
+                self.with_group(ty.into(), name.into(), Vec::<String>::new(), |l| {
+                    for v in elements.into_iter() {
+                        f(v, l)?
                     }
-                };
+                    Ok(())
+                })?;
+                Ok(())
+            }
+
+            pub fn log_pair<'n>(
+                &mut self,
+                src_ty: impl Into<String>,
+                src_names: Vec<Box<dyn Name + 'n>>,
+                src_val: impl Into<String>,
+                dst_ty: impl Into<String>,
+                dst_names: Vec<Box<dyn Name + 'n>>,
+                dst_val: impl Into<String>
+            ) -> Result<(), Error> {
+                let src_ty = src_ty.into();
+                let mut src_names = src_names.into_iter().map(|n| n.name()).collect::<Vec<String>>();
+                let src_val = src_val.into();
+                let dst_ty = dst_ty.into();
+                let mut dst_names = dst_names.into_iter().map(|n| n.name()).collect::<Vec<String>>();
+                let dst_val = dst_val.into();
+                src_names.append(&mut dst_names);
+                self.logs.push(Record::String{
+                    name: None,
+                    ty: Some(format!("{src_ty}->{dst_ty}")),
+                    names: src_names,
+                    val: format!("{src_val} -> {dst_val}")
+                });
+                Ok(())
             }
 
-            enum Flavor {
-                Box,
-                Arrow,
-            }
-            impl<V: Graphic, E: Graphic> From<&Geom<V, E>> for Flavor {
-                fn from(value: &Geom<V, E>) -> Self {
-                    match value {
-                        Geom::Node(_) | Geom::Container(_) | Geom::Border(_) | Geom::Label(_) => Flavor::Box,
-                        Geom::Hop(_) => Flavor::Arrow,
-                    }
-                }
+            pub fn log_element(
+                &mut self,
+                ty: impl Into<String>,
+                names: Vec<impl Into<String>>,
+                val: impl Into<String>
+            ) -> Result<(), Error> {
+                let ty = ty.into();
+                let names = names.into_iter().map(|n| n.into()).collect::<Vec<String>>();
+                let val = val.into();
+                self.logs.push(Record::String{
+                    name: None,
+                    ty: Some(ty),
+                    names,
+                    val,
+                });
+                Ok(())
             }
-            let src_flavor: Flavor = src.into();
-            let dst_flavor: Flavor = dst.into();
 
-            let margin = ConEdgeFlavor::Margin(ConEdgeMargin{margin: of(edge.margin.0)});
-            let symmetrize = ConEdgeFlavor::Symmetrize();
-            let horizontal = Direction::Horizontal;
-            let vertical = Direction::Vertical;
-            match (src_flavor, dst_flavor, &edge.reason, edge.dir) {
-                (Flavor::Box, Flavor::Box, _, Direction::Vertical) => {
-                    impl_con![src, B, dst, T, vertical, margin];
-                },
-                (Flavor::Box, Flavor::Box, ObjEdgeReason::HorizontalEdge(_), Direction::Horizontal) => {
-                    impl_con![src, R, dst, L, horizontal, margin];
-                    impl_con![src, T, dst, T, vertical, symmetrize];
-                    impl_con![src, B, dst, B, vertical, symmetrize];
-                },
-                (Flavor::Box, Flavor::Box, _, Direction::Horizontal) => {
-                    impl_con![src, R, dst, L, horizontal, margin];
-                },
-                (Flavor::Box, Flavor::Arrow, _, Direction::Vertical) => {
-                    impl_con![src, B, dst, T, vertical, margin];
-                    impl_con![src, L, dst, L, horizontal, margin];
-                    impl_con![dst, L, dst, S, horizontal, margin];
-                    impl_con![dst, S, dst, R, horizontal, margin];
-                    impl_con![dst, R, src, R, horizontal, margin];
-                },
-                (Flavor::Box, Flavor::Arrow, _, Direction::Horizontal) => {
-                    impl_con![src, R, dst, S, horizontal, margin];
-                },
-                (Flavor::Arrow, Flavor::Box, _, Direction::Vertical) => {
-                    impl_con![src, B, dst, T, vertical, margin];
-                    impl_con![dst, L, src, L, horizontal, margin];
-                    impl_con![src, L, src, S, horizontal, margin];
-                    impl_con![src, S, dst, R, horizontal, margin];
-                    impl_con![src, R, dst, R, horizontal, margin];
-                },
-                (Flavor::Arrow, Flavor::Box, _, Direction::Horizontal) => {
-                    impl_con![src, S, dst, L, horizontal, margin];
-                },
-                (Flavor::Arrow, Flavor::Arrow, _, Direction::Vertical) => {
-                    impl_con![src, S, dst, S, horizontal, symmetrize];
-                },
-                (Flavor::Arrow, Flavor::Arrow, _, Direction::Horizontal) => {
-                    impl_con![src, R, dst, L, horizontal, margin];
-                },
+            pub fn log_string(&mut self, name: impl Into<String>, val: impl std::fmt::Debug) -> Result<(), Error> {
+                self.logs.push(Record::String{
+                    name: Some(name.into()),
+                    ty: None,
+                    names: vec![],
+                    val: format!("{val:#?}")
+                });
+                Ok(())
             }
-        }
-
-        // eprintln!("con graph: {}", Dot::new(&con_graph));
 
-        if let Some(con_svg) = as_svg(
-            &con_graph,
-            &|_, (_sx, sol)| {
-                let Ok(varrank) = sol.try_into() else { return String::new() };
-                let obj = &loc_to_node[&loc_by_varrank[&varrank]];
-                match obj {
-                    Obj::Node(_) | Obj::Container(_)=> format!(r##"color="#000000"; class="box highlight_{obj}""##),
-                    Obj::Hop(ObjHop{vl, wl, ..}) => format!(r##"color="#000000"; class="arrow {vl}_{wl}""##),
-                    _ => unimplemented!(),
-                }
-            }, &|g, er| {
-                let src: Result<VarRank, _> = g.node_weight(er.source()).unwrap().try_into();
-                let dst: Result<VarRank, _> = g.node_weight(er.target()).unwrap().try_into();
-                let Ok(src) = src else { return r##"color="#000000"; class="arrow""##.into(); };
-                let Ok(dst) = dst else { return r##"color="#000000"; class="arrow""##.into(); };
-                let src = (&loc_to_node[&loc_by_varrank[&src]]).as_vl().unwrap();
-                let dst = (&loc_to_node[&loc_by_varrank[&dst]]).as_wl().unwrap();
-                format!(r##"color="#000000"; class="arrow {src}_{dst}""##)
+            pub fn log_svg(&mut self, name: Option<impl Into<String>>, ty: Option<impl Into<String>>, names: Vec<impl Into<String>>, val: String) -> Result<(), Error> {
+                let name = name.map(|name| name.into());
+                let ty = ty.map(|ty| ty.into());
+                let names = names.into_iter().map(|n| n.into()).collect::<Vec<String>>();
+                self.logs.push(Record::Svg{
+                    name,
+                    ty,
+                    names,
+                    val,
+                });
+                Ok(())
             }
-        ) {
-            logs.log_svg(Some("con_graph"), None::<String>, Vec::<String>::new(), con_svg).unwrap();
-        }
-
-        #[cfg(debug_assertions)]
-        if is_cyclic_directed(&con_graph) {
-            eprintln!("ERROR: CYCLIC CONSTRAINT GRAPH DETECTED");
-        }
 
-        // 3. Translate those variables and constraints into optimization problems to be solved.
-        let mut vertical_problem = OptimizationProblem { v: Vars::new(), c: Constraints::new(), pd: vec![], q: vec![] };
-        let mut horizontal_problem = OptimizationProblem { v: Vars::new(), c: Constraints::new(), pd: vec![], q: vec![] };
-
-        for (sol, _ix) in con_vxmap.iter() {
-            match sol {
-                AnySol::L(_) | AnySol::R(_) | AnySol::S(_) | AnySol::V(_) => {
-                    let var = horizontal_problem.v.get(*sol);
-                    horizontal_problem.c.push((of(0.), vec![var.clone()], of(f64::INFINITY)));
-                    horizontal_problem.q.push(var.clone());
-                },
-                AnySol::T(_) | AnySol::B(_) | AnySol::H(_) => {
-                    let var = vertical_problem.v.get(*sol);
-                    vertical_problem.c.push((of(0.), vec![var.clone()], of(f64::INFINITY)));
-                    vertical_problem.q.push(var.clone());
-                },
-                _ => {},
+            pub fn with_group<F>(
+                &mut self,
+                ty: impl Into<String>,
+                name: impl Into<String>,
+                names: Vec<impl Into<String>>,
+                f: F
+            ) -> Result<(), Error> where
+                F: FnOnce(&mut Logger) -> Result<(), Error>
+            {
+                let mut nested_logger = Logger::new();
+                f(&mut nested_logger)?;
+                self.logs.push(Record::Group{
+                    name: Some(name.into()),
+                    ty: Some(ty.into()),
+                    names: names.into_iter().map(|n| n.into()).collect::<Vec<_>>(),
+                    val: nested_logger.logs
+                });
+                Ok(())
             }
-        }
-        for er in con_graph.edge_references() {
-            let src = con_graph.node_weight(er.source()).unwrap();
-            let tgt = con_graph.node_weight(er.target()).unwrap();
-            let wgt = er.weight();
-            match (wgt.dir, &wgt.flavor) {
-                (Direction::Horizontal, ConEdgeFlavor::Margin(ConEdgeMargin{margin})) => {
-                    horizontal_problem.c.leqc(&mut horizontal_problem.v, *src, *tgt, *margin);
-                },
-                (Direction::Vertical, ConEdgeFlavor::Margin(ConEdgeMargin{margin})) => {
-                    vertical_problem.c.leqc(&mut vertical_problem.v, *src, *tgt, *margin);
-                },
-                (Direction::Horizontal, ConEdgeFlavor::Symmetrize()) => {
-                    horizontal_problem.c.sym(&mut horizontal_problem.v, &mut horizontal_problem.pd, *src, *tgt, 10.);
-                }
-                (Direction::Vertical, ConEdgeFlavor::Symmetrize()) => {
-                    vertical_problem.c.sym(&mut vertical_problem.v, &mut vertical_problem.pd, *src, *tgt, 1000.);
-                }
+
+            pub fn to_vec(self) -> Vec<Record> {
+                self.logs
             }
         }
-
-        Ok((horizontal_problem, vertical_problem))
     }
 
-    use petgraph::visit::{IntoEdgeReferences, IntoNodeReferences, NodeIndexable, GraphProp};
+    pub mod route {
+        //! Edge routing around boxes and containers
+        //!
+        //! # Summary
+        //!
+        //! [solve_optimization_problems()](crate::graph_drawing::geometry::solve_optimization_problems)
+        //! keeps boxes apart but does not stop an arrow from passing through a box
+        //! that it neither starts nor ends at, e.g., when a horizontal relationship
+        //! skips over a neighbor or a long hop cuts through a wide box.
+        //!
+        //! [route()] finds such segments by checking each segment's [Rect] against
+        //! the [Rect]s of the drawing's boxes and replaces them with orthogonal
+        //! detours that pass `margin` pixels outside the obstacle.
+        //!
+        //! Some overlaps are expected: containers overlap their contents, and arrows
+        //! cross the containers of their endpoints. [Exemptions] records these so
+        //! that [find_unexpected_collisions()] can report everything else.
+        use std::collections::{HashMap, HashSet};
 
-    #[cfg(feature="desktop")]
-    fn as_svg<'a, G: IntoNodeReferences + IntoEdgeReferences + NodeIndexable + GraphProp>(
-        graph: G,
-        node_attrs: &'a dyn Fn(G, G::NodeRef) -> String,
-        edge_attrs: &'a dyn Fn(G, G::EdgeRef) -> String
-    ) -> Option<String> where
-        G::NodeWeight: Display,
-        G::EdgeWeight: Display,
-    {
-        use std::{process::{Stdio, Command}, io::Write};
+        use super::dom::{collides, segment_rect, Collision, Label, Node, Rect};
+        use super::measure::TextMeasurer;
 
-        let dot = format!(r#"digraph {{
-                bgcolor="transparent";
-                {}
-            }}"#, petgraph::dot::Dot::with_attr_getters(graph,
-            &[petgraph::dot::Config::GraphContentOnly],
-            edge_attrs,
-            node_attrs,
-            ));
-        let mut child = Command::new("dot")
-            .arg("-Tsvg")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to execute dot");
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-        std::thread::spawn(move || {
-            stdin.write_all(dot.as_bytes()).expect("Failed to write to stdin");
-        });
-        let output = child.wait_with_output().expect("Failed to read stdout");
-        let svg = String::from_utf8_lossy(&output.stdout);
-        let svg = svg.replace("#000000", "currentColor");
-        // Some(format!("data:image/svg+xml;utf8,{svg}"))
-        Some(svg.into())
-    }
+        /// Which boxes each node of a drawing is expected to overlap
+        #[derive(Clone, Debug, Default)]
+        pub struct Exemptions {
+            by_key: HashMap<String, HashSet<String>>,
+            crossings_by_key: HashMap<String, HashSet<String>>,
+        }
 
-    #[cfg(not(feature="desktop"))]
-    fn as_svg<'a, G: IntoNodeReferences + IntoEdgeReferences + NodeIndexable + GraphProp>(
-        _graph: G,
-        _node_attrs: &'a dyn Fn(G, G::NodeRef) -> String,
-        _edge_attrs: &'a dyn Fn(G, G::EdgeRef) -> String
-    ) -> Option<String> where
-        G::NodeWeight: Display,
-        G::EdgeWeight: Display,
-    {
-        None
-    }
+        impl Exemptions {
+            pub fn new() -> Self {
+                Self::default()
+            }
 
-    #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
-    pub enum OSQPStatusKind {
-        Solved,
-        SolvedInaccurate,
-        MaxIterationsReached,
-        TimeLimitReached,
-        #[default] Default,
-    }
+            /// Expect the node keyed `key` to overlap the box keyed `other`.
+            pub fn allow(&mut self, key: impl Into<String>, other: impl Into<String>) {
+                self.by_key.entry(key.into()).or_default().insert(other.into());
+            }
 
-    fn solve_problem<C: Coeff>(
-        optimization_problem: &OptimizationProblem<AnySol, C>
-    ) -> Result<(Vec<(Var<AnySol>, f64)>, OSQPStatusKind), Error> {
-        let OptimizationProblem{v, c, pd, q} = optimization_problem;
+            /// Is either of the nodes keyed `a` and `b` expected to overlap the other?
+            pub fn allows(&self, a: &str, b: &str) -> bool {
+                self.by_key.get(a).is_some_and(|keys| keys.contains(b)) ||
+                self.by_key.get(b).is_some_and(|keys| keys.contains(a))
+            }
 
-        let settings = osqp::Settings::default()
-            .verbose(false)
-            // .adaptive_rho(false)
-            // .rho(6.)
-            // .check_termination(Some(200))
-            // .adaptive_rho_fraction(1.0) // https://github.com/osqp/osqp/issues/378
-            // .adaptive_rho_interval(Some(25))
-            .eps_abs(1e-1)
-            .eps_rel(1e-2)
-            // .max_iter(128_000)
-            // .max_iter(400)
-            // .polish(true)
-        ;
-        #[cfg(debug_assertions)]
-        let settings = settings.verbose(true);
-        let settings = &settings;
+            /// Expect the arrow keyed `key` to cross the box keyed `other`, though its label should not.
+            pub fn allow_crossing(&mut self, key: impl Into<String>, other: impl Into<String>) {
+                self.crossings_by_key.entry(key.into()).or_default().insert(other.into());
+            }
 
-        let n = v.len();
+            /// May an arrow of either of the nodes keyed `a` and `b` cross the other?
+            pub fn allows_crossing(&self, a: &str, b: &str) -> bool {
+                self.allows(a, b) ||
+                self.crossings_by_key.get(a).is_some_and(|keys| keys.contains(b)) ||
+                self.crossings_by_key.get(b).is_some_and(|keys| keys.contains(a))
+            }
+        }
 
-        let sparse_pd = &pd[..];
-        // eprintln!("sparsePd: {sparse_pd:?}");
-        let p2 = as_diag_csc_matrix(Some(n), Some(n), sparse_pd);
-        // print_tuples("P2", &p2);
+        /// The points of `path`, if it consists only of `M` and `L` commands.
+        fn path_points(path: &str) -> Option<Vec<(f64, f64)>> {
+            let mut points = vec![];
+            let mut x = None;
+            for token in path.split_whitespace() {
+                match (token, x) {
+                    ("M" | "L", None) => {},
+                    (_, None) => x = Some(token.parse::<f64>().ok()?),
+                    (_, Some(x0)) => {
+                        points.push((x0, token.parse::<f64>().ok()?));
+                        x = None;
+                    },
+                }
+            }
+            (x.is_none() && points.len() >= 2).then_some(points)
+        }
 
-        let mut q2 = Vec::with_capacity(n);
-        q2.resize(n, 0.);
-        for q in q.iter() {
-            if matches!(q.var.sol, AnySol::L(_) | AnySol::T(_)) {
-                q2[q.var.index] -= 0.5 * q.coeff.into();
+        fn points_path(points: &[(f64, f64)]) -> String {
+            points
+                .iter()
+                .enumerate()
+                .map(|(n, (x, y))| format!("{} {x} {y}", if n == 0 { "M" } else { "L" }))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+
+        /// The detour around `obstacle` that replaces the segment from `p` to `q`,
+        /// or `None` if the segment cannot leave the obstacle's margin.
+        fn detour(p: (f64, f64), q: (f64, f64), obstacle: &Rect, margin: f64, above: bool) -> Option<Vec<(f64, f64)>> {
+            let lerp = |t: f64| (p.0 + t * (q.0 - p.0), p.1 + t * (q.1 - p.1));
+            let vertical = (q.1 - p.1).abs() >= (q.0 - p.0).abs();
+            let (p_main, q_main, lo, hi) = if vertical {
+                (p.1, q.1, obstacle.t - margin, obstacle.b + margin)
             } else {
-                q2[q.var.index] += q.coeff.into();
+                (p.0, q.0, obstacle.l - margin, obstacle.r + margin)
+            };
+            // where, as fractions of the segment, does it enter and leave the obstacle's margin?
+            let at = |v: f64| ((v - p_main) / (q_main - p_main)).clamp(0., 1.);
+            let (t0, t1) = if p_main <= q_main { (at(lo), at(hi)) } else { (at(hi), at(lo)) };
+            let (a, d) = (lerp(t0), lerp(t1));
+            let (b, c) = if vertical {
+                let mid = (a.0 + d.0) / 2.;
+                let side = if mid - obstacle.l < obstacle.r - mid { obstacle.l - margin } else { obstacle.r + margin };
+                ((side, a.1), (side, d.1))
+            } else {
+                let side = if above { obstacle.t - margin } else { obstacle.b + margin };
+                ((a.0, side), (d.0, side))
+            };
+            let points = [a, b, c, d];
+            let clear = points.windows(2).all(|w| !collides(&segment_rect(String::new(), w[0], w[1]), obstacle));
+            clear.then(|| points.to_vec())
+        }
+
+        /// Reroute the arrows of `nodes` around the boxes that they are not expected to overlap.
+        ///
+        /// Returns the extent of the detours, if any, which may lie outside the boxes' extent.
+        pub fn route(nodes: &mut [Node], exemptions: &Exemptions, margin: f64) -> Option<Rect> {
+            let obstacles = nodes
+                .iter()
+                .filter_map(|node| match node {
+                    Node::Div{key, hpos, vpos, width, height, ..} => Some(Rect{id: key.clone(), l: *hpos, r: hpos + width, t: *vpos, b: vpos + height}),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let max_detours = 4 * obstacles.len();
+            let mut extent: Option<Rect> = None;
+
+            for node in nodes.iter_mut() {
+                let Node::Svg{key, path, dir, rel, labels, control_points, ..} = node else { continue };
+                let Some(mut points) = path_points(path) else { continue };
+                let obstacles = obstacles.iter().filter(|o| !exemptions.allows_crossing(key, &o.id)).collect::<Vec<_>>();
+                // labels of horizontal arrows sit above forward arrows and below reverse ones
+                let above = rel != "reverse";
+
+                let mut detours = 0;
+                let mut label_run = None;
+                let mut n = 0;
+                while n + 1 < points.len() && detours < max_detours {
+                    let (p, q) = (points[n], points[n+1]);
+                    let seg = segment_rect(String::new(), p, q);
+                    let distance = |o: &Rect| (o.l - p.0).abs().min((o.r - p.0).abs()) + (o.t - p.1).abs().min((o.b - p.1).abs());
+                    let nearest = obstacles
+                        .iter()
+                        .filter(|o| collides(&seg, o))
+                        .min_by(|a, b| distance(a).total_cmp(&distance(b)));
+                    match nearest.and_then(|o| detour(p, q, o, margin, above)) {
+                        Some(points2) => {
+                            if dir == "horizontal" {
+                                label_run = Some((points2[1], points2[2]));
+                            }
+                            points.splice(n+1..n+1, points2);
+                            points.dedup();
+                            detours += 1;
+                        },
+                        None => n += 1,
+                    }
+                }
+                if detours == 0 {
+                    continue
+                }
+
+                for (x, y) in points.iter().copied() {
+                    let e = extent.get_or_insert(Rect{id: String::new(), l: x, r: x, t: y, b: y});
+                    (e.l, e.r, e.t, e.b) = (e.l.min(x), e.r.max(x), e.t.min(y), e.b.max(y));
+                }
+                *path = points_path(&points);
+                *control_points = points;
+                if let (Some(Label{hpos, vpos, ..}), Some((b, c))) = (labels.first_mut(), label_run) {
+                    *hpos = if rel == "forward" { f64::max(b.0, c.0) } else { f64::min(b.0, c.0) };
+                    *vpos = b.1;
+                }
+            }
+            extent
+        }
+
+        /// Replace each diagonal segment of `points` by three axis-aligned segments that turn
+        /// halfway between its ends.
+        ///
+        /// So that the forward and reverse arrows of an edge bundle stay parallel, the turns of
+        /// `forward` arrows are moved `shift` pixels further along the segment's direction of
+        /// travel and those of reverse arrows, `shift` pixels back.
+        pub fn orthogonal_points(points: &[(f64, f64)], forward: bool, shift: f64, radius: f64) -> Vec<(f64, f64)> {
+            let mut res = vec![];
+            for window in points.windows(2) {
+                let [p, q] = [window[0], window[1]];
+                res.push(p);
+                let (dx, dy) = (q.0 - p.0, q.1 - p.1);
+                // leave nearly-straight segments be rather than drawing tiny jogs
+                if dx.abs() <= 2. || dy.abs() <= 2. {
+                    continue
+                }
+                let vertical = dy.abs() >= dx.abs();
+                let (lo, hi, cross) = if vertical { (p.1.min(q.1), p.1.max(q.1), dx) } else { (p.0.min(q.0), p.0.max(q.0), dy) };
+                let shift = if forward { shift } else { -shift };
+                let mid = (lo + hi) / 2. + cross.signum() * shift;
+                let turn = if hi - lo > 2. * radius { mid.clamp(lo + radius, hi - radius) } else { (lo + hi) / 2. };
+                if vertical {
+                    res.extend([(p.0, turn), (q.0, turn)]);
+                } else {
+                    res.extend([(turn, p.1), (turn, q.1)]);
+                }
             }
+            res.extend(points.last());
+            res.dedup();
+            // drop the middles of straight runs
+            let mut n = 1;
+            while n + 1 < res.len() {
+                let (a, b, c) = (res[n-1], res[n], res[n+1]);
+                if (a.0 == b.0 && b.0 == c.0) || (a.1 == b.1 && b.1 == c.1) {
+                    res.remove(n);
+                } else {
+                    n += 1;
+                }
+            }
+            res
         }
 
-        let mut l2 = vec![];
-        let mut u2 = vec![];
-        for (l, _, u) in c.iter() {
-            l2.push((*l).into());
-            u2.push((*u).into());
+        /// An SVG path through `points` whose corners are rounded with the given `radius`.
+        pub fn rounded_path(points: &[(f64, f64)], radius: f64) -> String {
+            let dist = |a: (f64, f64), b: (f64, f64)| f64::hypot(b.0 - a.0, b.1 - a.1);
+            let toward = |a: (f64, f64), b: (f64, f64), r: f64| {
+                let d = dist(a, b);
+                (a.0 + r * (b.0 - a.0) / d, a.1 + r * (b.1 - a.1) / d)
+            };
+            let mut path = vec![];
+            for (n, c) in points.iter().copied().enumerate() {
+                if n == 0 || n == points.len() - 1 {
+                    path.push(format!("{} {} {}", if n == 0 { "M" } else { "L" }, c.0, c.1));
+                    continue
+                }
+                let (a, b) = (points[n-1], points[n+1]);
+                let r = radius.min(dist(a, c) / 2.).min(dist(c, b) / 2.);
+                let (start, end) = (toward(c, a, r), toward(c, b, r));
+                path.push(format!("L {} {} Q {} {} {} {}", start.0, start.1, c.0, c.1, end.0, end.1));
+            }
+            path.join(" ")
         }
-        // eprintln!("V[{}]: {v}", v.len());
-        // eprintln!("C[{}]: {c}", &c.len());
 
-        let a2: osqp::CscMatrix = c.clone().into();
+        /// Draw the arrows of `nodes` orthogonally, either all of them or only those whose
+        /// arrows or labels are styled `orthogonal`.
+        pub fn orthogonalize(nodes: &mut [Node], all: bool, shift: f64, radius: f64) {
+            let styled = |classes: &str| classes.split_whitespace().any(|class| class == "orthogonal");
+            for node in nodes.iter_mut() {
+                let Node::Svg{path, rel, classes, labels, control_points, ..} = node else { continue };
+                if !all && !styled(classes) && !labels.iter().any(|label| styled(&label.classes)) {
+                    continue
+                }
+                let Some(points) = path_points(path) else { continue };
+                let points = orthogonal_points(&points, rel != "reverse", shift, radius);
+                *path = rounded_path(&points, radius);
+                *control_points = points;
+            }
+        }
 
-        // eprintln!("P2[{},{}]: {p2:?}", p2.nrows, p2.ncols);
-        // eprintln!("Q2[{}]: {q2:?}", q2.len());
-        // eprintln!("L2[{}]: {l2:?}", l2.len());
-        // eprintln!("U2[{}]: {u2:?}", u2.len());
-        // eprintln!("A2[{},{}]: {a2:?}", a2.nrows, a2.ncols);
-        // eprintln!("NUMPY");
-        // eprintln!("import osqp");
-        // eprintln!("import numpy as np");
-        // eprintln!("import scipy.sparse as sp");
-        // eprintln!("");
-        // eprintln!("inf = np.inf");
-        // eprintln!("np.set_printoptions(precision=1, suppress=True)");
-        // as_scipy("P", &p2);
-        // as_numpy("q", &q2);
-        // as_scipy("A", &a2);
-        // as_numpy("l", &l2);
-        // as_numpy("u", &u2);
-        // eprintln!("m = osqp.OSQP()");
-        // eprintln!("m.setup(P=P, q=q, A=A, l=l, u=u)");
-        // eprintln!("r = m.solve()");
-        // eprintln!("r.info.status");
-        // eprintln!("r.x");
+        /// Like [find_collisions()](super::dom::find_collisions) but only reporting overlaps with boxes
+        /// and labels that are not permitted by `exemptions`.
+        ///
+        /// (Arrows crossing arrows are counted by the layout's crossing number instead.)
+        pub fn find_unexpected_collisions(nodes: &[Node], measurer: &dyn TextMeasurer, exemptions: &Exemptions) -> Vec<Collision> {
+            let rects = nodes
+                .iter()
+                .flat_map(|node| {
+                    let solid = |rect| (node.key(), true, rect);
+                    match node {
+                        Node::Div{..} => node.rects(measurer).into_iter().map(solid).collect::<Vec<_>>(),
+                        Node::Svg{..} => node.label_rects(measurer).into_iter().map(solid)
+                            .chain(node.segment_rects().into_iter().map(|rect| (node.key(), false, rect)))
+                            .collect(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut collisions = vec![];
+            for (i, (ki, bi, ri)) in rects.iter().enumerate() {
+                for (kj, bj, rj) in rects[i+1..].iter() {
+                    let allowed = if *bi && *bj { exemptions.allows(ki, kj) } else { exemptions.allows_crossing(ki, kj) };
+                    if (*bi || *bj) && ki != kj && collides(ri, rj) && !allowed {
+                        collisions.push((ri.clone(), rj.clone()));
+                    }
+                }
+            }
+            collisions
+        }
 
-        let mut prob = osqp::Problem::new(p2, &q2[..], a2, &l2[..], &u2[..], settings)
-            .map_err(|e| Error::from(LayoutError::from(e)))?;
+        #[cfg(test)]
+        mod tests {
+            use pretty_assertions::assert_eq;
 
-        let result = prob.solve();
-        // eprintln!("STATUS {:?}", result);
-        let solution = match result {
-            osqp::Status::Solved(solution) => Ok((solution, OSQPStatusKind::Solved)),
-            osqp::Status::SolvedInaccurate(solution) => Ok((solution, OSQPStatusKind::SolvedInaccurate)),
-            osqp::Status::MaxIterationsReached(solution) => Ok((solution, OSQPStatusKind::MaxIterationsReached)),
-            osqp::Status::TimeLimitReached(solution) => Ok((solution, OSQPStatusKind::TimeLimitReached)),
-            _ => Err(LayoutError::OsqpError{error: "failed to solve problem".into(),}),
-        }?;
-        let x = solution.0.x();
+            use crate::graph_drawing::frontend::{dom::{self, Node}, tests::div, EdgeStyle, RenderOptions};
 
-        // eprintln!("{:?}", x);
-        let mut solutions = v.iter().map(|(_sol, var)| (*var, x[var.index])).collect::<Vec<_>>();
-        solutions.sort_by_key(|(a, _)| *a);
-        // for (var, val) in solutions {
-        //     if !matches!(var.sol, AnySol::F(_)) {
-        //         eprintln!("{} = {}", var.sol, val);
-        //     }
-        // }
+            use super::orthogonal_points;
 
-        Ok((solutions, solution.1))
-    }
+            #[test]
+            pub fn test_route() {
+                // b sits between a and c, so a's arrow to c must go around it
+                let drawing = dom::draw("- a b: x; - b c: y; - a c: z".into()).unwrap();
+                let arrow = drawing.nodes.iter().find(|node| node.key().starts_with("a_c_forward")).unwrap();
+                let Node::Svg{control_points, ..} = arrow else { unreachable!() };
+                let Node::Div{vpos, ..} = div(&drawing, "b") else { unreachable!() };
+                assert!(control_points.len() > 2);
+                assert!(control_points.iter().any(|&(_, y)| y < *vpos));
+                assert!(drawing.collisions.is_empty());
+            }
 
-    fn extract_variable<Idx: Copy + Debug + Ord, Val: Copy + Debug>(
-        v: &Vars<AnySol>,
-        solutions: &Vec<(Var<AnySol>, Val)>,
-        kind: AnySolKind,
-        _name: Cow<str>,
-        extract_index: impl Fn(AnySol) -> Idx,
-    ) -> BTreeMap<Idx, Val> {
-        let mut vs = v.iter()
-            .filter_map(|(sol, var)| {
-                if AnySolKind::from(sol) == kind {
-                    let sol_idx = extract_index(*sol);
-                    Some((sol_idx, solutions[var.index].1))
-                } else {
-                    None
+            #[test]
+            pub fn test_orthogonal_edges() {
+                let model = "- a b: x; - b c: y; - a c: z";
+                let options = RenderOptions{edge_style: EdgeStyle::Orthogonal, ..Default::default()};
+                let drawing = dom::draw_with(model.into(), &Default::default(), &options).unwrap();
+                for node in drawing.nodes.iter() {
+                    let Node::Svg{path, control_points, ..} = node else { continue };
+                    assert!(control_points.windows(2).all(|w| w[0].0 == w[1].0 || (w[0].1 - w[1].1).abs() < 1.));
+                    assert!(!path.contains('Q') || control_points.len() > 2);
                 }
-            })
-            .collect::<Vec<_>>();
-        vs.sort_by_key(|(idx, _)| *idx);
-        // eprintln!("{name}: {vs:?}");
-        let vs = vs.iter().copied().collect::<BTreeMap<Idx, Val>>();
-        vs
-    }
-
-    pub fn solve_optimization_problems(
-        horizontal_problem: &OptimizationProblem<AnySol, OrderedFloat<f64>>,
-        vertical_problem: &OptimizationProblem<AnySol, OrderedFloat<f64>>
-    ) -> Result<GeometrySolution, Error> {
-        let OptimizationProblem{v: vh, ..} = horizontal_problem;
-        let OptimizationProblem{v: vv, ..} = vertical_problem;
+                assert!(drawing.collisions.is_empty());
+
+                // by default, only arrows styled `orthogonal` get rounded corners
+                let drawing = dom::draw(format!("{model} @ orthogonal")).unwrap();
+                let path = |key: &str| drawing.nodes.iter().find_map(|n| match n {
+                    Node::Svg{key: k, path, ..} if k.starts_with(key) => Some(path.clone()),
+                    _ => None,
+                }).unwrap();
+                assert!(path("a_c_forward").contains('Q'));
+                assert!(!path("a_b_forward").contains('Q'));
+
+                // the forward and reverse arrows of a bundle turn at different heights so that they stay parallel
+                let forward = orthogonal_points(&[(0., 0.), (40., 100.)], true, 10., 6.);
+                let reverse = orthogonal_points(&[(20., 0.), (60., 100.)], false, 10., 6.);
+                assert_eq!(forward, vec![(0., 0.), (0., 60.), (40., 60.), (40., 100.)]);
+                assert_eq!(reverse, vec![(20., 0.), (20., 40.), (60., 40.), (60., 100.)]);
+
+                assert_eq!("orthogonal".parse::<EdgeStyle>().unwrap(), EdgeStyle::Orthogonal);
+                assert!("curly".parse::<EdgeStyle>().is_err());
+            }
+        }
+    }
+
+    pub mod placement {
+        //! Label placement
+        //!
+        //! # Summary
+        //!
+        //! [draw()](super::dom::draw) first puts each arrow's label beside the arrow's end:
+        //! left of forward and right of reverse vertical arrows, above forward and below
+        //! reverse horizontal arrows. Where that spot is taken by a box, another arrow, or
+        //! another label, [place_labels()] tries the other side of the arrow and then spots
+        //! further along the arrow on each side, and moves the label to the first free one.
+        //!
+        //! Labels that have no free spot stay put; [place_labels()] reports how much room
+        //! they lack so that the drawing can be laid out again with more room for labels
+        //! (see [RenderOptions::label_padding](super::RenderOptions::label_padding)).
+        use super::dom::{collides, label_gap, Label, Node, Rect};
+        use super::measure::{TextMeasurer, TextRole};
+        use super::route::Exemptions;
+
+        /// The label positions, as `(hpos, vpos)` pairs, to try for the `n`th label of `node`, best first
+        fn candidates(node: &Node, n: usize, measurer: &dyn TextMeasurer) -> Vec<(f64, f64)> {
+            let Node::Svg{dir, rel, labels, control_points, ..} = node else { return vec![] };
+            let (Some(Label{hpos, vpos, ..}), Some(rect)) = (labels.get(n), node.label_rect(n, measurer)) else { return vec![] };
+            let (width, height) = (rect.r - rect.l, rect.b - rect.t);
+            let gap = label_gap(measurer);
+            let step = measurer.line_height(TextRole::Label);
+            let (x0, x1) = control_points.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (x, _)| (lo.min(*x), hi.max(*x)));
+            let (y0, y1) = control_points.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (_, y)| (lo.min(*y), hi.max(*y)));
+            let forward = rel == "forward";
+
+            let mut res = vec![];
+            if dir == "horizontal" {
+                // the other side of the arrow
+                let flipped = if forward { vpos + gap + height } else { vpos - gap - height };
+                for v in [*vpos, flipped] {
+                    res.push((*hpos, v));
+                    for k in 1..=3 {
+                        for h in [hpos - k as f64 * width / 2., hpos + k as f64 * width / 2.] {
+                            let (l, r) = if forward { (h - width, h) } else { (h, h + width) };
+                            if l >= x0 && r <= x1 {
+                                res.push((h, v));
+                            }
+                        }
+                    }
+                }
+            } else {
+                // the other side of the arrow
+                let flipped = if forward { hpos + width + 2. * gap } else { hpos - width - 2. * gap };
+                for h in [*hpos, flipped] {
+                    res.push((h, *vpos));
+                    for k in 1..=3 {
+                        for v in [vpos - k as f64 * step, vpos + k as f64 * step] {
+                            if v >= y0 && v + height <= y1 {
+                                res.push((h, v));
+                            }
+                        }
+                    }
+                }
+            }
+            res
+        }
 
-        // eprintln!("SOLVE HORIZONTAL");
-        let (solutions_h, status_h) = solve_problem(&horizontal_problem)?;
+        /// Move labels of `nodes` that collide with anything they are not expected to overlap
+        /// to free spots nearby.
+        ///
+        /// Returns how much wider, at most, the room beside a label that found no free spot would
+        /// have to be for it to fit, or zero if every label found a spot.
+        pub fn place_labels(nodes: &mut [Node], measurer: &dyn TextMeasurer, exemptions: &Exemptions) -> f64 {
+            let fixed = nodes
+                .iter()
+                .flat_map(|node| match node {
+                    Node::Div{..} => node.rects(measurer),
+                    Node::Svg{..} => node.segment_rects(),
+                }.into_iter().map(move |rect| (node.key().clone(), rect)))
+                .collect::<Vec<_>>();
+            // the rectangles of every node's labels, keyed by the node's index and the label's
+            let mut labels = nodes
+                .iter()
+                .enumerate()
+                .flat_map(|(i, node)| {
+                    let count = match node { Node::Svg{labels, ..} => labels.len(), Node::Div{..} => 0 };
+                    (0..count).filter_map(move |n| node.label_rect(n, measurer).map(|rect| ((i, n), rect)))
+                })
+                .collect::<Vec<_>>();
 
-        // eprintln!("SOLVE VERTICAL");
-        let (solutions_v, status_v) = solve_problem(&vertical_problem)?;
+            let mut order = (0..labels.len()).collect::<Vec<_>>();
+            order.sort_by(|a, b| {
+                let ((i, m), (j, n)) = (labels[*a].0, labels[*b].0);
+                nodes[i].key().cmp(nodes[j].key()).then(m.cmp(&n))
+            });
 
-        let ls = extract_variable(&vh, &solutions_h, AnySolKind::L, "ls".into(), |s| {
-            if let AnySol::L(l) = s { l } else { panic!() }
-        });
+            let mut wanted = 0f64;
+            for a in order {
+                let ((i, n), rect) = labels[a].clone();
+                let key = nodes[i].key().clone();
+                let obstacles = |rect: &Rect, labels: &[((usize, usize), Rect)]| fixed
+                    .iter()
+                    .filter(|(k, r)| *k != key && !exemptions.allows(&key, k) && collides(rect, r))
+                    .map(|(_, r)| r.clone())
+                    .chain(labels
+                        .iter()
+                        .enumerate()
+                        .filter(|(b, (_, r))| *b != a && collides(rect, r))
+                        .map(|(_, (_, r))| r.clone()))
+                    .collect::<Vec<_>>();
 
-        let rs = extract_variable(&vh, &solutions_h, AnySolKind::R, "rs".into(), |s| {
-            if let AnySol::R(r) = s { r } else { panic!() }
-        });
+                let blocking = obstacles(&rect, &labels);
+                if blocking.is_empty() {
+                    continue
+                }
 
-        let ss = extract_variable(&vh, &solutions_h, AnySolKind::S, "ss".into(), |s| {
-            if let AnySol::S(s) = s { s } else { panic!() }
-        });
+                let mut placed = false;
+                for (hpos, vpos) in candidates(&nodes[i], n, measurer).into_iter().skip(1) {
+                    let Node::Svg{labels: node_labels, ..} = &mut nodes[i] else { break };
+                    let Some(label) = node_labels.get_mut(n) else { break };
+                    let (hpos0, vpos0) = (label.hpos, label.vpos);
+                    (label.hpos, label.vpos) = (hpos, vpos);
+                    if let Some(rect) = nodes[i].label_rect(n, measurer).filter(|rect| obstacles(rect, &labels).is_empty()) {
+                        labels[a].1 = rect;
+                        placed = true;
+                        break
+                    }
+                    let Node::Svg{labels: node_labels, ..} = &mut nodes[i] else { break };
+                    let Some(label) = node_labels.get_mut(n) else { break };
+                    (label.hpos, label.vpos) = (hpos0, vpos0);
+                }
 
-        let ts = extract_variable(&vv, &solutions_v, AnySolKind::T, "ts".into(), |s| {
-            if let AnySol::T(t) = s { t } else { panic!() }
-        });
+                if !placed {
+                    let overlap = blocking
+                        .iter()
+                        .map(|r| f64::min(rect.r, r.r) - f64::max(rect.l, r.l))
+                        .fold(0., f64::max);
+                    wanted = wanted.max(overlap + label_gap(measurer));
+                }
+            }
+            wanted
+        }
 
-        let bs = extract_variable(&vv, &solutions_v, AnySolKind::B, "bs".into(), |s| {
-            if let AnySol::B(b) = s { b } else { panic!() }
-        });
+        #[cfg(test)]
+        mod tests {
+            use pretty_assertions::assert_eq;
 
-        let res = GeometrySolution{ls, rs, ss, ts, bs, status_h, status_v};
-        Ok(res)
-    }
+            use crate::graph_drawing::{geometry::{HopSize, NodeSize}, index::VarRank};
+            use crate::graph_drawing::frontend::measure::DefaultMeasurer;
 
-}
+            use super::*;
 
-pub mod frontend {
-    use std::{borrow::Cow, collections::HashMap, cmp::{max}};
+            #[test]
+            pub fn test_label_placement() {
+                let measurer = DefaultMeasurer::default();
+                let boxed = |key: &str, hpos, vpos, width, height| Node::Div{key: key.into(), label: key.into(), hpos, vpos, width, height, z_index: 0, classes: String::new(), shape: Default::default(), loc: VarRank(0), estimated_size: NodeSize{width, left: 0., right: 0., height, top: 0., bottom: 0.}};
+                let arrow = Node::Svg{
+                    key: "a_b".into(), path: "M 100 0 L 100 100".into(), z_index: 0, dir: "vertical".into(), rel: "forward".into(),
+                    labels: vec![Label{text: "go".into(), classes: String::new(), hpos: 100., width: 20., vpos: 40.}],
+                    hops: vec![], classes: String::new(), estimated_size: HopSize{width: 0., left: 0., right: 0., height: 0., top: 0., bottom: 0.},
+                    control_points: vec![(100., 0.), (100., 100.)],
+                };
 
-    use logos::Logos;
-    use ordered_float::OrderedFloat;
-    use self_cell::self_cell;
+                // the label's usual spot, left of its arrow, is taken, so it moves
+                let mut nodes = vec![boxed("x", 40., 30., 50., 40.), arrow.clone()];
+                assert!(collides(&nodes[1].label_rect(0, &measurer).unwrap(), &nodes[0].rects(&measurer)[0]));
+                assert_eq!(place_labels(&mut nodes, &measurer, &Exemptions::new()), 0.);
+                assert!(!collides(&nodes[1].label_rect(0, &measurer).unwrap(), &nodes[0].rects(&measurer)[0]));
 
-    use crate::{graph_drawing::{layout::{minimize_edge_crossing, calculate_vcg, rank, calculate_locs_and_hops, ObjNode}, eval::{eval}, geometry::{calculate_sols, position_sols, HopSize}}, parser::{Item, Parser, Token}};
+                // with no free spot anywhere, the label stays put and asks for more room
+                let mut nodes = vec![boxed("x", 0., 0., 300., 100.), arrow.clone()];
+                assert!(place_labels(&mut nodes, &measurer, &Exemptions::new()) > 0.);
+                assert_eq!(nodes[1], arrow);
 
-    use self::styling::Styling;
+                // unless the label is expected to overlap the box
+                let mut exemptions = Exemptions::new();
+                exemptions.allow("a_b", "x");
+                assert_eq!(place_labels(&mut nodes, &measurer, &exemptions), 0.);
+            }
+        }
+    }
 
-    use super::{layout::{Vcg, LayoutProblem, Graphic, Len, Obj, RankedPaths, LayoutSolution, ObjContainer}, geometry::{GeometryProblem, GeometrySolution, NodeSize, OptimizationProblem, AnySol, solve_optimization_problems}, error::{Error, Kind, OrErrExt}, eval::{Val}, index::OriginalHorizontalRank};
+    pub mod dom {
+        use std::{borrow::Cow, collections::{BTreeMap, BTreeSet}, fmt::Display};
 
-    use log::{names};
+        use petgraph::visit::EdgeRef;
 
-    pub fn estimate_widths<I: Graphic>(
-        vcg: &Vcg<I, I>,
-        layout_problem: &LayoutProblem<I>,
-        geometry_problem: &mut GeometryProblem<I>
-    ) -> Result<(), Error> where
-        I: Graphic + Len,
-    {
-        let char_width = geometry_problem.char_width.unwrap();
-        let line_height = geometry_problem.line_height.unwrap();
-        let arrow_width = 0.0;
+        use crate::{graph_drawing::{error::{OrErrExt, Kind, Error}, layout::{Obj, ObjContainer, ObjNode, ObjHop, ObjGap}, index::{VarRank}, geometry::{NodeSize, HopSize, OSQPStatusKind}, frontend::log::{Names}, eval::{Dir, Position, Rel}, lint, diff}, names};
 
-        let vert = &vcg.vert;
-        let vert_node_labels = &vcg.vert_node_labels;
-        let vert_edge_labels = &vcg.vert_edge_labels;
-        let horz_edge_labels = &vcg.horz_edge_labels;
-        let size_by_loc = &mut geometry_problem.size_by_loc;
-        let size_by_hop = &mut geometry_problem.size_by_hop;
-        let hops_by_edge = &layout_problem.hops_by_edge;
-        let loc_to_node = &layout_problem.loc_to_node;
+        use super::log::{self, Log};
+        use super::measure::{DefaultMeasurer, TextMeasurer, TextRole};
+        use super::{note_size, EdgeStyle, Orientation, RenderOptions};
+        use super::route::{route, orthogonalize, find_unexpected_collisions, Exemptions};
+        use super::placement::place_labels;
+        use super::styling::{ArrowStyle, Properties, Shape};
+        use super::theme::Theme;
+        use super::legend::{Legend, Swatch};
 
-        // eprintln!("LOC_TO_NODE WIDTHS: {loc_to_node:#?}");
 
-        for (loc, node) in loc_to_node.iter() {
-            let (ovr, ohr) = loc;
-            match node {
-                Obj::Node(ObjNode{vl}) | Obj::Container(ObjContainer{vl}) => {
-                    let label = vert_node_labels
-                        .get(vl)
-                        .or_err(Kind::KeyNotFoundError{key: vl.to_string()})?
-                        .clone();
-                    // if !label.is_screaming_snake_case() {
-                    //     label = label.to_title_case();
-                    // }
-                    let mut left = 0;
-                    let mut right = 0;
-                    for (hc, lvl) in horz_edge_labels.iter() {
-                        if hc.0 == *vl {
-                            right = std::cmp::max(right, lvl.forward.as_ref().and_then(|fs| fs.iter().map(|f| f.len()).max()).unwrap_or(0));
-                        }
-                        if hc.1 == *vl {
-                            left = std::cmp::max(left, lvl.reverse.as_ref().and_then(|rs| rs.iter().map(|r| r.len()).max()).unwrap_or(0));
-                        }
-                    }
-                    let size = NodeSize{
-                        width: char_width * label.len() as f64,
-                        left: char_width * left as f64,
-                        right: char_width * right as f64,
-                        height: 26.,
-                    };
-                    size_by_loc.insert((*ovr, *ohr), size);
-                },
-                _ => {},
+        #[derive(Clone, Debug, PartialEq, PartialOrd)]
+        pub struct Label {
+            pub text: String,
+            pub classes: String,
+            pub hpos: f64,
+            pub width: f64,
+            pub vpos: f64,
+        }
+
+        #[derive(Clone, Debug, PartialEq, PartialOrd)]
+        pub enum Node {
+            Div { key: String, label: String, hpos: f64, vpos: f64, width: f64, height: f64, z_index: usize, classes: String, shape: Shape, loc: VarRank, estimated_size: NodeSize },
+            Svg { key: String, path: String, z_index: usize, dir: String, rel: String, labels: Vec<Label>, hops: Vec<VarRank>, classes: String, estimated_size: HopSize, control_points: Vec<(f64, f64)> },
+        }
+
+        impl Node {
+            pub fn key(&self) -> &String {
+                match self {
+                    Node::Div { key, .. } => key,
+                    Node::Svg { key, .. } => key,
+                }
             }
         }
 
-        for ((vl, wl), hops) in hops_by_edge.iter() {
-            let mut action_width = 10.0;
-            let mut percept_width = 10.0;
-            let ex = vert.find_edge(vcg.vert_vxmap[vl], vcg.vert_vxmap[wl]).unwrap();
-            let ew = vert.edge_weight(ex).unwrap();
-            if *ew != "vertical" { continue };
+        pub type Collision = (Rect, Rect);
 
-            let labels = vert_edge_labels.get(&(vl.clone(), wl.clone()));
-            let mut height = 10.;
-            if let Some(labels) = labels {
-                let forward_label_width = labels.forward
-                    .as_ref()
-                    .and_then(|forward| forward
-                        .iter()
-                        .map(|label| label.len())
-                        .max()
-                    );
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Rect {
+            pub id: String,
+            pub l: f64,
+            pub r: f64,
+            pub t: f64,
+            pub b: f64 }
 
-                let reverse_label_width = labels.reverse
-                    .as_ref()
-                    .and_then(|reverse| reverse
-                        .iter()
-                        .map(|label| label.len())
-                        .max()
-                    );
+        impl Display for Rect {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "Rect {{ l: {:7.2}, r: {:7.2}, t: {:7.2}, b: {:7.2}, id: {:?} }}", self.l, self.r, self.t, self.b, self.id)
+            }
+        }
 
-                let level_height = max(
-                    labels.forward
-                        .as_ref()
-                        .map(|f| f.len())
-                        .unwrap_or(0),
-                    labels.reverse
-                        .as_ref()
-                        .map(|r| r.len())
-                        .unwrap_or(0)
-                );
+        /// The rectangles of [Node::rects()] as sized by [DefaultMeasurer]; drawings laid out with
+        /// another [TextMeasurer] should call [Node::rects()] with it instead.
+        impl From<&Node> for Vec<Rect> {
+            fn from(node: &Node) -> Self {
+                node.rects(&DefaultMeasurer::default())
+            }
+        }
 
-                action_width = forward_label_width.map(|label_width| arrow_width + char_width * label_width as f64).unwrap_or(20.);
-                percept_width = reverse_label_width.map(|label_width| arrow_width + char_width * label_width as f64).unwrap_or(20.);
+        /// Labels are offset from their arrows by 1.5ex
+        pub fn label_gap(measurer: &dyn TextMeasurer) -> f64 {
+            1.5 * measurer.width("x", TextRole::Label)
+        }
 
-                height = level_height as f64 * line_height;
+        /// Where [draw()] expects text to be drawn, for renderers that place text themselves,
+        /// like [svg](super::svg) and [pdf](super::pdf)
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Typography {
+            /// Font size of box text
+            pub box_font_size: f64,
+            /// Font size of arrow labels and notes
+            pub label_font_size: f64,
+            /// Distance between the baselines of box text
+            pub box_line_height: f64,
+            /// Distance between the baselines of arrow labels and notes
+            pub label_line_height: f64,
+            /// Gap between arrows and their labels; see [label_gap()]
+            pub label_gap: f64,
+        }
+
+        impl Typography {
+            pub fn new(options: &RenderOptions, measurer: &dyn TextMeasurer) -> Self {
+                Self {
+                    box_font_size: options.box_font_size,
+                    label_font_size: options.label_font_size,
+                    box_line_height: measurer.line_height(TextRole::Box),
+                    label_line_height: measurer.line_height(TextRole::Label),
+                    label_gap: label_gap(measurer),
+                }
             }
 
-            let mut hops = hops.clone();
-            let (lvl, (mhr, nhr)) = {
-                #[allow(clippy::unwrap_used)] // an edge with no hops really should panic
-                let kv = hops.last_key_value().unwrap();
-                (*kv.0, *kv.1)
-            };
-            hops.insert(lvl+1, (nhr, OriginalHorizontalRank(std::usize::MAX - mhr.0)));
+            fn font_size(&self, role: TextRole) -> f64 {
+                match role {
+                    TextRole::Box => self.box_font_size,
+                    TextRole::Label => self.label_font_size,
+                }
+            }
 
-            for (lvl, (mhr, _nhr)) in hops.iter() {
-                size_by_hop.insert((*lvl, *mhr, vl.clone(), wl.clone()), HopSize{
-                    width: 0.,
-                    left: action_width,
-                    right: percept_width,
-                    height,
-                    top: 0.,
-                    bottom: 0.,
-                });
-                if size_by_loc.get(&(*lvl, *mhr)).is_none() {
-                    size_by_loc.insert((*lvl, *mhr), NodeSize{
-                        width: action_width + percept_width,
-                        left: 0.,
-                        right: 0.,
-                        height: 0.,
-                    });
+            fn line_height(&self, role: TextRole) -> f64 {
+                match role {
+                    TextRole::Box => self.box_line_height,
+                    TextRole::Label => self.label_line_height,
                 }
             }
-        }
 
-        // eprintln!("SIZE_BY_LOC: {size_by_loc:#?}");
-        // eprintln!("SIZE_BY_HOP: {size_by_hop:#?}");
+            /// How far below the top of a box's text is the baseline of line `lineno`?
+            pub fn box_baseline(&self, role: TextRole, lineno: usize) -> f64 {
+                self.font_size(role) + self.line_height(role) * lineno as f64
+            }
+
+            /// Where is line `lineno` of the label of an arrow running `dir` and `rel` anchored?
+            ///
+            /// Lines are anchored on their baselines, at their ends for forward arrows and at
+            /// their starts for reverse arrows, so that they fill [Node::label_rect()].
+            pub fn label_anchor(&self, label: &Label, dir: &str, rel: &str, lineno: usize) -> (f64, f64) {
+                let Label{text, hpos, vpos, ..} = label;
+                let lines = text.lines().count().max(1) as f64;
+                let baseline = self.box_baseline(TextRole::Label, lineno);
+                let gap = self.label_gap;
+                match (dir, rel) {
+                    ("horizontal", "forward") => (*hpos, vpos - gap / 2. - lines * self.label_line_height + baseline),
+                    ("horizontal", _) => (*hpos, vpos + gap / 2. + baseline),
+                    (_, "forward") => (hpos - gap, vpos + 1. + baseline),
+                    (_, _) => (hpos + gap, vpos + 1. + baseline),
+                }
+            }
 
-        Ok(())
-    }
+            /// CSS giving drawn text these sizes
+            pub fn css(&self) -> String {
+                let Typography{box_font_size, label_font_size, box_line_height, label_line_height, ..} = self;
+                format!(r#"div.box {{ font-size: {box_font_size}px; line-height: {box_line_height}px; }}
+g.box text {{ font-size: {box_font_size}px; }}
+div.label, .box.note {{ font-size: {label_font_size}px; line-height: {label_line_height}px; }}
+g.label, g.box.note text {{ font-size: {label_font_size}px; }}
+"#)
+            }
+        }
 
-    pub mod styling {
-        use std::collections::{HashMap, BTreeSet};
+        impl Node {
+            /// The rectangles that this node occupies when its labels are measured by `measurer`
+            pub fn rects(&self, measurer: &dyn TextMeasurer) -> Vec<Rect> {
+                match self {
+                    Node::Div { key, hpos, vpos, width, height, .. } => {
+                        vec![Rect { id: key.clone(), l: *hpos, r: hpos + width, t: *vpos, b: vpos + height }]
+                    },
+                    Node::Svg { .. } => {
+                        self.label_rects(measurer).into_iter().chain(self.segment_rects()).collect()
+                    },
+                }
+            }
 
-        use crate::graph_drawing::{eval::{visit::{*}, Val, Body, Dir, Rel, Level}, error::Error, layout::Graphic};
+            /// The rectangles occupied by this node's labels, if any.
+            pub fn label_rects(&self, measurer: &dyn TextMeasurer) -> Vec<Rect> {
+                let Node::Svg { labels, .. } = self else { return vec![] };
+                (0..labels.len()).filter_map(|n| self.label_rect(n, measurer)).collect()
+            }
+
+            /// The rectangle occupied by this node's `n`th label, if any.
+            ///
+            /// Vertical arrows' forward labels sit left of their arrows and reverse labels, right;
+            /// horizontal arrows' forward labels sit above their arrows and reverse labels, below.
+            pub fn label_rect(&self, n: usize, measurer: &dyn TextMeasurer) -> Option<Rect> {
+                let Node::Svg { key, dir, rel, labels, .. } = self else { return None };
+                let Label{text, hpos, vpos, ..} = labels.get(n)?;
+                let key = if n == 0 { key.clone() } else { format!("{key}_{n}") };
+                let label_gap = label_gap(measurer);
+                let width = text.lines().map(|line| measurer.width(line, TextRole::Label)).fold(0., f64::max);
+                let height = text.lines().count().max(1) as f64 * measurer.line_height(TextRole::Label);
+                let (hpos, vpos) = (*hpos, *vpos);
+                let (id, l, r, t, b) = match (dir.as_str(), rel.as_str()) {
+                    ("horizontal", "forward") => (format!("{key}_fwd"), hpos - width, hpos, vpos - label_gap / 2. - height, vpos - label_gap / 2.),
+                    ("horizontal", _) => (format!("{key}_rev"), hpos, hpos + width, vpos + label_gap / 2., vpos + label_gap / 2. + height),
+                    (_, "forward") => (format!("{key}_fwd"), hpos - width - label_gap, hpos - label_gap, vpos + 1., vpos + height),
+                    (_, _) => (format!("{key}_rev"), hpos + label_gap, hpos + width + label_gap, vpos + 1., vpos + height),
+                };
+                Some(Rect { id, l, r, t, b })
+            }
 
-        #[derive(Clone, Debug, Default)]
-        pub struct Styling<V: Graphic> {
-            pub style_by_name: HashMap<V, BTreeSet<V>>,
-            pub style_by_arrow: HashMap<(V, V, Dir), BTreeSet<V>>,
-            pub style_by_label: HashMap<(V, V, Dir, V), BTreeSet<V>>,
-            pub style_by_label_star: HashMap<(V, V, Dir), BTreeSet<V>>,
+            /// The rectangles occupied by the segments of this node's arrow, if any.
+            pub fn segment_rects(&self) -> Vec<Rect> {
+                let Node::Svg { key, control_points, .. } = self else { return vec![] };
+                control_points
+                    .windows(2)
+                    .enumerate()
+                    .map(|(n, window)| segment_rect(format!("{key},wnd{n}"), window[0], window[1]))
+                    .collect()
+            }
         }
 
-        impl<V: Graphic> Visit<V> for Styling<V> {
-            fn visit_process(&mut self, name: &Option<V>, label: &Option<V>, body: &Option<Body<V>>, style: &Option<Vec<V>>) {
-                eprintln!("STYLE PROCESS: {name:?} {label:?} {body:?} {style:?}");
-                visit_process(self, name, label, body, style);
-                // TODO: correctly construct nested keys.
-                let key = if let Some(name) = name {
-                    name
-                } else if let Some(label) = label {
-                    label
-                } else {
-                    return;
-                };
-                let Some(style) = style else { return };
-                for style in style {
-                    self.style_by_name.entry(key.clone()).or_default().insert(style.clone());
-                }
+        /// The rectangle occupied by the line segment from `cur` to `nxt`, shrunk
+        /// by a pixel so that segments that merely touch do not collide.
+        pub fn segment_rect(id: String, cur: (f64, f64), nxt: (f64, f64)) -> Rect {
+            Rect {
+                id,
+                l: (f64::min(cur.0, nxt.0) + 1.).trunc(),
+                r: (f64::max(cur.0, nxt.0)).trunc(),
+                t: (f64::min(cur.1, nxt.1) + 1.).trunc(),
+                b: (f64::max(cur.1, nxt.1)).trunc(),
             }
+        }
 
-            fn visit_chain(&mut self, name: &Option<V>, rel: &Rel, path: &Vec<Val<V>>, labels: &Vec<Level<V>>, style: &Option<Vec<V>>) {
-                eprintln!("STYLE CHAIN: {name:?} {rel:?} {path:?} {labels:?} {style:?}");
-                visit_chain(self, name, rel, path, labels, style);
-                let Some(style) = style else { return };
+        pub fn collides(a: &Rect, b: &Rect) -> bool {
+            a.r > b.l &&
+            a.l < b.r &&
+            a.b > b.t &&
+            a.t < b.b
+        }
 
-                for (n, pair) in path.windows(2).enumerate() {
-                    let Some(p0) = pair[0].key().cloned() else {continue};
-                    let Some(p1) = pair[1].key().cloned() else {continue};
-                    let Some(level) = labels.get(n) else {
-                        for s in style {
-                            self.style_by_name.entry(p0.clone()).or_default().insert(s.clone());
-                            self.style_by_name.entry(p1.clone()).or_default().insert(s.clone());
-                            self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                            self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                            self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                            self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                        }
-                        return;
-                    };
-                    if let Some(forward) = &level.forward {
-                        for label in forward {
-                            if *label == "*" {
-                                if level.reverse.is_none() {
-                                    for s in style {
-                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                                        self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                                        self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                                    }
-                                } else {
-                                    for s in style {
-                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                                        self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                                    }
-                                }
-                            } else if *label == ">" {
-                                if level.reverse.is_none() && forward.len() == 1 {
-                                    for s in style {
-                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                                    }
-                                } else {
-                                    for s in style {
-                                        self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Forward)).or_default().insert(s.clone());
-                                    }
-                                }
-                            } else {
-                                for s in style {
-                                    self.style_by_label.entry((p0.clone(), p1.clone(), Dir::Forward, label.clone())).or_default().insert(s.clone());
-                                }
-                            }
-                        }
-                    }
-                    if let Some(reverse) = &level.reverse {
-                        for label in reverse {
-                            if *label == "*" {
-                                for s in style {
-                                    self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                                    self.style_by_label_star.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                                }
-                            } else if *label == ">" {
-                                for s in style {
-                                    self.style_by_arrow.entry((p0.clone(), p1.clone(), Dir::Reverse)).or_default().insert(s.clone());
-                                }
-                            } else {
-                                for s in style {
-                                    self.style_by_label.entry((p0.clone(), p1.clone(), Dir::Reverse, label.clone())).or_default().insert(s.clone());
-                                }
-                            }
-                        }
+        pub fn find_collisions(nodes: &Vec<Node>, measurer: &dyn TextMeasurer) -> Vec<Collision> {
+            let rects = nodes
+                .iter()
+                .flat_map(|node| node.rects(measurer))
+                .collect::<Vec<_>>();
+            let mut collisions = vec![];
+            for i in 0..rects.len() {
+                for j in i+1..rects.len() {
+                    let ri = &rects[i];
+                    let rj = &rects[j];
+                    if collides(ri, rj) {
+                        collisions.push((ri.clone(), rj.clone()))
                     }
                 }
             }
+            return collisions;
         }
 
-        impl<V: Graphic> Styling<V> {
-            pub fn new(val: &Val<V>) -> Result<Self, Error> {
-                let mut s = Self {
-                    style_by_name: Default::default(),
-                    style_by_arrow: Default::default(),
-                    style_by_label: Default::default(),
-                    style_by_label_star: Default::default(),
-                };
-                s.visit_val(val);
-                eprintln!("STYLING: {s:#?}");
-                Ok(s)
+
+        #[derive(Clone, Debug)]
+        pub struct Drawing {
+            pub crossing_number: Option<usize>,
+            pub status_v: OSQPStatusKind,
+            pub status_h: OSQPStatusKind,
+            pub viewbox_width: f64,
+            pub viewbox_height: f64,
+            pub nodes: Vec<Node>,
+            pub collisions: Vec<Collision>,
+            pub logs: Vec<log::Record>,
+            pub warnings: Vec<lint::Warning>,
+            pub styles: BTreeMap<String, Properties>,
+            pub theme: Theme,
+            pub legend: Option<Legend>,
+            pub typography: Typography,
+        }
+
+        impl Default for Drawing {
+            fn default() -> Self {
+                let options = RenderOptions::default();
+                Self {
+                    crossing_number: Default::default(),
+                    status_v: Default::default(),
+                    status_h: Default::default(),
+                    viewbox_width: options.viewbox_width,
+                    viewbox_height: options.viewbox_height,
+                    nodes: Default::default(),
+                    collisions: Default::default(),
+                    logs: vec![],
+                    warnings: vec![],
+                    styles: Default::default(),
+                    typography: Typography::new(&options, &options.measurer()),
+                    theme: options.theme,
+                    legend: None,
+                }
             }
         }
-    }
 
+        /// Apply `f` to each coordinate pair of the SVG path data `path`.
+        fn transform_path(path: &str, f: impl Fn((f64, f64)) -> (f64, f64)) -> String {
+            let mut out = vec![];
+            let mut x = None;
+            for token in path.split_whitespace() {
+                match (token.parse::<f64>(), x) {
+                    (Ok(y), Some(x0)) => {
+                        let (x1, y1) = f((x0, y));
+                        out.push(format!("{x1} {y1}"));
+                        x = None;
+                    },
+                    (Ok(x0), None) => x = Some(x0),
+                    (Err(_), _) => out.push(token.to_string()),
+                }
+            }
+            out.join(" ")
+        }
 
-    #[derive(Default)]
-    pub struct Depiction<'s> {
-        pub items: Vec<Item<'s>>,
-        pub val: Val<Cow<'s, str>>,
-        pub styling: Styling<Cow<'s, str>>,
-        pub vcg: Vcg<Cow<'s, str>, Cow<'s, str>>,
-        pub paths_by_rank: RankedPaths<Cow<'s, str>>,
-        pub layout_problem: LayoutProblem<Cow<'s, str>>,
-        pub layout_solution: LayoutSolution,
-        pub geometry_problem: GeometryProblem<Cow<'s, str>>,
-        pub horizontal_problem: OptimizationProblem<AnySol, OrderedFloat<f64>>,
-        pub vertical_problem: OptimizationProblem<AnySol, OrderedFloat<f64>>,
-        pub geometry_solution: GeometrySolution,
-    }
+        /// The key of the `n`th note attached to `owner`
+        pub fn note_key(owner: &str, n: usize) -> String {
+            format!("{owner}_note_{n}")
+        }
 
-    self_cell!{
-        pub struct RenderCell<'s> {
-            owner: Cow<'s, str>,
+        /// Move `nodes` right by `dx` and down by `dy`.
+        fn translate(nodes: &mut [Node], (dx, dy): (f64, f64)) {
+            let point = |(x, y)| (x + dx, y + dy);
+            for node in nodes.iter_mut() {
+                match node {
+                    Node::Div{hpos, vpos, ..} => {
+                        (*hpos, *vpos) = point((*hpos, *vpos));
+                    },
+                    Node::Svg{path, labels, control_points, ..} => {
+                        *path = transform_path(path, point);
+                        for p in control_points.iter_mut() {
+                            *p = point(*p);
+                        }
+                        for Label{hpos, vpos, ..} in labels.iter_mut() {
+                            (*hpos, *vpos) = point((*hpos, *vpos));
+                        }
+                    },
+                }
+            }
+        }
 
-            #[covariant]
-            dependent: Depiction,
+        /// Reflect or transpose `nodes`, positioned in the top-to-bottom rank frame
+        /// whose vertical extent is `y0..y1`, into `orientation`.
+        fn orient(nodes: &mut [Node], orientation: Orientation, (y0, y1): (f64, f64), measurer: &dyn TextMeasurer) {
+            if orientation == Orientation::TopToBottom {
+                return
+            }
+            let line_height = measurer.line_height(TextRole::Label);
+            let transposed = orientation.is_transposed();
+            let point = |p| orientation.transform(p, (y0, y1));
+            let rect = |l: f64, t: f64, w: f64, h: f64| {
+                let (a, b) = (point((l, t)), point((l + w, t + h)));
+                (f64::min(a.0, b.0), f64::min(a.1, b.1), (a.0 - b.0).abs(), (a.1 - b.1).abs())
+            };
+            for node in nodes.iter_mut() {
+                match node {
+                    Node::Div{hpos, vpos, width, height, ..} => {
+                        (*hpos, *vpos, *width, *height) = rect(*hpos, *vpos, *width, *height);
+                    },
+                    Node::Svg{path, dir, rel, labels, control_points, ..} => {
+                        *path = transform_path(path, point);
+                        for p in control_points.iter_mut() {
+                            *p = point(*p);
+                        }
+                        for Label{text, hpos, vpos, ..} in labels.iter_mut() {
+                            let lines = text.lines().count().max(1) as f64;
+                            if dir == "vertical" {
+                                // labels of vertical arrows are blocks beside their arrows:
+                                // keep the block's extent along the arrow and re-anchor it
+                                let extent = if transposed { measurer.width(text, TextRole::Label) } else { lines * line_height };
+                                let (l, t, w, _) = rect(*hpos, *vpos, 0., extent);
+                                *hpos = if rel == "forward" { l + w } else { l };
+                                *vpos = t;
+                            } else {
+                                (*hpos, *vpos) = point((*hpos, *vpos));
+                                if transposed {
+                                    *vpos -= lines * line_height;
+                                }
+                            }
+                        }
+                        if transposed {
+                            *dir = if dir == "vertical" { "horizontal" } else { "vertical" }.into();
+                        }
+                    },
+                }
+            }
         }
-    }
 
-    pub fn render<'s, 't>(data: Cow<'s, str>, logs: &'t mut log::Logger) -> Result<RenderCell<'s>, Error> {
-        RenderCell::try_new(data, |data| {
-            let mut p = Parser::new();
-            let mut lex = Token::lexer(data);
-            while let Some(tk) = lex.next() {
-                p.parse(tk)
-                    .map_err(|_| {
-                        Kind::PomeloError{span: lex.span(), text: lex.slice().into()}
-                    })?
+        /// Append `class`, if any, to the space-separated `classes`.
+        fn add_class(mut classes: String, class: Option<&str>) -> String {
+            if let Some(class) = class {
+                if !classes.is_empty() {
+                    classes.push(' ');
+                }
+                classes.push_str(class);
+            }
+            classes
+        }
+
+        /// Fill the frame of `legend` among `nodes`, placed and oriented by [draw()], with its entries, one per row.
+        fn draw_legend(nodes: &mut Vec<Node>, legend: &Legend, exemptions: &mut Exemptions, measurer: &dyn TextMeasurer, options: &RenderOptions) {
+            let Some(Node::Div{hpos, vpos, loc, ..}) = nodes.iter().find(|node| node.key() == "legend") else { return };
+            let (left, loc) = (hpos + options.container_padding, *loc);
+            let mut top = vpos + options.box_height;
+            for entry in legend.entries.iter() {
+                let (width, height) = Legend::entry_size(entry, measurer, options);
+                let key = format!("legend_{}", entry.style);
+                exemptions.allow(key.clone(), "legend");
+                nodes.push(match entry.swatch {
+                    Swatch::Box => {
+                        let classes = format!("legend_entry {}", entry.style);
+                        let shape = entry.properties.shape().unwrap_or_default();
+                        let estimated_size = NodeSize{width, left: 0., right: 0., height, top: 0., bottom: 0.};
+                        Node::Div{key, label: entry.text.clone(), hpos: left, vpos: top, width, height, z_index: 1, classes, shape, loc, estimated_size}
+                    },
+                    Swatch::Arrow => {
+                        // the label sits above its arrow, ending where the arrow does
+                        let y = top + height - label_gap(measurer) / 2.;
+                        let (x0, x1) = (left, left + width);
+                        let label = Label{text: entry.text.clone(), classes: entry.style.clone(), hpos: x1, width, vpos: y};
+                        let classes = format!("arrow horizontal forward legend_entry {}", entry.style);
+                        let estimated_size = HopSize{width, left: 0., right: 0., height, top: 0., bottom: 0.};
+                        Node::Svg{key, path: format!("M {x0} {y} L {x1} {y}"), z_index: 1, dir: "horizontal".into(), rel: "forward".into(), labels: vec![label], hops: vec![], classes, estimated_size, control_points: vec![(x0, y), (x1, y)]}
+                    },
+                });
+                top += height + options.container_padding;
             }
+        }
 
-            let items = p.end_of_input()
-                .map_err(|_| {
-                    Kind::PomeloError{span: lex.span(), text: lex.slice().into()}
-                })?;
+        pub fn draw(data: String) -> Result<Drawing, Error> {
+            draw_with(data, &lint::Config::default(), &RenderOptions::default())
+        }
 
-            eprintln!("PARSE {items:#?}");
+        /// Like [draw()] but applying only the lint rules enabled in `lints` and laying out
+        /// (and sizing text) according to `options`.
+        pub fn draw_with(data: String, lints: &lint::Config, options: &RenderOptions) -> Result<Drawing, Error> {
+            draw_impl(data, lints, options, None)
+        }
 
-            let mut val = eval(&items);
+        /// Draw both `old` and `new`, marking the [diff::Change]s between them with CSS classes.
+        pub fn draw_diff(old: String, new: String) -> Result<Drawing, Error> {
+            draw_diff_with(old, new, &lint::Config::default(), &RenderOptions::default())
+        }
 
-            eprintln!("EVAL {val:#?}");
+        /// Like [draw_diff()] but with `lints` and `options` as for [draw_with()].
+        pub fn draw_diff_with(old: String, new: String, lints: &lint::Config, options: &RenderOptions) -> Result<Drawing, Error> {
+            let diff = diff::diff(&old, &new)?;
+            let data = format!("{new}\n{}", diff.removed_source());
+            draw_impl(data, lints, options, Some(&diff))
+        }
 
-            let styling = Styling::new(&val)?;
+        fn draw_impl(data: String, lints: &lint::Config, options: &RenderOptions, diff: Option<&diff::Diff>) -> Result<Drawing, Error> {
+            let measurer = &options.measurer();
+            let (drawing, wanted) = draw_once(data.clone(), lints, measurer, options, diff)?;
+            if wanted <= 0. {
+                return Ok(drawing)
+            }
+            // some labels found no free spot: make room for them and try once more
+            let options = RenderOptions{label_padding: options.label_padding + wanted, ..options.clone()};
+            Ok(draw_once(data, lints, measurer, &options, diff)?.0)
+        }
 
-            let vcg = calculate_vcg(&val, logs)?;
+        /// Draw `data` once, also returning how much more room its labels want (see [place_labels()]).
+        fn draw_once(data: String, lints: &lint::Config, measurer: &dyn TextMeasurer, options: &RenderOptions, diff: Option<&diff::Diff>) -> Result<(Drawing, f64), Error> {
+            let mut logs = log::Logger::new();
 
-            // eprintln!("HCG {hcg:#?}");
+            let render_cell = super::render(Cow::Owned(data), options, &mut logs)?;
+            let depiction = render_cell.borrow_dependent();
 
-            let Vcg{vert, containers, nodes_by_container_transitive: nodes_by_container, container_depths, ..} = &vcg;
+            let val = &depiction.val;
+            let styling = &depiction.styling;
+            let geometry_solution = &depiction.geometry_solution;
+            let rs = &depiction.geometry_solution.rs;
+            let ls = &depiction.geometry_solution.ls;
+            let ss = &depiction.geometry_solution.ss;
+            let ts = &depiction.geometry_solution.ts;
+            let bs = &depiction.geometry_solution.bs;
+            let status_v = depiction.geometry_solution.status_v;
+            let status_h = depiction.geometry_solution.status_h;
+            let varrank_by_obj = &depiction.geometry_problem.varrank_by_obj;
+            let loc_by_varrank = &depiction.geometry_problem.loc_by_varrank;
+            let loc_to_node = &depiction.layout_problem.loc_to_node;
+            let node_to_loc = &depiction.layout_problem.node_to_loc;
+            let vert = &depiction.vcg.vert;
+            let vert_node_labels = &depiction.vcg.vert_node_labels;
+            let vert_edge_labels = &depiction.vcg.vert_edge_labels;
+            let horz_edge_labels = &depiction.vcg.horz_edge_labels;
+            let hops_by_edge = &depiction.layout_problem.hops_by_edge;
+            let size_by_loc = &depiction.geometry_problem.size_by_loc;
+            let size_by_hop = &depiction.geometry_problem.size_by_hop;
+            let crossing_number = depiction.layout_solution.crossing_number;
+            let containers = &depiction.vcg.containers;
+            let container_depths = &depiction.vcg.container_depths;
+            let nesting_depths = &depiction.vcg.nesting_depths;
+            let solved_locs = &depiction.layout_solution.solved_locs;
+            let horizontal_problem = &depiction.horizontal_problem;
+            let vertical_problem = &depiction.vertical_problem;
 
-            // eprintln!("DISTANCE VERT: {:?}", Dot::new(&vert));
+            let mut texts = vec![];
 
-            let distance = {
-                let containers = &containers;
-                let nodes_by_container = &nodes_by_container;
-                let container_depths = &container_depths;
-                |src: Cow<str>, dst: Cow<str>, l: &mut log::Logger| {
-                    if !containers.contains(&src) {
-                        // if hcg.constraints.contains(&HorizontalConstraint{a: src.clone(), b: dst.clone()})
-                        // || hcg.constraints.contains(&HorizontalConstraint{a: dst.clone(), b: src.clone()}) {
-                        //     0
-                        // } else {
-                        //     -1
-                        // }
-                        l.log_pair(
-                            "(V, V)",
-                            names![src, dst],
-                            format!("{}, {}", src, dst),
-                            "isize, &str",
-                            vec![],
-                            format!("-1, src-not-container")
-                        ).unwrap();
-                        -1
-                    } else {
-                        if nodes_by_container[&src].contains(&dst) {
-                            l.log_pair(
-                                "(V, V)",
-                                names![src, dst],
-                                format!("{}, {}", src, dst),
-                                "isize, &str",
-                                vec![],
-                                format!("-1, src-contains-dst")
-                            ).unwrap();
-                            -1
-                        } else {
-                            l.log_pair(
-                                "(V, V)",
-                                names![src, dst],
-                                format!("{}, {}", src, dst),
-                                "isize, &str",
-                                vec![],
-                                format!("{}, container_depths", -(container_depths[&src] as isize))
-                            ).unwrap();
-                            -(container_depths[&src] as isize)
-                        }
+            // containers may overlap their contents and arrows may cross their endpoints' containers
+            let nodes_by_container_transitive = &depiction.vcg.nodes_by_container_transitive;
+            let mut exemptions = Exemptions::new();
+            for (container, contents) in nodes_by_container_transitive {
+                for vl in contents {
+                    exemptions.allow(vl.to_string(), container.to_string());
+                }
+            }
+            let notes_by_node = &depiction.vcg.notes_by_node;
+            // arrows may cross their endpoints, but their labels must still keep clear of them
+            let allow_arrow = |exemptions: &mut Exemptions, key: &str, vl: &Cow<str>, wl: &Cow<str>| {
+                for end in [vl, wl] {
+                    exemptions.allow_crossing(key, end.to_string());
+                    for n in 0..notes_by_node.get(end).map_or(0, Vec::len) {
+                        exemptions.allow_crossing(key, note_key(end, n));
+                    }
+                }
+                for (container, contents) in nodes_by_container_transitive {
+                    match (contents.contains(vl), contents.contains(wl)) {
+                        // the whole arrow, label and all, lies inside this container
+                        (true, true) => exemptions.allow(key, container.to_string()),
+                        (true, false) | (false, true) => exemptions.allow_crossing(key, container.to_string()),
+                        (false, false) => {},
                     }
                 }
             };
 
-            let filtered_vert = vert.filter_map(|_vx, vl| Some(vl.clone()), |_ex, er| { if er == "horizontal" || er == "implied_horizontal" { None } else { Some(er.clone()) }});
+            // Log the resolved value
+            // logs.log_string("VAL", val);
+            logs.with_group("Eval", "", Vec::<String>::new(), |mut logs| {
+                val.log("VAL".into(), &mut logs)
+            })?;
 
-            let paths_by_rank = rank(&filtered_vert, distance, logs)?;
+            let l2n = |ovr, ohr| match &loc_to_node[&(ovr, ohr)] {
+                Obj::Node(ObjNode{vl: node}) => node.to_string().names(),
+                Obj::Hop(ObjHop{lvl: ovr, mhr, vl, wl}) => names![ovr, mhr, vl.to_string(), wl.to_string()],
+                Obj::Container(ObjContainer{vl}) => vl.to_string().names(),
+                Obj::Gap(ObjGap{container, ..}) => container.to_string().names(),
+            };
+            let v2n = |varrank| {
+                let Some(loc_ix) = loc_by_varrank.get(&varrank) else { return "legend".to_string().names() };
+                l2n(loc_ix.0, loc_ix.1)
+            };
 
-            logs.with_map("paths_by_rank", "BTreeMap<VerticalRank, SortedVec<(V, V)>>", paths_by_rank.iter(), |rank, paths, l| {
-                l.with_map(format!("paths_by_rank[{rank}]"), "SortedVec<(V, V)>", paths.iter().map(|p| (&p.0, &p.1)), |from, to, l| {
-                    l.log_pair(
-                        "V",
-                        names![from],
-                        format!("{from}"),
-                        "V",
-                        names![to],
-                        format!("{to}"),
-                    )
-                })
+
+            logs.with_group("Layout", "", Vec::<String>::new(), |mut logs| {
+                loc_to_node.log((), &mut logs)?;
+                // sol_by_loc.log(l2n, &mut logs)?;
+                // sol_by_hop.log(l2n, &mut logs)?;
+                varrank_by_obj.log((), &mut logs)?;
+                // loc_by_varrank.log(l2n, &mut logs)?;
+                solved_locs.log(l2n, &mut logs)?;
+                size_by_loc.log(l2n, &mut logs)?;
+                size_by_hop.log(l2n, &mut logs)
             })?;
+            horizontal_problem.log(("horizontal_problem".into(), v2n), &mut logs)?;
+            vertical_problem.log(("vertical_problem".into(), v2n), &mut logs)?;
+            geometry_solution.log(v2n, &mut logs)?;
 
-            let mut layout_problem = calculate_locs_and_hops(&val, &paths_by_rank, &vcg, logs)?;
+            // Render Nodes
+            let mut viewbox_width = (rs.values().copied().map(ordered_float::OrderedFloat).max().unwrap_or_default() - ls.values().copied().map(ordered_float::OrderedFloat).min().unwrap_or_default()).0;
+            let mut viewbox_height = (bs.values().copied().map(ordered_float::OrderedFloat).max().unwrap_or_default() - ts.values().copied().map(ordered_float::OrderedFloat).min().unwrap_or_default()).0;
 
-            // ... adjust problem for horizontal edges
+            for (loc, node) in loc_to_node.iter() {
+                let (ovr, ohr) = loc;
 
-            let layout_solution = minimize_edge_crossing(&vcg, &layout_problem, logs)?;
-            layout_problem.loc_to_node = layout_problem.loc_to_node.into_iter().filter(|(_loc, node)| !matches!(node, Obj::Gap(..))).collect::<HashMap<_, _>>();
-            layout_problem.node_to_loc = layout_problem.node_to_loc.into_iter().filter(|(node, _loc)| !matches!(node, Obj::Gap(..))).collect::<HashMap<_, _>>();
+                if let Obj::Node(ObjNode{vl}) = node {
+                    let n = varrank_by_obj[node];
 
-            let mut geometry_problem = calculate_sols(&layout_problem, &layout_solution);
+                    // eprintln!("TEXT {vl} {ovr} {ohr} {n}");
 
-            estimate_widths(&vcg, &layout_problem, &mut geometry_problem)?;
+                    let lpos = ls[&n];
+                    let rpos = rs[&n];
 
-            let (horizontal_problem, vertical_problem) = position_sols(&vcg, &layout_problem, &layout_solution, &geometry_problem, logs)?;
+                    let z_index = nesting_depths[vl];
 
-            let geometry_solution = solve_optimization_problems(&horizontal_problem, &vertical_problem)?;
+                    let vpos = ts[&n];
+                    let width = (rpos - lpos).round();
+                    let hpos = lpos.round();
+                    let height = bs[&n] - ts[&n];
 
-            Ok(Depiction{
-                items,
-                val,
-                styling,
-                vcg,
-                paths_by_rank,
-                layout_problem,
-                layout_solution,
-                geometry_problem,
-                horizontal_problem,
-                vertical_problem,
-                geometry_solution,
-            })
-        })
-    }
+                    let key = vl.to_string();
+                    let mut label = vert_node_labels
+                        .get(vl)
+                        .or_err(Kind::KeyNotFoundError{key: vl.to_string()})?
+                        .clone();
+                    if label.starts_with("_") { label = String::new(); };
+                    // if !label.is_screaming_snake_case() {
+                    //     label = label.to_title_case();
+                    // }
+                    let estimated_size = size_by_loc[&(*ovr, *ohr)].clone();
+                    let classes = itertools::join(styling.inherited(vl).iter().chain(styling.style_by_name.get(vl).iter().copied().flatten()), " ");
+                    let classes = add_class(classes, diff.and_then(|d| d.node_class(vl)));
+                    let shape = styling.shape(vl);
+                    texts.push(Node::Div{key, label, hpos, vpos, width, height, z_index, classes, shape, loc: n, estimated_size});
+                }
+            }
 
-    pub mod log {
+            for container in containers {
+                let container_obj = Obj::Container(ObjContainer{ vl: container.clone() });
+                let container_loc_ix = &node_to_loc[&container_obj];
+                let (ovr, ohr) = container_loc_ix;
+                let cn = varrank_by_obj[&container_obj];
+                let lpos = ls[&cn];
+                let rpos = rs[&cn];
+                let z_index = nesting_depths[container];
+                let vpos = ts[&cn];
+                let width = (rpos - lpos).round();
+                let hpos = lpos.round();
+                let height = bs[&cn] - ts[&cn];
+                // eprintln!("HEIGHT: {container} {height}");
 
-        #[derive(Clone, Debug, PartialEq, PartialOrd)]
-        pub enum Record {
-            String { name: Option<String>, ty: Option<String>, names: Vec<String>, val: String, },
-            Group { name: Option<String>, ty: Option<String>, names: Vec<String>, val: Vec<Record>, },
-            Svg { name: Option<String>, ty: Option<String>, names: Vec<String>, val: String, }
-        }
+                let key = format!("{container}");
+                let mut label = vert_node_labels
+                    .get(container)
+                    .or_err(Kind::KeyNotFoundError{key: container.to_string()})?
+                    .clone();
+                if label.starts_with("_") { label = String::new(); };
 
-        #[derive(Clone, Debug, thiserror::Error)]
-        #[error("LogError")]
-        pub enum Error {
-            FmtError{ #[from] source: std::fmt::Error }
-        }
+                let estimated_size = size_by_loc[&(*ovr, *ohr)].clone();
+                let classes = itertools::join(styling.inherited(container).iter().chain(styling.style_by_name.get(container).iter().copied().flatten()), " ");
+                let classes = add_class(classes, Some("container"));
+                let classes = add_class(classes, diff.and_then(|d| d.node_class(container)));
+                texts.push(Node::Div{key, label, hpos, vpos, width, height, z_index, classes, shape: Shape::Rect, loc: cn, estimated_size});
+            }
+
+            // notes stack outward from the edges of the boxes they annotate, as reserved by estimate_widths()
+            let transposed = options.orientation.is_transposed();
+            let mut note_rects = vec![];
+            for (owner, notes) in notes_by_node {
+                let owner_obj = Obj::from_vl(owner, containers);
+                let on = varrank_by_obj[&owner_obj];
+                let (l, r, t, b) = (ls[&on], rs[&on], ts[&on], bs[&on]);
+                let z_index = nesting_depths[owner] + 1;
+                let label = vert_node_labels
+                    .get(owner)
+                    .or_err(Kind::KeyNotFoundError{key: owner.to_string()})?;
+                let label_extent = if transposed {
+                    measurer.width(label, TextRole::Box) + options.box_padding
+                } else {
+                    options.box_height + (label.lines().count().max(1) - 1) as f64 * measurer.line_height(TextRole::Box)
+                };
+                let spacing = options.note_spacing;
+                let (mut nwo, mut neo, mut nwi, mut si) = (t - spacing, t - spacing, t + label_extent, b - spacing);
+                for (n, note) in notes.iter().enumerate() {
+                    let (width, height) = note_size(&note.label, measurer, options);
+                    let (width, height) = if transposed { (height, width) } else { (width, height) };
+                    let (hpos, vpos) = match note.pos {
+                        Position::NWO => { nwo -= height; let vpos = nwo; nwo -= spacing; (l, vpos) },
+                        Position::NEO => { neo -= height; let vpos = neo; neo -= spacing; (r - width, vpos) },
+                        Position::NWI => { let vpos = nwi; nwi += height + spacing; (l + spacing, vpos) },
+                        Position::SI => { si -= height; let vpos = si; si -= spacing; ((l + r - width) / 2., vpos) },
+                    };
+                    let hpos = hpos.round();
+                    let key = note_key(owner, n);
+                    exemptions.allow(key.clone(), owner.to_string());
+                    for (container, contents) in nodes_by_container_transitive {
+                        if contents.contains(owner) {
+                            exemptions.allow(key.clone(), container.to_string());
+                        }
+                    }
+                    note_rects.push(Rect{id: key.clone(), l: hpos, r: hpos + width, t: vpos, b: vpos + height});
+                    let classes = format!("note note_{} {owner}_note", note.pos);
+                    let estimated_size = NodeSize{width, left: 0., right: 0., height, top: 0., bottom: 0.};
+                    texts.push(Node::Div{key, label: note.label.to_string(), hpos, vpos, width, height, z_index, classes, shape: Shape::Rect, loc: on, estimated_size});
+                }
+            }
 
-        pub trait Log<Cx = ()> {
-            fn log(&self, cx: Cx, l: &mut Logger) -> Result<(), Error>;
-        }
+            // the legend's frame sits where position_sols() reserved room for it
+            if let Some((n, estimated_size)) = &depiction.geometry_problem.legend {
+                let (hpos, vpos) = (ls[n].round(), ts[n]);
+                let (width, height) = ((rs[n] - ls[n]).round(), bs[n] - ts[n]);
+                texts.push(Node::Div{key: "legend".into(), label: Legend::TITLE.into(), hpos, vpos, width, height, z_index: 0, classes: "legend".into(), shape: Shape::Rect, loc: *n, estimated_size: estimated_size.clone()});
+            }
 
-        pub trait Name {
-            fn name(&self) -> String;
-        }
+            let mut arrows = vec![];
 
-        impl Name for String {
-            fn name(&self) -> String {
-                self.clone()
-            }
-        }
+            for er in vert.edge_references() {
+                let vl = vert.node_weight(er.source()).unwrap();
+                let wl = vert.node_weight(er.target()).unwrap();
+                let ew = er.weight();
 
-        impl<'s> Name for std::borrow::Cow<'s, str> {
-            fn name(&self) -> String {
-                self.to_string()
-            }
-        }
+                if ew == "implied_vertical" {
+                    continue;
+                }
 
-        pub trait Names<'n> {
-            fn names(&self) -> Vec<Box<dyn Name + 'n>>;
-        }
+                let Some(level) = vert_edge_labels.get(&(vl.clone(), wl.clone())) else {continue};
 
-        #[macro_export]
-        macro_rules! names {
-            ($($x:expr),*) => {{
-                let mut v = Vec::new();
-                $(
-                    v.push(Box::new(($x).clone()) as Box<dyn crate::graph_drawing::frontend::log::Name>);
-                )*
-                v
-            }}
-        }
+                for (dir, dir2, labels) in &[("forward", Dir::Forward, level.forward.as_ref()), ("reverse", Dir::Reverse, level.reverse.as_ref())] {
+                    if labels.is_none() { continue; }
+                    let label_text = labels.map(|labels| labels.iter().cloned().map(|f| if f == "_" { "".into() } else { f }).collect::<Vec<_>>().join("\n"));
+                    let hops = hops_by_edge.get(&(vl.clone(), wl.clone()));
+                    let hops = if let Some(hops) = hops { hops } else { continue; };
+                    // eprintln!("vl: {}, wl: {}, hops: {:?}", vl, wl, hops);
 
-        pub use names;
+                    let offset = match *dir {
+                        x if x == "forward" => -10.0,
+                        x if x == "forward" => -10.0,
+                        x if x == "reverse" => 10.0,
+                        x if x == "reverse" => 10.0,
+                        _ => 0.0,
+                    };
 
-        impl<'n, A: Clone + Name + 'n> Names<'n> for A {
-            fn names(&self) -> Vec<Box<dyn Name + 'n>> {
-                names![self]
-            }
-        }
+                    let z_index = std::cmp::max(nesting_depths[vl], nesting_depths[wl]) + 1;
 
-        impl<'n, A1: Clone + Name + 'n, A2: Clone + Name + 'n> Names<'n> for (A1, A2) {
-            fn names(&self) -> Vec<Box<dyn Name + 'n>> {
-                names![self.0, self.1]
-            }
-        }
+                    let inherited = styling.inherited_by_arrow(vl, wl);
+                    let keywords = styling.label_keywords(vl, wl, dir2, labels.map(Vec::as_slice).unwrap_or_default());
+                    let classes = itertools::join(inherited.iter().chain(styling.style_by_arrow.get(&(vl.clone(), wl.clone(), dir2.clone())).iter().copied().flatten()).chain(keywords.iter()), " ");
+                    let (start_gap, end_gap) = ArrowStyle::from_classes(&classes).gaps(*dir == "forward", options.theme.arrowhead);
 
-        #[derive(Clone, Debug, Default)]
-        pub struct Logger {
-            logs: Vec<Record>,
-        }
+                    let mut path = vec![];
+                    let mut control_points = vec![];
+                    // use rand::Rng;
+                    // let mut rng = rand::thread_rng();
+                    let mut hn0 = vec![];
+                    let mut estimated_size0 = None;
+                    let mut xs = vec![];
 
-        impl Logger {
-            pub fn new() -> Self {
-                Self {
-                    logs: vec![],
-                }
-            }
+                    let fs = container_depths.get(vl).copied().and_then(|d| d.checked_sub(1)).unwrap_or(0);
+                    let hops = if fs == 0 {
+                        hops.iter().collect::<Vec<_>>()
+                    } else {
+                        hops.iter().skip(fs).collect::<Vec<_>>()
+                    };
+                    let (vn, wn) = (varrank_by_obj[&Obj::from_vl(vl, containers)], varrank_by_obj[&Obj::from_vl(wl, containers)]);
+                    let vmin = bs[&vn];
+                    let vmax = ts[&wn];
+                    // arrows meet shaped boxes at their outlines rather than at their bounding boxes
+                    let anchor_inset = |x: f64, n: &VarRank, name: &Cow<str>| {
+                        let shape = if containers.contains(name) { Shape::Rect } else { styling.shape(name) };
+                        shape.inset(x - (ls[n] + rs[n]) / 2., rs[n] - ls[n], bs[n] - ts[n], options.orientation.is_transposed())
+                    };
+                    let nh = hops.len();
+                    let vs = (0..=nh).map(|lvl|  {
+                        let fraction = lvl as f64 / nh as f64;
+                        vmin + fraction * (vmax - vmin)
+                    })
+                        .collect::<Vec<_>>();
+                    // eprintln!("vl: {vl}, wl: {wl}, fs: {fs}, vmin: {vmin}, vmax: {vmax}, nh: {nh}, vs: {vs:?}");
+                    for (n, hop) in hops.iter().enumerate() {
+                        let (lvl, (mhr, nhr)) = *hop;
+                        let hn = varrank_by_obj[&Obj::Hop(ObjHop{lvl: *lvl, mhr: *mhr, vl: vl.clone(), wl: wl.clone()})];
+                        let spos = ss[&hn];
+                        let hnd = varrank_by_obj[&Obj::Hop(ObjHop{lvl: *lvl+1, mhr: *nhr, vl: vl.clone(), wl: wl.clone()})];
+                        let sposd = ss[&hnd];
+                        let hpos = (spos + offset).round(); // + rng.gen_range(-0.1..0.1));
+                        let hposd = (sposd + offset).round(); //  + 10. * lvl.0 as f64;
+                        let vpos = vs[n];
+                        let mut vpos2 = vs[n+1];
 
-            pub fn with_map<K, V, F>(
-                &mut self,
-                name: impl Into<String>,
-                ty: impl Into<String>,
-                pairs: impl IntoIterator<Item=(K, V)>,
-                mut f: F
-            ) -> Result<(), Error> where
-                F: FnMut(K, V, &mut Logger) -> Result<(), Error>
-            {
-                self.with_group(ty.into(), name.into(), Vec::<String>::new(), |l| {
-                    for (k, v) in pairs.into_iter() {
-                        f(k, v, l)?
+                        if n == 0 {
+                            hn0.push(hn);
+                            xs.push(hpos);
+                            estimated_size0 = Some(size_by_hop[&(*lvl, *mhr, vl.clone(), wl.clone())].clone());
+                            let vpos = vpos - anchor_inset(hpos, &vn, vl) + start_gap;
+                            path.push(format!("M {hpos} {vpos}"));
+                            control_points.push((hpos, vpos));
+                        }
+                        hn0.push(hnd);
+                        xs.push(hposd);
+
+                        if n == hops.len() - 1 {
+                            vpos2 += anchor_inset(hposd, &wn, wl) - end_gap;
+                        }
+
+                        path.push(format!("L {hposd} {vpos2}"));
+                        control_points.push((hpos, vpos2));
                     }
-                    Ok(())
-                })?;
-                Ok(())
-            }
 
-            pub fn with_set<V, F>(
-                &mut self,
-                name: impl Into<String>,
-                ty: impl Into<String>,
-                elements: impl IntoIterator<Item=V>,
-                mut f: F
-            ) -> Result<(), Error> where
-                F: FnMut(V, &mut Logger) -> Result<(), Error>
-            {
-                self.with_group(ty.into(), name.into(), Vec::<String>::new(), |l| {
-                    for v in elements.into_iter() {
-                        f(v, l)?
+                    let key = format!("{vl}_{wl}_{ew}_{dir}_{}", er.id().index());
+                    allow_arrow(&mut exemptions, &key, vl, wl);
+                    // let key = format!("{vl}_{wl}_{ew}_{dir}");
+                    let path = path.join(" ");
+
+                    let diff_class = diff.and_then(|d| d.arrow_class(vl, wl, &Rel::Vertical, dir2));
+
+                    // labels sit beside the vertices chosen by options.label_hop: forward labels end
+                    // above the arrowhead and reverse labels start below the tail, while labels of
+                    // inner vertices, or of the segment between two vertices, are centered on them
+                    let forward = *dir == "forward";
+                    let groups = options.label_hop.vertices(nh + 1, forward);
+                    // estimate_widths() stacked the heights of repeated labels
+                    let height = estimated_size0.as_ref().map(|size| size.height).unwrap_or_default() / groups.len().max(1) as f64;
+                    let place = |k: usize| {
+                        let hn = hn0[k];
+                        let width = if forward { ss[&hn] - ls[&hn] } else { rs[&hn] - ss[&hn] };
+                        let vpos = match k {
+                            0 => vs[0] + start_gap,
+                            k if k == nh && forward => vs[nh] - end_gap - height,
+                            k if k == nh => vs[nh] - height,
+                            k => vs[k] - height / 2.,
+                        };
+                        (xs[k], width, vpos)
+                    };
+                    let mut labels = vec![];
+                    if let Some(label_text) = label_text {
+                        let label_classes = itertools::join(
+                            inherited.iter().chain(styling.style_by_label.get(&(vl.clone(), wl.clone(), dir2.clone(), Cow::from(label_text.clone()))).iter().copied().flatten()).chain(
+                                styling.style_by_label_star.get(&(vl.clone(), wl.clone(), dir2.clone())).iter().copied().flatten()
+                            ),
+                            " "
+                        );
+                        let label_classes = add_class(label_classes, diff_class);
+                        for vertices in groups {
+                            let (hpos, width, vpos) = match vertices[..] {
+                                [k] => place(k),
+                                [k, ..] => {
+                                    let ((h0, w0, _), (h1, w1, _)) = (place(k), place(k+1));
+                                    (((h0 + h1) / 2.).round(), f64::min(w0, w1), (vs[k] + vs[k+1] - height) / 2.)
+                                },
+                                [] => continue,
+                            };
+                            labels.push(Label{text: label_text.clone(), classes: label_classes.clone(), hpos, width, vpos});
+                        }
                     }
-                    Ok(())
-                })?;
-                Ok(())
-            }
+                    let classes = add_class(format!("arrow vertical {ew} {vl}_{wl} {vl}_{wl}_{ew} {classes}"), diff_class);
 
-            pub fn log_pair<'n>(
-                &mut self,
-                src_ty: impl Into<String>,
-                src_names: Vec<Box<dyn Name + 'n>>,
-                src_val: impl Into<String>,
-                dst_ty: impl Into<String>,
-                dst_names: Vec<Box<dyn Name + 'n>>,
-                dst_val: impl Into<String>
-            ) -> Result<(), Error> {
-                let src_ty = src_ty.into();
-                let mut src_names = src_names.into_iter().map(|n| n.name()).collect::<Vec<String>>();
-                let src_val = src_val.into();
-                let dst_ty = dst_ty.into();
-                let mut dst_names = dst_names.into_iter().map(|n| n.name()).collect::<Vec<String>>();
-                let dst_val = dst_val.into();
-                src_names.append(&mut dst_names);
-                self.logs.push(Record::String{
-                    name: None,
-                    ty: Some(format!("{src_ty}->{dst_ty}")),
-                    names: src_names,
-                    val: format!("{src_val} -> {dst_val}")
-                });
-                Ok(())
+                    arrows.push(Node::Svg{key, path, z_index, dir: "vertical".into(), rel: dir.to_string(), labels, hops: hn0, classes, estimated_size: estimated_size0.unwrap(), control_points});
+                }
             }
+            let forward_voffset = 6.;
+            let reverse_voffset = 20.;
 
-            pub fn log_element(
-                &mut self,
-                ty: impl Into<String>,
-                names: Vec<impl Into<String>>,
-                val: impl Into<String>
-            ) -> Result<(), Error> {
-                let ty = ty.into();
-                let names = names.into_iter().map(|n| n.into()).collect::<Vec<String>>();
-                let val = val.into();
-                self.logs.push(Record::String{
-                    name: None,
-                    ty: Some(ty),
-                    names,
-                    val,
-                });
-                Ok(())
-            }
+            // number horizontal arrows in a stable order so that their keys do not vary between runs
+            let mut horz_edge_labels = horz_edge_labels.iter().collect::<Vec<_>>();
+            horz_edge_labels.sort_by(|a, b| a.0.cmp(b.0));
+            for (m, ((vl, wl), lvl)) in horz_edge_labels.into_iter().enumerate() {
 
-            pub fn log_string(&mut self, name: impl Into<String>, val: impl std::fmt::Debug) -> Result<(), Error> {
-                self.logs.push(Record::String{
-                    name: Some(name.into()),
-                    ty: None,
-                    names: vec![],
-                    val: format!("{val:#?}")
-                });
-                Ok(())
-            }
+                let z_index = std::cmp::max(nesting_depths[vl], nesting_depths[wl]) + 1;
 
-            pub fn log_svg(&mut self, name: Option<impl Into<String>>, ty: Option<impl Into<String>>, names: Vec<impl Into<String>>, val: String) -> Result<(), Error> {
-                let name = name.map(|name| name.into());
-                let ty = ty.map(|ty| ty.into());
-                let names = names.into_iter().map(|n| n.into()).collect::<Vec<String>>();
-                self.logs.push(Record::Svg{
-                    name,
-                    ty,
-                    names,
-                    val,
-                });
-                Ok(())
+                if let Some(forward) = &lvl.forward {
+                    let key = format!("{vl}_{wl}_forward_{m}");
+                    allow_arrow(&mut exemptions, &key, vl, wl);
+                    let diff_class = diff.and_then(|d| d.arrow_class(vl, wl, &Rel::Horizontal, &Dir::Forward));
+                    let inherited = styling.inherited_by_arrow(vl, wl);
+                    let keywords = styling.label_keywords(vl, wl, &Dir::Forward, forward);
+                    let classes = itertools::join(inherited.iter().chain(styling.style_by_arrow.get(&(vl.clone(), wl.clone(), Dir::Forward)).iter().copied().flatten()).chain(keywords.iter()), " ");
+                    let (start_gap, end_gap) = ArrowStyle::from_classes(&classes).gaps(true, options.theme.arrowhead);
+                    let label_classes = itertools::join(
+                        inherited.iter().chain(forward.iter().flat_map(|label| styling.style_by_label.get(&(vl.clone(), wl.clone(), Dir::Forward, label.clone()))).flatten()).chain(
+                            styling.style_by_label_star.get(&(vl.clone(), wl.clone(), Dir::Forward)).iter().copied().flatten()
+                        ).collect::<BTreeSet<_>>(),
+                        " "
+                    );
+                    let classes = add_class(format!("arrow horizontal forward {vl}_{wl} {vl}_{wl}_forward {classes}"), diff_class);
+                    let locl = node_to_loc[&Obj::from_vl(vl, containers)];
+                    let locr = node_to_loc[&Obj::from_vl(wl, containers)];
+                    let nl = varrank_by_obj[&Obj::from_vl(vl, containers)];
+                    let nr = varrank_by_obj[&Obj::from_vl(wl, containers)];
+                    let lr = rs[&nl] + start_gap;
+                    let rl = ls[&nr] - end_gap;
+                    let wl = size_by_loc[&locl].right;
+                    let wr = size_by_loc[&locr].left;
+                    let vposl = ts[&nl] + forward_voffset;
+                    let vposr = ts[&nr] + forward_voffset;
+                    let path = format!("M {} {} L {} {}", lr, vposl, rl, vposr);
+                    let control_points = vec![(lr, vposl), (rl, vposr)];
+                    let label_text = forward.iter().cloned().map(|f| if f == "_" { "".into() } else { f }).collect::<Vec<_>>().join("\n");
+                    let label_width = measurer.width(&label_text, TextRole::Label);
+                    let labels = if !forward.is_empty() {
+                        vec![Label{text: label_text, classes: add_class(label_classes, diff_class), hpos: rl, width: label_width, vpos: vposl }]
+                    } else {
+                        vec![]
+                    };
+                    let estimated_size = HopSize{ width: 0., left: wl, right: wr, height: 0., top: 0., bottom: 0. };
+                    arrows.push(Node::Svg{key, path, z_index, labels, dir: "horizontal".into(), rel: "forward".into(), hops: vec![], classes, estimated_size, control_points });
+                }
+                if let Some(reverse) = &lvl.reverse {
+                    let key = format!("{vl}_{wl}_reverse_{m}");
+                    allow_arrow(&mut exemptions, &key, vl, wl);
+                    let diff_class = diff.and_then(|d| d.arrow_class(vl, wl, &Rel::Horizontal, &Dir::Reverse));
+                    let inherited = styling.inherited_by_arrow(vl, wl);
+                    let keywords = styling.label_keywords(vl, wl, &Dir::Reverse, reverse);
+                    let classes = itertools::join(inherited.iter().chain(styling.style_by_arrow.get(&(vl.clone(), wl.clone(), Dir::Reverse)).iter().copied().flatten()).chain(keywords.iter()), " ");
+                    let (start_gap, end_gap) = ArrowStyle::from_classes(&classes).gaps(false, options.theme.arrowhead);
+                    let label_classes = itertools::join(
+                        inherited.iter().chain(reverse.iter().flat_map(|label| styling.style_by_label.get(&(vl.clone(), wl.clone(), Dir::Reverse, label.clone()))).flatten()).chain(
+                            styling.style_by_label_star.get(&(vl.clone(), wl.clone(), Dir::Reverse)).iter().copied().flatten()
+                        ).collect::<BTreeSet<_>>(),
+                        " "
+                    );
+                    let classes = add_class(format!("arrow horizontal reverse {vl}_{wl} {vl}_{wl}_reverse {classes}"), diff_class);
+                    let locl = node_to_loc[&Obj::from_vl(vl, containers)];
+                    let locr = node_to_loc[&Obj::from_vl(wl, containers)];
+                    let nl = varrank_by_obj[&Obj::from_vl(vl, containers)];
+                    let nr = varrank_by_obj[&Obj::from_vl(wl, containers)];
+                    let lr = rs[&nl] + start_gap;
+                    let rl = ls[&nr] - end_gap;
+                    let wl = size_by_loc[&locl].right;
+                    let wr = size_by_loc[&locr].left;
+                    let vposl = ts[&nl] + reverse_voffset;
+                    let vposr = ts[&nr] + reverse_voffset;
+                    let path = format!("M {} {} L {} {}", lr, vposl, rl, vposr);
+                    let control_points = vec![(lr, vposl), (rl, vposr)];
+                    let label_text = reverse.iter().cloned().map(|f| if f == "_" { "".into() } else { f }).collect::<Vec<_>>().join("\n");
+                    let label_width = measurer.width(&label_text, TextRole::Label);
+                    let labels = if !reverse.is_empty() {
+                        vec![Label{text: label_text, classes: add_class(label_classes, diff_class), hpos: lr, width: label_width, vpos: vposl }]
+                    } else {
+                        vec![]
+                    };
+                    let estimated_size = HopSize{ width: 0., left: wl, right: wr, height: 0., top: 0., bottom: 0. };
+                    arrows.push(Node::Svg{key, path, z_index, labels, dir: "horizontal".into(), rel: "reverse".into(), hops: vec![], classes, estimated_size, control_points });
+                }
             }
 
-            pub fn with_group<F>(
-                &mut self,
-                ty: impl Into<String>,
-                name: impl Into<String>,
-                names: Vec<impl Into<String>>,
-                f: F
-            ) -> Result<(), Error> where
-                F: FnOnce(&mut Logger) -> Result<(), Error>
-            {
-                let mut nested_logger = Logger::new();
-                f(&mut nested_logger)?;
-                self.logs.push(Record::Group{
-                    name: Some(name.into()),
-                    ty: Some(ty.into()),
-                    names: names.into_iter().map(|n| n.into()).collect::<Vec<_>>(),
-                    val: nested_logger.logs
-                });
-                Ok(())
-            }
+            let mut nodes = texts
+                .into_iter()
+                .chain(arrows.into_iter())
+                .collect::<Vec<_>>();
 
-            pub fn to_vec(self) -> Vec<Record> {
-                self.logs
+            nodes.sort_by_key(|node| match node {
+                Node::Div{z_index, ..} => *z_index,
+                Node::Svg{z_index, ..} => *z_index,
+            });
+
+            let x0 = ls.values().copied().map(ordered_float::OrderedFloat).min().unwrap_or_default().0;
+            let x1 = rs.values().copied().map(ordered_float::OrderedFloat).max().unwrap_or_default().0;
+            let y0 = ts.values().copied().map(ordered_float::OrderedFloat).min().unwrap_or_default().0;
+            let mut y1 = bs.values().copied().map(ordered_float::OrderedFloat).max().unwrap_or_default().0;
+
+            // detours and notes may leave the boxes' extent; grow the drawing to fit them
+            let mut extent = route(&mut nodes, &exemptions, options.arrow_spacing);
+            for rect in note_rects {
+                let e = extent.get_or_insert(rect.clone());
+                (e.l, e.r, e.t, e.b) = (e.l.min(rect.l), e.r.max(rect.r), e.t.min(rect.t), e.b.max(rect.b));
+            }
+            if let Some(extent) = extent {
+                let (dx, dy) = (f64::max(x0 - extent.l, 0.), f64::max(y0 - extent.t, 0.));
+                translate(&mut nodes, (dx, dy));
+                viewbox_width += dx + f64::max(extent.r - x1, 0.);
+                viewbox_height += dy + f64::max(extent.b - y1, 0.);
+                y1 += dy + f64::max(extent.b - y1, 0.);
+            }
+            orthogonalize(&mut nodes, options.edge_style == EdgeStyle::Orthogonal, options.arrow_spacing, options.corner_radius);
+
+            orient(&mut nodes, options.orientation, (y0, y1), measurer);
+            let (viewbox_width, viewbox_height) = if options.orientation.is_transposed() {
+                (viewbox_height, viewbox_width)
+            } else {
+                (viewbox_width, viewbox_height)
+            };
+
+            if let Some(legend) = &depiction.legend {
+                draw_legend(&mut nodes, legend, &mut exemptions, measurer, options);
             }
-        }
-    }
 
-    pub mod dom {
-        use std::{borrow::Cow, fmt::Display};
+            let wanted = place_labels(&mut nodes, measurer, &exemptions);
+            let collisions = find_unexpected_collisions(&nodes, measurer, &exemptions);
+            let mut colliding_rects = collisions.iter().flat_map(|(ri, rj)| vec![ri, rj]).collect::<Vec<_>>();
+            colliding_rects.sort_by_key(|r| &r.id);
+            colliding_rects.dedup();
 
-        use petgraph::visit::EdgeRef;
+            logs.with_group("Visual Elements", "", Vec::<String>::new(), |logs| {
+                logs.with_set("Nodes", "", &nodes, |node, logs| {
+                    logs.log_element("Node", Vec::<String>::new(), format!("{node:#?}"))
+                })?;
+                logs.with_set("Collisions", "", &colliding_rects, |r, logs| {
+                    logs.log_element("Rect", Vec::<String>::new(), r.to_string())
+                })
+            })?;
 
-        use crate::{graph_drawing::{error::{OrErrExt, Kind, Error}, layout::{Obj, ObjContainer, ObjNode, ObjHop, ObjGap}, index::{VarRank}, geometry::{NodeSize, HopSize, OSQPStatusKind}, frontend::log::{Names}, eval::Dir}, names};
+            let warnings = lint::check(render_cell.borrow_owner(), val, &depiction.vcg, lints);
+            logs.with_group("Lints", "", Vec::<String>::new(), |logs| {
+                for warning in warnings.iter() {
+                    logs.log_element("Warning", vec![warning.rule.name()], warning.message.clone())?;
+                }
+                Ok(())
+            })?;
 
-        use super::log::{self, Log};
+            let mut logs = logs.to_vec();
+            logs.reverse();
 
+            // eprintln!("NODES: {nodes:#?}");
 
-        #[derive(Clone, Debug, PartialEq, PartialOrd)]
-        pub struct Label {
-            pub text: String,
-            pub classes: String,
-            pub hpos: f64,
-            pub width: f64,
-            pub vpos: f64,
-        }
+            let (viewbox_width, viewbox_height) = if nodes.is_empty() {
+                (options.viewbox_width, options.viewbox_height)
+            } else {
+                (viewbox_width, viewbox_height)
+            };
 
-        #[derive(Clone, Debug, PartialEq, PartialOrd)]
-        pub enum Node {
-            Div { key: String, label: String, hpos: f64, vpos: f64, width: f64, height: f64, z_index: usize, classes: String, loc: VarRank, estimated_size: NodeSize },
-            Svg { key: String, path: String, z_index: usize, dir: String, rel: String, label: Option<Label>, hops: Vec<VarRank>, classes: String, estimated_size: HopSize, control_points: Vec<(f64, f64)> },
+            Ok((Drawing{
+                crossing_number: Some(crossing_number),
+                status_v,
+                status_h,
+                viewbox_width,
+                viewbox_height,
+                nodes,
+                collisions,
+                logs,
+                warnings,
+                styles: styling.blocks.iter().map(|(name, properties)| (name.to_string(), properties.clone())).collect(),
+                theme: options.theme.clone(),
+                legend: depiction.legend.clone(),
+                typography: Typography::new(options, measurer),
+            }, wanted))
+        }
+    }
+
+    pub mod svg {
+        //! Standalone SVG
+        //!
+        //! # Summary
+        //!
+        //! [as_svg()] writes a [Drawing] as a standalone SVG document, for command-line tools, servers,
+        //! and anything else that wants files rather than a user interface, without depending on any GUI
+        //! crate.
+        //!
+        //! Documents
+        //!
+        //! * are sized by a `viewBox`, so that they scale cleanly,
+        //! * embed the CSS of their [Theme](super::theme::Theme) and style blocks, and also inline the
+        //!   style blocks' properties so that they look the same where CSS is ignored,
+        //! * begin with a `<title>` and a `<desc>` for assistive technology, and
+        //! * give each box, arrow, and label an `id` derived from its node's key (see [id()]).
+        use std::cmp::Ordering;
+        use std::collections::BTreeSet;
+        use std::fmt::Write;
+
+        use super::dom::{Drawing, Node};
+        use super::measure::TextRole;
+        use super::styling::{resolve, stylesheet, ArrowStyle};
+        use super::theme::Arrowhead;
+
+        /// Options for [as_svg()]
+        #[derive(Clone, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(default))]
+        pub struct SvgOptions {
+            /// Title of the document, read by assistive technology
+            pub title: String,
+            /// Description of the document, or else a summary of its boxes and arrows
+            pub description: Option<String>,
+            /// Room around the drawing
+            pub margin: f64,
+            /// CSS color to fill the document with, or else none, leaving it transparent
+            pub background: Option<String>,
+        }
+
+        impl Default for SvgOptions {
+            fn default() -> Self {
+                Self {
+                    title: "Drawing".into(),
+                    description: None,
+                    margin: 10.,
+                    background: None,
+                }
+            }
         }
 
-        impl Node {
-            pub fn key(&self) -> &String {
-                match self {
-                    Node::Div { key, .. } => key,
-                    Node::Svg { key, .. } => key,
+        /// Escape `text` for use in XML text and attribute values.
+        pub fn escape(text: &str) -> String {
+            let mut escaped = String::with_capacity(text.len());
+            for c in text.chars() {
+                match c {
+                    '&' => escaped.push_str("&amp;"),
+                    '<' => escaped.push_str("&lt;"),
+                    '>' => escaped.push_str("&gt;"),
+                    '"' => escaped.push_str("&quot;"),
+                    '\'' => escaped.push_str("&apos;"),
+                    _ => escaped.push(c),
                 }
             }
+            escaped
         }
 
-        pub type Collision = (Rect, Rect);
+        /// The `id` of the element drawing the node keyed `key`
+        ///
+        /// Characters other than ASCII letters, digits, and underscores become underscores, so ids
+        /// never contain the hyphens of the ids of the document's own elements, like `depict-title`.
+        pub fn id(key: &str) -> String {
+            let id = key.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_");
+            if id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') { id } else { format!("_{id}") }
+        }
 
-        #[derive(Clone, Debug, PartialEq)]
-        pub struct Rect {
-            pub id: String,
-            pub l: f64,
-            pub r: f64,
-            pub t: f64,
-            pub b: f64 }
+        /// A `style` attribute holding the CSS declarations `css`, if any
+        fn style_attr(css: Vec<String>) -> String {
+            if css.is_empty() { String::new() } else { format!(r#" style="{}""#, escape(&css.join("; "))) }
+        }
 
-        impl Display for Rect {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                write!(f, "Rect {{ l: {:7.2}, r: {:7.2}, t: {:7.2}, b: {:7.2}, id: {:?} }}", self.l, self.r, self.t, self.b, self.id)
-            }
+        /// Ids of the markers for `head` at the ends and at the starts of arrows
+        fn marker_ids(head: Arrowhead) -> (String, String) {
+            (format!("depict-arrowhead-{}", head.name()), format!("depict-arrowheadrev-{}", head.name()))
         }
 
-        impl From<&Node> for Vec<Rect> {
-            fn from(node: &Node) -> Self {
-                let char_width = 8.66;
+        /// A summary of the boxes, in reading order, and arrow labels of `drawing`, for its `<desc>`
+        #[allow(clippy::unwrap_used)] // writing to a String cannot fail
+        pub(super) fn summary(drawing: &Drawing) -> String {
+            let mut boxes = vec![];
+            let mut labels = BTreeSet::new();
+            for node in drawing.nodes.iter() {
                 match node {
-                    Node::Div { key, hpos, vpos, width, height, .. } => {
-                        vec![Rect { id: key.clone(), l: *hpos, r: hpos + width, t: *vpos, b: vpos + height }]
+                    Node::Div{label, classes, hpos, vpos, ..} if !label.is_empty() && !classes.split_whitespace().any(|c| c == "legend") => {
+                        boxes.push((*vpos, *hpos, label.replace('\n', " ")));
                     },
-                    Node::Svg { key, rel, label, estimated_size, control_points, .. } => {
-                        let mut res = vec![];
-                        if let Some(Label{hpos, width, vpos, ..}) = label {
-                            if rel == "forward" {
-                                res.push(Rect { id: format!("{key}_fwd"), l: (*hpos + - width - 1.5 * char_width), r: (*hpos - 1.5 * char_width), t: (*vpos + 1.), b: (vpos + estimated_size.height) });
-                            } else {
-                                res.push(Rect { id: format!("{key}_rev"), l: (*hpos + 1.5 * char_width), r: (hpos + width + 1.5 * char_width), t: (*vpos + 1.), b: (vpos + estimated_size.height) });
+                    Node::Svg{labels: arrow_labels, ..} => {
+                        labels.extend(arrow_labels.iter().filter(|label| !label.text.is_empty()).map(|label| label.text.replace('\n', ", ")));
+                    },
+                    _ => {},
+                }
+            }
+            boxes.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            let boxes = boxes.into_iter().map(|(_, _, label)| label).collect::<Vec<_>>();
+            let arrows = drawing.nodes.iter().filter(|node| matches!(node, Node::Svg{..})).count();
+            let mut summary = format!("{} boxes", boxes.len());
+            if !boxes.is_empty() {
+                write!(summary, ": {}", boxes.join(", ")).unwrap();
+            }
+            write!(summary, "; {arrows} arrows").unwrap();
+            if !labels.is_empty() {
+                write!(summary, ", labeled {}", labels.into_iter().collect::<Vec<_>>().join(", ")).unwrap();
+            }
+            summary.push('.');
+            summary
+        }
+
+        /// `drawing` as a standalone SVG document
+        #[allow(clippy::unwrap_used)] // writing to a String cannot fail
+        pub fn as_svg(drawing: &Drawing, options: &SvgOptions) -> String {
+            let Drawing{viewbox_width, viewbox_height, styles, theme, typography, ..} = drawing;
+            let margin = options.margin;
+            let (width, height) = (viewbox_width + 2. * margin, viewbox_height + 2. * margin);
+            let description = options.description.clone().unwrap_or_else(|| summary(drawing));
+
+            let mut svg = String::new();
+            writeln!(svg, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+            writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="{} {} {width} {height}" role="img" aria-labelledby="depict-title depict-desc" data-theme="{}">"#, -margin, -margin, escape(&theme.name)).unwrap();
+            writeln!(svg, r#"<title id="depict-title">{}</title>"#, escape(&options.title)).unwrap();
+            writeln!(svg, r#"<desc id="depict-desc">{}</desc>"#, escape(&description)).unwrap();
+            writeln!(svg, "<style><![CDATA[\n{}{}\n{}]]></style>", theme.css(), typography.css(), stylesheet(styles)).unwrap();
+
+            let mut heads = vec![theme.arrowhead];
+            for node in drawing.nodes.iter() {
+                if let Node::Svg{classes, ..} = node {
+                    let head = ArrowStyle::from_classes(classes).arrowhead(theme.arrowhead);
+                    if head != Arrowhead::None && !heads.contains(&head) {
+                        heads.push(head);
+                    }
+                }
+            }
+            writeln!(svg, "<defs>").unwrap();
+            for head in heads {
+                let (id, rev_id) = marker_ids(head);
+                let fill = if head.is_filled() { theme.light.arrow.as_str() } else { "none" };
+                for (id, orient) in [(id, "auto"), (rev_id, "auto-start-reverse")] {
+                    writeln!(svg, r#"<marker id="{id}" class="{}" markerWidth="7" markerHeight="10" refX="0" refY="5" orient="{orient}" viewBox="0 0 10 10"><path d="{}" fill="{}" stroke="{}"/></marker>"#,
+                        head.name(), head.path(), escape(fill), escape(&theme.light.arrow)).unwrap();
+                }
+            }
+            writeln!(svg, "</defs>").unwrap();
+            if let Some(background) = &options.background {
+                writeln!(svg, r#"<rect class="background" x="{}" y="{}" width="{width}" height="{height}" fill="{}"/>"#, -margin, -margin, escape(background)).unwrap();
+            }
+
+            // containers first, so that their contents are drawn over them
+            let z_index = |node: &Node| match node {
+                Node::Div{z_index, ..} | Node::Svg{z_index, ..} => *z_index,
+            };
+            let mut nodes = drawing.nodes.clone();
+            nodes.sort_by(|a, b| z_index(a).cmp(&z_index(b)).then(a.partial_cmp(b).unwrap_or(Ordering::Equal)));
+            for node in nodes {
+                match node {
+                    Node::Div{key, label, hpos, vpos, width, height, classes, shape, ..} => {
+                        // style blocks are inlined so that the SVG looks the same without its stylesheet
+                        let properties = resolve(styles, &classes);
+                        let (rect_style, text_style) = (style_attr(properties.rect_css()), style_attr(properties.text_css()));
+                        writeln!(svg, r#"<g id="{}" class="{}" transform="translate({hpos}, {vpos})">"#, id(&key), escape(format!("box {classes}").trim_end())).unwrap();
+                        match shape.outline(width, height) {
+                            Some(outline) => writeln!(svg, r#"<path class="outline" d="{outline}"{rect_style}/>"#).unwrap(),
+                            None => writeln!(svg, r#"<rect width="{width}" height="{height}"{rect_style}/>"#).unwrap(),
+                        }
+                        let text_inset = shape.text_inset(height);
+                        let role = if classes.split_whitespace().any(|c| c == "note") { TextRole::Label } else { TextRole::Box };
+                        for (lineno, line) in label.lines().enumerate() {
+                            writeln!(svg, r#"<text text-anchor="middle" transform="translate({}, {})"{text_style}>{}</text>"#,
+                                width / 2., text_inset + typography.box_baseline(role, lineno), escape(line)).unwrap();
+                        }
+                        writeln!(svg, "</g>").unwrap();
+                    },
+                    Node::Svg{key, path, dir, rel, labels, classes, ..} => {
+                        let id = id(&key);
+                        for (n, label) in labels.iter().enumerate() {
+                            let label_style = style_attr(resolve(styles, &label.classes).text_css());
+                            let anchor = if rel == "forward" { "end" } else { "start" };
+                            let label_id = if n == 0 { format!("{id}-label") } else { format!("{id}-label-{n}") };
+                            writeln!(svg, r#"<g id="{label_id}" class="{}">"#, escape(format!("label {dir} {rel} {}", label.classes).trim_end())).unwrap();
+                            for (lineno, line) in label.text.lines().enumerate() {
+                                let (x, y) = typography.label_anchor(label, &dir, &rel, lineno);
+                                writeln!(svg, r#"<text text-anchor="{anchor}" transform="translate({x}, {y})"{label_style}>{}</text>"#, escape(line)).unwrap();
                             }
+                            writeln!(svg, "</g>").unwrap();
                         }
-                        for (n, window) in control_points.windows(2).enumerate() {
-                            let [cur, nxt, ..] = window else { continue };
-                            res.push(Rect {
-                                id: format!("{key},wnd{n}"),
-                                l: (f64::min(cur.0, nxt.0) + 1.).trunc(),
-                                r: (f64::max(cur.0, nxt.0)).trunc(),
-                                t: (f64::min(cur.1, nxt.1) + 1.).trunc(),
-                                b: (f64::max(cur.1, nxt.1)).trunc(),
-                            })
+
+                        let arrow_style = ArrowStyle::from_classes(&classes);
+                        let head = arrow_style.arrowhead(theme.arrowhead);
+                        let mut markers = String::new();
+                        if head != Arrowhead::None {
+                            let (head_id, rev_id) = marker_ids(head);
+                            let (start, end) = match rel.as_str() {
+                                "forward" => (arrow_style.both, true),
+                                "reverse" => (true, arrow_style.both),
+                                _ => (false, false),
+                            };
+                            if start {
+                                write!(markers, r#" marker-start="url(#{rev_id})""#).unwrap();
+                            }
+                            if end {
+                                write!(markers, r#" marker-end="url(#{head_id})""#).unwrap();
+                            }
                         }
-                        res
+                        let path_style = style_attr(resolve(styles, &classes).path_css());
+                        writeln!(svg, r#"<path id="{id}" class="{}" d="{path}" stroke="{}" fill="none"{markers}{path_style}/>"#,
+                            escape(classes.trim_end()), escape(&theme.light.arrow)).unwrap();
                     },
                 }
             }
+            writeln!(svg, "</svg>").unwrap();
+            svg
         }
 
-        pub fn collides(a: &Rect, b: &Rect) -> bool {
-            a.r > b.l &&
-            a.l < b.r &&
-            a.b > b.t &&
-            a.t < b.b
+        impl Drawing {
+            /// This drawing as a standalone SVG document; see [as_svg()].
+            pub fn to_svg(&self) -> String {
+                as_svg(self, &SvgOptions::default())
+            }
         }
 
-        pub fn find_collisions(nodes: &Vec<Node>) -> Vec<Collision> {
-            let rects = nodes
-                .iter()
-                .flat_map(Into::<Vec<Rect>>::into)
-                .collect::<Vec<_>>();
-            let mut collisions = vec![];
-            for i in 0..rects.len() {
-                for j in i+1..rects.len() {
-                    let ri = &rects[i];
-                    let rj = &rects[j];
-                    if collides(ri, rj) {
-                        collisions.push((ri.clone(), rj.clone()))
-                    }
+        #[cfg(test)]
+        mod tests {
+            use pretty_assertions::assert_eq;
+
+            use crate::graph_drawing::frontend::{dom, measure::DefaultMeasurer};
+
+            use super::*;
+
+            #[test]
+            pub fn test_svg() {
+                assert_eq!(id("a"), "a");
+                assert_eq!(id("1 x-y"), "_1_x_y");
+
+                let drawing = dom::draw("warn: @ { fill: yellow }; a b: go; b @ warn".into()).unwrap();
+                let svg = drawing.to_svg();
+                assert!(svg.starts_with("<?xml") && svg.trim_end().ends_with("</svg>"));
+                assert!(svg.contains(&format!(r#"viewBox="-10 -10 {} {}""#, drawing.viewbox_width + 20., drawing.viewbox_height + 20.)));
+                assert!(svg.contains(r#"<title id="depict-title">Drawing</title>"#));
+                assert!(svg.contains("2 boxes: a, b; 1 arrows, labeled go."));
+                assert!(svg.contains(&drawing.theme.css()) && !svg.contains("%23"));
+                assert!(svg.contains(r#"<g id="b" class="box warn" "#) && svg.contains(r#"style="fill: yellow""#));
+                for node in drawing.nodes.iter() {
+                    assert!(svg.contains(&format!(r#" id="{}""#, id(node.key()))), "{}", node.key());
+                }
+                assert_eq!(svg.matches("<g ").count(), svg.matches("</g>").count());
+
+                // labels are drawn where their arrows made room for them
+                let arrow = drawing.nodes.iter().find(|n| matches!(n, Node::Svg{..})).unwrap();
+                let Node::Svg{dir, rel, labels, ..} = arrow else { unreachable!() };
+                let (label, rect) = (&labels[0], arrow.label_rect(0, &DefaultMeasurer::default()).unwrap());
+                let (x, y) = drawing.typography.label_anchor(label, dir, rel, 0);
+                assert!(x == rect.r && rect.t < y && y <= rect.b, "{x} {y} {rect}");
+                assert!(svg.contains(&format!(r#"transform="translate({x}, {y})""#)));
+
+                let options = SvgOptions{title: "a < b & c".into(), description: Some("\"quoted\"".into()), ..Default::default()};
+                let svg = as_svg(&drawing, &options);
+                assert!(svg.contains(">a &lt; b &amp; c</title>") && svg.contains(">&quot;quoted&quot;</desc>"));
+            }
+        }
+    }
+
+    #[cfg(any(feature="png", feature="pdf"))]
+    pub mod fonts {
+        //! Bundled fonts
+        //!
+        //! # Summary
+        //!
+        //! The [PNG](super::png) and [PDF](super::pdf) renderers set text in the DejaVu fonts bundled
+        //! with depict rather than in system fonts, so that their output looks the same everywhere,
+        //! including on headless machines with no fonts installed. Each bundled [Face] stands in for
+        //! one of CSS's generic font families.
+        use super::measure::Family;
+
+        /// A bundled font
+        #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        pub enum Face {
+            /// DejaVu Serif, for `serif`
+            Serif,
+            /// DejaVu Sans, for `sans-serif`
+            Sans,
+            /// DejaVu Sans Mono, for `monospace`
+            Mono,
+        }
+
+        impl Face {
+            /// All the bundled fonts
+            pub const ALL: [Face; 3] = [Face::Serif, Face::Sans, Face::Mono];
+
+            /// The font's family name
+            pub fn family(&self) -> &'static str {
+                match self {
+                    Face::Serif => "DejaVu Serif",
+                    Face::Sans => "DejaVu Sans",
+                    Face::Mono => "DejaVu Sans Mono",
                 }
             }
-            return collisions;
-        }
 
+            /// The font's PostScript name
+            pub fn postscript_name(&self) -> &'static str {
+                match self {
+                    Face::Serif => "DejaVuSerif",
+                    Face::Sans => "DejaVuSans",
+                    Face::Mono => "DejaVuSansMono",
+                }
+            }
 
-        #[derive(Clone, Debug)]
-        pub struct Drawing {
-            pub crossing_number: Option<usize>,
-            pub status_v: OSQPStatusKind,
-            pub status_h: OSQPStatusKind,
-            pub viewbox_width: f64,
-            pub viewbox_height: f64,
-            pub nodes: Vec<Node>,
-            pub collisions: Vec<Collision>,
-            pub logs: Vec<log::Record>,
-        }
+            /// The font's TrueType data
+            pub fn data(&self) -> &'static [u8] {
+                match self {
+                    Face::Serif => include_bytes!("../resources/fonts/DejaVuSerif.ttf"),
+                    Face::Sans => include_bytes!("../resources/fonts/DejaVuSans.ttf"),
+                    Face::Mono => include_bytes!("../resources/fonts/DejaVuSansMono.ttf"),
+                }
+            }
 
-        impl Default for Drawing {
-            fn default() -> Self {
-                Self {
-                    crossing_number: Default::default(),
-                    status_v: Default::default(),
-                    status_h: Default::default(),
-                    viewbox_width: 1024.0,
-                    viewbox_height: 400.0,
-                    nodes: Default::default(),
-                    collisions: Default::default(),
-                    logs: vec![],
+            /// The font standing in for the CSS font family list `families`; see [Family::from_css()]
+            pub fn for_css(families: &str) -> Self {
+                match Family::from_css(families) {
+                    Family::Serif => Face::Serif,
+                    Family::Sans => Face::Sans,
+                    Family::Mono => Face::Mono,
                 }
             }
         }
+    }
 
-        pub fn draw(data: String) -> Result<Drawing, Error> {
-            let mut logs = log::Logger::new();
-
-            let render_cell = super::render(Cow::Owned(data), &mut logs)?;
-            let depiction = render_cell.borrow_dependent();
-
-            let val = &depiction.val;
-            let styling = &depiction.styling;
-            let geometry_solution = &depiction.geometry_solution;
-            let rs = &depiction.geometry_solution.rs;
-            let ls = &depiction.geometry_solution.ls;
-            let ss = &depiction.geometry_solution.ss;
-            let ts = &depiction.geometry_solution.ts;
-            let bs = &depiction.geometry_solution.bs;
-            let status_v = depiction.geometry_solution.status_v;
-            let status_h = depiction.geometry_solution.status_h;
-            let varrank_by_obj = &depiction.geometry_problem.varrank_by_obj;
-            let loc_by_varrank = &depiction.geometry_problem.loc_by_varrank;
-            let loc_to_node = &depiction.layout_problem.loc_to_node;
-            let node_to_loc = &depiction.layout_problem.node_to_loc;
-            let vert = &depiction.vcg.vert;
-            let vert_node_labels = &depiction.vcg.vert_node_labels;
-            let vert_edge_labels = &depiction.vcg.vert_edge_labels;
-            let horz_edge_labels = &depiction.vcg.horz_edge_labels;
-            let hops_by_edge = &depiction.layout_problem.hops_by_edge;
-            let size_by_loc = &depiction.geometry_problem.size_by_loc;
-            let size_by_hop = &depiction.geometry_problem.size_by_hop;
-            let crossing_number = depiction.layout_solution.crossing_number;
-            let containers = &depiction.vcg.containers;
-            let container_depths = &depiction.vcg.container_depths;
-            let nesting_depths = &depiction.vcg.nesting_depths;
-            let solved_locs = &depiction.layout_solution.solved_locs;
-            let horizontal_problem = &depiction.horizontal_problem;
-            let vertical_problem = &depiction.vertical_problem;
+    #[cfg(feature="png")]
+    pub mod png {
+        //! PNG
+        //!
+        //! # Summary
+        //!
+        //! [as_png()] rasterizes the [standalone SVG](super::svg) of a [Drawing] with [resvg], for
+        //! documentation sites, chat tools, and anything else that only takes images.
+        //!
+        //! Text is set in the [bundled fonts](super::fonts) rather than in system fonts.
+        use std::sync::Arc;
 
-            let char_width = &depiction.geometry_problem.char_width.unwrap();
+        use resvg::tiny_skia::{Pixmap, Transform};
+        use resvg::usvg::{self, fontdb};
 
-            let mut texts = vec![];
+        use crate::graph_drawing::error::PngError;
 
-            // Log the resolved value
-            // logs.log_string("VAL", val);
-            logs.with_group("Eval", "", Vec::<String>::new(), |mut logs| {
-                val.log("VAL".into(), &mut logs)
-            })?;
+        use super::dom::Drawing;
+        use super::fonts::Face;
+        use super::svg::{as_svg, SvgOptions};
 
-            let l2n = |ovr, ohr| match &loc_to_node[&(ovr, ohr)] {
-                Obj::Node(ObjNode{vl: node}) => node.to_string().names(),
-                Obj::Hop(ObjHop{lvl: ovr, mhr, vl, wl}) => names![ovr, mhr, vl.to_string(), wl.to_string()],
-                Obj::Container(ObjContainer{vl}) => vl.to_string().names(),
-                Obj::Gap(ObjGap{container, ..}) => container.to_string().names(),
-            };
-            let v2n = |varrank| {
-                let loc_ix = loc_by_varrank[&varrank];
-                l2n(loc_ix.0, loc_ix.1)
-            };
+        /// Options for [as_png()]
+        #[derive(Clone, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(default))]
+        pub struct PngOptions {
+            /// Factor to enlarge the drawing by
+            pub scale: f32,
+            /// Pixels per inch, taking a CSS pixel to be 1/96 of an inch
+            pub dpi: f32,
+            /// CSS color to fill the image with, or else none, leaving it transparent
+            pub background: Option<String>,
+            /// Options for the SVG document that is rasterized
+            pub svg: SvgOptions,
+        }
+
+        impl Default for PngOptions {
+            fn default() -> Self {
+                Self {
+                    scale: 1.,
+                    dpi: 96.,
+                    background: Some("white".into()),
+                    svg: SvgOptions::default(),
+                }
+            }
+        }
 
+        impl PngOptions {
+            /// Pixels per CSS pixel
+            pub fn zoom(&self) -> f32 {
+                self.scale * self.dpi / 96.
+            }
+        }
 
-            logs.with_group("Layout", "", Vec::<String>::new(), |mut logs| {
-                loc_to_node.log((), &mut logs)?;
-                // sol_by_loc.log(l2n, &mut logs)?;
-                // sol_by_hop.log(l2n, &mut logs)?;
-                varrank_by_obj.log((), &mut logs)?;
-                // loc_by_varrank.log(l2n, &mut logs)?;
-                solved_locs.log(l2n, &mut logs)?;
-                size_by_loc.log(l2n, &mut logs)?;
-                size_by_hop.log(l2n, &mut logs)
-            })?;
-            horizontal_problem.log(("horizontal_problem".into(), v2n), &mut logs)?;
-            vertical_problem.log(("vertical_problem".into(), v2n), &mut logs)?;
-            geometry_solution.log(v2n, &mut logs)?;
+        /// The bundled fonts, standing in for the generic font families
+        pub fn fonts() -> fontdb::Database {
+            let mut fonts = fontdb::Database::new();
+            for face in Face::ALL {
+                fonts.load_font_data(face.data().to_vec());
+            }
+            fonts.set_serif_family(Face::Serif.family());
+            fonts.set_sans_serif_family(Face::Sans.family());
+            fonts.set_monospace_family(Face::Mono.family());
+            fonts.set_cursive_family(Face::Serif.family());
+            fonts.set_fantasy_family(Face::Serif.family());
+            fonts
+        }
 
-            // Render Nodes
-            let viewbox_width = (rs.values().copied().map(ordered_float::OrderedFloat).max().unwrap_or_default() - ls.values().copied().map(ordered_float::OrderedFloat).min().unwrap_or_default()).0;
-            let viewbox_height = (bs.values().copied().map(ordered_float::OrderedFloat).max().unwrap_or_default() - ts.values().copied().map(ordered_float::OrderedFloat).min().unwrap_or_default()).0;
+        /// `drawing` as a PNG image
+        pub fn as_png(drawing: &Drawing, options: &PngOptions) -> Result<Vec<u8>, PngError> {
+            let svg_options = SvgOptions{background: options.background.clone(), ..options.svg.clone()};
+            let svg = as_svg(drawing, &svg_options);
 
-            for (loc, node) in loc_to_node.iter() {
-                let (ovr, ohr) = loc;
+            let usvg_options = usvg::Options{
+                font_family: Face::Serif.family().into(),
+                fontdb: Arc::new(fonts()),
+                ..Default::default()
+            };
+            let tree = usvg::Tree::from_str(&svg, &usvg_options)?;
 
-                if let Obj::Node(ObjNode{vl}) = node {
-                    let n = varrank_by_obj[node];
+            let zoom = options.zoom();
+            let size = tree.size();
+            let (width, height) = (size.width() * zoom, size.height() * zoom);
+            let mut pixmap = Pixmap::new(width.ceil() as u32, height.ceil() as u32)
+                .ok_or(PngError::SizeError{width, height})?;
+            resvg::render(&tree, Transform::from_scale(zoom, zoom), &mut pixmap.as_mut());
+            pixmap.encode_png().map_err(|e| PngError::EncodingError{error: e.to_string()})
+        }
 
-                    // eprintln!("TEXT {vl} {ovr} {ohr} {n}");
+        impl Drawing {
+            /// This drawing as a PNG image; see [as_png()].
+            pub fn to_png(&self, options: &PngOptions) -> Result<Vec<u8>, PngError> {
+                as_png(self, options)
+            }
+        }
 
-                    let lpos = ls[&n];
-                    let rpos = rs[&n];
+        #[cfg(test)]
+        mod tests {
+            use pretty_assertions::assert_eq;
 
-                    let z_index = nesting_depths[vl];
+            use crate::graph_drawing::frontend::dom;
 
-                    let vpos = ts[&n];
-                    let width = (rpos - lpos).round();
-                    let hpos = lpos.round();
-                    let height = bs[&n] - ts[&n];
+            use super::*;
 
-                    let key = vl.to_string();
-                    let mut label = vert_node_labels
-                        .get(vl)
-                        .or_err(Kind::KeyNotFoundError{key: vl.to_string()})?
-                        .clone();
-                    if label.starts_with("_") { label = String::new(); };
-                    // if !label.is_screaming_snake_case() {
-                    //     label = label.to_title_case();
-                    // }
-                    let estimated_size = size_by_loc[&(*ovr, *ohr)].clone();
-                    let classes = itertools::join(styling.style_by_name.get(vl).iter().copied().flatten(), " ");
-                    texts.push(Node::Div{key, label, hpos, vpos, width, height, z_index, classes, loc: n, estimated_size});
+            #[test]
+            pub fn test_png() {
+                let drawing = dom::draw("a b: go".into()).unwrap();
+                let size = |png: &[u8]| (u32::from_be_bytes(png[16..20].try_into().unwrap()), u32::from_be_bytes(png[20..24].try_into().unwrap()));
+
+                let expected = |zoom: f32| (
+                    ((drawing.viewbox_width + 20.) as f32 * zoom).ceil() as u32,
+                    ((drawing.viewbox_height + 20.) as f32 * zoom).ceil() as u32,
+                );
+
+                let png = drawing.to_png(&Default::default()).unwrap();
+                assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+                assert_eq!(size(&png), expected(1.));
+
+                let options = PngOptions{scale: 2., dpi: 144., background: None, ..Default::default()};
+                assert_eq!(options.zoom(), 3.);
+                assert_eq!(size(&drawing.to_png(&options).unwrap()), expected(3.));
+            }
+        }
+    }
+
+    #[cfg(feature="pdf")]
+    pub mod pdf {
+        //! PDF
+        //!
+        //! # Summary
+        //!
+        //! [as_pdf()] writes a [Drawing] as a one-page vector PDF, for print and for formal documents.
+        //!
+        //! Boxes, containers, arrows, arrowheads, and labels are drawn directly from the drawing, in
+        //! the colors, strokes, and shapes that its [Theme](super::theme::Theme) and style blocks give
+        //! them. Text is set in the [bundled fonts](super::fonts), of which only the glyphs that the
+        //! drawing uses are embedded, along with maps back to Unicode so that the text of PDFs can
+        //! be searched and copied.
+        //!
+        //! Pages are either sized to fit the drawing or are of a standard [PageSize], in which case
+        //! drawings are centered and scaled as [Fit] directs.
+        use std::collections::btree_map::Entry;
+        use std::collections::{BTreeMap, BTreeSet};
+
+        use miniz_oxide::deflate::compress_to_vec_zlib;
+        use pdf_writer::types::{CidFontType, FontFlags, SystemInfo, TextRenderingMode};
+        use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+        use svgtypes::{Color, SimplePathSegment, SimplifyingPathParser};
+
+        use crate::graph_drawing::error::{Error, PdfError, TypeError};
+
+        use super::dom::{Drawing, Node};
+        use super::fonts::Face;
+        use super::measure::TextRole;
+        use super::styling::{resolve, ArrowStyle, Properties, Shape};
+        use super::theme::{Arrowhead, Theme};
+
+        /// Points per CSS pixel
+        const POINTS_PER_PIXEL: f32 = 0.75;
+
+        /// The size of the page
+        #[derive(Clone, Copy, Debug, Default, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(rename_all = "lowercase"))]
+        pub enum PageSize {
+            /// Just big enough for the drawing and its margins
+            #[default]
+            Drawing,
+            /// ISO A4, 210 by 297 mm
+            A4,
+            /// ISO A3, 297 by 420 mm
+            A3,
+            /// US Letter, 8.5 by 11 in
+            Letter,
+            /// US Legal, 8.5 by 14 in
+            Legal,
+            /// `width` by `height` points
+            Custom{width: f32, height: f32},
+        }
+
+        impl PageSize {
+            /// The width and height of portrait pages of this size, in points, unless sized to the drawing
+            pub fn dimensions(&self) -> Option<(f32, f32)> {
+                match self {
+                    PageSize::Drawing => None,
+                    PageSize::A4 => Some((595.28, 841.89)),
+                    PageSize::A3 => Some((841.89, 1190.55)),
+                    PageSize::Letter => Some((612., 792.)),
+                    PageSize::Legal => Some((612., 1008.)),
+                    PageSize::Custom{width, height} => Some((*width, *height)),
                 }
             }
+        }
 
-            for container in containers {
-                let container_obj = Obj::Container(ObjContainer{ vl: container.clone() });
-                let container_loc_ix = &node_to_loc[&container_obj];
-                let (ovr, ohr) = container_loc_ix;
-                let cn = varrank_by_obj[&container_obj];
-                let lpos = ls[&cn];
-                let rpos = rs[&cn];
-                let z_index = nesting_depths[container];
-                let vpos = ts[&cn];
-                let width = (rpos - lpos).round();
-                let hpos = lpos.round();
-                let height = bs[&cn] - ts[&cn];
-                // eprintln!("HEIGHT: {container} {height}");
+        impl std::fmt::Display for PageSize {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    PageSize::Drawing => f.write_str("drawing"),
+                    PageSize::A4 => f.write_str("a4"),
+                    PageSize::A3 => f.write_str("a3"),
+                    PageSize::Letter => f.write_str("letter"),
+                    PageSize::Legal => f.write_str("legal"),
+                    PageSize::Custom{width, height} => write!(f, "{width}x{height}"),
+                }
+            }
+        }
 
-                let key = format!("{container}");
-                let mut label = vert_node_labels
-                    .get(container)
-                    .or_err(Kind::KeyNotFoundError{key: container.to_string()})?
-                    .clone();
-                if label.starts_with("_") { label = String::new(); };
+        impl std::str::FromStr for PageSize {
+            type Err = Error;
 
-                let estimated_size = size_by_loc[&(*ovr, *ohr)].clone();
-                let classes = itertools::join(styling.style_by_name.get(container).iter().copied().flatten(), " ");;
-                texts.push(Node::Div{key, label, hpos, vpos, width, height, z_index, classes, loc: cn, estimated_size});
+            /// Parse a page size's name, or `WIDTHxHEIGHT` in points.
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    "drawing" => Ok(PageSize::Drawing),
+                    "a4" => Ok(PageSize::A4),
+                    "a3" => Ok(PageSize::A3),
+                    "letter" => Ok(PageSize::Letter),
+                    "legal" => Ok(PageSize::Legal),
+                    _ => s.split_once('x')
+                        .and_then(|(width, height)| Some(PageSize::Custom{width: width.parse().ok()?, height: height.parse().ok()?}))
+                        .filter(|page_size| page_size.dimensions().is_some_and(|(width, height)| width > 0. && height > 0.))
+                        .ok_or_else(|| TypeError::UnknownPageSizeError{page_size: s.into()}.into()),
+                }
+            }
+        }
+
+        /// How drawings are scaled to fit pages of a standard [PageSize]
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(rename_all = "lowercase"))]
+        pub enum Fit {
+            /// Shrink drawings that are too big for the page
+            #[default]
+            Shrink,
+            /// Shrink or enlarge drawings to fill the page
+            Fill,
+            /// Draw at natural size, a CSS pixel to 3/4 of a point, even if the page is too small
+            Natural,
+        }
+
+        impl std::fmt::Display for Fit {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(match self {
+                    Fit::Shrink => "shrink",
+                    Fit::Fill => "fill",
+                    Fit::Natural => "natural",
+                })
             }
+        }
 
-            let mut arrows = vec![];
+        impl std::str::FromStr for Fit {
+            type Err = Error;
 
-            for er in vert.edge_references() {
-                let vl = vert.node_weight(er.source()).unwrap();
-                let wl = vert.node_weight(er.target()).unwrap();
-                let ew = er.weight();
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    "shrink" => Ok(Fit::Shrink),
+                    "fill" => Ok(Fit::Fill),
+                    "natural" => Ok(Fit::Natural),
+                    _ => Err(TypeError::UnknownFitError{fit: s.into()}.into()),
+                }
+            }
+        }
 
-                if ew == "implied_vertical" {
-                    continue;
+        /// Options for [as_pdf()]
+        #[derive(Clone, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize), serde(default))]
+        pub struct PdfOptions {
+            /// Size of the page
+            pub page_size: PageSize,
+            /// Whether pages of a standard size are turned sideways
+            pub landscape: bool,
+            /// Room around the drawing, in points
+            pub margin: f32,
+            /// How the drawing is scaled to fit pages of a standard size
+            pub fit: Fit,
+            /// Title of the document
+            pub title: String,
+            /// Whether to compress the page's drawing instructions, which are otherwise left legible
+            pub compress: bool,
+        }
+
+        impl Default for PdfOptions {
+            fn default() -> Self {
+                Self {
+                    page_size: PageSize::Drawing,
+                    landscape: false,
+                    margin: 36.,
+                    fit: Fit::Shrink,
+                    title: "Drawing".into(),
+                    compress: true,
                 }
+            }
+        }
 
-                let Some(level) = vert_edge_labels.get(&(vl.clone(), wl.clone())) else {continue};
+        /// Something that a PDF paints, in the order that it paints them
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum Mark {
+            /// A box or container, of some shape, and its lines of text
+            Box{key: String, shape: Shape, face: Face, text: Vec<String>},
+            /// An arrow and the arrowheads at its ends
+            Arrow{key: String, heads: Vec<Arrowhead>},
+            /// The lines of text of an arrow's label
+            Label{key: String, face: Face, text: Vec<String>},
+        }
 
-                for (dir, dir2, labels) in &[("forward", Dir::Forward, level.forward.as_ref()), ("reverse", Dir::Reverse, level.reverse.as_ref())] {
-                    if labels.is_none() { continue; }
-                    let label_text = labels.map(|labels| labels.iter().cloned().map(|f| if f == "_" { "".into() } else { f }).collect::<Vec<_>>().join("\n"));
-                    let hops = hops_by_edge.get(&(vl.clone(), wl.clone()));
-                    let hops = if let Some(hops) = hops { hops } else { continue; };
-                    // eprintln!("vl: {}, wl: {}, hops: {:?}", vl, wl, hops);
+        /// A PDF and what it contains
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct Document {
+            /// The PDF
+            pub bytes: Vec<u8>,
+            /// Width and height of its page, in points
+            pub page: (f32, f32),
+            /// How many points wide each CSS pixel of the drawing is
+            pub scale: f32,
+            /// The fonts embedded in it, each with the characters whose glyphs it embeds
+            pub fonts: Vec<(Face, String)>,
+            /// Its page's drawing instructions, uncompressed
+            pub content: Vec<u8>,
+            /// What it paints
+            pub marks: Vec<Mark>,
+        }
+
+        /// An embedded font and the glyphs used from it
+        struct Font {
+            face: ttf_parser::Face<'static>,
+            glyphs: BTreeMap<u16, char>,
+        }
+
+        impl Font {
+            fn new(face: Face) -> Result<Self, PdfError> {
+                let font = ttf_parser::Face::parse(face.data(), 0)
+                    .map_err(|e| PdfError::FontError{font: face.family().into(), error: e.to_string()})?;
+                Ok(Self{face: font, glyphs: BTreeMap::new()})
+            }
+
+            /// Convert font units to thousandths of an em
+            fn to_milli_em(&self, units: impl Into<f32>) -> f32 {
+                units.into() * 1000. / self.face.units_per_em() as f32
+            }
+
+            /// The glyph ids of `text`, as 2-byte codes, and its width in ems
+            fn encode(&mut self, text: &str) -> (Vec<u8>, f32) {
+                let mut codes = Vec::with_capacity(2 * text.len());
+                let mut width = 0.;
+                for c in text.chars() {
+                    let glyph = self.face.glyph_index(c).unwrap_or(ttf_parser::GlyphId(0));
+                    codes.extend(glyph.0.to_be_bytes());
+                    width += self.to_milli_em(self.face.glyph_hor_advance(glyph).unwrap_or(0)) / 1000.;
+                    self.glyphs.entry(glyph.0).or_insert(c);
+                }
+                (codes, width)
+            }
+
+            /// The font's TrueType data with the outlines of only the glyphs it uses, and of the glyphs
+            /// that those are composed of, or `None` if its tables are malformed.
+            ///
+            /// Unused glyphs are emptied rather than removed, so that glyph ids, and the widths and
+            /// maps back to Unicode that refer to them, stay the same.
+            fn subset(&self) -> Option<Vec<u8>> {
+                let raw = self.face.raw_face();
+                let table = |tag: &[u8; 4]| raw.table(ttf_parser::Tag::from_bytes(tag));
+                let (head, loca, glyf) = (table(b"head")?, table(b"loca")?, table(b"glyf")?);
+                let long = read::<2>(head, 50)? == 1;
+                let offset = |glyph: usize| if long { read::<4>(loca, 4 * glyph) } else { read::<2>(loca, 2 * glyph).map(|n| 2 * n) };
+                let outline = |glyph: usize| glyf.get(offset(glyph)? as usize..offset(glyph + 1)? as usize);
+
+                // the glyphs used, .notdef, and the components of composite glyphs
+                let mut used = BTreeSet::new();
+                let mut pending = std::iter::once(0).chain(self.glyphs.keys().map(|glyph| *glyph as usize)).collect::<Vec<_>>();
+                while let Some(glyph) = pending.pop() {
+                    if !used.insert(glyph) {
+                        continue
+                    }
+                    // composite glyphs have a negative number of contours
+                    let outline = outline(glyph)?;
+                    if outline.is_empty() || read::<2>(outline, 0)? & 0x8000 == 0 {
+                        continue
+                    }
+                    let mut at = 10;
+                    loop {
+                        let (flags, component) = (read::<2>(outline, at)?, read::<2>(outline, at + 2)?);
+                        pending.push(component as usize);
+                        let args = if flags & 0x0001 != 0 { 4 } else { 2 };
+                        let transform = if flags & 0x0008 != 0 { 2 } else if flags & 0x0040 != 0 { 4 } else if flags & 0x0080 != 0 { 8 } else { 0 };
+                        at += 4 + args + transform;
+                        if flags & 0x0020 == 0 {
+                            break
+                        }
+                    }
+                }
 
-                    let offset = match *dir {
-                        x if x == "forward" => -10.0,
-                        x if x == "forward" => -10.0,
-                        x if x == "reverse" => 10.0,
-                        x if x == "reverse" => 10.0,
-                        _ => 0.0,
+                let (mut new_glyf, mut new_loca) = (vec![], vec![]);
+                for glyph in 0..self.face.number_of_glyphs() as usize {
+                    new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+                    if used.contains(&glyph) {
+                        new_glyf.extend(outline(glyph)?);
+                        new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+                    }
+                }
+                new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+                // the new loca table has long offsets; the checksum adjustment is recomputed below
+                let mut new_head = head.to_vec();
+                new_head.get_mut(8..12)?.fill(0);
+                new_head.get_mut(50..52)?.copy_from_slice(&1u16.to_be_bytes());
+
+                let mut tables = vec![];
+                for tag in EMBEDDED_TABLES {
+                    let data = match tag {
+                        b"head" => std::mem::take(&mut new_head),
+                        b"loca" => std::mem::take(&mut new_loca),
+                        b"glyf" => std::mem::take(&mut new_glyf),
+                        _ => match table(tag) {
+                            Some(data) => data.to_vec(),
+                            None => continue,
+                        },
                     };
+                    tables.push((tag, data));
+                }
 
-                    let z_index = std::cmp::max(nesting_depths[vl], nesting_depths[wl]) + 1;
-
-                    let mut path = vec![];
-                    let mut control_points = vec![];
-                    let mut label_hpos = None;
-                    let mut label_width = None;
-                    let mut label_vpos = None;
-                    // use rand::Rng;
-                    // let mut rng = rand::thread_rng();
-                    let mut hn0 = vec![];
-                    let mut estimated_size0 = None;
+                let count = tables.len() as u16;
+                let log = count.checked_ilog2()? as u16;
+                let mut out = raw.data.get(0..4)?.to_vec();
+                for field in [count, 16 << log, log, 16 * count - (16 << log)] {
+                    out.extend(field.to_be_bytes());
+                }
+                let mut offset = 12 + 16 * tables.len();
+                for (tag, data) in tables.iter() {
+                    out.extend(*tag);
+                    out.extend(checksum(data).to_be_bytes());
+                    out.extend((offset as u32).to_be_bytes());
+                    out.extend((data.len() as u32).to_be_bytes());
+                    offset += data.len().next_multiple_of(4);
+                }
+                let mut head_at = 0;
+                for (tag, data) in tables.iter() {
+                    if *tag == b"head" {
+                        head_at = out.len();
+                    }
+                    out.extend(data);
+                    out.resize(out.len().next_multiple_of(4), 0);
+                }
+                let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&out));
+                out.get_mut(head_at + 8..head_at + 12)?.copy_from_slice(&adjustment.to_be_bytes());
+                Some(out)
+            }
+        }
 
-                    let fs = container_depths.get(vl).copied().and_then(|d| d.checked_sub(1)).unwrap_or(0);
-                    let hops = if fs == 0 {
-                        hops.iter().collect::<Vec<_>>()
-                    } else {
-                        hops.iter().skip(fs).collect::<Vec<_>>()
-                    };
-                    let vmin = bs[&varrank_by_obj[&Obj::from_vl(vl, containers)]];
-                    let vmax = ts[&varrank_by_obj[&Obj::from_vl(wl, containers)]];
-                    let nh = hops.len();
-                    let vs = (0..=nh).map(|lvl|  {
-                        let fraction = lvl as f64 / nh as f64;
-                        vmin + fraction * (vmax - vmin)
-                    })
-                        .collect::<Vec<_>>();
-                    // eprintln!("vl: {vl}, wl: {wl}, fs: {fs}, vmin: {vmin}, vmax: {vmax}, nh: {nh}, vs: {vs:?}");
-                    for (n, hop) in hops.iter().enumerate() {
-                        let (lvl, (mhr, nhr)) = *hop;
-                        // let hn = sol_by_hop[&(*lvl+1, *nhr, vl.clone(), wl.clone())];
-                        let hn = varrank_by_obj[&Obj::Hop(ObjHop{lvl: *lvl, mhr: *mhr, vl: vl.clone(), wl: wl.clone()})];
-                        let spos = ss[&hn];
-                        let hnd = varrank_by_obj[&Obj::Hop(ObjHop{lvl: *lvl+1, mhr: *nhr, vl: vl.clone(), wl: wl.clone()})];
-                        let sposd = ss[&hnd];
-                        let hpos = (spos + offset).round(); // + rng.gen_range(-0.1..0.1));
-                        let hposd = (sposd + offset).round(); //  + 10. * lvl.0 as f64;
-                        // eprintln!("HOP {vl} {wl} {n} {hop:?} {lvl} {} {}", ndv, lvl_offset);
-                        let vpos = vs[n];
-                        let mut vpos2 = vs[n+1];
+        /// The tables of embedded TrueType fonts that PDF readers use
+        const EMBEDDED_TABLES: [&[u8; 4]; 9] = [b"cvt ", b"fpgm", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"prep"];
 
-                        if n == 0 {
-                            hn0.push(hn);
-                        }
-                        hn0.push(hnd);
+        /// The big-endian integer of `N` bytes at `at` in `data`, if there is one
+        fn read<const N: usize>(data: &[u8], at: usize) -> Option<u32> {
+            let bytes = data.get(at..at.checked_add(N)?)?;
+            Some(bytes.iter().fold(0, |n, byte| n << 8 | *byte as u32))
+        }
 
-                        if n == 0 {
-                            estimated_size0 = Some(size_by_hop[&(*lvl, *mhr, vl.clone(), wl.clone())].clone());
-                            let mut vpos = vpos;
-                            if *dir == "reverse" {
-                                vpos += 7.0; // box height + arrow length
-                                label_vpos = Some(vpos);
-                            } else {
-                                // vpos += 26.0;
-                            }
-                            path.push(format!("M {hpos} {vpos}"));
-                            control_points.push((hpos, vpos));
-                        }
+        /// The sum of `data` as big-endian 32-bit words, as TrueType checksums its tables
+        fn checksum(data: &[u8]) -> u32 {
+            data.chunks(4).fold(0u32, |sum, chunk| {
+                let mut word = [0; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                sum.wrapping_add(u32::from_be_bytes(word))
+            })
+        }
 
-                        if n == 0 {
-                            // let n = varrank_by_obj[&Obj::Hop(ObjHop{lvl: *lvl+1, mhr: *nhr, vl: vl.clone(), wl: wl.clone()})];
-                            let n = varrank_by_obj[&Obj::Hop(ObjHop{lvl: *lvl, mhr: *mhr, vl: vl.clone(), wl: wl.clone()})];
-                            // sol_by_loc[&((*lvl+1), *nhr)];
-                            // let n = sol_by_loc[&((*lvl), *mhr)];
-                            label_hpos = Some(match *dir {
-                                "reverse" => {
-                                    // ls[n]
-                                    hposd
-                                },
-                                "forward" => {
-                                    // ls[n]
-                                    hposd
-                                },
-                                _ => hposd + 9.
-                            });
-                            label_width = Some(match *dir {
-                                "reverse" => {
-                                    // ls[n]
-                                    rs[&n] - spos
-                                },
-                                "forward" => {
-                                    // ls[n]
-                                    spos - ls[&n]
-                                },
-                                _ => {
-                                    rs[&n] - ls[&n]
-                                }
-                            });
-                        }
+        /// The fonts used on a page
+        struct Fonts {
+            fonts: BTreeMap<Face, Font>,
+        }
 
-                        // if n < hops.len() - 1 {
-                        //     vpos2 += 26.;
-                        // }
+        impl Fonts {
+            fn name(face: Face) -> Name<'static> {
+                Name(face.postscript_name().as_bytes())
+            }
 
-                        if n == hops.len() - 1 && *dir == "forward" {
-                            vpos2 -= 7.0; // arrowhead length
-                            label_vpos = Some(vpos2 - estimated_size0.as_ref().unwrap().height);
-                            label_hpos = Some(hposd);
-                            label_width = Some(sposd - ls[&hnd]);
-                        }
+            fn get(&mut self, face: Face) -> Result<&mut Font, PdfError> {
+                Ok(match self.fonts.entry(face) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(Font::new(face)?),
+                })
+            }
 
-                        path.push(format!("L {hposd} {vpos2}"));
-                        control_points.push((hpos, vpos2));
+            /// Write the fonts, whose top-level objects are `ids`, using objects from `id` on for
+            /// their parts.
+            fn write(&self, pdf: &mut Pdf, ids: &BTreeMap<Face, Ref>, mut id: Ref) -> Result<(), PdfError> {
+                for (face, font) in self.fonts.iter() {
+                    let Some(type0_id) = ids.get(face).copied() else { continue };
+                    let (cid_id, descriptor_id, file_id, cmap_id) = (id.bump(), id.bump(), id.bump(), id.bump());
+                    let name = Self::name(*face);
+                    let system_info = SystemInfo{registry: Str(b"Adobe"), ordering: Str(b"Identity"), supplement: 0};
+
+                    pdf.type0_font(type0_id)
+                        .base_font(name)
+                        .encoding_predefined(Name(b"Identity-H"))
+                        .descendant_font(cid_id)
+                        .to_unicode(cmap_id);
+
+                    let mut cid = pdf.cid_font(cid_id);
+                    cid.subtype(CidFontType::Type2)
+                        .base_font(name)
+                        .system_info(system_info)
+                        .font_descriptor(descriptor_id)
+                        .default_width(0.)
+                        .cid_to_gid_map_predefined(Name(b"Identity"));
+                    let mut widths = cid.widths();
+                    for glyph in font.glyphs.keys() {
+                        let advance = font.face.glyph_hor_advance(ttf_parser::GlyphId(*glyph)).unwrap_or(0);
+                        widths.consecutive(*glyph, [font.to_milli_em(advance)]);
                     }
+                    widths.finish();
+                    cid.finish();
+
+                    let bbox = font.face.global_bounding_box();
+                    let mut flags = FontFlags::SYMBOLIC;
+                    if *face == Face::Serif { flags |= FontFlags::SERIF; }
+                    if font.face.is_monospaced() { flags |= FontFlags::FIXED_PITCH; }
+                    pdf.font_descriptor(descriptor_id)
+                        .name(name)
+                        .family(Str(face.family().as_bytes()))
+                        .flags(flags)
+                        .bbox(Rect::new(
+                            font.to_milli_em(bbox.x_min), font.to_milli_em(bbox.y_min),
+                            font.to_milli_em(bbox.x_max), font.to_milli_em(bbox.y_max),
+                        ))
+                        .italic_angle(0.)
+                        .ascent(font.to_milli_em(font.face.ascender()))
+                        .descent(font.to_milli_em(font.face.descender()))
+                        .cap_height(font.to_milli_em(font.face.capital_height().unwrap_or(font.face.ascender())))
+                        .stem_v(80.)
+                        .font_file2(file_id);
+
+                    let data = font.subset()
+                        .ok_or_else(|| PdfError::FontError{font: face.family().into(), error: "malformed TrueType tables".into()})?;
+                    pdf.stream(file_id, &compress_to_vec_zlib(&data, 6))
+                        .filter(Filter::FlateDecode)
+                        .pair(Name(b"Length1"), data.len() as i32);
+
+                    let mut cmap = pdf_writer::types::UnicodeCmap::new(Name(b"Custom"), system_info);
+                    for (glyph, c) in font.glyphs.iter() {
+                        cmap.pair(*glyph, *c);
+                    }
+                    pdf.cmap(cmap_id, &cmap.finish());
+                }
+                Ok(())
+            }
+        }
 
-                    let key = format!("{vl}_{wl}_{ew}_{dir}_{:?}", er.id());
-                    // let key = format!("{vl}_{wl}_{ew}_{dir}");
-                    let path = path.join(" ");
+        /// `css` as RGB components, unless it is transparent or not a color
+        fn rgb(css: &str) -> Option<(f32, f32, f32)> {
+            let color = css.trim().trim_end_matches("!important").trim().parse::<Color>().ok()?;
+            (color.alpha > 0).then(|| (color.red as f32 / 255., color.green as f32 / 255., color.blue as f32 / 255.))
+        }
 
-                    let mut label = None;
+        /// `dash`, an SVG `stroke-dasharray`, as a PDF dash pattern
+        fn dash_pattern(dash: Option<&str>) -> Vec<f32> {
+            dash.into_iter()
+                .flat_map(|dash| dash.split(|c: char| c == ',' || c.is_whitespace()))
+                .filter_map(|length| length.trim_end_matches("px").parse::<f32>().ok())
+                .collect()
+        }
 
-                    if let (Some(label_text), Some(label_hpos), Some(label_width), Some(label_vpos)) = (label_text, label_hpos, label_width, label_vpos) {
-                        let label_classes = itertools::join(
-                            styling.style_by_label.get(&(vl.clone(), wl.clone(), dir2.clone(), Cow::from(label_text.clone()))).iter().copied().flatten().chain(
-                                styling.style_by_label_star.get(&(vl.clone(), wl.clone(), dir2.clone())).iter().copied().flatten()
-                            ),
-                            " "
+        /// Is `weight`, a CSS `font-weight`, bold?
+        fn is_bold(weight: Option<&str>) -> bool {
+            match weight {
+                Some("bold" | "bolder") => true,
+                Some(weight) => weight.parse::<u32>().is_ok_and(|weight| weight >= 600),
+                None => false,
+            }
+        }
+
+        /// Add the SVG path data `path` to `content`, returning the points that define it, in order.
+        fn add_path(content: &mut Content, path: &str) -> Vec<(f32, f32)> {
+            let mut points = vec![];
+            let (mut current, mut start) = ((0., 0.), (0., 0.));
+            for segment in SimplifyingPathParser::from(path).flatten() {
+                match segment {
+                    SimplePathSegment::MoveTo{x, y} => {
+                        let (x, y) = (x as f32, y as f32);
+                        content.move_to(x, y);
+                        (current, start) = ((x, y), (x, y));
+                        points.push(current);
+                    },
+                    SimplePathSegment::LineTo{x, y} => {
+                        current = (x as f32, y as f32);
+                        content.line_to(current.0, current.1);
+                        points.push(current);
+                    },
+                    SimplePathSegment::CurveTo{x1, y1, x2, y2, x, y} => {
+                        let (x1, y1, x2, y2) = (x1 as f32, y1 as f32, x2 as f32, y2 as f32);
+                        let end = (x as f32, y as f32);
+                        content.cubic_to(x1, y1, x2, y2, end.0, end.1);
+                        points.extend([(x1, y1), (x2, y2), end]);
+                        current = end;
+                    },
+                    SimplePathSegment::Quadratic{x1, y1, x, y} => {
+                        let (x1, y1) = (x1 as f32, y1 as f32);
+                        let end = (x as f32, y as f32);
+                        let (c1, c2) = (
+                            (current.0 + 2. / 3. * (x1 - current.0), current.1 + 2. / 3. * (y1 - current.1)),
+                            (end.0 + 2. / 3. * (x1 - end.0), end.1 + 2. / 3. * (y1 - end.1)),
                         );
-                        label = Some(Label{text: label_text, classes: label_classes.into(), hpos: label_hpos, width: label_width, vpos: label_vpos})
-                    }
-                    let classes = itertools::join(styling.style_by_arrow.get(&(vl.clone(), wl.clone(), dir2.clone())).iter().copied().flatten(), " ");
-                    let classes = format!("arrow vertical {ew} {vl}_{wl} {vl}_{wl}_{ew} {classes}");
+                        content.cubic_to(c1.0, c1.1, c2.0, c2.1, end.0, end.1);
+                        points.extend([c1, c2, end]);
+                        current = end;
+                    },
+                    SimplePathSegment::ClosePath => {
+                        content.close_path();
+                        current = start;
+                    },
+                }
+            }
+            points
+        }
 
-                    arrows.push(Node::Svg{key, path, z_index, dir: "vertical".into(), rel: dir.to_string(), label, hops: hn0, classes, estimated_size: estimated_size0.unwrap(), control_points});
+        /// Add a `width` by `height` rectangle whose corners are rounded by `radius` to `content`.
+        fn add_rect(content: &mut Content, width: f32, height: f32, radius: f32) {
+            let r = radius.min(width / 2.).min(height / 2.);
+            if r <= 0. {
+                content.rect(0., 0., width, height);
+                return;
+            }
+            // control points of quarter circles lie this far from their ends
+            let k = r * (1. - 0.5523);
+            content.move_to(r, 0.);
+            content.line_to(width - r, 0.);
+            content.cubic_to(width - k, 0., width, k, width, r);
+            content.line_to(width, height - r);
+            content.cubic_to(width, height - k, width - k, height, width - r, height);
+            content.line_to(r, height);
+            content.cubic_to(k, height, 0., height - k, 0., height - r);
+            content.line_to(0., r);
+            content.cubic_to(0., k, k, 0., r, 0.);
+            content.close_path();
+        }
+
+        /// Paint `head` at `tip`, pointing from `from`, on an arrow `stroke_width` wide.
+        ///
+        /// Like the SVG markers that it stands in for, the head is drawn in a 10 by 10 box, scaled
+        /// to 7 stroke widths, whose point (0, 5) lies at the tip.
+        fn add_arrowhead(content: &mut Content, head: Arrowhead, tip: (f32, f32), from: (f32, f32), stroke_width: f32) {
+            let (dx, dy) = (tip.0 - from.0, tip.1 - from.1);
+            let length = (dx * dx + dy * dy).sqrt();
+            if length == 0. {
+                return;
+            }
+            let (cos, sin) = (dx / length, dy / length);
+            let scale = 0.7 * stroke_width;
+            content.save_state();
+            content.transform([cos * scale, sin * scale, -sin * scale, cos * scale, tip.0, tip.1]);
+            content.transform([1., 0., 0., 1., 0., -5.]);
+            content.set_line_width(1.);
+            content.set_dash_pattern([], 0.);
+            add_path(content, head.path());
+            if head.is_filled() { content.fill_nonzero_and_stroke(); } else { content.stroke(); }
+            content.restore_state();
+        }
+
+        /// The text of a box or label
+        struct Text<'a> {
+            face: Face,
+            size: f32,
+            color: Option<(f32, f32, f32)>,
+            bold: bool,
+            lines: &'a [&'a str],
+            /// Distance from each baseline to the next
+            leading: f32,
+            /// Where lines are anchored: 0 at their starts, 0.5 at their middles, and 1 at their ends
+            anchor: f32,
+            /// Width to squeeze lines to, if they are wider
+            max_width: Option<f32>,
+        }
+
+        impl Text<'_> {
+            /// Paint the lines, anchored at `x`, the first with its baseline at `y`.
+            fn paint(&self, content: &mut Content, fonts: &mut Fonts, x: f32, y: f32) -> Result<(), PdfError> {
+                let Text{leading, anchor, max_width, ..} = *self;
+                let font = fonts.get(self.face)?;
+                let (r, g, b) = self.color.unwrap_or((0., 0., 0.));
+                content.save_state();
+                content.set_fill_rgb(r, g, b);
+                if self.bold {
+                    content.set_stroke_rgb(r, g, b);
+                    content.set_line_width(self.size / 30.);
+                }
+                content.begin_text();
+                content.set_font(Fonts::name(self.face), 1.);
+                content.set_text_rendering_mode(if self.bold { TextRenderingMode::FillStroke } else { TextRenderingMode::Fill });
+                for (lineno, line) in self.lines.iter().enumerate() {
+                    let (codes, width) = font.encode(line);
+                    let width = width * self.size;
+                    let squeeze = match max_width {
+                        Some(max_width) if width > max_width && max_width > 0. => max_width / width,
+                        _ => 1.,
+                    };
+                    let (x, y) = (x - anchor * width * squeeze, y + leading * lineno as f32);
+                    content.set_text_matrix([self.size * squeeze, 0., 0., -self.size, x, y]);
+                    content.show(Str(&codes));
                 }
+                content.end_text();
+                content.restore_state();
+                Ok(())
             }
-            let forward_voffset = 6.;
-            let reverse_voffset = 20.;
+        }
 
-            for (m, ((vl, wl), lvl)) in horz_edge_labels.iter().enumerate() {
+        /// Paint the nodes of `drawing`, returning what was painted.
+        fn paint(drawing: &Drawing, content: &mut Content, fonts: &mut Fonts) -> Result<Vec<Mark>, PdfError> {
+            let Drawing{styles, theme, typography, ..} = drawing;
+            let Theme{light: palette, ..} = theme;
+            let has_class = |classes: &str, class: &str| classes.split_whitespace().any(|c| c == class);
+            let mut marks = vec![];
 
-                let z_index = std::cmp::max(nesting_depths[vl], nesting_depths[wl]) + 1;
+            // containers first, so that their contents are painted over them
+            let z_index = |node: &Node| match node {
+                Node::Div{z_index, ..} | Node::Svg{z_index, ..} => *z_index,
+            };
+            let mut nodes = drawing.nodes.iter().collect::<Vec<_>>();
+            nodes.sort_by(|a, b| z_index(a).cmp(&z_index(b)).then(a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)));
+            for node in nodes {
+                match node {
+                    Node::Div{key, label, hpos, vpos, width, height, classes, shape, ..} => {
+                        let properties = resolve(styles, classes);
+                        let Properties{stroke, fill, dash, weight, ..} = &properties;
+                        let (note, red) = (has_class(classes, "note"), has_class(classes, "red"));
+                        let dash = dash.as_deref()
+                            .or(note.then_some("4 2"))
+                            .or(has_class(classes, "legend").then_some("1 3"));
+                        let stroke = stroke.as_deref().and_then(rgb).or_else(|| rgb(&palette.stroke));
+                        let fill = fill.as_deref().or(red.then_some("red")).and_then(rgb).or_else(|| rgb(&palette.fill));
+                        let (width, height) = (*width as f32, *height as f32);
+
+                        content.save_state();
+                        content.transform([1., 0., 0., 1., *hpos as f32, *vpos as f32]);
+                        content.set_line_width(theme.box_stroke_width as f32);
+                        content.set_dash_pattern(dash_pattern(dash), 0.);
+                        if let Some((r, g, b)) = stroke { content.set_stroke_rgb(r, g, b); }
+                        if let Some((r, g, b)) = fill { content.set_fill_rgb(r, g, b); }
+                        match shape.outline(width as f64, height as f64) {
+                            Some(outline) => { add_path(content, &outline); },
+                            None => add_rect(content, width, height, shape.radius().unwrap_or(0.) as f32),
+                        }
+                        match (stroke, fill) {
+                            (Some(_), Some(_)) => { content.fill_nonzero_and_stroke(); },
+                            (Some(_), None) => { content.stroke(); },
+                            (None, Some(_)) => { content.fill_nonzero(); },
+                            (None, None) => { content.end_path(); },
+                        }
 
-                if let Some(forward) = &lvl.forward {
-                    let key = format!("{vl}_{wl}_forward_{m}");
-                    let classes = format!("arrow horizontal forward {vl}_{wl} {vl}_{wl}_forward");
-                    let locl = node_to_loc[&Obj::from_vl(vl, containers)];
-                    let locr = node_to_loc[&Obj::from_vl(wl, containers)];
-                    let nl = varrank_by_obj[&Obj::from_vl(vl, containers)];
-                    let nr = varrank_by_obj[&Obj::from_vl(wl, containers)];
-                    let lr = rs[&nl];
-                    let rl = ls[&nr] - 7.;
-                    let wl = size_by_loc[&locl].right;
-                    let wr = size_by_loc[&locr].left;
-                    let vposl = ts[&nl] + forward_voffset;
-                    let vposr = ts[&nr] + forward_voffset;
-                    let path = format!("M {} {} L {} {}", lr, vposl, rl, vposr);
-                    let control_points = vec![(lr, vposl), (rl, vposr)];
-                    let label_text = forward.iter().cloned().map(|f| if f == "_" { "".into() } else { f }).collect::<Vec<_>>().join("\n");
-                    let label_width = char_width * forward.iter().map(|f| f.len()).max().unwrap_or(0) as f64;
-                    let label = if !forward.is_empty() {
-                        Some(Label{text: label_text, classes: "".into(), hpos: rl, width: label_width, vpos: vposl })
-                    } else {
-                        None
-                    };
-                    let estimated_size = HopSize{ width: 0., left: wl, right: wr, height: 0., top: 0., bottom: 0. };
-                    arrows.push(Node::Svg{key, path, z_index, label, dir: "horizontal".into(), rel: "forward".into(), hops: vec![], classes, estimated_size, control_points });
-                }
-                if let Some(reverse) = &lvl.reverse {
-                    let key = format!("{vl}_{wl}_reverse_{m}");
-                    let classes = format!("arrow horizontal reverse {vl}_{wl} {vl}_{wl}_reverse");
-                    let locl = node_to_loc[&Obj::from_vl(vl, containers)];
-                    let locr = node_to_loc[&Obj::from_vl(wl, containers)];
-                    let nl = varrank_by_obj[&Obj::from_vl(vl, containers)];
-                    let nr = varrank_by_obj[&Obj::from_vl(wl, containers)];
-                    let lr = rs[&nl] + 7.;
-                    let rl = ls[&nr];
-                    let wl = size_by_loc[&locl].right;
-                    let wr = size_by_loc[&locr].left;
-                    let vposl = ts[&nl] + reverse_voffset;
-                    let vposr = ts[&nr] + reverse_voffset;
-                    let path = format!("M {} {} L {} {}", lr, vposl, rl, vposr);
-                    let control_points = vec![(lr, vposl), (rl, vposr)];
-                    let label_text = reverse.iter().cloned().map(|f| if f == "_" { "".into() } else { f }).collect::<Vec<_>>().join("\n");
-                    let label_width = char_width * reverse.iter().map(|f| f.len()).max().unwrap_or(0) as f64;
-                    let label = if !reverse.is_empty() {
-                        Some(Label{text: label_text, classes: "".into(), hpos: lr, width: label_width, vpos: vposl })
+                        let (face, size, role) = if note {
+                            (Face::for_css(&theme.label_font), typography.label_font_size as f32, TextRole::Label)
+                        } else {
+                            (Face::for_css(&theme.box_font), typography.box_font_size as f32, TextRole::Box)
+                        };
+                        let lines = label.lines().collect::<Vec<_>>();
+                        let color = if red { rgb("white") } else { rgb(&palette.text) };
+                        let leading = (typography.box_baseline(role, 1) - typography.box_baseline(role, 0)) as f32;
+                        let text = Text{face, size, color, bold: is_bold(weight.as_deref()), lines: &lines, leading, anchor: 0.5, max_width: Some(width - 2.)};
+                        let top = (shape.text_inset(height as f64) + typography.box_baseline(role, 0)) as f32;
+                        text.paint(content, fonts, width / 2., top)?;
+                        content.restore_state();
+
+                        marks.push(Mark::Box{key: key.clone(), shape: *shape, face, text: lines.iter().map(|line| line.to_string()).collect()});
+                    },
+                    Node::Svg{key, path, dir, rel, labels, classes, ..} => {
+                        let properties = resolve(styles, classes);
+                        let style = ArrowStyle::from_classes(classes);
+                        let color = properties.stroke.as_deref()
+                            .or(has_class(classes, "red").then_some("red"))
+                            .and_then(rgb)
+                            .or_else(|| rgb(&palette.arrow))
+                            .unwrap_or((0., 0., 0.));
+                        let stroke_width = (theme.arrow_stroke_width * style.weight.unwrap_or(1.)) as f32;
+
+                        content.save_state();
+                        content.set_stroke_rgb(color.0, color.1, color.2);
+                        content.set_fill_rgb(color.0, color.1, color.2);
+                        content.set_line_width(stroke_width);
+                        content.set_dash_pattern(dash_pattern(properties.dash.as_deref().or(style.line.dash())), 0.);
+                        let points = add_path(content, path);
+                        content.stroke();
+
+                        let head = style.arrowhead(theme.arrowhead);
+                        let (start, end) = match rel.as_str() {
+                            "forward" => (style.both, true),
+                            "reverse" => (true, style.both),
+                            _ => (false, false),
+                        };
+                        let mut heads = vec![];
+                        if head != Arrowhead::None {
+                            // arrowheads point along the ends of their arrows
+                            let first = points.first().copied();
+                            let last = points.last().copied();
+                            let after_first = points.iter().copied().find(|point| Some(*point) != first);
+                            let before_last = points.iter().rev().copied().find(|point| Some(*point) != last);
+                            if let (true, Some(tip), Some(from)) = (start, first, after_first) {
+                                add_arrowhead(content, head, tip, from, stroke_width);
+                                heads.push(head);
+                            }
+                            if let (true, Some(tip), Some(from)) = (end, last, before_last) {
+                                add_arrowhead(content, head, tip, from, stroke_width);
+                                heads.push(head);
+                            }
+                        }
+                        content.restore_state();
+                        marks.push(Mark::Arrow{key: key.clone(), heads});
+
+                        for label in labels.iter() {
+                            let label_properties = resolve(styles, &label.classes);
+                            let face = Face::for_css(&theme.label_font);
+                            let lines = label.text.lines().collect::<Vec<_>>();
+                            let (x, y) = typography.label_anchor(label, dir, rel, 0);
+                            let (x, y) = (x as f32, y as f32);
+                            let leading = typography.label_line_height as f32;
+                            let anchor = if rel == "forward" { 1. } else { 0. };
+                            let color = label_properties.stroke.as_deref().and_then(rgb).or_else(|| rgb(&palette.label));
+                            let bold = is_bold(label_properties.weight.as_deref());
+                            let label = Text{face, size: typography.label_font_size as f32, color, bold, lines: &lines, leading, anchor, max_width: None};
+                            label.paint(content, fonts, x, y)?;
+                            marks.push(Mark::Label{key: key.clone(), face, text: lines.iter().map(|line| line.to_string()).collect()});
+                        }
+                    },
+                }
+            }
+            Ok(marks)
+        }
+
+        /// `drawing` as a PDF, with what it contains
+        pub fn document(drawing: &Drawing, options: &PdfOptions) -> Result<Document, PdfError> {
+            let PdfOptions{page_size, landscape, margin, fit, title, compress} = options;
+            let (width, height) = (drawing.viewbox_width as f32, drawing.viewbox_height as f32);
+            let (natural_width, natural_height) = (width * POINTS_PER_PIXEL, height * POINTS_PER_PIXEL);
+
+            let (page, scale) = match page_size.dimensions() {
+                None => ((natural_width + 2. * margin, natural_height + 2. * margin), 1.),
+                Some((short, long)) => {
+                    let page = if *landscape { (long, short) } else { (short, long) };
+                    let fill = if natural_width > 0. && natural_height > 0. {
+                        ((page.0 - 2. * margin) / natural_width).min((page.1 - 2. * margin) / natural_height).max(0.)
                     } else {
-                        None
+                        1.
                     };
-                    let estimated_size = HopSize{ width: 0., left: wl, right: wr, height: 0., top: 0., bottom: 0. };
-                    arrows.push(Node::Svg{key, path, z_index, label, dir: "horizontal".into(), rel: "reverse".into(), hops: vec![], classes, estimated_size, control_points });
-                }
+                    let scale = match fit {
+                        Fit::Shrink => fill.min(1.),
+                        Fit::Fill => fill,
+                        Fit::Natural => 1.,
+                    };
+                    (page, scale)
+                },
+            };
+            let scale = scale * POINTS_PER_PIXEL;
+
+            let mut content = Content::new();
+            let mut fonts = Fonts{fonts: BTreeMap::new()};
+            // center the drawing, turning it right side up since PDF's y axis points up
+            let (left, top) = ((page.0 - width * scale) / 2., (page.1 - height * scale) / 2.);
+            content.transform([scale, 0., 0., -scale, left, page.1 - top]);
+            let marks = paint(drawing, &mut content, &mut fonts)?;
+            let content = content.finish();
+
+            let mut pdf = Pdf::new();
+            let mut id = Ref::new(1);
+            let (catalog_id, pages_id, page_id, content_id, info_id) = (id.bump(), id.bump(), id.bump(), id.bump(), id.bump());
+            pdf.catalog(catalog_id).pages(pages_id);
+            pdf.pages(pages_id).kids([page_id]).count(1);
+
+            let font_ids = fonts.fonts.keys().map(|face| (*face, id.bump())).collect::<BTreeMap<_, _>>();
+            let mut pdf_page = pdf.page(page_id);
+            pdf_page.media_box(Rect::new(0., 0., page.0, page.1))
+                .parent(pages_id)
+                .contents(content_id);
+            let mut resources = pdf_page.resources();
+            let mut resource_fonts = resources.fonts();
+            for (face, font_id) in font_ids.iter() {
+                resource_fonts.pair(Fonts::name(*face), *font_id);
+            }
+            resource_fonts.finish();
+            resources.finish();
+            pdf_page.finish();
+
+            if *compress {
+                pdf.stream(content_id, &compress_to_vec_zlib(&content, 6)).filter(Filter::FlateDecode);
+            } else {
+                pdf.stream(content_id, &content);
+            }
+            let embedded = fonts.fonts.iter().map(|(face, font)| (*face, font.glyphs.values().collect())).collect();
+            fonts.write(&mut pdf, &font_ids, id)?;
+
+            pdf.document_info(info_id)
+                .title(TextStr(title))
+                .subject(TextStr(&super::svg::summary(drawing)))
+                .producer(TextStr("depict"));
+
+            Ok(Document{
+                bytes: pdf.finish(),
+                page,
+                scale,
+                fonts: embedded,
+                content,
+                marks,
+            })
+        }
+
+        /// `drawing` as a PDF
+        pub fn as_pdf(drawing: &Drawing, options: &PdfOptions) -> Result<Vec<u8>, PdfError> {
+            Ok(document(drawing, options)?.bytes)
+        }
+
+        impl Drawing {
+            /// This drawing as a PDF; see [as_pdf()].
+            pub fn to_pdf(&self, options: &PdfOptions) -> Result<Vec<u8>, PdfError> {
+                as_pdf(self, options)
             }
+        }
 
-            let mut nodes = texts
-                .into_iter()
-                .chain(arrows.into_iter())
-                .collect::<Vec<_>>();
+        #[cfg(test)]
+        mod tests {
+            use std::fmt::Write;
 
-            nodes.sort_by_key(|node| match node {
-                Node::Div{z_index, ..} => *z_index,
-                Node::Svg{z_index, ..} => *z_index,
-            });
+            use pretty_assertions::assert_eq;
 
-            let collisions = find_collisions(&nodes);
-            let mut colliding_rects = collisions.iter().flat_map(|(ri, rj)| vec![ri, rj]).collect::<Vec<_>>();
-            colliding_rects.sort_by_key(|r| &r.id);
-            colliding_rects.dedup();
+            use crate::graph_drawing::frontend::{dom, tests::{small_diagrams, KNOWN_BAD}, RenderOptions};
 
-            logs.with_group("Visual Elements", "", Vec::<String>::new(), |logs| {
-                logs.with_set("Nodes", "", &nodes, |node, logs| {
-                    logs.log_element("Node", Vec::<String>::new(), format!("{node:#?}"))
-                })?;
-                logs.with_set("Collisions", "", &colliding_rects, |r, logs| {
-                    logs.log_element("Rect", Vec::<String>::new(), r.to_string())
-                })
-            })?;
-            let mut logs = logs.to_vec();
-            logs.reverse();
+            use super::*;
 
-            // eprintln!("NODES: {nodes:#?}");
+            #[test]
+            pub fn test_pdf() {
+                assert_eq!("a4".parse::<PageSize>().unwrap(), PageSize::A4);
+                assert_eq!("200x100".parse::<PageSize>().unwrap(), PageSize::Custom{width: 200., height: 100.});
+                assert!("0x100".parse::<PageSize>().is_err() && "tabloid".parse::<PageSize>().is_err());
+                assert_eq!("fill".parse::<Fit>().unwrap(), Fit::Fill);
+                assert!("stretch".parse::<Fit>().is_err());
+
+                let drawing = dom::draw("a b: go".into()).unwrap();
+                let doc = document(&drawing, &Default::default()).unwrap();
+                assert!(doc.bytes.starts_with(b"%PDF-") && doc.bytes.trim_ascii_end().ends_with(b"%%EOF"));
+                for needle in ["/FontFile2", "/ToUnicode", "/Identity-H", "/DejaVuSerif", "/DejaVuSansMono"] {
+                    assert!(doc.bytes.windows(needle.len()).any(|w| w == needle.as_bytes()), "{needle}");
+                }
+                assert_eq!(doc.page, (drawing.viewbox_width as f32 * 0.75 + 72., drawing.viewbox_height as f32 * 0.75 + 72.));
+                assert_eq!(doc.fonts, vec![(Face::Serif, "ab".into()), (Face::Mono, "go".into())]);
+                assert!(doc.bytes.len() < 20_000, "{}", doc.bytes.len());
+                let arrow = drawing.nodes.iter().find(|n| matches!(n, Node::Svg{..})).unwrap().key().to_string();
+                assert_eq!(doc.marks, vec![
+                    Mark::Box{key: "a".into(), shape: Shape::Rect, face: Face::Serif, text: vec!["a".into()]},
+                    Mark::Box{key: "b".into(), shape: Shape::Rect, face: Face::Serif, text: vec!["b".into()]},
+                    Mark::Arrow{key: arrow.clone(), heads: vec![Arrowhead::Triangle]},
+                    Mark::Label{key: arrow, face: Face::Mono, text: vec!["go".into()]},
+                ]);
+
+                let options = PdfOptions{page_size: PageSize::A4, landscape: true, fit: Fit::Fill, compress: false, ..Default::default()};
+                let doc = document(&drawing, &options).unwrap();
+                assert_eq!(doc.page, (841.89, 595.28));
+                assert!(doc.scale > 0.75);
+                assert!(doc.bytes.windows(3).any(|w| w == b"Tj\n"));
+                let options = PdfOptions{page_size: PageSize::Custom{width: 20., height: 20.}, margin: 0., ..Default::default()};
+                let doc = document(&drawing, &options).unwrap();
+                assert!(doc.scale < 0.75 && drawing.viewbox_height as f32 * doc.scale <= 20.);
+
+                // text is set at the sizes it was measured at
+                let options = RenderOptions{box_font_size: 32., ..Default::default()};
+                let drawing = dom::draw_with("a b: go".into(), &Default::default(), &options).unwrap();
+                let content = String::from_utf8(document(&drawing, &Default::default()).unwrap().content).unwrap();
+                assert!(content.contains("32 0 0 -32 ") && content.contains("14 0 0 -14 "), "{content}");
+            }
 
-            Ok(Drawing{
-                crossing_number: Some(crossing_number),
-                status_v,
-                status_h,
-                viewbox_width,
-                viewbox_height,
-                nodes,
-                collisions,
-                logs,
-            })
+            #[test]
+            pub fn test_font_subset() {
+                let mut font = Font::new(Face::Serif).unwrap();
+                font.encode("aé");
+                let data = font.subset().unwrap();
+                assert!(data.len() < Face::Serif.data().len() / 10, "{}", data.len());
+                let subset = ttf_parser::Face::parse(&data, 0).unwrap();
+                let glyph = |c| font.face.glyph_index(c).unwrap();
+                assert_eq!(subset.number_of_glyphs(), font.face.number_of_glyphs());
+                // the glyphs used, and the components of composite ones, keep their outlines
+                for c in ['a', 'é', 'e'] {
+                    assert_eq!(subset.glyph_bounding_box(glyph(c)), font.face.glyph_bounding_box(glyph(c)), "{c}");
+                }
+                assert!(subset.glyph_bounding_box(glyph('z')).is_none());
+                assert_eq!(subset.glyph_hor_advance(glyph('z')), font.face.glyph_hor_advance(glyph('z')));
+            }
+
+            #[test]
+            pub fn test_pdf_small_diagrams() {
+                // round coordinates so that the snapshot does not depend on the solver's last digits
+                let round = |token: &str| match token.parse::<f64>() {
+                    Ok(n) if token.contains('.') => format!("{:.2}", if n.abs() < 0.005 { 0. } else { n }),
+                    _ => token.to_string(),
+                };
+                let options = PdfOptions{page_size: PageSize::A4, ..Default::default()};
+                let mut pages = String::new();
+                for (prompt, _) in small_diagrams() {
+                    if KNOWN_BAD.contains(&prompt) { continue; }
+                    let drawing = dom::draw(prompt.into()).unwrap();
+                    let doc = document(&drawing, &options).unwrap();
+                    writeln!(pages, "{prompt}\n  page: {} x {}, scale: {}", round(&doc.page.0.to_string()), round(&doc.page.1.to_string()), doc.scale).unwrap();
+                    for (face, chars) in doc.fonts.iter() {
+                        writeln!(pages, "  font {}: {chars:?}", face.postscript_name()).unwrap();
+                    }
+                    for line in String::from_utf8(doc.content).unwrap().lines() {
+                        writeln!(pages, "    {}", line.split(' ').map(round).collect::<Vec<_>>().join(" ")).unwrap();
+                    }
+                }
+                insta::assert_snapshot!(pages);
+            }
         }
     }
 
@@ -6635,152 +12471,82 @@ pub mod frontend {
         use dioxus::prelude::*;
 
         use super::dom::{Drawing, Node, Label};
+        use super::styling::{stylesheet, ArrowStyle};
+        use super::theme::Arrowhead;
 
-        use svg::{Document, node::{element::{Group, Marker, Path, Rectangle, Style, Text as TextElt}, Node as _, Text}};
-
-        use std::io::BufWriter;
-
+        /// `drawing` as a `data:` URL of a standalone SVG document (see [as_svg()](super::svg::as_svg)),
+        /// whose `#`s are escaped if `urlencode` is set
         pub fn as_data_svg(drawing: Drawing, urlencode: bool) -> String {
-            let viewbox_width = drawing.viewbox_width + 20.;
-            let viewbox_height = drawing.viewbox_height + 20.;
-            let mut nodes = drawing.nodes;
-
-            let mut svg = Document::new()
-                // .set("viewBox", (-20f64, -20f64, viewbox_width+40., viewbox_height+20.))
-                .set("width", format!("{viewbox_width}"))
-                .set("height", format!("{viewbox_height}"))
-                .set("text-depiction", "optimizeLegibility")
-                .set("preserveAspectRatio", "xMidyMid");
-
-            let escaped_css = DEFAULT_CSS.replace("#", "%23");
-            svg.append(Style::new(format!("<![CDATA[\n{escaped_css}\n]]>")));
-
-            svg.append(Marker::new()
-                .set("id", "arrowhead")
-                .set("markerWidth", 7)
-                .set("markerHeight", 10)
-                .set("refX", 0)
-                .set("refY", 5)
-                .set("orient", "auto")
-                .set("viewBox", "0 0 10 10")
-                .add(Path::new()
-                    .set("d", "M 0 0 L 10 5 L 0 10 z")
-                    .set("fill", "black")
-                )
-            );
-            svg.append(Marker::new()
-                .set("id", "arrowheadrev")
-                .set("markerWidth", 7)
-                .set("markerHeight", 10)
-                .set("refX", 0)
-                .set("refY", 5)
-                .set("orient", "auto-start-reverse")
-                .set("viewBox", "0 0 10 10")
-                .add(Path::new()
-                    .set("d", "M 0 0 L 10 5 L 0 10 z")
-                    .set("fill", "black")
-                )
-            );
-
-            nodes.sort_by(|a, b| a.partial_cmp(b).unwrap());
-            for node in nodes {
-                match node {
-                    Node::Div{label, hpos, vpos, width, height, classes, ..} => {
-                            svg.append(Group::new()
-                                .set("transform", format!("translate({hpos}, {vpos})"))
-                                .set("class", format!("box {classes}"))
-                                .add(Rectangle::new()
-                                    .set("width", width)
-                                    .set("height", height)
-                                    .set("viewBox", (-20f64, -20f64, viewbox_width+40., viewbox_height+20.))
-                                )
-                                .add(TextElt::new()
-                                    .set("text-anchor", "middle")
-                                    .set("transform", format!("translate({}, {})", width / 2., 16.))
-                                    .add(Text::new(label)))
-                            );
-                    },
-                    Node::Svg{path, dir, rel, label, classes, ..} => {
-
-                        let mut path_elt = Path::new()
-                            .set("class", classes)
-                            .set("d", path)
-                            .set("stroke", "black")
-                            .set("fill", "none")
-                            .set("viewBox", (-20f64, -20f64, viewbox_width+40., viewbox_height+20.))
-                            .set("vector-effect", "non-scaling-stroke")
-                            ;
-
-                        let octothorpe = if urlencode { "%23" } else { "#" };
-                        match rel.as_str() {
-                            "forward" => path_elt = path_elt.set("marker-end", format!("url({octothorpe}arrowhead)")),
-                            "reverse" => path_elt = path_elt.set("marker-start", format!("url({octothorpe}arrowheadrev)")),
-                            _ => {},
-                        };
-
-                        if let Some(Label{text, classes: label_classes, hpos, width: _, vpos, ..}) = label {
-                            for (lineno, line) in text.lines().enumerate() {
-                                let translate = match (dir.as_ref(), rel.as_ref()) {
-                                    ("vertical", "forward") => format!("translate({}, {})", hpos-12., vpos + 16. + (20. * lineno as f64)),
-                                    ("vertical", "reverse") => format!("translate({}, {})", hpos+12., vpos + 16. + (20. * lineno as f64)),
-                                    ("horizontal", "forward") => format!("translate({}, {})", hpos, vpos - 10. - 20. * lineno as f64),
-                                    ("horizontal", "reverse") => format!("translate({}, {})", hpos, vpos + 20. + 20. * lineno as f64),
-                                    ("vertical", "fake") => format!("translate({}, {})", hpos, vpos + 20.),
-                                    _ => format!("translate({}, {})", hpos, (vpos + 20. * lineno as f64)),
-                                };
-                                let anchor = match rel.as_ref() {
-                                    "forward" => "end",
-                                    _ => "start",
-                                };
-                                svg.append(Group::new()
-                                    .set("class", format!("label {dir} {rel} {label_classes}"))
-                                    .add(TextElt::new()
-                                        .set("transform", translate)
-                                        .set("text-anchor", anchor)
-                                        .add(Text::new(line))
-                                    )
-                                );
-                            }
-                        }
-
-                        svg.append(Group::new()
-                            .add(path_elt)
-                        );
-                    },
-                }
-            }
-
-            let mut buf = BufWriter::new(Vec::new());
-            svg::write(&mut buf, &svg).unwrap();
-            let bytes = buf.into_inner().unwrap();
-            let svg_str = String::from_utf8(bytes).unwrap();
-            // eprintln!("SVG {svg_str}");
-            format!(r#"data:image/svg+xml;utf8,<?xml version="1.0" standalone="no"?><!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd">{svg_str}"#)
+            let svg = super::svg::as_svg(&drawing, &Default::default());
+            let svg = if urlencode { svg.replace('#', "%23") } else { svg };
+            format!("data:image/svg+xml;utf8,{svg}")
         }
 
         pub fn render<P>(cx: Scope<P>, drawing: Drawing)-> Option<VNode> {
             let viewbox_width = drawing.viewbox_width;
+            let css = format!("{}{}\n{}", drawing.theme.css(), drawing.typography.css(), stylesheet(&drawing.styles));
+            let default_head = drawing.theme.arrowhead;
             let mut nodes = drawing.nodes;
             let viewbox_height = 768;
             let mut children = vec![];
+            children.push(cx.render(rsx!{
+                style { "{css}" }
+            }));
             nodes.sort_by(|a, b| a.partial_cmp(b).unwrap());
             for node in nodes {
                 match node {
-                    Node::Div{key, label, hpos, vpos, width, height, z_index, classes, ..} if label.len() > 0 => {
-                        children.push(cx.render(rsx! {
-                            div {
-                                key: "{key}",
-                                class: "box highlight_{label} {classes}",
-                                style: "position: absolute; top: {vpos}px; left: {hpos}px; width: {width}px; height: {height}px; z-index: {z_index}; padding-top: 3px; padding-bottom: 3px; box-sizing: border-box; border: 1px solid black; text-align: center;", // bg-opacity-50
-                                span {
-                                    "{label}"
-                                }
-                            }
-                        }));
+                    Node::Div{key, label, hpos, vpos, width, height, z_index, classes, shape, ..} if label.len() > 0 => {
+                        match shape.outline(width, height) {
+                            Some(outline) => {
+                                // shaped boxes draw their outlines in place of their borders and backgrounds
+                                let padding_top = 3. + shape.text_inset(height);
+                                children.push(cx.render(rsx! {
+                                    div {
+                                        key: "{key}",
+                                        class: "box highlight_{key} {classes}",
+                                        style: "position: absolute; top: {vpos}px; left: {hpos}px; width: {width}px; height: {height}px; z-index: {z_index}; padding-top: {padding_top}px; padding-bottom: 3px; box-sizing: border-box; border-style: none !important; background: none !important; text-align: center; white-space: pre-line;",
+                                        svg {
+                                            style: "position: absolute; top: 0px; left: 0px; overflow: visible; z-index: -1;",
+                                            width: "{width}px",
+                                            height: "{height}px",
+                                            path {
+                                                class: "outline",
+                                                d: "{outline}",
+                                            }
+                                        }
+                                        span {
+                                            "{label}"
+                                        }
+                                    }
+                                }));
+                            },
+                            None => {
+                                children.push(cx.render(rsx! {
+                                    div {
+                                        key: "{key}",
+                                        class: "box highlight_{key} {classes}",
+                                        style: "position: absolute; top: {vpos}px; left: {hpos}px; width: {width}px; height: {height}px; z-index: {z_index}; padding-top: 3px; padding-bottom: 3px; box-sizing: border-box; border-style: solid; text-align: center; white-space: pre-line;", // bg-opacity-50
+                                        span {
+                                            "{label}"
+                                        }
+                                    }
+                                }));
+                            },
+                        }
                     },
-                    Node::Svg{key, path, z_index, dir, rel, label, classes, ..} => {
-                        let marker_id = if rel == "forward" { "arrowhead" } else { "arrowheadrev" };
-                        let marker_orient = if rel == "forward" { "auto" } else { "auto-start-reverse" };
+                    Node::Svg{key, path, z_index, dir, rel, labels, classes, ..} => {
+                        // every arrow defines its own markers, since arrows' heads may differ
+                        let arrow_style = ArrowStyle::from_classes(&classes);
+                        let head = arrow_style.arrowhead(default_head);
+                        let arrowhead = head.path();
+                        let head_class = head.name();
+                        let marker_id = key.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+                        let (start, end) = match (head, rel.as_str()) {
+                            (Arrowhead::None, _) => (false, false),
+                            (_, "forward") => (arrow_style.both, true),
+                            (_, "reverse") => (true, arrow_style.both),
+                            _ => (false, false),
+                        };
                         // let stroke_dasharray = if rel == "fake" { "5 5" } else { "none" };
                         // let stroke_color = if rel == "fake" { "hsl(0, 0%, 50%)" } else { "currentColor" };
                         children.push(cx.render(rsx!{
@@ -6798,29 +12564,50 @@ pub mod frontend {
                                     width: "{viewbox_width}px",
                                     height: "{viewbox_height}px",
                                     marker {
-                                        id: "{marker_id}",
+                                        id: "{marker_id}_head",
+                                        class: "{head_class}",
+                                        marker_width: "7",
+                                        marker_height: "10",
+                                        ref_x: "0",
+                                        ref_y: "5",
+                                        orient: "auto",
+                                        view_box: "0 0 10 10",
+                                        path {
+                                            d: "{arrowhead}",
+                                        }
+                                    }
+                                    marker {
+                                        id: "{marker_id}_tail",
+                                        class: "{head_class}",
                                         marker_width: "7",
                                         marker_height: "10",
                                         ref_x: "0",
                                         ref_y: "5",
-                                        orient: "{marker_orient}",
+                                        orient: "auto-start-reverse",
                                         view_box: "0 0 10 10",
                                         path {
-                                            d: "M 0 0 L 10 5 L 0 10 z",
+                                            d: "{arrowhead}",
                                         }
                                     }
                                     {
-                                        match rel.as_str() {
-                                            "forward" => {
+                                        match (start, end) {
+                                            (true, true) => {
+                                                rsx!(path {
+                                                    d: "{path}",
+                                                    "marker-start": "url(#{marker_id}_tail)",
+                                                    marker_end: "url(#{marker_id}_head)",
+                                                })
+                                            },
+                                            (false, true) => {
                                                 rsx!(path {
                                                     d: "{path}",
-                                                    marker_end: "url(#arrowhead)",
+                                                    marker_end: "url(#{marker_id}_head)",
                                                 })
                                             },
-                                            "reverse" => {
+                                            (true, false) => {
                                                 rsx!(path {
                                                     d: "{path}",
-                                                    "marker-start": "url(#arrowheadrev)",
+                                                    "marker-start": "url(#{marker_id}_tail)",
                                                     // marker_start: "url(#arrowhead)", // BUG: should work, but doesn't.
                                                 })
                                             },
@@ -6833,35 +12620,32 @@ pub mod frontend {
                                         }
                                     }
                                 }
-                                {match label {
-                                    Some(Label{text, classes, hpos, width: _, vpos}) => {
-                                        let translate = match (dir.as_ref(), rel.as_ref()) {
-                                            ("vertical", "forward") => "translate(calc(-100% - 1.5ex))",
-                                            ("horizontal", "forward") => "translate(calc(-100% - 1.5ex), calc(-100% + 20px))",
-                                            (_, "reverse") => "translate(1.5ex)",
-                                            _ => "translate(0px, 0px)",
-                                        };
-                                        let offset = match (dir.as_ref(), rel.as_ref()) {
-                                            ("horizontal", "forward") => "-24px",
-                                            ("horizontal", "reverse") => "4px",
-                                            _ => "0px",
-                                        };
-                                        // let border = match rel.as_str() {
-                                        //     // "actuates" => "border border-red-300",
-                                        //     // "senses" => "border border-blue-300",
-                                        //     _ => "",
-                                        // };
-                                        rsx!(div {
-                                            style: "position: absolute; left: {hpos}px; top: calc({vpos}px + {offset});", // width: "{width}px",
-                                            div {
-                                                class: "label {dir} {rel} {classes}",
-                                                style: "transform: {translate};",
-                                                "{text}"
-                                            }
-                                        })
-                                    },
-                                    _ => rsx!(div {}),
-                                }}
+                                {labels.into_iter().map(|Label{text, classes, hpos, width: _, vpos}| {
+                                    let translate = match (dir.as_ref(), rel.as_ref()) {
+                                        ("vertical", "forward") => "translate(calc(-100% - 1.5ex))",
+                                        ("horizontal", "forward") => "translate(calc(-100% - 1.5ex), calc(-100% + 20px))",
+                                        (_, "reverse") => "translate(1.5ex)",
+                                        _ => "translate(0px, 0px)",
+                                    };
+                                    let offset = match (dir.as_ref(), rel.as_ref()) {
+                                        ("horizontal", "forward") => "-24px",
+                                        ("horizontal", "reverse") => "4px",
+                                        _ => "0px",
+                                    };
+                                    // let border = match rel.as_str() {
+                                    //     // "actuates" => "border border-red-300",
+                                    //     // "senses" => "border border-blue-300",
+                                    //     _ => "",
+                                    // };
+                                    rsx!(div {
+                                        style: "position: absolute; left: {hpos}px; top: calc({vpos}px + {offset});", // width: "{width}px",
+                                        div {
+                                            class: "label {dir} {rel} {classes}",
+                                            style: "transform: {translate};",
+                                            "{text}"
+                                        }
+                                    })
+                                })}
                             }
                         }));
                     },
@@ -7104,10 +12888,8 @@ pub mod frontend {
             })
         }
 
-        pub const DEFAULT_CSS: &'static str = r#"
-            .arrow svg { stroke: black; }
-            path { stroke-dasharray: none; }
-            marker path { fill: #000; }
+        /// CSS for the chrome of depict's apps, around drawings styled by [Theme::css()](super::theme::Theme::css)
+        pub const APP_CSS: &'static str = r#"
             .main_editor {
                 box-sizing: border-box;
                 position: fixed;
@@ -7123,21 +12905,6 @@ pub mod frontend {
             .keyword { font-weight: bold; color: rgb(207, 34, 46); }
             .example { font-size: 0.625rem; font-family: ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,"Liberation Mono","Courier New",monospace; }
             .svg text { stroke: none; fill: black; }
-            div.label { padding: 2px 0px; white-space: pre; z-index: 50; background-color: white; box-sizing: border-box; font-size: .875rem; line-height: 1.25rem; font-family: ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,"Liberation Mono","Courier New",monospace; }
-            g.box rect {
-                stroke: black;
-                fill: none;
-            }
-            g.box text {
-                font-family: serif;
-                fill: black;
-                stroke: none;
-            }
-            g.label {
-                font-family: ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,"Liberation Mono","Courier New",monospace;
-                fill: black;
-                stroke: none;
-            }
 
             @media only screen and (max-width: 575.98px) {
                 .main_editor > div { max-width: calc(100% - 2em); }
@@ -7150,48 +12917,9 @@ pub mod frontend {
                 a { color: #809fff; }
                 textarea { background-color: #aaa; color: #000; }
                 .main_editor { background-color: rgba(32, 32, 32, 0.7); }
-                .box { color: black; background-color: #aaa; }
-                .arrow { color: #eee; }
-                .arrow svg { stroke: #eee; }
-                .arrow div div { color: black; background-color: #aaa; padding: 0px 2px; }
                 .svg text { fill: #aaa; }
                 .svg ellipse { stroke: #aaa; }
-                marker path { fill: #eee; }
-            }
-
-            .red {
-                color: white;
-                background-color: red;
-            }
-            .arrow.red {
-                background-color: inherit;
-            }
-            .arrow.red path {
-                stroke: red;
-            }
-            .arrow.red marker path {
-                fill: red;
-            }
-            g.box.red rect {
-                fill: red;
-            }
-            g.box.red text {
-                fill: white;
-            }
-            @media (prefers-color-scheme: dark) {
-                .red {
-                    background-color: red;
-                    color: black;
-                }
-                div.red.label {
-                    background-color: red;
-                }
-                g.box.red text {
-                    fill: black;
-                }
             }
-
-            .hidden { display: none; }
         "#;
     }
 
@@ -7199,13 +12927,13 @@ pub mod frontend {
     #[cfg(test)]
     mod tests {
         use pretty_assertions::{assert_eq};
-        use crate::graph_drawing::{error::Error, frontend::dom::{Node, find_collisions}};
+        use crate::graph_drawing::{error::Error, frontend::{dom::{Node, find_collisions}, measure::DefaultMeasurer}};
 
         use super::dom::{Drawing, Rect};
 
         use std::fmt::{Debug, Display};
 
-        trait Check: Debug {
+        pub(super) trait Check: Debug {
             fn check(&self, drawing: &Result<Drawing, Error>);
         }
 
@@ -7215,8 +12943,19 @@ pub mod frontend {
         impl Check for NoCollisions {
             fn check(&self, drawing: &Result<Drawing, Error>) {
                 let Drawing{nodes, ..} = drawing.as_ref().unwrap();
-                let collisions = find_collisions(nodes);
-                assert!(collisions.is_empty());
+                let collisions = find_collisions(nodes, &DefaultMeasurer::default());
+                assert!(collisions.is_empty(), "collisions: {collisions:?}");
+            }
+        }
+
+        /// Like [NoCollisions] but excusing the overlaps that [Exemptions](super::route::Exemptions) allow
+        #[derive(Debug)]
+        struct NoUnexpectedCollisions {}
+
+        impl Check for NoUnexpectedCollisions {
+            fn check(&self, drawing: &Result<Drawing, Error>) {
+                let Drawing{collisions, ..} = drawing.as_ref().unwrap();
+                assert!(collisions.is_empty(), "unexpected collisions: {collisions:?}");
             }
         }
 
@@ -7226,7 +12965,7 @@ pub mod frontend {
         impl<'a, V: Clone + Debug + Display> Check for OnlyCollisions<'a, V> {
             fn check(&self, drawing: &Result<Drawing, Error>) {
                 let Drawing{nodes, ..} = drawing.as_ref().unwrap();
-                let collisions = find_collisions(nodes);
+                let collisions = find_collisions(nodes, &DefaultMeasurer::default());
                 let mut expected_collisions = self.0.clone().into_iter().map(|(a,b)| (a.to_string(), b.to_string())).collect::<Vec<_>>();
                 expected_collisions.sort();
                 expected_collisions.dedup();
@@ -7263,7 +13002,12 @@ pub mod frontend {
             }
         }
 
-        fn rect(nodes: &Vec<Node>, key: &str) -> Rect {
+        /// The box with key `key` in `drawing`
+        pub(super) fn div<'a>(drawing: &'a Drawing, key: &str) -> &'a Node {
+            drawing.nodes.iter().find(|n| matches!(n, Node::Div{key: k, ..} if k == key)).unwrap()
+        }
+
+        pub(super) fn rect(nodes: &Vec<Node>, key: &str) -> Rect {
             let node = nodes.iter().find(|n| n.key() == key).unwrap();
             <&Node as Into<Vec<Rect>>>::into(node).first().unwrap().clone()
         }
@@ -7351,9 +13095,8 @@ pub mod frontend {
             }
         }
 
-        #[test]
-        pub fn test_small_diagrams() {
-            let tests: Vec<(&str, Vec<&dyn Check>)> = vec![
+        pub(super) fn small_diagrams() -> Vec<(&'static str, Vec<&'static dyn Check>)> {
+            vec![
                 ("a b", vec![&Above("a", "b")]),
                 ("b a", vec![&Above("b", "a")]),
                 ("a ; b", vec![]),
@@ -7394,13 +13137,43 @@ pub mod frontend {
                 ("a b; a c; a e; e d; b c d f -", vec![&Above("a", "b"), &Above("a", "c"), &Above("a", "e"), &Above("e", "d"), &Left("b", "c"), &Left("c", "d"), &Left("d", "f")]),
                 ("a b; a c; a e; e d; b c f g -", vec![&Above("a", "b"), &Above("a", "c"), &Above("a", "e"), &Above("e", "d"), &Left("b", "c"), &Left("c", "f"), &Left("f", "g")]),
 
-            ];
-            for (prompt, checks) in tests {
+            ]
+        }
+
+        #[test]
+        pub fn test_small_diagrams() {
+            for (prompt, checks) in small_diagrams() {
                 eprintln!("PROMPT: {prompt}. CHECKS: {checks:?}");
                 check(prompt, checks);
             }
         }
 
+        /// Prompts of [small_diagrams()] that no layout handles yet
+        pub(super) const KNOWN_BAD: [&str; 2] = [
+            // no solution found, which fails test_small_diagrams()
+            "a [ b ]; a c -; b d -; c d",
+            // see test_ranking_ignores_horizontal_relationships()
+            "- a b: vvvvvv; - a c: pppppp; d f: qqqqqq; d b: rrrrrr; e f: tttttt; e b: uuuuuu; f c: ssssss",
+        ];
+
+        #[test]
+        #[ignore = "known bug: ranking ignores horizontal relationships"]
+        pub fn test_ranking_ignores_horizontal_relationships() {
+            // b (below d) and c (below f) are solved on different ranks; geometry then pulls both
+            // onto a's row, where nothing keeps them apart
+            check(KNOWN_BAD[1], vec![&NoUnexpectedCollisions{}]);
+        }
+
+        #[test]
+        pub fn test_small_diagrams_route_around_boxes() {
+            for (prompt, _) in small_diagrams() {
+                if KNOWN_BAD.contains(&prompt) { continue; }
+                eprintln!("PROMPT: {prompt}");
+                // containers overlap their contents, which find_collisions() reports
+                check(prompt, vec![&NoUnexpectedCollisions{}]);
+            }
+        }
+
         #[test]
         pub fn test_long_hop() {
             check("a b c; a c", vec![&NoCollisions{}]);
@@ -7491,5 +13264,374 @@ pub mod frontend {
                 )
             ]);
         }
+
+        #[test]
+        pub fn test_text_measurement() {
+            use super::measure::{FontMetrics, TextMeasurer, TextRole};
+            let mono = FontMetrics::monospace();
+            assert!((mono.width("abc") - 3. * 8.66).abs() < 1e-9);
+            assert_eq!(mono.width("ab\nabcd"), mono.width("abcd"));
+            let serif = FontMetrics::serif();
+            assert!(serif.width("iiii") < serif.width("MMMM"));
+
+            let wide = |text: &str, _role: TextRole| 30. * text.chars().count() as f64;
+            let options = super::RenderOptions{text_measurer: Some(super::measure::SharedMeasurer::new(wide)), ..Default::default()};
+            let drawing = super::dom::draw_with("ab cd".into(), &Default::default(), &options).unwrap();
+            let widths = drawing.nodes.iter().filter_map(|n| match n {
+                Node::Div{width, ..} => Some(*width),
+                _ => None,
+            }).collect::<Vec<_>>();
+            assert!(widths.iter().all(|w| *w >= 60.), "{widths:?}");
+            assert_eq!(wide.line_height(TextRole::Box), 20.);
+
+            // narrower measurers draw narrower boxes too
+            let narrow = |text: &str, _role: TextRole| 2. * text.chars().count() as f64;
+            let options = super::RenderOptions{text_measurer: Some(super::measure::SharedMeasurer::new(narrow)), ..Default::default()};
+            let drawing = super::dom::draw_with("controller process".into(), &Default::default(), &options).unwrap();
+            let default = super::dom::draw("controller process".into()).unwrap();
+            let [Node::Div{width: narrow_width, ..}, Node::Div{width: default_width, ..}] = [div(&drawing, "controller"), div(&default, "controller")] else { unreachable!() };
+            assert!(narrow_width < default_width, "{narrow_width} {default_width}");
+            assert!(*narrow_width < 9. * "controller".len() as f64, "{narrow_width}");
+        }
+
+        #[test]
+        pub fn test_unicode_widths() {
+            use crate::graph_drawing::layout::{display_width, Len};
+            use super::measure::FontMetrics;
+            assert_eq!(display_width("Überwachung"), 11);
+            assert_eq!(display_width("U\u{308}berwachung"), 11);
+            assert_eq!(display_width("制御器"), 6);
+            assert_eq!(display_width("👩\u{200d}🔬"), 2);
+            assert_eq!(Len::len(&"制御器"), 6);
+
+            let mono = FontMetrics::monospace();
+            assert_eq!(mono.width("U\u{308}"), mono.width("U"));
+            assert!((mono.width("制御器") - mono.width("abcdef")).abs() < 1e-9);
+            assert_eq!(mono.width("👩\u{200d}🔬"), mono.width("ab"));
+
+            let narrow = super::dom::draw("ab 制御器".into()).unwrap();
+            let [Node::Div{width: ab, ..}, Node::Div{width: cjk, ..}] = [div(&narrow, "ab"), div(&narrow, "制御器")] else { unreachable!() };
+            assert!(cjk > ab);
+        }
+
+        #[test]
+        pub fn test_multiline_labels() {
+            let drawing = super::dom::draw(r"a\nb c".into()).unwrap();
+            let [Node::Div{label, height: h1, ..}, Node::Div{height: h2, ..}] = [div(&drawing, r"a\nb"), div(&drawing, "c")] else { unreachable!() };
+            assert_eq!(label, "a\nb");
+            assert!(*h1 >= h2 + 19., "{h1} {h2}");
+
+            let measurer = super::measure::DefaultMeasurer::default();
+            let drawing = super::dom::draw_with("long_process_name".into(), &Default::default(), &super::RenderOptions{max_box_width: Some(60.), ..Default::default()}).unwrap();
+            let Node::Div{label, ..} = div(&drawing, "long_process_name") else { unreachable!() };
+            assert_eq!(label, "long_\nprocess_\nname");
+            assert_eq!(super::wrap_line("abcdefgh", 25., &measurer), vec!["abc", "def", "gh"]);
+        }
+
+        #[test]
+        pub fn test_render_options() {
+            use super::{RenderOptions, measure::{TextMeasurer, TextRole}};
+            let draw = |options: &RenderOptions| super::dom::draw_with("a b c: d".into(), &Default::default(), options).unwrap();
+            let drawing = draw(&RenderOptions::default());
+            let [Node::Div{vpos: a_vpos, height: a_height, ..}, Node::Div{vpos: b_vpos, ..}] = [div(&drawing, "a"), div(&drawing, "b")] else { unreachable!() };
+            assert!((a_height - 26.).abs() < 1.);
+            let tall = draw(&RenderOptions{box_height: 40., hop_separation: 40., ..Default::default()});
+            let [Node::Div{vpos: tall_a_vpos, height: tall_a_height, ..}, Node::Div{vpos: tall_b_vpos, ..}] = [div(&tall, "a"), div(&tall, "b")] else { unreachable!() };
+            assert!((tall_a_height - 40.).abs() < 1.);
+            assert!(tall_b_vpos - tall_a_vpos > b_vpos - a_vpos + 50.);
+
+            let big = RenderOptions{box_font_size: 32., ..Default::default()}.measurer();
+            assert!(big.width("abc", TextRole::Box) > DefaultMeasurer::default().width("abc", TextRole::Box));
+            // and text is drawn at the sizes it was measured at
+            let drawing = draw(&RenderOptions{box_font_size: 32., label_font_size: 10., ..Default::default()});
+            let svg = drawing.to_svg();
+            assert!(svg.contains("g.box text { font-size: 32px; }") && svg.contains("g.label, g.box.note text { font-size: 10px; }"), "{svg}");
+        }
+
+        #[test]
+        pub fn test_orientation() {
+            use super::{Orientation, RenderOptions};
+            let draw = |orientation: Orientation| {
+                let options = RenderOptions{orientation, ..Default::default()};
+                super::dom::draw_with("controller process: go / done".into(), &Default::default(), &options).unwrap()
+            };
+            let tb = draw(Orientation::TopToBottom);
+            let [Node::Div{vpos: a_top, width: a_width, ..}, Node::Div{vpos: b_top, ..}] = [div(&tb, "controller"), div(&tb, "process")] else { unreachable!() };
+            assert!(a_top < b_top);
+
+            let bt = draw(Orientation::BottomToTop);
+            let [Node::Div{vpos: a_top, width: a_width2, ..}, Node::Div{vpos: b_top, ..}] = [div(&bt, "controller"), div(&bt, "process")] else { unreachable!() };
+            assert!(a_top > b_top);
+            assert!((a_width - a_width2).abs() < 1.);
+
+            let lr = draw(Orientation::LeftToRight);
+            let [Node::Div{hpos: a_left, vpos: a_top, width: a_width, height: a_height, ..}, Node::Div{hpos: b_left, vpos: b_top, ..}] = [div(&lr, "controller"), div(&lr, "process")] else { unreachable!() };
+            assert!(a_left + a_width <= *b_left);
+            assert!((a_top - b_top).abs() < 1.);
+            assert!(a_width > a_height);
+            assert!(lr.viewbox_width > lr.viewbox_height && tb.viewbox_height > tb.viewbox_width);
+            assert!(lr.nodes.iter().any(|n| matches!(n, Node::Svg{dir, path, ..} if dir == "horizontal" && path.starts_with("M "))));
+
+            let rl = draw(Orientation::RightToLeft);
+            let [Node::Div{hpos: a_left, ..}, Node::Div{hpos: b_left, width: b_width, ..}] = [div(&rl, "controller"), div(&rl, "process")] else { unreachable!() };
+            assert!(b_left + b_width <= *a_left);
+
+            // containers stay as wide as their labels whichever way ranks run
+            let container_width = |orientation: Orientation| {
+                let options = RenderOptions{orientation, ..Default::default()};
+                let drawing = super::dom::draw_with("supervisory_controller [ b ]".into(), &Default::default(), &options).unwrap();
+                let Node::Div{width, ..} = div(&drawing, "supervisory_controller") else { unreachable!() };
+                *width
+            };
+            assert!((container_width(Orientation::LeftToRight) - container_width(Orientation::TopToBottom)).abs() < 1.);
+
+            assert_eq!("LR".parse::<Orientation>().unwrap(), Orientation::LeftToRight);
+            assert!("XY".parse::<Orientation>().is_err());
+        }
+
+        #[test]
+        pub fn test_style_blocks() {
+            use super::styling::{resolve, stylesheet, Properties};
+            let model = "warn: @ { stroke: red; fill: yellow; dash: 4 2; weight: bold }; x [ a b: go ]; x @ warn; c @ red";
+            let drawing = super::dom::draw(model.into()).unwrap();
+            let [Node::Div{classes: x, ..}, Node::Div{classes: a, ..}, Node::Div{classes: c, ..}] = [div(&drawing, "x"), div(&drawing, "a"), div(&drawing, "c")] else { unreachable!() };
+            let Some(Node::Svg{classes: a_b, ..}) = drawing.nodes.iter().find(|n| n.key().starts_with("a_b_")) else { unreachable!() };
+            assert!(x.contains("warn") && a.contains("warn") && a_b.contains("warn"));
+            // plain classes are not inherited
+            assert!(!c.contains("warn") && c.contains("red"));
+
+            let warn = &drawing.styles["warn"];
+            assert_eq!(warn, &Properties{
+                stroke: Some("red".into()),
+                fill: Some("yellow".into()),
+                dash: Some("4 2".into()),
+                weight: Some("bold".into()),
+                shape: None,
+                description: None,
+            });
+            let css = stylesheet(&drawing.styles);
+            assert!(css.contains("div.box.warn { border-color: red !important; background-color: yellow;"));
+            assert!(css.contains(".arrow.warn path, path.warn { stroke: red; stroke-dasharray: 4 2; }"));
+            assert_eq!(resolve(&drawing.styles, a).fill.as_deref(), Some("yellow"));
+            assert_eq!(resolve(&drawing.styles, "red"), Properties::default());
+        }
+
+        #[test]
+        pub fn test_themes() {
+            use super::RenderOptions;
+            use super::measure::{Family, TextMeasurer, TextRole};
+            use super::theme::{Arrowhead, Theme};
+            for name in Theme::BUILTIN {
+                let theme: Theme = name.parse().unwrap();
+                assert_eq!(theme.name, name);
+            }
+            assert!("neon".parse::<Theme>().is_err());
+            assert_eq!(Theme::default(), Theme::classic());
+
+            let blueprint = Theme::blueprint();
+            let css = blueprint.css();
+            assert!(css.contains(&format!("g.box rect, .box path.outline {{ stroke: {};", blueprint.light.stroke)));
+            assert!(css.contains("@media (prefers-color-scheme: dark)"));
+            assert!(css.contains(&blueprint.dark.as_ref().unwrap().fill));
+            assert!(css.contains(".hidden { display: none; }"));
+
+            let ink = Theme::ink();
+            assert_eq!(ink.arrowhead, Arrowhead::Open);
+            assert!(ink.css().contains("marker.open path, marker.none path { fill: none !important; }"));
+            assert!(!ink.css().contains("prefers-color-scheme: dark) {\ndiv.box"));
+
+            // boxes are sized for the theme's fonts
+            let width = |theme: Theme| RenderOptions{theme, ..Default::default()}.measurer().width("controller", TextRole::Box);
+            assert!(width(Theme::ink()) > width(Theme::classic()));
+            assert_eq!(Family::from_css(&Theme::blueprint().box_font), Family::Sans);
+            assert_eq!(Family::from_css(r#""Fira Code", monospace"#), Family::Mono);
+
+            let options = RenderOptions{theme: ink.clone(), ..Default::default()};
+            let drawing = super::dom::draw_with("a [ b c ]".into(), &Default::default(), &options).unwrap();
+            assert_eq!(drawing.theme, ink);
+            assert!(matches!(div(&drawing, "a"), Node::Div{classes, ..} if classes.contains("container")));
+            assert!(matches!(div(&drawing, "b"), Node::Div{classes, ..} if !classes.contains("container")));
+        }
+
+        #[test]
+        pub fn test_shapes() {
+            use super::styling::Shape;
+            assert_eq!("database".parse::<Shape>().unwrap(), Shape::Cylinder);
+            assert!("hexagon".parse::<Shape>().is_err());
+            for shape in [Shape::Ellipse, Shape::Diamond, Shape::Cylinder, Shape::Queue, Shape::Actor] {
+                assert_eq!(shape.to_string().parse::<Shape>().unwrap(), shape);
+                let (width, height) = shape.grow(40., 26.);
+                assert!(width >= 40. && height >= 26. && width * height > 40. * 26., "{shape}");
+                assert!(shape.outline(width, height).is_some(), "{shape}");
+            }
+            assert_eq!(Shape::Rounded.outline(40., 26.), None);
+            assert_eq!(Shape::Diamond.inset(0., 80., 52., false), 0.);
+            assert_eq!(Shape::Diamond.inset(20., 80., 52., false), 13.);
+
+            let model = "db: @ { shape: cylinder }; choice: @ { shape: diamond }; a b: go; b c: save; b @ choice; c @ db";
+            let drawing = super::dom::draw(model.into()).unwrap();
+            let [
+                Node::Div{width: a_width, height: a_height, shape: a_shape, ..},
+                Node::Div{width: b_width, height: b_height, shape: b_shape, vpos: b_top, ..},
+                Node::Div{height: c_height, shape: c_shape, ..},
+            ] = [div(&drawing, "a"), div(&drawing, "b"), div(&drawing, "c")] else { unreachable!() };
+            assert_eq!((*a_shape, *b_shape, *c_shape), (Shape::Rect, Shape::Diamond, Shape::Cylinder));
+            assert!(b_width > a_width && b_height > a_height && c_height > a_height);
+
+            // the arrow into the diamond ends on its outline, below the top of its bounding box
+            let control_points = drawing.nodes.iter().find_map(|n| match n {
+                Node::Svg{key, control_points, ..} if key.starts_with("a_b_") => Some(control_points.clone()),
+                _ => None,
+            }).unwrap();
+            let (_, end) = control_points.last().unwrap();
+            assert!(end + 7. > *b_top, "{end} {b_top}");
+        }
+
+        #[test]
+        pub fn test_arrow_styles() {
+            use super::styling::{ArrowStyle, Line};
+            use super::theme::{Arrowhead, Theme};
+            let style = ArrowStyle::from_classes("red dashed open thick dotted");
+            assert_eq!(style, ArrowStyle{line: Line::Dotted, head: Some(Arrowhead::Open), both: false, weight: Some(2.)});
+            assert_eq!(ArrowStyle::from_classes("both").gaps(false, Arrowhead::Triangle), (7., 7.));
+            assert_eq!(ArrowStyle::from_classes("none both").gaps(true, Arrowhead::Triangle), (0., 0.));
+            let css = Theme::default().css();
+            assert!(css.contains(".arrow.dashed svg > path, path.arrow.dashed { stroke-dasharray: 6 4; }"));
+            assert!(css.contains(".arrow.heavy svg > path, path.arrow.heavy { stroke-width: 3; }"));
+
+            let model = "a b: go; a b: > @ dashed; b c: save @ none red; c d: sync @ both";
+            let drawing = super::dom::draw(model.into()).unwrap();
+            let arrow = |prefix: &str| drawing.nodes.iter().find_map(|n| match n {
+                Node::Svg{key, classes, control_points, ..} if key.starts_with(prefix) => Some((classes.clone(), control_points.clone())),
+                _ => None,
+            }).unwrap();
+            let [Node::Div{vpos: c_top, height: c_height, ..}, Node::Div{vpos: d_top, ..}] = [div(&drawing, "c"), div(&drawing, "d")] else { unreachable!() };
+            let (classes, _) = arrow("a_b_");
+            assert!(classes.split_whitespace().any(|c| c == "dashed"));
+            // keywords applied to labels style their arrows too, but other label classes do not
+            let (classes, points) = arrow("b_c_");
+            assert!(classes.split_whitespace().any(|c| c == "none") && !classes.split_whitespace().any(|c| c == "red"));
+            assert!((points.last().unwrap().1 - c_top).abs() < 1.);
+            let (classes, points) = arrow("c_d_");
+            assert!(classes.split_whitespace().any(|c| c == "both"));
+            assert!((points.first().unwrap().1 - (c_top + c_height) - 7.).abs() < 1.);
+            assert!((d_top - points.last().unwrap().1 - 7.).abs() < 1.);
+        }
+
+        #[test]
+        pub fn test_selectors() {
+            use crate::graph_drawing::eval::glob;
+            assert!(glob("*", "a") && glob("sensor_*", "sensor_1") && glob("*_in", "x_in") && glob("a*b*c", "abbc"));
+            assert!(!glob("sensor_*", "actuator_1") && !glob("a*b", "abc") && !glob("a", "ab"));
+
+            let model = "x [ a b: go ]; c a: go / back; c b: stop; sensor_1 c: run; x.* @ blue; sensor_* @ hidden; c * @ red; * *: go @ bold; * b: > @ dashed";
+            let drawing = super::dom::draw(model.into()).unwrap();
+            let classes = |prefix: &str| drawing.nodes.iter().filter_map(|n| match n {
+                Node::Div{key, classes, ..} if key == prefix => Some(classes.clone()),
+                Node::Svg{key, classes, labels, ..} if prefix.ends_with('_') && key.starts_with(prefix) => Some(format!("{classes} | {}", labels.first().map(|l| l.classes.as_str()).unwrap_or_default())),
+                _ => None,
+            }).collect::<Vec<_>>();
+            let has = |prefix: &str, class: &str| !classes(prefix).is_empty() && classes(prefix).iter().all(|classes| classes.split_whitespace().any(|c| c == class));
+            let lacks = |prefix: &str, class: &str| !classes(prefix).is_empty() && classes(prefix).iter().all(|classes| !classes.split_whitespace().any(|c| c == class));
+
+            // patterns never become processes
+            assert!(drawing.nodes.iter().all(|n| !matches!(n, Node::Div{key, ..} if key.contains('*'))));
+            // processes by wildcard and by container
+            assert!(has("sensor_1", "hidden") && lacks("c", "hidden"));
+            assert!(has("a", "blue") && has("b", "blue") && lacks("x", "blue") && lacks("c", "blue"));
+            // arrows out of c, but not c itself
+            assert!(has("c_a_vertical_forward_", "red") && has("c_a_vertical_reverse_", "red") && has("c_b_", "red"));
+            assert!(lacks("c", "red") && lacks("a_b_", "red") && lacks("sensor_1_c_", "red"));
+            // arrows labeled go, and their labels
+            assert!(has("a_b_", "bold") && has("c_a_vertical_forward_", "bold") && lacks("c_a_vertical_reverse_", "bold") && lacks("c_b_", "bold"));
+            // forward arrows into b
+            assert!(has("a_b_", "dashed") && has("c_b_", "dashed") && lacks("c_a_", "dashed"));
+        }
+
+        #[test]
+        pub fn test_label_hop() {
+            use super::{LabelHop, RenderOptions};
+            let model = "a b; b c; c d; a d: go / back";
+            let labels = |label_hop: LabelHop| {
+                let options = RenderOptions{label_hop, ..Default::default()};
+                let drawing = super::dom::draw_with(model.into(), &Default::default(), &options).unwrap();
+                assert!(drawing.collisions.is_empty(), "{label_hop}: {:?}", drawing.collisions);
+                let arrows = drawing.nodes.iter().filter(|n| n.key().starts_with("a_d_")).count();
+                assert_eq!(arrows, 2, "{label_hop}");
+                assert!(!drawing.to_svg().contains(r#"d="""#), "{label_hop}");
+                let mut labels = drawing.nodes.iter().flat_map(|n| match n {
+                    Node::Svg{key, rel, labels, ..} if key.starts_with("a_d_") => labels.iter().map(|label| (rel.clone(), label.vpos)).collect(),
+                    _ => vec![],
+                }).collect::<Vec<_>>();
+                labels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                labels
+            };
+            let vpos = |labels: &[(String, f64)], rel: &str| labels.iter().find(|(r, _)| r == rel).unwrap().1;
+
+            let head = labels(LabelHop::Head);
+            let (top, middle, bottom) = (labels(LabelHop::Top), labels(LabelHop::Middle), labels(LabelHop::Bottom));
+            assert_eq!(head.len(), 2);
+            assert!(vpos(&head, "reverse") < vpos(&head, "forward"));
+            for rel in ["forward", "reverse"] {
+                assert!(vpos(&top, rel) < vpos(&middle, rel) && vpos(&middle, rel) < vpos(&bottom, rel));
+            }
+            // one label along each of the arrow's three segments, in each direction
+            assert_eq!(labels(LabelHop::Repeated).len(), 6);
+
+            assert_eq!("middle".parse::<LabelHop>().unwrap(), LabelHop::Middle);
+            assert!("sideways".parse::<LabelHop>().is_err());
+            assert_eq!(LabelHop::Middle.vertices(4, true), vec![vec![1, 2]]);
+            assert_eq!(LabelHop::Head.vertices(4, false), vec![vec![0]]);
+            assert_eq!(LabelHop::Repeated.vertices(3, true), vec![vec![0, 1], vec![1, 2]]);
+        }
+
+        #[test]
+        pub fn test_notes() {
+            use crate::graph_drawing::eval::{Note, Position, Val};
+            let model = "a b: go; a @ nwo: runs often; x [ b ] @ red si: plant, model";
+            let val = crate::graph_drawing::query::parse(model).unwrap();
+            let Val::Process{body: Some(body), ..} = &val else { panic!("expected a body") };
+            let notes = body.iter().find_map(|v| match v {
+                Val::Process{label: Some(l), notes: Some(notes), style, ..} if l == "x" => Some((notes.clone(), style.clone())),
+                _ => None,
+            });
+            assert_eq!(notes, Some((vec![Note{label: "plant model".into(), pos: Position::SI}], Some(vec!["red".into()]))));
+
+            let drawing = super::dom::draw(model.into()).unwrap();
+            let (a, note) = (rect(&drawing.nodes, "a"), rect(&drawing.nodes, "a_note_0"));
+            assert!(matches!(div(&drawing, "a_note_0"), Node::Div{classes, ..} if classes.contains("note_nwo")));
+            assert!(note.b <= a.t && (note.l - a.l).abs() < 1.);
+            let (x, note) = (rect(&drawing.nodes, "x"), rect(&drawing.nodes, "x_note_0"));
+            assert!(matches!(div(&drawing, "x_note_0"), Node::Div{classes, ..} if classes.contains("note_si")));
+            assert!(x.l <= note.l && note.r <= x.r && x.t <= note.t && note.b <= x.b);
+            assert!(rect(&drawing.nodes, "b").b <= note.t);
+            assert!(drawing.collisions.is_empty());
+        }
+
+        #[test]
+        pub fn test_inline_style_targets() {
+            // an inline `@` styles the process it follows, as it did before notes
+            for (model, styled, unstyled) in [
+                ("a; b @ red", "b", "a"),
+                ("a @ red; b", "a", "b"),
+                ("a b; c @ red", "c", "a"),
+                ("a b: go; c @ red", "c", "b"),
+                ("x [ a ] @ red", "x", "a"),
+                ("x [ a @ red ]", "a", "x"),
+            ] {
+                let drawing = super::dom::draw(model.into()).unwrap();
+                let red = |key: &str| matches!(div(&drawing, key), Node::Div{classes, ..} if classes.split_whitespace().any(|c| c == "red"));
+                assert!(red(styled) && !red(unstyled), "{model}");
+            }
+        }
+
+        #[test]
+        pub fn test_draw_diff() {
+            let drawing = super::dom::draw_diff("a b: x; a c: z".into(), "a b: x, y; a d".into()).unwrap();
+            assert!(matches!(div(&drawing, "c"), Node::Div{classes, ..} if classes.contains("removed")));
+            assert!(matches!(div(&drawing, "d"), Node::Div{classes, ..} if classes.contains("added")));
+            assert!(matches!(div(&drawing, "b"), Node::Div{classes, ..} if !classes.contains("added")));
+            assert!(drawing.nodes.iter().any(|n| matches!(n, Node::Svg{classes, ..} if classes.contains("a_b") && classes.ends_with("added"))));
+        }
     }
 }
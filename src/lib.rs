@@ -164,7 +164,12 @@ pub mod parser {
     //! * relations ::=  *name* *name* ... (**:** *labels* (**/** */ *labels*)?)*
     //! * labels ::= *label*... for single-word labels or *label* (**,** *label*)* for multi-word labels
     //! * nesting ::= **[** *model* **]**
+    //! * line breaks ::= **\\n** within a *name*, e.g., `long\nname`
     //! * alternatives ::= **{** *model* **}**
+    //! * templates ::= *name* **:** **$***param*... **[** *model* **]**, instantiated by *name* **:** **$***template* *arg*...
+    //! * notes ::= *name* **@** *style*... (**nwo** | **neo** | **nwi** | **si**)? **:** *text*
+    //! * style blocks ::= *style* **:** **@** **{** *property* **:** *value*... (**;** *property* **:** *value*...)* **}**, applied by *name* **@** *style*
+    //! * selectors ::= styled processes or relations whose names are patterns, like `* @ red`, `sensor_* @ hidden`, `plant.* @ blue` (the contents of `plant`), `controller * @ red`, or `* *: go @ bold`
     //!
     //! # Links
     //!
@@ -397,7 +402,7 @@ pub mod parser {
         %type #[token("@")] At;
         %type #[token("!")] Bang;
         %type #[regex("[\r\n;]+")] Nl;
-        %type #[regex(r#"[\p{XID_Start}$<>\-\*()_0-9][\p{XID_Continue}().\-\*_>&&[^:/@]]*(\\/[\p{XID_Continue}().\-\*_>&&[^:/@]]*)*"#)] Text &'s str;
+        %type #[regex(r#"[\p{XID_Start}$<>\-\*()_0-9][\p{XID_Continue}().\-\*_>&&[^:/@]]*(\\[/n][\p{XID_Continue}().\-\*_>&&[^:/@]]*)*"#)] Text &'s str;
         %type start Model<'s>;
         %type model Vec<Item<'s>>;
         %type item Item<'s>;
@@ -701,28 +706,66 @@ pub mod rest {
             height: f64,
             z_index: usize,
         },
-        /// Arrows with optional textual labels
+        /// Arrows with any number of textual labels
         Svg {
             key: String,
             path: String,
             z_index: usize,
             rel: String,
-            label: Option<Label>,
+            labels: Vec<Label>,
         },
     }
 
+    /// A lint warning about a "depiction", with the byte range it refers to, if known.
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    pub struct Warning {
+        pub rule: String,
+        pub message: String,
+        pub span: Option<(usize, usize)>,
+    }
+
     /// The data of a drawing of a "depiction".
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub struct Drawing {
         pub crossing_number: Option<usize>,
         pub viewbox_width: f64,
         pub nodes: Vec<Node>,
+        #[serde(default)]
+        pub warnings: Vec<Warning>,
     }
 
-    /// A drawing request containing a "depiction" to draw.
+    /// A drawing request containing a "depiction" to draw and, optionally, how to lay it out.
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub struct Draw {
-        pub text: String
+        pub text: String,
+        #[cfg(any(feature="osqp", feature="osqp-rust"))]
+        #[serde(default)]
+        pub options: crate::graph_drawing::frontend::RenderOptions,
+        /// Names of lint rules to disable, like the command line's `-A`, e.g. `["missing-feedback"]`
+        #[serde(default)]
+        pub allow: Vec<String>,
+    }
+
+    /// A request to draw a "depiction" as a PNG image, optionally with layout and image options.
+    #[cfg(all(any(feature="osqp", feature="osqp-rust"), feature="png"))]
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct DrawPng {
+        pub text: String,
+        #[serde(default)]
+        pub options: crate::graph_drawing::frontend::RenderOptions,
+        #[serde(default)]
+        pub png: crate::graph_drawing::frontend::png::PngOptions,
+    }
+
+    /// A request to draw a "depiction" as a PDF, optionally with layout and page options.
+    #[cfg(all(any(feature="osqp", feature="osqp-rust"), feature="pdf"))]
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct DrawPdf {
+        pub text: String,
+        #[serde(default)]
+        pub options: crate::graph_drawing::frontend::RenderOptions,
+        #[serde(default)]
+        pub pdf: crate::graph_drawing::frontend::pdf::PdfOptions,
     }
 
     /// A drawing response containing a [Drawing].
@@ -1,5 +1,6 @@
 use depict::graph_drawing::error::{Error};
 use depict::graph_drawing::frontend::log::Logger;
+use depict::graph_drawing::frontend::RenderOptions;
 use depict::graph_drawing::geometry::{*};
 use depict::graph_drawing::index::{LocSol, VerticalRank};
 use depict::graph_drawing::layout::{*};
@@ -20,7 +21,8 @@ pub fn tikz_escape(s: &str) -> String {
 
 pub fn render<'s>(data: String) -> Result<(), Error> {
     let mut logs = Logger::new();
-    let render_cell = depict::graph_drawing::frontend::render(Cow::Owned(data), &mut logs)?;
+    let options = RenderOptions{legend: true, ..Default::default()};
+    let render_cell = depict::graph_drawing::frontend::render(Cow::Owned(data), &options, &mut logs)?;
     let depiction = render_cell.borrow_dependent();
     
     let rs = &depiction.geometry_solution.rs;
@@ -255,6 +257,9 @@ pub fn render<'s>(data: String) -> Result<(), Error> {
     //     }}
     //     \endscope
     // "#), l, b, r, t, l, r, b, t);
+    if let Some(legend) = &depiction.legend {
+        print!("{}", legend.to_tikz());
+    }
     println!(indoc!(r#"
         }}
         \end{{document}}